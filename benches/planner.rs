@@ -0,0 +1,165 @@
+//! Times `planner::plan` on a synthetic bridge with hundreds of lights, to compare the serial
+//! planner with the `parallel` one:
+//!
+//! ```sh
+//! cargo bench --bench planner
+//! cargo bench --bench planner --features parallel
+//! ```
+
+use chrono::prelude::*;
+use hue_mie::backend::Backend;
+use hue_mie::config::Config;
+use hue_mie::curve::LightTarget;
+use hue_mie::hue::{Group, Light, LightStateChange, ResourceLink, Scene};
+use hue_mie::planner;
+use hue_mie::state::BridgeState;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const ROOMS: usize = 100;
+const LIGHTS_PER_ROOM: usize = 8;
+const ITERATIONS: u32 = 50;
+
+/// A bridge with `ROOMS` rooms of `LIGHTS_PER_ROOM` lights, each room with a dayshift scene
+/// that is active.
+struct SyntheticBridge {
+    lights: BTreeMap<usize, Light>,
+    groups: BTreeMap<usize, Group>,
+    scenes: BTreeMap<String, Scene>,
+}
+
+impl SyntheticBridge {
+    fn new() -> SyntheticBridge {
+        let mut bridge = SyntheticBridge {
+            lights: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            scenes: BTreeMap::new(),
+        };
+        for room in 0..ROOMS {
+            let light_ids: Vec<usize> = (0..LIGHTS_PER_ROOM)
+                .map(|light| room * LIGHTS_PER_ROOM + light + 1)
+                .collect();
+            let ids: Vec<String> = light_ids.iter().map(usize::to_string).collect();
+            let mut lightstates = serde_json::Map::new();
+            for light_id in light_ids.iter() {
+                let light = json!({
+                    "name": format!("Light {}", light_id),
+                    "type": "Color temperature light",
+                    "modelid": "LTW001",
+                    "state": { "on": true, "bri": 120, "ct": 350, "reachable": true },
+                });
+                bridge
+                    .lights
+                    .insert(*light_id, serde_json::from_value(light).unwrap());
+                lightstates.insert(
+                    light_id.to_string(),
+                    json!({ "on": true, "bri": 120, "ct": 350 }),
+                );
+            }
+            let group = json!({ "name": format!("Room {}", room), "type": "Room", "lights": ids });
+            bridge
+                .groups
+                .insert(room + 1, serde_json::from_value(group).unwrap());
+            let scene = json!({
+                "name": format!("Room {} dayshift", room),
+                "type": "LightScene",
+                "lights": ids,
+                "lightstates": lightstates,
+            });
+            bridge.scenes.insert(
+                format!("scene{}", room),
+                serde_json::from_value(scene).unwrap(),
+            );
+        }
+        bridge
+    }
+}
+
+impl Backend for SyntheticBridge {
+    fn get_all_lights(&self) -> Result<BTreeMap<usize, Light>, Box<dyn Error>> {
+        Ok(self.lights.clone())
+    }
+
+    fn get_all_groups(&self) -> Result<BTreeMap<usize, Group>, Box<dyn Error>> {
+        Ok(self.groups.clone())
+    }
+
+    fn get_all_scenes(&self) -> Result<BTreeMap<String, Scene>, Box<dyn Error>> {
+        Ok(self.scenes.clone())
+    }
+
+    fn get_scene_with_states(&self, scene_id: &str) -> Result<Scene, Box<dyn Error>> {
+        Ok(self.scenes[scene_id].clone())
+    }
+
+    fn set_light_state(&self, _: usize, _: &LightStateChange) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn set_light_state_in_scene(
+        &self,
+        _: &str,
+        _: usize,
+        _: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn recall_scene_in_group(&self, _: usize, _: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn create_scene(&self, _: &str, _: &[usize]) -> Result<String, Box<dyn Error>> {
+        Err("not supported".into())
+    }
+
+    fn set_scene_lights(&self, _: &str, _: &[usize]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn set_light_config(&self, _: usize, _: &Value) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn get_all_resourcelinks(&self) -> Result<BTreeMap<String, ResourceLink>, Box<dyn Error>> {
+        Ok(BTreeMap::new())
+    }
+
+    fn create_resourcelink(&self, _: &str, _: &str) -> Result<String, Box<dyn Error>> {
+        Err("not supported".into())
+    }
+
+    fn set_resourcelink_description(&self, _: &str, _: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let config: Config = toml::from_str("").unwrap();
+    let state = BridgeState::fetch(&SyntheticBridge::new(), planner::needs_states).unwrap();
+    let evening = Utc.with_ymd_and_hms(2024, 6, 21, 19, 0, 0).unwrap();
+    let light_target = LightTarget::at(&evening, &config.transitions, &config.location);
+    // Warm up, and check every scene is planned.
+    let commands = planner::plan(&state, &light_target, &config);
+    assert!(commands.len() >= ROOMS);
+    let mut total = Duration::from_secs(0);
+    for _ in 0..ITERATIONS {
+        let started = Instant::now();
+        let commands = planner::plan(&state, &light_target, &config);
+        total += started.elapsed();
+        assert!(!commands.is_empty());
+    }
+    println!(
+        "plan, {} scenes of {} lights, {}: {:.2} ms per plan",
+        ROOMS,
+        LIGHTS_PER_ROOM,
+        if cfg!(feature = "parallel") {
+            "parallel"
+        } else {
+            "serial"
+        },
+        total.as_secs_f64() * 1000. / f64::from(ITERATIONS)
+    );
+}
@@ -25,7 +25,63 @@ pub fn decimal_day(day: &DayOfMonth) -> f64 {
         - day.time_zone / 24.
 }
 
-pub fn sun_altitude(dt: DateTime<Utc>, geopoint: coords::GeographPoint) -> f64 {
+/// How the solar position is computed.
+///
+/// `Vsop87` evaluates the full VSOP87 series for the Earth's orbit, accurate to about an arc
+/// second but costing a few hundred periodic terms per evaluation. `Fast` uses the low-accuracy
+/// formulae from Meeus (chapter 25): about 0.01 degrees off, which shifts dawn by a few seconds,
+/// at a fraction of the CPU time; useful on small boards with `sun_cache_secs = 0`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SolarModel {
+    Fast,
+    #[default]
+    Vsop87,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarOptions {
+    /// Report the apparent altitude, raised by atmospheric refraction, rather than the true
+    /// (geometric) one. Refraction lifts the sun by about half a degree at the horizon.
+    pub refraction: bool,
+    pub model: SolarModel,
+}
+
+impl Default for SolarOptions {
+    fn default() -> Self {
+        SolarOptions {
+            refraction: true,
+            model: SolarModel::Vsop87,
+        }
+    }
+}
+
+/// The sun's apparent ecliptic longitude (radians) by the low-accuracy method; its latitude is
+/// taken to be zero.
+fn fast_sun_longitude(julian_day: f64) -> f64 {
+    let t = (julian_day - 2_451_545.0) / 36525.0;
+    let mean_long = 280.466_46 + 36_000.769_83 * t + 0.000_303_2 * t * t;
+    let mean_anomaly = (357.529_11 + 35_999.050_29 * t - 0.000_153_7 * t * t).to_radians();
+    let center = (1.914_602 - 0.004_817 * t - 0.000_014 * t * t) * mean_anomaly.sin()
+        + (0.019_993 - 0.000_101 * t) * (2. * mean_anomaly).sin()
+        + 0.000_289 * (3. * mean_anomaly).sin();
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    (mean_long + center - 0.005_69 - 0.004_78 * omega.sin())
+        .rem_euclid(360.)
+        .to_radians()
+}
+
+/// The altitude (apparent by default) and the azimuth (measured westward from south) of the
+/// sun, in radians.
+pub fn sun_position(dt: DateTime<Utc>, geopoint: coords::GeographPoint) -> (f64, f64) {
+    sun_position_with(dt, geopoint, &SolarOptions::default())
+}
+
+pub fn sun_position_with(
+    dt: DateTime<Utc>,
+    geopoint: coords::GeographPoint,
+    options: &SolarOptions,
+) -> (f64, f64) {
     let day_of_month = DayOfMonth {
         day: dt.day() as u8,
         hr: dt.hour() as u8,
@@ -43,14 +99,17 @@ pub fn sun_altitude(dt: DateTime<Utc>, geopoint: coords::GeographPoint) -> f64 {
     let julian_day = julian_day(&date);
     debug!("julian_day: {}", julian_day);
 
-    let (sun_ecl_point, _) = sun::geocent_ecl_pos(julian_day);
-    debug!(
-        "Ecliptic point of sun: {}, {}",
-        sun_ecl_point.long, sun_ecl_point.lat
-    );
+    let (sun_long, sun_lat) = match options.model {
+        SolarModel::Vsop87 => {
+            let (sun_ecl_point, _) = sun::geocent_ecl_pos(julian_day);
+            (sun_ecl_point.long, sun_ecl_point.lat)
+        }
+        SolarModel::Fast => (fast_sun_longitude(julian_day), 0.),
+    };
+    debug!("Ecliptic point of sun: {}, {}", sun_long, sun_lat);
 
     let oblq_eclip = ecliptic::mn_oblq_laskar(julian_day);
-    let (asc, dec) = eq_frm_ecl2!(sun_ecl_point.long, sun_ecl_point.lat, oblq_eclip);
+    let (asc, dec) = eq_frm_ecl2!(sun_long, sun_lat, oblq_eclip);
     debug!("Sun asc: {}, dec: {}", asc, dec);
 
     let hr_angle = mn_sidr(julian_day) + geopoint.long - asc;
@@ -59,8 +118,556 @@ pub fn sun_altitude(dt: DateTime<Utc>, geopoint: coords::GeographPoint) -> f64 {
     let alt = coords::alt_frm_eq(hr_angle, dec, geopoint.lat);
     debug!("Real altitude: {}", alt);
 
+    let azimuth = coords::az_frm_eq(hr_angle, dec, geopoint.lat);
+    if !options.refraction {
+        return (alt, azimuth);
+    }
     let apparent_alt = atmos::refrac_frm_true_alt(alt) + alt;
     debug!("Apparent altitude: {}", apparent_alt);
 
-    apparent_alt
+    (apparent_alt, azimuth)
+}
+
+pub fn sun_altitude(dt: DateTime<Utc>, geopoint: coords::GeographPoint) -> f64 {
+    sun_position(dt, geopoint).0
+}
+
+/// What the observer's surroundings do to the perceived sun: an elevated observer sees past the
+/// geometric horizon, while hills and buildings hide the sun until it clears them.
+#[derive(Clone, Debug, Default)]
+pub struct Horizon {
+    /// How far (radians) the visible horizon lies below the geometric one.
+    dip: f64,
+    /// (azimuth from north, altitude) pairs in radians, sorted by azimuth.
+    profile: Vec<(f64, f64)>,
+}
+
+impl Horizon {
+    /// `elevation` in metres, `profile` as (azimuth from north, altitude) pairs in degrees.
+    pub fn new(elevation: f64, profile: &[(f64, f64)]) -> Horizon {
+        let mut profile: Vec<(f64, f64)> = profile
+            .iter()
+            .map(|(azimuth, alt)| (azimuth.rem_euclid(360.).to_radians(), alt.to_radians()))
+            .collect();
+        profile.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Horizon {
+            dip: (0.0293 * elevation.max(0.).sqrt()).to_radians(),
+            profile,
+        }
+    }
+
+    /// The altitude of the obstruction at `azimuth` (from north), interpolated between the
+    /// neighbouring profile points, wrapping around north.
+    fn obstruction(self: &Horizon, azimuth: f64) -> f64 {
+        let full = 2. * std::f64::consts::PI;
+        let (first, last) = match (self.profile.first(), self.profile.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return 0.,
+        };
+        let before = self
+            .profile
+            .iter()
+            .rev()
+            .find(|(az, _)| *az <= azimuth)
+            .cloned()
+            .unwrap_or((last.0 - full, last.1));
+        let after = self
+            .profile
+            .iter()
+            .find(|(az, _)| *az > azimuth)
+            .cloned()
+            .unwrap_or((first.0 + full, first.1));
+        if after.0 - before.0 <= 0. {
+            return before.1;
+        }
+        before.1 + (after.1 - before.1) * (azimuth - before.0) / (after.0 - before.0)
+    }
+
+    /// The sun's altitude above the horizon as the observer sees it. `azimuth` is measured
+    /// westward from south, as returned by `sun_position`.
+    pub fn correct(self: &Horizon, alt: f64, azimuth: f64) -> f64 {
+        let from_north = (azimuth + std::f64::consts::PI).rem_euclid(2. * std::f64::consts::PI);
+        alt + self.dip - self.obstruction(from_north)
+    }
+}
+
+/// The lowest and highest solar altitude (radians) over a day, and when the sun peaks.
+#[derive(Clone, Copy, Debug)]
+pub struct DayAltitudes {
+    pub min: f64,
+    pub max: f64,
+    pub solar_noon: DateTime<Utc>,
+}
+
+/// The altitude from `altitude` every ten minutes over the 24 hours from `start`, with the
+/// moments it peaks and bottoms out added to the second. The altitude only rises or only sets
+/// between any two neighbouring samples, so each crossing of an altitude lies between two of them.
+fn altitude_samples(
+    start: DateTime<Utc>,
+    altitude: &dyn Fn(DateTime<Utc>) -> f64,
+) -> Vec<(DateTime<Utc>, f64)> {
+    let grid: Vec<(DateTime<Utc>, f64)> = (0..=144)
+        .map(|step| {
+            let dt = start + chrono::Duration::minutes(step * 10);
+            (dt, altitude(dt))
+        })
+        .collect();
+    let mut samples = grid.clone();
+    for window in grid.windows(3) {
+        let (before, before_alt) = window[0];
+        let alt = window[1].1;
+        let (after, after_alt) = window[2];
+        let peak = alt >= before_alt && alt >= after_alt;
+        if peak || (alt <= before_alt && alt <= after_alt) {
+            let extremum = extremum(altitude, before, after, peak);
+            samples.push((extremum, altitude(extremum)));
+        }
+    }
+    samples.sort_by_key(|(dt, _)| *dt);
+    samples
+}
+
+/// When `altitude` peaks (or with `peak` false, bottoms out) between `low` and `high`, to the
+/// second, found by bisection on the direction the altitude moves in.
+fn extremum(
+    altitude: &dyn Fn(DateTime<Utc>) -> f64,
+    mut low: DateTime<Utc>,
+    mut high: DateTime<Utc>,
+    peak: bool,
+) -> DateTime<Utc> {
+    while (high - low).num_seconds() > 1 {
+        let middle = low + (high - low) / 2;
+        let rising = altitude(middle + chrono::Duration::seconds(1)) > altitude(middle);
+        if rising == peak {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// The range of `altitude` over the 24 hours starting at `start`, and its peak to the second.
+fn altitude_range(start: DateTime<Utc>, altitude: &dyn Fn(DateTime<Utc>) -> f64) -> DayAltitudes {
+    let mut altitudes = DayAltitudes {
+        min: f64::MAX,
+        max: f64::MIN,
+        solar_noon: start,
+    };
+    for (dt, alt) in altitude_samples(start, altitude) {
+        if alt < altitudes.min {
+            altitudes.min = alt;
+        }
+        if alt > altitudes.max {
+            altitudes.max = alt;
+            altitudes.solar_noon = dt;
+        }
+    }
+    altitudes
+}
+
+/// The solar altitude's range over the 24 hours starting at `start`, and its peak to the second.
+pub fn day_altitudes(
+    start: DateTime<Utc>,
+    geopoint: &coords::GeographPoint,
+    options: &SolarOptions,
+) -> DayAltitudes {
+    let altitude = |dt: DateTime<Utc>| {
+        let point = coords::GeographPoint {
+            long: geopoint.long,
+            lat: geopoint.lat,
+        };
+        sun_position_with(dt, point, options).0
+    };
+    let altitudes = altitude_range(start, &altitude);
+    debug!(
+        "Day altitudes: min {}, max {}, solar noon {}",
+        altitudes.min.to_degrees(),
+        altitudes.max.to_degrees(),
+        altitudes.solar_noon
+    );
+    altitudes
+}
+
+/// Sun altitudes (degrees) of sunrise and sunset, and of the start of civil, nautical and
+/// astronomical twilight.
+pub const SUNRISE_ALTITUDE: f64 = -0.833;
+pub const CIVIL_TWILIGHT_ALTITUDE: f64 = -6.;
+pub const NAUTICAL_TWILIGHT_ALTITUDE: f64 = -12.;
+pub const ASTRONOMICAL_TWILIGHT_ALTITUDE: f64 = -18.;
+
+/// When the sun crosses the sunrise and twilight altitudes during one day, each as (rising,
+/// setting). `None` where it doesn't cross, e.g. near midsummer at high latitudes.
+#[derive(Clone, Copy, Debug)]
+pub struct SunTimes {
+    pub solar_noon: DateTime<Utc>,
+    pub sun: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    pub civil: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    pub nautical: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    pub astronomical: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+}
+
+impl SunTimes {
+    /// One line with every time in `tz`, e.g. for logs and `hue_mie status`.
+    pub fn describe<Tz: TimeZone>(self: &SunTimes, tz: &Tz) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let time = |dt: Option<DateTime<Utc>>| {
+            dt.map_or_else(
+                || String::from("--:--"),
+                |dt| dt.with_timezone(tz).format("%H:%M").to_string(),
+            )
+        };
+        let range = |(rising, setting): (Option<DateTime<Utc>>, Option<DateTime<Utc>>)| {
+            format!("{}-{}", time(rising), time(setting))
+        };
+        format!(
+            "sunrise {}, solar noon {}, sunset {}; twilight: civil {}, nautical {}, \
+             astronomical {} ({})",
+            time(self.sun.0),
+            time(Some(self.solar_noon)),
+            time(self.sun.1),
+            range(self.civil),
+            range(self.nautical),
+            range(self.astronomical),
+            self.solar_noon.with_timezone(tz).format("%Z")
+        )
+    }
+}
+
+/// Rescales `alt` so that the day's highest altitude maps to `reference` and its lowest to
+/// `-reference`, keeping the horizon at zero. This keeps the evening transition equally wide at
+/// high latitudes, where the sun barely sets in summer.
+pub fn normalize_altitude(alt: f64, day: &DayAltitudes, reference: f64) -> f64 {
+    if alt >= 0. {
+        alt * reference / day.max.max(1e-3)
+    } else {
+        alt * reference / (-day.min).max(1e-3)
+    }
+}
+
+/// Avoids recomputing the solar position on every tick: the exact altitude is computed at the
+/// start and end of each `step_secs` bucket and interpolated linearly in between, and the day's
+/// altitude range is computed once per day. A step of zero computes every altitude exactly.
+#[derive(Clone, Debug)]
+pub struct AltitudeCache {
+    long: f64,
+    lat: f64,
+    step_secs: i64,
+    bucket: Option<(DateTime<Utc>, f64, f64)>,
+    day: Option<(DateTime<Utc>, DayAltitudes)>,
+    sunrise: Option<(DateTime<Utc>, Option<DateTime<Utc>>)>,
+    horizon: Horizon,
+    options: SolarOptions,
+}
+
+impl AltitudeCache {
+    pub fn new(geopoint: &coords::GeographPoint, step_secs: i64) -> AltitudeCache {
+        AltitudeCache {
+            long: geopoint.long,
+            lat: geopoint.lat,
+            step_secs,
+            bucket: None,
+            day: None,
+            sunrise: None,
+            horizon: Horizon::default(),
+            options: SolarOptions::default(),
+        }
+    }
+
+    pub fn with_options(self: AltitudeCache, options: SolarOptions) -> AltitudeCache {
+        AltitudeCache { options, ..self }
+    }
+
+    /// Corrects the altitudes this cache returns for the observer's horizon.
+    pub fn with_horizon(self: AltitudeCache, horizon: Horizon) -> AltitudeCache {
+        AltitudeCache { horizon, ..self }
+    }
+
+    fn corrected_altitude(self: &AltitudeCache, dt: DateTime<Utc>) -> f64 {
+        let (alt, azimuth) = sun_position_with(dt, self.geopoint(), &self.options);
+        self.horizon.correct(alt, azimuth)
+    }
+
+    fn geopoint(self: &AltitudeCache) -> coords::GeographPoint {
+        coords::GeographPoint {
+            long: self.long,
+            lat: self.lat,
+        }
+    }
+
+    pub fn altitude(self: &mut AltitudeCache, dt: DateTime<Utc>) -> f64 {
+        if self.step_secs <= 0 {
+            return self.corrected_altitude(dt);
+        }
+        let start = Utc
+            .timestamp_opt(
+                dt.timestamp() - dt.timestamp().rem_euclid(self.step_secs),
+                0,
+            )
+            .unwrap();
+        let (start_alt, end_alt) = match self.bucket {
+            Some((cached_start, start_alt, end_alt)) if cached_start == start => {
+                (start_alt, end_alt)
+            }
+            _ => {
+                let end = start + chrono::Duration::seconds(self.step_secs);
+                let start_alt = self.corrected_altitude(start);
+                let end_alt = self.corrected_altitude(end);
+                self.bucket = Some((start, start_alt, end_alt));
+                (start_alt, end_alt)
+            }
+        };
+        let fraction = (dt - start).num_milliseconds() as f64 / (self.step_secs * 1000) as f64;
+        start_alt + (end_alt - start_alt) * fraction
+    }
+
+    /// The first moment in the 24 hours from `start` the altitude rises above (or sets below)
+    /// `degrees`, to the second. The sunrise and twilight altitudes are true altitudes (the
+    /// sunrise one already allows for refraction), so refraction is left out whatever the
+    /// options say.
+    fn crossing(
+        self: &AltitudeCache,
+        start: DateTime<Utc>,
+        degrees: f64,
+        rising: bool,
+    ) -> Option<DateTime<Utc>> {
+        let threshold = degrees.to_radians();
+        let options = SolarOptions {
+            refraction: false,
+            ..self.options
+        };
+        let altitude = |dt: DateTime<Utc>| {
+            let (alt, azimuth) = sun_position_with(dt, self.geopoint(), &options);
+            self.horizon.correct(alt, azimuth)
+        };
+        let above = |dt: DateTime<Utc>| altitude(dt) >= threshold;
+        let samples = altitude_samples(start, &altitude);
+        samples.windows(2).find_map(|pair| {
+            let ((mut low, low_alt), (mut high, high_alt)) = (pair[0], pair[1]);
+            if (low_alt >= threshold) == rising || (high_alt >= threshold) != rising {
+                return None;
+            }
+            while (high - low).num_seconds() > 1 {
+                let middle = low + (high - low) / 2;
+                if above(middle) == rising {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            Some(high)
+        })
+    }
+
+    /// Sunrise, sunset and twilight times in the 24 hours from `start`.
+    pub fn sun_times(self: &mut AltitudeCache, start: DateTime<Utc>) -> SunTimes {
+        let both = |degrees| {
+            (
+                self.crossing(start, degrees, true),
+                self.crossing(start, degrees, false),
+            )
+        };
+        let times = SunTimes {
+            solar_noon: start,
+            sun: both(SUNRISE_ALTITUDE),
+            civil: both(CIVIL_TWILIGHT_ALTITUDE),
+            nautical: both(NAUTICAL_TWILIGHT_ALTITUDE),
+            astronomical: both(ASTRONOMICAL_TWILIGHT_ALTITUDE),
+        };
+        SunTimes {
+            solar_noon: self.day_altitudes(start).solar_noon,
+            ..times
+        }
+    }
+
+    /// The range of the horizon-corrected altitude over the 24 hours from `start`, computed once
+    /// per `start`.
+    pub fn day_altitudes(self: &mut AltitudeCache, start: DateTime<Utc>) -> DayAltitudes {
+        match self.day {
+            Some((cached_start, day)) if cached_start == start => day,
+            _ => {
+                let day = day_altitudes(start, &self.geopoint(), &self.options);
+                // The range of the altitudes `altitude` returns, so normalizing maps the
+                // highest one the observer sees to the reference. Solar noon stays when the sun
+                // truly peaks.
+                let seen = altitude_range(start, &|dt| self.corrected_altitude(dt));
+                let day = DayAltitudes {
+                    min: seen.min,
+                    max: seen.max,
+                    ..day
+                };
+                self.day = Some((start, day));
+                day
+            }
+        }
+    }
+
+    /// The sunrise in the 24 hours from `start`, searched once per `start`.
+    pub fn sunrise(self: &mut AltitudeCache, start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.sunrise {
+            Some((cached_start, sunrise)) if cached_start == start => sunrise,
+            _ => {
+                let sunrise = self.crossing(start, SUNRISE_ALTITUDE, true);
+                self.sunrise = Some((start, sunrise));
+                sunrise
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, long: f64) -> coords::GeographPoint {
+        coords::GeographPoint {
+            long: long.to_radians(),
+            lat: lat.to_radians(),
+        }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn options(refraction: bool, model: SolarModel) -> SolarOptions {
+        SolarOptions { refraction, model }
+    }
+
+    /// A published sunrise and sunset (UTC, rounded to the minute) for the day starting at
+    /// `midnight`.
+    struct ReferenceDay {
+        lat: f64,
+        long: f64,
+        midnight: DateTime<Utc>,
+        sunrise: DateTime<Utc>,
+        sunset: DateTime<Utc>,
+    }
+
+    fn reference_days() -> Vec<ReferenceDay> {
+        vec![
+            // London at the summer and winter solstices.
+            ReferenceDay {
+                lat: 51.5074,
+                long: -0.1278,
+                midnight: utc(2024, 6, 21, 0, 0),
+                sunrise: utc(2024, 6, 21, 3, 43),
+                sunset: utc(2024, 6, 21, 20, 21),
+            },
+            ReferenceDay {
+                lat: 51.5074,
+                long: -0.1278,
+                midnight: utc(2024, 12, 21, 0, 0),
+                sunrise: utc(2024, 12, 21, 8, 4),
+                sunset: utc(2024, 12, 21, 15, 53),
+            },
+            // Amsterdam at the summer solstice.
+            ReferenceDay {
+                lat: 52.3676,
+                long: 4.9041,
+                midnight: utc(2024, 6, 21, 0, 0),
+                sunrise: utc(2024, 6, 21, 3, 18),
+                sunset: utc(2024, 6, 21, 20, 6),
+            },
+        ]
+    }
+
+    fn assert_close(computed: Option<DateTime<Utc>>, expected: DateTime<Utc>) {
+        let computed = computed.expect("the sun rises and sets");
+        let off = (computed - expected).num_seconds().abs();
+        assert!(off <= 90, "{} is {}s off {}", computed, off, expected);
+    }
+
+    #[test]
+    fn sun_times_match_published_times() {
+        // Crossings are found on the true altitude, so only the model matters here.
+        for day in reference_days() {
+            for model in [SolarModel::Vsop87, SolarModel::Fast].iter() {
+                let mut cache = AltitudeCache::new(&point(day.lat, day.long), 0)
+                    .with_options(options(false, *model));
+                let times = cache.sun_times(day.midnight);
+                assert_close(times.sun.0, day.sunrise);
+                assert_close(times.sun.1, day.sunset);
+                assert_close(cache.sunrise(day.midnight), day.sunrise);
+            }
+        }
+    }
+
+    #[test]
+    fn refraction_raises_the_sun_near_the_horizon() {
+        let lift = |dt: DateTime<Utc>| {
+            let altitude = |refraction| {
+                AltitudeCache::new(&point(51.5074, -0.1278), 0)
+                    .with_options(options(refraction, SolarModel::Vsop87))
+                    .altitude(dt)
+            };
+            (altitude(true) - altitude(false)).to_degrees()
+        };
+        // About 34 arc minutes at the horizon, next to nothing with the sun high up.
+        let at_sunrise = lift(utc(2024, 6, 21, 3, 43));
+        assert!(at_sunrise > 0.4 && at_sunrise < 0.8, "{}", at_sunrise);
+        let at_noon = lift(utc(2024, 6, 21, 12, 0));
+        assert!(at_noon > 0. && at_noon < 0.02, "{}", at_noon);
+    }
+
+    #[test]
+    fn refraction_leaves_sun_times_alone() {
+        let day = &reference_days()[0];
+        let sun_times = |refraction| {
+            AltitudeCache::new(&point(day.lat, day.long), 0)
+                .with_options(options(refraction, SolarModel::Vsop87))
+                .sun_times(day.midnight)
+                .sun
+        };
+        assert_eq!(sun_times(true), sun_times(false));
+    }
+
+    #[test]
+    fn fast_model_stays_close_to_vsop87() {
+        for hour in 0..24 {
+            let dt = utc(2024, 3, 20, hour, 30);
+            let altitude = |model| {
+                let solar_options = options(true, model);
+                sun_position_with(dt, point(52.3676, 4.9041), &solar_options).0
+            };
+            let off = (altitude(SolarModel::Vsop87) - altitude(SolarModel::Fast))
+                .to_degrees()
+                .abs();
+            assert!(off < 0.02, "{} degrees off at {}", off, dt);
+        }
+    }
+
+    #[test]
+    fn solar_noon_is_when_the_sun_peaks() {
+        let mut cache = AltitudeCache::new(&point(52.3676, 4.9041), 0);
+        let day = cache.day_altitudes(utc(2024, 6, 21, 0, 0));
+        // Amsterdam's solar noon is at 11:42:18 UTC, with the sun 61 degrees high.
+        let published = Utc.with_ymd_and_hms(2024, 6, 21, 11, 42, 18).unwrap();
+        let off = (day.solar_noon - published).num_seconds().abs();
+        assert!(off <= 20, "{} is {}s off", day.solar_noon, off);
+        assert!((day.max.to_degrees() - 61.1).abs() < 0.2, "{}", day.max);
+        assert!((day.min.to_degrees() + 14.3).abs() < 0.3, "{}", day.min);
+    }
+
+    #[test]
+    fn the_day_range_follows_the_horizon_correction() {
+        let midnight = utc(2024, 6, 21, 0, 0);
+        let open = AltitudeCache::new(&point(52.3676, 4.9041), 0).day_altitudes(midnight);
+        // Hills ten degrees high all around.
+        let mut hidden = AltitudeCache::new(&point(52.3676, 4.9041), 0)
+            .with_horizon(Horizon::new(0., &[(0., 10.), (180., 10.)]));
+        let day = hidden.day_altitudes(midnight);
+        assert!((open.max - day.max - 10f64.to_radians()).abs() < 1e-9);
+        assert!((open.min - day.min - 10f64.to_radians()).abs() < 1e-9);
+        assert_eq!(day.solar_noon, open.solar_noon);
+
+        // Normalized, the highest altitude the observer sees maps to the reference.
+        let reference = 45f64.to_radians();
+        let noon = hidden.altitude(day.solar_noon);
+        let normalized = normalize_altitude(noon, &day, reference);
+        assert!((normalized - reference).abs() < 1e-9, "{}", normalized);
+    }
 }
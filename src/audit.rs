@@ -0,0 +1,215 @@
+use crate::config::AuditLogConfig;
+use crate::hue::{LightState, LightStateChange};
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Why a command was sent.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Curve,
+    Ramp,
+    Wakeup,
+    Override,
+    Hook,
+}
+
+#[derive(Serialize, Debug)]
+struct StateValues {
+    on: Option<bool>,
+    bri: Option<u8>,
+    ct: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bri_inc: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ct_inc: Option<i32>,
+}
+
+impl StateValues {
+    fn from_change(change: &LightStateChange) -> StateValues {
+        StateValues {
+            on: change.on,
+            bri: change.bri,
+            ct: change.ct,
+            bri_inc: change.bri_inc,
+            ct_inc: change.ct_inc,
+        }
+    }
+
+    fn from_state(state: &LightState) -> StateValues {
+        StateValues {
+            on: Some(state.on),
+            bri: Some(state.bri),
+            ct: state.ct,
+            bri_inc: None,
+            ct_inc: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    reason: Reason,
+    action: &'static str,
+    scene_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scene_name: Option<&'a str>,
+    group_id: Option<usize>,
+    light_id: Option<usize>,
+    old: Option<StateValues>,
+    new: Option<StateValues>,
+    success: bool,
+}
+
+/// An append-only JSON lines log of every command sent to the bridge. When the file grows past
+/// `max_bytes` it is rotated to `<path>.1`, `<path>.2`, ... keeping `keep` old files.
+pub struct AuditLog {
+    config: AuditLogConfig,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditLogConfig) -> AuditLog {
+        AuditLog { config }
+    }
+
+    fn rotated_path(self: &AuditLog, index: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.config.path, index))
+    }
+
+    fn rotate_if_needed(self: &AuditLog) -> std::io::Result<()> {
+        let size = fs::metadata(&self.config.path)
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if size < self.config.max_bytes {
+            return Ok(());
+        }
+        if self.config.keep == 0 {
+            return fs::remove_file(&self.config.path);
+        }
+        for index in (1..self.config.keep).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.config.path, self.rotated_path(1))
+    }
+
+    /// Appends an entry for `command`, with the previous values taken from `state` before the
+    /// command was applied.
+    pub fn record(
+        self: &AuditLog,
+        command: &LightCommand,
+        state: &BridgeState,
+        reason: Reason,
+        success: bool,
+    ) {
+        let entry = match command {
+            LightCommand::SetLightState {
+                light_id,
+                state: new,
+            } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "set_light_state",
+                scene_id: None,
+                scene_name: None,
+                group_id: None,
+                light_id: Some(*light_id),
+                old: state
+                    .lights
+                    .get(light_id)
+                    .map(|light| StateValues::from_state(&light.state)),
+                new: Some(StateValues::from_change(new)),
+                success,
+            },
+            LightCommand::SetSceneLightState {
+                scene_id,
+                light_id,
+                state: new,
+            } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "set_scene_light_state",
+                scene_id: Some(scene_id),
+                scene_name: None,
+                group_id: None,
+                light_id: Some(*light_id),
+                old: state
+                    .scenes
+                    .get(scene_id)
+                    .and_then(|scene| scene.lightstates.get(light_id))
+                    .map(StateValues::from_change),
+                new: Some(StateValues::from_change(new)),
+                success,
+            },
+            LightCommand::CreateScene { name, .. } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "create_scene",
+                scene_id: None,
+                scene_name: Some(name),
+                group_id: None,
+                light_id: None,
+                old: None,
+                new: None,
+                success,
+            },
+            LightCommand::SetSceneLights { scene_id, .. } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "set_scene_lights",
+                scene_id: Some(scene_id),
+                scene_name: None,
+                group_id: None,
+                light_id: None,
+                old: None,
+                new: None,
+                success,
+            },
+            LightCommand::SetLightConfig { light_id, .. } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "set_light_config",
+                scene_id: None,
+                scene_name: None,
+                group_id: None,
+                light_id: Some(*light_id),
+                old: None,
+                new: None,
+                success,
+            },
+            LightCommand::RecallScene { scene_id, group_id } => AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                reason,
+                action: "recall_scene",
+                scene_id: Some(scene_id),
+                scene_name: None,
+                group_id: Some(*group_id),
+                light_id: None,
+                old: None,
+                new: None,
+                success,
+            },
+        };
+        if let Err(err) = self.append(&entry) {
+            error!("Could not write audit log {}: {}", self.config.path, err);
+        }
+    }
+
+    fn append(self: &AuditLog, entry: &AuditEntry) -> Result<(), Box<dyn std::error::Error>> {
+        self.rotate_if_needed()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+#[cfg(feature = "daemon")]
+use crate::bridge::Bridge;
+use crate::hue::{Group, Light, LightStateChange, ResourceLink, Scene};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// The operations hue_mie needs from a bridge. Keeping this narrow lets the planner and executor
+/// run against anything that can list and modify scenes, not just a live `Bridge`.
+pub trait Backend {
+    fn get_all_lights(&self) -> Result<BTreeMap<usize, Light>, Box<dyn Error>>;
+
+    fn get_all_groups(&self) -> Result<BTreeMap<usize, Group>, Box<dyn Error>>;
+
+    fn get_all_scenes(&self) -> Result<BTreeMap<String, Scene>, Box<dyn Error>>;
+
+    fn get_scene_with_states(&self, scene_id: &str) -> Result<Scene, Box<dyn Error>>;
+
+    fn set_light_state(
+        &self,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>>;
+
+    fn set_light_state_in_scene(
+        &self,
+        scene_id: &str,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>>;
+
+    fn recall_scene_in_group(&self, group_id: usize, scene_id: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Creates a scene capturing the current state of `lights` and returns its id.
+    fn create_scene(&self, name: &str, lights: &[usize]) -> Result<String, Box<dyn Error>>;
+
+    /// Replaces the lights of a LightScene.
+    fn set_scene_lights(&self, scene_id: &str, lights: &[usize]) -> Result<(), Box<dyn Error>>;
+
+    /// Changes fields of a light's device config.
+    fn set_light_config(&self, light_id: usize, config: &Value) -> Result<(), Box<dyn Error>>;
+
+    fn get_all_resourcelinks(&self) -> Result<BTreeMap<String, ResourceLink>, Box<dyn Error>>;
+
+    /// Creates a resource link and returns its id.
+    fn create_resourcelink(&self, name: &str, description: &str) -> Result<String, Box<dyn Error>>;
+
+    fn set_resourcelink_description(
+        &self,
+        id: &str,
+        description: &str,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+#[cfg(feature = "daemon")]
+impl Backend for Bridge {
+    fn get_all_lights(&self) -> Result<BTreeMap<usize, Light>, Box<dyn Error>> {
+        Ok(Bridge::get_all_lights(self)?)
+    }
+
+    fn get_all_groups(&self) -> Result<BTreeMap<usize, Group>, Box<dyn Error>> {
+        Ok(Bridge::get_all_groups(self)?)
+    }
+
+    fn get_all_scenes(&self) -> Result<BTreeMap<String, Scene>, Box<dyn Error>> {
+        Ok(Bridge::get_all_scenes(self)?)
+    }
+
+    fn get_scene_with_states(&self, scene_id: &str) -> Result<Scene, Box<dyn Error>> {
+        Ok(Bridge::get_scene_with_states(self, scene_id)?)
+    }
+
+    fn set_light_state(
+        &self,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>> {
+        Bridge::set_light_state(self, light_id, state)?;
+        Ok(())
+    }
+
+    fn set_light_state_in_scene(
+        &self,
+        scene_id: &str,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>> {
+        Bridge::set_light_state_in_scene(self, scene_id, light_id, state)?;
+        Ok(())
+    }
+
+    fn recall_scene_in_group(&self, group_id: usize, scene_id: &str) -> Result<(), Box<dyn Error>> {
+        Bridge::recall_scene_in_group(self, group_id, scene_id)?;
+        Ok(())
+    }
+
+    fn create_scene(&self, name: &str, lights: &[usize]) -> Result<String, Box<dyn Error>> {
+        Ok(Bridge::create_scene(self, name, lights.to_vec(), false)?)
+    }
+
+    fn set_scene_lights(&self, scene_id: &str, lights: &[usize]) -> Result<(), Box<dyn Error>> {
+        Bridge::set_scene_lights(self, scene_id, lights)?;
+        Ok(())
+    }
+
+    fn set_light_config(&self, light_id: usize, config: &Value) -> Result<(), Box<dyn Error>> {
+        Bridge::set_light_config(self, light_id, config)?;
+        Ok(())
+    }
+
+    fn get_all_resourcelinks(&self) -> Result<BTreeMap<String, ResourceLink>, Box<dyn Error>> {
+        Ok(Bridge::get_all_resourcelinks(self)?)
+    }
+
+    fn create_resourcelink(&self, name: &str, description: &str) -> Result<String, Box<dyn Error>> {
+        Ok(Bridge::create_resourcelink(self, name, description)?)
+    }
+
+    fn set_resourcelink_description(
+        &self,
+        id: &str,
+        description: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        Bridge::set_resourcelink_description(self, id, description)?;
+        Ok(())
+    }
+}
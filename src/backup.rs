@@ -0,0 +1,86 @@
+use crate::backend::Backend;
+use crate::hue::{LightStateChange, Scene};
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SceneVersion {
+    pub name: String,
+    pub lightstates: BTreeMap<usize, LightStateChange>,
+}
+
+/// The lightstates managed scenes had before hue_mie first rewrote them, kept on disk so the
+/// scenes can be restored after uninstalling.
+#[derive(Debug, Default)]
+pub struct SceneBackup {
+    path: PathBuf,
+    scenes: BTreeMap<String, SceneVersion>,
+}
+
+impl SceneBackup {
+    pub fn default_path() -> PathBuf {
+        let mut path: PathBuf = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("hue_mie");
+        path.push("scene_backup.json");
+        path
+    }
+
+    pub fn load(path: PathBuf) -> Result<SceneBackup, Box<dyn Error>> {
+        let scenes = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(SceneBackup { path, scenes })
+    }
+
+    fn save(self: &SceneBackup) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.scenes)?)?;
+        Ok(())
+    }
+
+    pub fn scenes(self: &SceneBackup) -> &BTreeMap<String, SceneVersion> {
+        &self.scenes
+    }
+
+    /// Stores the scene's current lightstates unless an earlier version is already kept.
+    pub fn remember(self: &mut SceneBackup, scene_id: &str, scene: &Scene) {
+        if self.scenes.contains_key(scene_id) {
+            return;
+        }
+        info!("Backing up original lightstates of scene {}", scene.name);
+        self.scenes.insert(
+            scene_id.to_owned(),
+            SceneVersion {
+                name: scene.name.clone(),
+                lightstates: scene.lightstates.clone(),
+            },
+        );
+        if let Err(err) = self.save() {
+            error!("Could not write scene backup {:?}: {}", self.path, err);
+        }
+    }
+
+    /// Writes every backed-up scene back to the bridge. Returns the number of failed writes.
+    pub fn restore(self: &SceneBackup, backend: &dyn Backend) -> usize {
+        let mut failures = 0;
+        for (scene_id, version) in self.scenes.iter() {
+            println!("Restoring scene {} ({})", version.name, scene_id);
+            for (light_id, state) in version.lightstates.iter() {
+                if let Err(err) = backend.set_light_state_in_scene(scene_id, *light_id, state) {
+                    error!(
+                        "Could not restore light {} in scene {}: {}",
+                        light_id, version.name, err
+                    );
+                    failures += 1;
+                }
+            }
+        }
+        failures
+    }
+}
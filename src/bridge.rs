@@ -0,0 +1,302 @@
+//! A small blocking client for the Hue v1 REST API.
+
+use crate::http;
+use crate::hue::{
+    ApiError, Group, HueError, Light, LightStateChange, ResourceLink, Scene, Schedule, Sensor,
+};
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, HueError>;
+
+/// The bridge answers errors with HTTP 200 and a list of `{"error": {...}}` objects.
+fn api_error(value: &Value) -> Option<ApiError> {
+    value
+        .as_array()?
+        .iter()
+        .filter_map(|entry| entry.get("error"))
+        .filter_map(|error| serde_json::from_value(error.clone()).ok())
+        .next()
+}
+
+fn parse<T: DeserializeOwned>(value: Value) -> Result<T> {
+    match api_error(&value) {
+        Some(error) => Err(HueError::Api(error)),
+        None => Ok(serde_json::from_value(value)?),
+    }
+}
+
+fn parse_id_map<T: DeserializeOwned>(value: Value) -> Result<BTreeMap<usize, T>> {
+    let map: BTreeMap<String, T> = parse(value)?;
+    Ok(map
+        .into_iter()
+        .filter_map(|(id, item)| id.parse().ok().map(|id| (id, item)))
+        .collect())
+}
+
+pub struct Bridge {
+    ip: String,
+    username: String,
+    client: reqwest::blocking::Client,
+}
+
+impl Bridge {
+    pub fn new<S: Into<String>, U: Into<String>>(ip: S, username: U) -> Bridge {
+        Bridge {
+            ip: ip.into(),
+            username: username.into(),
+            client: http::bridge_client().expect("Could not create HTTP client"),
+        }
+    }
+
+    fn url(self: &Bridge, path: &str) -> String {
+        format!("http://{}/api/{}/{}", self.ip, self.username, path)
+    }
+
+    fn get<T: DeserializeOwned>(self: &Bridge, path: &str) -> Result<T> {
+        debug!("GET {}", path);
+        parse(self.client.get(self.url(path)).send()?.json()?)
+    }
+
+    fn send<B: Serialize>(
+        self: &Bridge,
+        method: reqwest::Method,
+        path: &str,
+        body: &B,
+    ) -> Result<Value> {
+        debug!("{} {}", method, path);
+        let response: Value = self
+            .client
+            .request(method, self.url(path))
+            .json(body)
+            .send()?
+            .json()?;
+        match api_error(&response) {
+            Some(error) => Err(HueError::Api(error)),
+            None => Ok(response),
+        }
+    }
+
+    fn put<B: Serialize>(self: &Bridge, path: &str, body: &B) -> Result<Value> {
+        self.send(reqwest::Method::PUT, path, body)
+    }
+
+    fn post<B: Serialize>(self: &Bridge, path: &str, body: &B) -> Result<Value> {
+        self.send(reqwest::Method::POST, path, body)
+    }
+
+    pub fn get_all_lights(self: &Bridge) -> Result<BTreeMap<usize, Light>> {
+        parse_id_map(self.get("lights")?)
+    }
+
+    pub fn get_light(self: &Bridge, id: usize) -> Result<Light> {
+        self.get(&format!("lights/{}", id))
+    }
+
+    pub fn set_light_state(self: &Bridge, id: usize, state: &LightStateChange) -> Result<Value> {
+        self.put(&format!("lights/{}/state", id), state)
+    }
+
+    pub fn get_all_groups(self: &Bridge) -> Result<BTreeMap<usize, Group>> {
+        parse_id_map(self.get("groups")?)
+    }
+
+    pub fn get_all_scenes(self: &Bridge) -> Result<BTreeMap<String, Scene>> {
+        self.get("scenes")
+    }
+
+    pub fn get_scene_with_states(self: &Bridge, id: &str) -> Result<Scene> {
+        self.get(&format!("scenes/{}", id))
+    }
+
+    pub fn set_light_state_in_scene(
+        self: &Bridge,
+        scene_id: &str,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<Value> {
+        self.put(
+            &format!("scenes/{}/lightstates/{}", scene_id, light_id),
+            state,
+        )
+    }
+
+    pub fn recall_scene_in_group(self: &Bridge, group_id: usize, scene_id: &str) -> Result<Value> {
+        self.put(
+            &format!("groups/{}/action", group_id),
+            &json!({ "scene": scene_id }),
+        )
+    }
+
+    /// Creates a scene capturing the current state of `lights` and returns its id.
+    pub fn create_scene(
+        self: &Bridge,
+        name: &str,
+        lights: Vec<usize>,
+        recycle: bool,
+    ) -> Result<String> {
+        let lights: Vec<String> = lights.iter().map(usize::to_string).collect();
+        let response = self.post(
+            "scenes",
+            &json!({ "name": name, "lights": lights, "recycle": recycle }),
+        )?;
+        Ok(response
+            .pointer("/0/success/id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned())
+    }
+
+    pub fn set_scene_lights(self: &Bridge, scene_id: &str, lights: &[usize]) -> Result<Value> {
+        let lights: Vec<String> = lights.iter().map(usize::to_string).collect();
+        self.put(
+            &format!("scenes/{}", scene_id),
+            &json!({ "lights": lights }),
+        )
+    }
+
+    pub fn set_light_config(self: &Bridge, light_id: usize, config: &Value) -> Result<Value> {
+        self.put(&format!("lights/{}/config", light_id), config)
+    }
+
+    pub fn get_all_schedules(self: &Bridge) -> Result<BTreeMap<String, Schedule>> {
+        self.get("schedules")
+    }
+
+    /// Creates a schedule that PUTs `body` to `path` (relative to this user's API root) at
+    /// `localtime`, and returns its id.
+    pub fn create_schedule(
+        self: &Bridge,
+        schedule: &Schedule,
+        path: &str,
+        body: &Value,
+    ) -> Result<String> {
+        let response = self.post(
+            "schedules",
+            &json!({
+                "name": schedule.name,
+                "description": schedule.description,
+                "localtime": schedule.localtime,
+                "command": {
+                    "address": format!("/api/{}/{}", self.username, path),
+                    "method": "PUT",
+                    "body": body,
+                },
+            }),
+        )?;
+        Ok(response
+            .pointer("/0/success/id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned())
+    }
+
+    pub fn delete_schedule(self: &Bridge, id: &str) -> Result<Value> {
+        self.send(
+            reqwest::Method::DELETE,
+            &format!("schedules/{}", id),
+            &Value::Null,
+        )
+    }
+
+    pub fn get_all_resourcelinks(self: &Bridge) -> Result<BTreeMap<String, ResourceLink>> {
+        self.get("resourcelinks")
+    }
+
+    /// Creates a resource link (linking the group of all lights) and returns its id.
+    pub fn create_resourcelink(self: &Bridge, name: &str, description: &str) -> Result<String> {
+        let response = self.post(
+            "resourcelinks",
+            &json!({
+                "name": name,
+                "description": description,
+                "classid": 10_000,
+                "links": ["/groups/0"],
+            }),
+        )?;
+        Ok(response
+            .pointer("/0/success/id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned())
+    }
+
+    pub fn set_resourcelink_description(
+        self: &Bridge,
+        id: &str,
+        description: &str,
+    ) -> Result<Value> {
+        self.put(
+            &format!("resourcelinks/{}", id),
+            &json!({ "description": description }),
+        )
+    }
+
+    pub fn get_all_sensors(self: &Bridge) -> Result<BTreeMap<usize, Sensor>> {
+        parse_id_map(self.get("sensors")?)
+    }
+}
+
+/// Asks the bridge at `ip` for a new username. Fails with the link button error until the
+/// button on the bridge has been pressed.
+pub fn register_user(ip: &str, devicetype: &str) -> Result<String> {
+    let response: Value = http::bridge_client()?
+        .post(format!("http://{}/api", ip))
+        .json(&json!({ "devicetype": devicetype }))
+        .send()?
+        .json()?;
+    if let Some(error) = api_error(&response) {
+        return Err(HueError::Api(error));
+    }
+    Ok(response
+        .pointer("/0/success/username")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned())
+}
+
+/// Finds bridges on the local network with an SSDP search.
+pub fn discover_upnp() -> std::io::Result<Vec<String>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(3)))?;
+    let search = "M-SEARCH * HTTP/1.1\r\n\
+                  HOST: 239.255.255.250:1900\r\n\
+                  MAN: \"ssdp:discover\"\r\n\
+                  MX: 2\r\n\
+                  ST: ssdp:all\r\n\r\n";
+    socket.send_to(search.as_bytes(), "239.255.255.250:1900")?;
+    let mut ips = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+        let response = String::from_utf8_lossy(&buffer[..len]);
+        if response.contains("IpBridge") || response.to_lowercase().contains("hue-bridgeid") {
+            let ip = from.ip().to_string();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    Ok(ips)
+}
+
+#[derive(Debug, Deserialize)]
+struct NupnpEntry {
+    internalipaddress: String,
+}
+
+/// Finds bridges through the Hue discovery portal.
+pub fn discover_nupnp() -> Result<Vec<String>> {
+    let entries: Vec<NupnpEntry> = http::client()?
+        .get("https://discovery.meethue.com")
+        .send()?
+        .json()?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| entry.internalipaddress)
+        .collect())
+}
@@ -0,0 +1,129 @@
+use crate::config::HueConfig;
+use crate::http;
+use chrono::prelude::*;
+use log::info;
+use std::error::Error;
+
+/// What the bridge's hardware and firmware support.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// The CLIP v2 API, used for palettes, gradients and scene metadata.
+    pub v2: bool,
+    /// Dynamic scene palettes (v2).
+    pub dynamic_scenes: bool,
+    pub entertainment: bool,
+    /// Custom power-on settings in the light config (API 1.26).
+    pub startup_settings: bool,
+}
+
+impl Capabilities {
+    /// Assumed when the bridge cannot be asked.
+    pub fn all() -> Capabilities {
+        Capabilities {
+            v2: true,
+            dynamic_scenes: true,
+            entertainment: true,
+            startup_settings: true,
+        }
+    }
+
+    pub fn log(self: &Capabilities) {
+        let on_off = |enabled: bool| if enabled { "enabled" } else { "not available" };
+        info!("CLIP v2 API: {}", on_off(self.v2));
+        info!("Dynamic scenes: {}", on_off(self.dynamic_scenes));
+        info!("Entertainment: {}", on_off(self.entertainment));
+        info!("Power-on settings: {}", on_off(self.startup_settings));
+    }
+}
+
+/// The fields of the bridge's v1 `/config` resource hue_mie looks at.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BridgeInfo {
+    #[serde(default)]
+    pub name: String,
+
+    #[serde(default)]
+    pub bridgeid: String,
+
+    #[serde(default)]
+    pub modelid: String,
+
+    #[serde(default)]
+    pub apiversion: String,
+
+    #[serde(default)]
+    pub swversion: String,
+
+    /// The bridge's idea of the current time in UTC, e.g. "2019-03-01T12:00:00".
+    #[serde(default, rename = "UTC")]
+    pub utc: Option<String>,
+
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl BridgeInfo {
+    /// The unauthenticated subset of the config (name, bridgeid, modelid and versions), for
+    /// bridges we have not paired with.
+    pub fn fetch_public(ip: &str) -> Result<BridgeInfo, Box<dyn Error>> {
+        Ok(http::bridge_client()?
+            .get(format!("http://{}/api/config", ip))
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+
+    /// Whether this bridge's id is in `allowed`. An empty list allows any bridge.
+    pub fn is_allowed(self: &BridgeInfo, allowed: &[String]) -> bool {
+        allowed.is_empty()
+            || allowed
+                .iter()
+                .any(|id| id.eq_ignore_ascii_case(&self.bridgeid))
+    }
+
+    pub fn fetch(hue_config: &HueConfig) -> Result<BridgeInfo, Box<dyn Error>> {
+        let url = format!(
+            "http://{}/api/{}/config",
+            hue_config.bridge_ip, hue_config.bridge_password
+        );
+        Ok(http::bridge_client()?
+            .get(&url)
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+
+    /// `apiversion` as (major, minor, patch); missing parts are 0.
+    pub fn api_version(self: &BridgeInfo) -> (u32, u32, u32) {
+        let mut parts = self
+            .apiversion
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0));
+        (
+            parts.next().unwrap_or(0),
+            parts.next().unwrap_or(0),
+            parts.next().unwrap_or(0),
+        )
+    }
+
+    /// Only the square (BSB002) bridge runs the newer firmware; the round v1 bridge stopped
+    /// at API 1.16.
+    pub fn capabilities(self: &BridgeInfo) -> Capabilities {
+        let (major, minor, _) = self.api_version();
+        let at_least = |wanted: u32| major > 1 || (major == 1 && minor >= wanted);
+        let square = self.modelid == "BSB002";
+        Capabilities {
+            v2: square && at_least(46),
+            dynamic_scenes: square && at_least(53),
+            entertainment: square && at_least(22),
+            startup_settings: at_least(26),
+        }
+    }
+
+    pub fn utc(self: &BridgeInfo) -> Option<DateTime<Utc>> {
+        let utc = self.utc.as_ref()?;
+        NaiveDateTime::parse_from_str(utc, "%Y-%m-%dT%H:%M:%S")
+            .ok()
+            .map(|naive| DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc))
+    }
+}
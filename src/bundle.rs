@@ -0,0 +1,119 @@
+//! Shared curve profiles: a name, a description and a `[transitions]` table in a TOML, JSON or
+//! YAML file, read from disk or a URL by `hue_mie import-profile`.
+
+use crate::config::{Config, ConfigFix, ConfigFormat, Transitions};
+use crate::curve;
+use crate::http;
+use crate::lint;
+use chrono::prelude::*;
+use std::error::Error;
+use std::path::Path;
+use toml::Value;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileBundle {
+    /// The profile name, used as the key under `[profiles]` unless another is given.
+    pub name: String,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    pub transitions: Transitions,
+}
+
+impl ProfileBundle {
+    /// Reads and validates a bundle. The format follows the extension of `source`.
+    pub fn load(source: &str) -> Result<ProfileBundle, Box<dyn Error>> {
+        let text = if source.starts_with("http://") || source.starts_with("https://") {
+            http::client()?
+                .get(source)
+                .send()?
+                .error_for_status()?
+                .text()?
+        } else {
+            std::fs::read_to_string(source)?
+        };
+        let value = ConfigFormat::from_path(Path::new(source)).read(&text)?;
+        let bundle: ProfileBundle = value
+            .try_into()
+            .map_err(|err| format!("{} is not a profile bundle: {}", source, err))?;
+        bundle
+            .transitions
+            .validate(&format!("profile {}", bundle.name))?;
+        Ok(bundle)
+    }
+
+    /// The fields to write to store the bundle as profile `name`, as paths from the config root.
+    pub fn fields(self: &ProfileBundle, name: &str) -> Result<Vec<ConfigFix>, Box<dyn Error>> {
+        let table = match Value::try_from(&self.transitions)? {
+            Value::Table(table) => table,
+            _ => return Err("transitions are not a table".into()),
+        };
+        Ok(table
+            .into_iter()
+            .map(|(key, value)| (vec!["profiles".to_owned(), name.to_owned(), key], value))
+            .collect())
+    }
+
+    /// Lines describing what storing the bundle as profile `name` would change: the fields that
+    /// differ from the profile it replaces (or the main transitions), the lints of the imported
+    /// curve, and both curves side by side over today.
+    pub fn preview(
+        self: &ProfileBundle,
+        config: &Config,
+        name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let current = config.profiles.get(name).unwrap_or(&config.transitions);
+        let mut lines = vec![format!("Profile {}", name)];
+        if let Some(description) = &self.description {
+            lines.push(format!("  {}", description));
+        }
+
+        let current_fields = Value::try_from(current)?;
+        let imported_fields = Value::try_from(&self.transitions)?;
+        if let (Some(current_fields), Some(imported_fields)) =
+            (current_fields.as_table(), imported_fields.as_table())
+        {
+            for (key, imported) in imported_fields.iter() {
+                match current_fields.get(key) {
+                    Some(current) if current == imported => {}
+                    Some(current) => lines.push(format!("  {}: {} -> {}", key, current, imported)),
+                    None => lines.push(format!("  {}: {}", key, imported)),
+                }
+            }
+        }
+
+        let mut imported_config = config.clone();
+        imported_config
+            .profiles
+            .insert(name.to_owned(), self.transitions.clone());
+        let prefix = format!("profiles.{}.", name);
+        for found in lint::lint(&imported_config)
+            .iter()
+            .filter(|found| found.message.starts_with(&prefix))
+        {
+            lines.push(format!("  warning: {}", found));
+        }
+
+        lines.push(format!(
+            "  {:>5}  {:>16}  {:>16}",
+            "time", "current bri/K", "imported bri/K"
+        ));
+        let midnight = curve::midnight(&Local::now());
+        let now = curve::day_curve(&midnight, current, &config.location, 120);
+        let imported = curve::day_curve(&midnight, &self.transitions, &config.location, 120);
+        for ((minute, now), (_, imported)) in now.iter().zip(imported.iter()) {
+            lines.push(format!(
+                "  {:02}:{:02}  {:>9.2}/{:>6.0}  {:>9.2}/{:>6.0}",
+                minute / 60,
+                minute % 60,
+                now.bri,
+                now.kelvin,
+                imported.bri,
+                imported.kelvin
+            ));
+        }
+        Ok(lines)
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::CalendarConfig;
+use crate::http;
+use chrono::prelude::*;
+use log::{debug, error, info};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// What a calendar event asks hue_mie to do while it runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Exception {
+    Pause,
+    Profile(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct CalendarEvent {
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Joins folded lines: in iCalendar a line starting with a space or tab continues the previous one.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        lines.push(line.to_owned());
+    }
+    lines
+}
+
+/// Whether the parameters mark an all-day value.
+fn is_date(params: &str) -> bool {
+    params.contains("VALUE=DATE") && !params.contains("VALUE=DATE-TIME")
+}
+
+/// Parses DTSTART/DTEND values. UTC times end in `Z`; times with a TZID or without zone are
+/// taken as host local time; all-day values (`VALUE=DATE`) start at local midnight.
+fn parse_datetime(params: &str, value: &str) -> Option<DateTime<Utc>> {
+    if is_date(params) {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+            .earliest()
+            .map(|dt| dt.with_timezone(&Utc));
+    }
+    if value.ends_with('Z') {
+        return NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+            .ok()
+            .map(|naive| DateTime::from_naive_utc_and_offset(naive, Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// When an event without DTEND and the given DTSTART ends: an all-day event lasts the day
+/// (RFC 5545), anything else ends as it starts.
+fn default_end(params: &str, value: &str) -> Option<DateTime<Utc>> {
+    if is_date(params) {
+        let next = NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()?
+            .succ_opt()?;
+        parse_datetime(params, &next.format("%Y%m%d").to_string())
+    } else {
+        parse_datetime(params, value)
+    }
+}
+
+/// A small iCalendar reader that only understands what hue_mie needs: the summary and time span
+/// of each VEVENT. Recurrence rules are not expanded.
+pub fn parse_ics(text: &str) -> Vec<CalendarEvent> {
+    let mut events = Vec::new();
+    let mut summary = None;
+    let mut start = None;
+    let mut end = None;
+    let mut start_end = None;
+    let mut in_event = false;
+    for line in unfold(text) {
+        let (key, value) = match line.find(':') {
+            Some(idx) => (&line[..idx], &line[idx + 1..]),
+            None => continue,
+        };
+        let (name, params) = match key.find(';') {
+            Some(idx) => (&key[..idx], &key[idx + 1..]),
+            None => (key, ""),
+        };
+        match (name, value) {
+            ("BEGIN", "VEVENT") => {
+                in_event = true;
+                summary = None;
+                start = None;
+                end = None;
+                start_end = None;
+            }
+            ("END", "VEVENT") => {
+                in_event = false;
+                if let (Some(summary), Some(start)) = (summary.take(), start.take()) {
+                    let end = end.take().or(start_end.take()).unwrap_or(start);
+                    events.push(CalendarEvent {
+                        summary,
+                        start,
+                        end,
+                    });
+                }
+            }
+            ("SUMMARY", _) if in_event => summary = Some(value.to_owned()),
+            ("DTSTART", _) if in_event => {
+                start = parse_datetime(params, value);
+                start_end = default_end(params, value);
+            }
+            ("DTEND", _) if in_event => end = parse_datetime(params, value),
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Keeps the events of the configured calendar, fetching them again once the refresh interval
+/// has passed.
+pub struct Calendar {
+    config: CalendarConfig,
+    events: Vec<CalendarEvent>,
+    fetched: Option<Instant>,
+}
+
+impl Calendar {
+    pub fn new(config: CalendarConfig) -> Calendar {
+        Calendar {
+            config,
+            events: Vec::new(),
+            fetched: None,
+        }
+    }
+
+    fn load(self: &Calendar) -> Result<String, Box<dyn Error>> {
+        let source = &self.config.source;
+        if source.starts_with("http://") || source.starts_with("https://") {
+            Ok(http::client()?
+                .get(source.as_str())
+                .send()?
+                .error_for_status()?
+                .text()?)
+        } else {
+            Ok(std::fs::read_to_string(source)?)
+        }
+    }
+
+    pub fn refresh_if_due(self: &mut Calendar) {
+        let due = self.fetched.is_none_or(|fetched| {
+            fetched.elapsed() >= Duration::from_secs(self.config.refresh_secs)
+        });
+        if !due {
+            return;
+        }
+        self.fetched = Some(Instant::now());
+        match self.load() {
+            Ok(text) => {
+                self.events = parse_ics(&text);
+                info!(
+                    "Read {} events from calendar {}",
+                    self.events.len(),
+                    self.config.source
+                );
+            }
+            // Keep the events read before rather than dropping every pause and profile.
+            Err(err) => error!("Could not read calendar {}: {}", self.config.source, err),
+        }
+    }
+
+    /// The exception of the first rule whose keyword occurs in the summary of an event running
+    /// at `now`.
+    pub fn active_exception(self: &Calendar, now: DateTime<Utc>) -> Option<Exception> {
+        self.events
+            .iter()
+            .filter(|event| event.start <= now && now < event.end)
+            .filter_map(|event| {
+                let summary = event.summary.to_lowercase();
+                self.config
+                    .rules
+                    .iter()
+                    .find(|rule| summary.contains(&rule.keyword.to_lowercase()))
+                    .map(|rule| {
+                        debug!(
+                            "Calendar event {:?} matches {:?}",
+                            event.summary, rule.keyword
+                        );
+                        match &rule.profile {
+                            Some(profile) => Exception::Profile(profile.clone()),
+                            None => Exception::Pause,
+                        }
+                    })
+            })
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+SUMMARY:Movie\r
+  night\r
+DTSTART:20240621T190000Z\r
+DTEND:20240621T220000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20240622\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn utc(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, day, hour, 0, 0).unwrap()
+    }
+
+    fn local_midnight(day: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(2024, 6, day, 0, 0, 0)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn calendar(source: &str) -> Calendar {
+        Calendar::new(
+            toml::from_str(&format!(
+                r#"
+                source = "{}"
+                refresh_secs = 0
+                rules = [{{ keyword = "movie", profile = "cinema" }}, {{ keyword = "holiday" }}]
+                "#,
+                source
+            ))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn reads_the_summary_and_span_of_each_event() {
+        let events = parse_ics(ICS);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Movie night");
+        assert_eq!((events[0].start, events[0].end), (utc(21, 19), utc(21, 22)));
+    }
+
+    #[test]
+    fn an_all_day_event_without_an_end_lasts_the_day() {
+        let events = parse_ics(ICS);
+        assert_eq!(events[1].start, local_midnight(22));
+        assert_eq!(events[1].end, local_midnight(23));
+    }
+
+    #[test]
+    fn keeps_the_events_when_the_calendar_cannot_be_read() {
+        let path =
+            std::env::temp_dir().join(format!("hue_mie-calendar-{}.ics", std::process::id()));
+        std::fs::write(&path, ICS).unwrap();
+        let mut calendar = calendar(&path.to_string_lossy());
+        calendar.refresh_if_due();
+        assert_eq!(
+            calendar.active_exception(utc(21, 20)),
+            Some(Exception::Profile("cinema".to_owned()))
+        );
+        assert_eq!(
+            calendar.active_exception(local_midnight(22) + chrono::Duration::hours(12)),
+            Some(Exception::Pause)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        calendar.refresh_if_due();
+        assert_eq!(calendar.events.len(), 2);
+        assert_eq!(
+            calendar.active_exception(utc(21, 20)),
+            Some(Exception::Profile("cinema".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! The command line of the hue_mie binary.
+
+/// What the binary was asked to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Run the daemon.
+    Run,
+    /// Print the effective (merged) configuration and what looks wrong in it, and exit.
+    Check { apply_fixes: bool },
+    /// Print today's sun times and the current light target.
+    Status,
+    /// Ask the running daemon to rewrite every light now.
+    Resync,
+    /// Ask the running daemon to stop writing, for a while or until resumed.
+    Pause { duration: Option<chrono::Duration> },
+    /// Ask the running daemon to start writing again.
+    Resume,
+    /// Write the original lightstates of managed scenes back to the bridge.
+    RestoreScenes,
+    /// Show the scenes hue_mie manages and when it last wrote to them.
+    ListManaged,
+    /// List scenes, groups or lights with whether hue_mie manages them, after adding the
+    /// scene ids in `exclude` to `excluded_scenes`.
+    List {
+        kind: ListKind,
+        exclude: Vec<String>,
+    },
+    /// Set a group by hand and keep hue_mie off it for a while.
+    Set(SetArgs),
+    /// Create and configure dayshift scenes from a template file.
+    ApplyTemplate(String),
+    /// Preview a shared transitions profile from a file or URL and store it under `[profiles]`.
+    ImportProfile {
+        source: String,
+        name: Option<String>,
+    },
+    /// Show what the next update would change in each managed scene.
+    Diff { json: bool },
+    /// Write the default configuration, or print it.
+    ConfigInit { force: bool, stdout: bool },
+    /// Write the curve to the bridge as native schedules, or remove them again.
+    ExportSchedules { steps: usize, remove: bool },
+    /// Show recorded targets and light states.
+    History {
+        light: Option<usize>,
+        last: chrono::Duration,
+    },
+    /// Rewrite a config file in another format (TOML, JSON or YAML, by extension).
+    ConfigConvert { input: String, output: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListKind {
+    Scenes,
+    Groups,
+    Lights,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetArgs {
+    pub group: String,
+    pub bri: Option<u8>,
+    pub ct: Option<u16>,
+    pub duration: chrono::Duration,
+}
+
+impl SetArgs {
+    fn new() -> SetArgs {
+        SetArgs {
+            group: String::new(),
+            bri: None,
+            ct: None,
+            duration: chrono::Duration::hours(DEFAULT_OVERRIDE_HOURS),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub command: Command,
+    pub dry_run: bool,
+    /// Run through a whole day in this many minutes instead of following the real clock.
+    pub simulate_day_minutes: Option<f64>,
+    /// Pair with this bridge when discovery finds several.
+    pub bridge_id: Option<String>,
+    /// Read the config from this file instead of the platform config directory.
+    pub config_path: Option<String>,
+    /// Config fields set with `--set <field>=<value>` or `--read-only`, as dotted paths and raw
+    /// values.
+    pub settings: Vec<(String, String)>,
+}
+
+pub const USAGE: &str =
+    "Usage: hue_mie [run|check [--apply-fixes]|status|resync|diff [--json]|restore-scenes|list-managed|apply-template <template.toml>] [--dry-run] [--bridge-id <id>] [--simulate-day [--simulate-minutes <minutes>]]
+               [--config <file>] [--read-only] [--set <field>=<value>]...
+       hue_mie config init [--force] [--stdout]
+       hue_mie export-schedules [--steps <n>] [--remove] [--dry-run]
+       hue_mie history [--light <id>] [--last <n>[s|m|h]]
+       hue_mie config convert <input.toml|json|yaml> <output.toml|json|yaml>
+       hue_mie pause [--for <n>[s|m|h]] | hue_mie resume
+       hue_mie list [scenes [--exclude <scene id>]...|groups|lights]
+       hue_mie import-profile <file|url> [--name <profile>] [--dry-run]
+       hue_mie set --group <name> [--bri <0-254|percent%>] [--ct <mired>] [--duration <n>[s|m|h]]";
+
+const DEFAULT_SIMULATE_MINUTES: f64 = 5.;
+
+const DEFAULT_OVERRIDE_HOURS: i64 = 1;
+
+const DEFAULT_HISTORY_HOURS: i64 = 24;
+
+const DEFAULT_SCHEDULE_STEPS: usize = 20;
+
+/// Parses "80%" as a percentage of full brightness, or a plain bridge value (0-254).
+fn parse_bri(value: &str) -> Option<u8> {
+    if value.ends_with('%') {
+        let percent = value.trim_end_matches('%').parse::<f64>().ok()?;
+        if !(0. ..=100.).contains(&percent) {
+            return None;
+        }
+        Some((percent / 100. * 254.).round() as u8)
+    } else {
+        value.parse::<u8>().ok().filter(|bri| *bri <= 254)
+    }
+}
+
+/// Parses "90s", "30m" or "2h"; a bare number is taken as minutes.
+fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    let (number, unit) = match value.chars().last()? {
+        's' | 'm' | 'h' => value.split_at(value.len() - 1),
+        _ => (value, "m"),
+    };
+    let number = number.parse::<i64>().ok().filter(|number| *number > 0)?;
+    Some(match unit {
+        "s" => chrono::Duration::seconds(number),
+        "h" => chrono::Duration::hours(number),
+        _ => chrono::Duration::minutes(number),
+    })
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args {
+        command: Command::Run,
+        dry_run: false,
+        simulate_day_minutes: None,
+        bridge_id: None,
+        config_path: None,
+        settings: Vec::new(),
+    };
+    let mut command = None;
+    let mut set_args: Option<SetArgs> = None;
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => parsed.dry_run = true,
+            "--simulate-day" => {
+                parsed.simulate_day_minutes = parsed
+                    .simulate_day_minutes
+                    .or(Some(DEFAULT_SIMULATE_MINUTES))
+            }
+            "--bridge-id" => {
+                parsed.bridge_id = Some(
+                    args.next()
+                        .ok_or_else(|| format!("--bridge-id needs a bridge id\n{}", USAGE))?,
+                );
+            }
+            "--config" => {
+                parsed.config_path = Some(
+                    args.next()
+                        .ok_or_else(|| format!("--config needs a file\n{}", USAGE))?,
+                );
+            }
+            "--read-only" => parsed
+                .settings
+                .push(("read_only".to_owned(), "true".to_owned())),
+            "--set" => {
+                let setting = args
+                    .next()
+                    .ok_or_else(|| format!("--set needs <field>=<value>\n{}", USAGE))?;
+                let mut split = setting.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(field), Some(value)) if !field.is_empty() => {
+                        parsed.settings.push((field.to_owned(), value.to_owned()))
+                    }
+                    _ => return Err(format!("--set needs <field>=<value>\n{}", USAGE)),
+                }
+            }
+            "--simulate-minutes" => {
+                let minutes = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|minutes| *minutes > 0.)
+                    .ok_or_else(|| {
+                        format!("--simulate-minutes needs a positive number\n{}", USAGE)
+                    })?;
+                parsed.simulate_day_minutes = Some(minutes);
+            }
+            "--group" | "--bri" | "--ct" | "--duration" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))?;
+                let invalid = || format!("Invalid value {:?} for {}\n{}", value, arg, USAGE);
+                let set_args = set_args.get_or_insert_with(SetArgs::new);
+                match arg.as_str() {
+                    "--group" if !set_args.group.is_empty() => {
+                        return Err(format!("--group given more than once\n{}", USAGE))
+                    }
+                    "--group" => set_args.group = value.clone(),
+                    "--bri" => set_args.bri = Some(parse_bri(&value).ok_or_else(invalid)?),
+                    "--ct" => set_args.ct = Some(value.parse().map_err(|_| invalid())?),
+                    _ => set_args.duration = parse_duration(&value).ok_or_else(invalid)?,
+                }
+            }
+            "set" if command.is_none() => command = Some(Command::Set(SetArgs::new())),
+            "config" if command.is_none() => match args.next().as_deref() {
+                Some("init") => {
+                    command = Some(Command::ConfigInit {
+                        force: false,
+                        stdout: false,
+                    })
+                }
+                Some("convert") => {
+                    let mut path = || {
+                        args.next()
+                            .ok_or_else(|| format!("config convert needs two files\n{}", USAGE))
+                    };
+                    let input = path()?;
+                    let output = path()?;
+                    command = Some(Command::ConfigConvert { input, output })
+                }
+                _ => return Err(format!("config needs a subcommand\n{}", USAGE)),
+            },
+            "--force" | "--stdout" => match command.as_mut() {
+                Some(Command::ConfigInit { force, stdout }) => {
+                    if arg == "--force" {
+                        *force = true;
+                    } else {
+                        *stdout = true;
+                    }
+                }
+                _ => return Err(format!("{} belongs to config init\n{}", arg, USAGE)),
+            },
+            "export-schedules" if command.is_none() => {
+                command = Some(Command::ExportSchedules {
+                    steps: DEFAULT_SCHEDULE_STEPS,
+                    remove: false,
+                })
+            }
+            "--steps" => {
+                let value = args
+                    .next()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .filter(|value| *value > 0 && *value <= 24 * 60)
+                    .ok_or_else(|| format!("--steps needs a number of steps\n{}", USAGE))?;
+                match command.as_mut() {
+                    Some(Command::ExportSchedules { steps, .. }) => *steps = value,
+                    _ => return Err(format!("--steps belongs to export-schedules\n{}", USAGE)),
+                }
+            }
+            "--remove" => match command.as_mut() {
+                Some(Command::ExportSchedules { remove, .. }) => *remove = true,
+                _ => return Err(format!("--remove belongs to export-schedules\n{}", USAGE)),
+            },
+            "history" if command.is_none() => {
+                command = Some(Command::History {
+                    light: None,
+                    last: chrono::Duration::hours(DEFAULT_HISTORY_HOURS),
+                })
+            }
+            "--light" | "--last" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))?;
+                let invalid = || format!("Invalid value {:?} for {}\n{}", value, arg, USAGE);
+                match command.as_mut() {
+                    Some(Command::History { light, .. }) if arg == "--light" => {
+                        *light = Some(value.parse().map_err(|_| invalid())?)
+                    }
+                    Some(Command::History { last, .. }) => {
+                        *last = parse_duration(&value).ok_or_else(invalid)?
+                    }
+                    _ => return Err(format!("{} belongs to history\n{}", arg, USAGE)),
+                }
+            }
+            "diff" if command.is_none() => command = Some(Command::Diff { json: false }),
+            "--json" => match command.as_mut() {
+                Some(Command::Diff { json }) => *json = true,
+                _ => return Err(format!("--json belongs to diff\n{}", USAGE)),
+            },
+            "apply-template" if command.is_none() => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| format!("apply-template needs a template file\n{}", USAGE))?;
+                command = Some(Command::ApplyTemplate(path));
+            }
+            "import-profile" if command.is_none() => {
+                let source = args
+                    .next()
+                    .ok_or_else(|| format!("import-profile needs a file or URL\n{}", USAGE))?;
+                command = Some(Command::ImportProfile { source, name: None });
+            }
+            "--name" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("--name needs a profile name\n{}", USAGE))?;
+                match command.as_mut() {
+                    Some(Command::ImportProfile { name, .. }) => *name = Some(value),
+                    _ => return Err(format!("--name belongs to import-profile\n{}", USAGE)),
+                }
+            }
+            "run" if command.is_none() => command = Some(Command::Run),
+            "check" if command.is_none() => command = Some(Command::Check { apply_fixes: false }),
+            "--apply-fixes" => match command.as_mut() {
+                Some(Command::Check { apply_fixes }) => *apply_fixes = true,
+                _ => return Err(format!("--apply-fixes belongs to check\n{}", USAGE)),
+            },
+            "status" if command.is_none() => command = Some(Command::Status),
+            "resync" if command.is_none() => command = Some(Command::Resync),
+            "pause" if command.is_none() => command = Some(Command::Pause { duration: None }),
+            "--for" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("--for needs a duration\n{}", USAGE))?;
+                match command.as_mut() {
+                    Some(Command::Pause { duration }) => {
+                        *duration = Some(parse_duration(&value).ok_or_else(|| {
+                            format!("Invalid value {:?} for --for\n{}", value, USAGE)
+                        })?)
+                    }
+                    _ => return Err(format!("--for belongs to pause\n{}", USAGE)),
+                }
+            }
+            "resume" if command.is_none() => command = Some(Command::Resume),
+            "restore-scenes" if command.is_none() => command = Some(Command::RestoreScenes),
+            "list-managed" if command.is_none() => command = Some(Command::ListManaged),
+            "list" if command.is_none() => {
+                command = Some(Command::List {
+                    kind: ListKind::Scenes,
+                    exclude: Vec::new(),
+                })
+            }
+            "--exclude" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("--exclude needs a scene id\n{}", USAGE))?;
+                match command.as_mut() {
+                    Some(Command::List { exclude, .. }) => exclude.push(value),
+                    _ => return Err(format!("--exclude belongs to list\n{}", USAGE)),
+                }
+            }
+            "scenes" | "groups" | "lights" => match command.as_mut() {
+                Some(Command::List { kind, .. }) => {
+                    *kind = match arg.as_str() {
+                        "groups" => ListKind::Groups,
+                        "lights" => ListKind::Lights,
+                        _ => ListKind::Scenes,
+                    }
+                }
+                _ => return Err(format!("Unexpected argument {:?}\n{}", arg, USAGE)),
+            },
+            _ => return Err(format!("Unexpected argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+    parsed.command = match (command, set_args) {
+        (Some(Command::Set(_)), Some(set_args))
+            if !set_args.group.is_empty() && (set_args.bri.is_some() || set_args.ct.is_some()) =>
+        {
+            Command::Set(set_args)
+        }
+        (Some(Command::Set(_)), _) => {
+            return Err(format!("set needs --group and --bri or --ct\n{}", USAGE))
+        }
+        (_, Some(_)) => {
+            return Err(format!(
+                "--group, --bri, --ct and --duration belong to set\n{}",
+                USAGE
+            ))
+        }
+        (command, None) => command.unwrap_or(Command::Run),
+    };
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        parse_args(
+            std::iter::once("hue_mie")
+                .chain(args.split_whitespace())
+                .map(String::from),
+        )
+    }
+
+    #[test]
+    fn parses_brightness_as_percentage_or_bridge_value() {
+        assert_eq!(parse_bri("80%"), Some(203));
+        assert_eq!(parse_bri("100%"), Some(254));
+        assert_eq!(parse_bri("0%"), Some(0));
+        assert_eq!(parse_bri("200"), Some(200));
+        assert_eq!(parse_bri("254"), Some(254));
+        assert_eq!(parse_bri("255"), None);
+        assert_eq!(parse_bri("101%"), None);
+        assert_eq!(parse_bri("-5%"), None);
+        assert_eq!(parse_bri("bright"), None);
+    }
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("2h"), Some(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("90m"), Some(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("45s"), Some(chrono::Duration::seconds(45)));
+        assert_eq!(parse_duration("15"), Some(chrono::Duration::minutes(15)));
+        assert_eq!(parse_duration("2d"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn parses_set() {
+        let args = parse("set --group Living --bri 80% --duration 2h").unwrap();
+        assert_eq!(
+            args.command,
+            Command::Set(SetArgs {
+                group: "Living".to_owned(),
+                bri: Some(203),
+                ct: None,
+                duration: chrono::Duration::hours(2),
+            })
+        );
+        match parse("set --group Living --ct 366").unwrap().command {
+            Command::Set(set_args) => {
+                assert_eq!(set_args.ct, Some(366));
+                assert_eq!(set_args.duration, chrono::Duration::hours(1));
+            }
+            command => panic!("{:?}", command),
+        }
+    }
+
+    #[test]
+    fn rejects_incomplete_or_ambiguous_set() {
+        assert!(parse("set --bri 200")
+            .unwrap_err()
+            .contains("needs --group"));
+        assert!(parse("set --group Living")
+            .unwrap_err()
+            .contains("needs --group"));
+        assert!(parse("set --group Living --group Hall --bri 200")
+            .unwrap_err()
+            .contains("more than once"));
+        assert!(parse("set --group").unwrap_err().contains("needs a value"));
+        assert!(parse("set --group Living --bri 300")
+            .unwrap_err()
+            .contains("Invalid value"));
+        assert!(parse("set --group Living --bri 50% --duration 2d")
+            .unwrap_err()
+            .contains("Invalid value"));
+        assert!(parse("status --group Living --bri 50%")
+            .unwrap_err()
+            .contains("belong to set"));
+    }
+
+    #[test]
+    fn parses_commands_and_their_flags() {
+        assert_eq!(parse("").unwrap().command, Command::Run);
+        assert_eq!(
+            parse("pause --for 90m").unwrap().command,
+            Command::Pause {
+                duration: Some(chrono::Duration::minutes(90))
+            }
+        );
+        assert_eq!(
+            parse("diff --json").unwrap().command,
+            Command::Diff { json: true }
+        );
+        assert_eq!(
+            parse("list groups").unwrap().command,
+            Command::List {
+                kind: ListKind::Groups,
+                exclude: Vec::new()
+            }
+        );
+        let args = parse("--dry-run --config hue.toml --set transitions.day_brightness=0.8 check")
+            .unwrap();
+        assert!(args.dry_run);
+        assert_eq!(args.config_path.as_deref(), Some("hue.toml"));
+        assert_eq!(
+            args.settings,
+            vec![("transitions.day_brightness".to_owned(), "0.8".to_owned())]
+        );
+        assert_eq!(args.command, Command::Check { apply_fixes: false });
+    }
+
+    #[test]
+    fn rejects_unknown_and_misplaced_flags() {
+        assert!(parse("--verbose")
+            .unwrap_err()
+            .contains("Unexpected argument"));
+        assert!(parse("status now")
+            .unwrap_err()
+            .contains("Unexpected argument"));
+        assert!(parse("status --json")
+            .unwrap_err()
+            .contains("belongs to diff"));
+        assert!(parse("pause --for 2d")
+            .unwrap_err()
+            .contains("Invalid value"));
+        assert!(parse("--set =1").unwrap_err().contains("--set needs"));
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::prelude::*;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The source of time for the scheduler and the curve. The daemon uses `SystemClock`; tests and
+/// simulations use `FakeClock` to run through a day without waiting for it.
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Waits `duration` of real time without it counting as time passing on this clock, for
+    /// pacing the bridge: the bridge needs the same pauses however fast the clock runs.
+    fn pause(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when slept on or advanced explicitly.
+pub struct FakeClock {
+    now: Cell<DateTime<Local>>,
+}
+
+impl FakeClock {
+    pub fn new(start: DateTime<Local>) -> FakeClock {
+        FakeClock {
+            now: Cell::new(start),
+        }
+    }
+
+    pub fn advance(self: &FakeClock, duration: Duration) {
+        let step =
+            chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+        self.now.set(self.now.get() + step);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Local> {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn pause(&self, _duration: Duration) {}
+}
+
+/// A clock running `factor` times faster than real time from `start`, for demonstrating a whole
+/// day of the curve in a few minutes. The clock stands still while the executor paces the bridge,
+/// but the bridge's response times still count `factor` times over, so a tick over many lights
+/// takes a while in simulated time.
+pub struct ScaledClock {
+    start: DateTime<Local>,
+    started: Instant,
+    factor: f64,
+    /// Real time spent in `pause`, which does not count.
+    paused: Cell<Duration>,
+}
+
+impl ScaledClock {
+    pub fn new(start: DateTime<Local>, factor: f64) -> ScaledClock {
+        ScaledClock {
+            start,
+            started: Instant::now(),
+            factor,
+            paused: Cell::new(Duration::from_secs(0)),
+        }
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now(&self) -> DateTime<Local> {
+        let elapsed = self.started.elapsed().saturating_sub(self.paused.get());
+        let simulated_ms =
+            (elapsed.as_secs() as f64 * 1000. + f64::from(elapsed.subsec_millis())) * self.factor;
+        self.start + chrono::Duration::milliseconds(simulated_ms as i64)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let real_ms =
+            (duration.as_secs() as f64 * 1000. + f64::from(duration.subsec_millis())) / self.factor;
+        thread::sleep(Duration::from_millis(real_ms as u64));
+    }
+
+    fn pause(&self, duration: Duration) {
+        thread::sleep(duration);
+        self.paused.set(self.paused.get() + duration);
+    }
+}
@@ -1,56 +1,446 @@
+#[cfg(feature = "daemon")]
 extern crate dirs;
 extern crate toml;
 
-use philipshue::errors::{BridgeError, HueError, HueErrorKind};
+use crate::astro_calc::{AltitudeCache, Horizon, SolarModel, SolarOptions};
+#[cfg(feature = "daemon")]
+use crate::backup::SceneBackup;
+#[cfg(feature = "daemon")]
+use crate::bridge_info::BridgeInfo;
+#[cfg(feature = "daemon")]
+use crate::control;
+use crate::curve::{self, LightTarget};
+use crate::hue::LightStateChange;
+#[cfg(feature = "daemon")]
+use crate::overrides::Overrides;
+use crate::quirks::Quirks;
+use chrono::{DateTime, Local, TimeZone, Utc};
 use std::boxed::Box;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// A commented configuration with the defaults, written by `hue_mie config init`.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+/// Guards against include cycles.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// A value to write into the config file, by table path from the root of the config.
+pub type ConfigFix = (Vec<String>, Value);
+
+/// Config file formats, told apart by extension; anything unknown is read as TOML.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => ConfigFormat::Json,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Toml,
+        }
+    }
+
+    /// Parses `str` into a TOML value, so includes merge the same way whatever the format.
+    pub fn read(self: ConfigFormat, str: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ConfigFormat::Toml => toml::from_str(str)?,
+            ConfigFormat::Json => serde_json::from_str(str)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(str)?,
+        })
+    }
+
+    fn write<T: serde::Serialize>(
+        self: ConfigFormat,
+        value: &T,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ConfigFormat::Toml => toml::to_string(value)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(value)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(value)?,
+        })
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// Further config files, relative to this one. They are merged in order, and this file's
+    /// own values override theirs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Never write this file, e.g. when a system manager generates it. Pairing then has to be
+    /// done by hand, by setting `hue.bridge_ip` and `hue.bridge_password`.
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Store new pairing details (bridge credentials, the allowed bridge, a looked up address)
+    /// in this file. Only those keys are touched; comments and layout are kept.
+    #[serde(default = "Config::default_persist_changes")]
+    pub persist_changes: bool,
+
     #[serde(default)]
     pub hue: Option<HueConfig>,
 
+    /// Ids of the bridges hue_mie may control. Filled with the paired bridge on first start;
+    /// other bridges are refused, even if discovery finds them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_bridges: Vec<String>,
+
     #[serde(default)]
     pub location: Location,
 
     #[serde(default)]
     pub transitions: Transitions,
+
+    /// Alternative transitions, selectable by name (e.g. from calendar rules).
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Transitions>,
+
+    #[serde(default)]
+    pub calendar: Option<CalendarConfig>,
+
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+
+    /// Enter deep night when the bedroom lights have been off for a while, instead of at
+    /// `deep_night_start_hour`.
+    #[serde(default)]
+    pub bedtime: Option<BedtimeConfig>,
+
+    /// Learn the dawn point from when the household switches lights on around dusk and dawn.
+    #[serde(default)]
+    pub learning: Option<LearningConfig>,
+
+    #[serde(default)]
+    pub hooks: HooksConfig,
+
+    /// Wake-up fallbacks, keyed by bridge group name.
+    #[serde(default)]
+    pub get_up_nudges: BTreeMap<String, GetUpNudgeConfig>,
+
+    /// Report statistics once a day.
+    #[serde(default)]
+    pub summary: Option<SummaryConfig>,
+
+    /// Dim during peak electricity price hours.
+    #[serde(default)]
+    pub energy: Option<EnergyConfig>,
+
+    /// Coordinate with other instances controlling the same bridge, so only one writes.
+    #[serde(default)]
+    pub leader_election: Option<LeaderElectionConfig>,
+
+    /// Record targets and light states over time; needs the `history` feature.
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+
+    /// Serve the configuration page on localhost; needs the `web-ui` feature.
+    #[serde(default)]
+    pub web_ui: Option<WebUiConfig>,
+
+    /// Desktop notifications and a tray icon; needs the `desktop` feature.
+    #[serde(default)]
+    pub desktop: Option<DesktopConfig>,
+
+    /// A script adjusting the light target of each scene; needs the `scripting` feature.
+    #[serde(default)]
+    pub script: Option<ScriptConfig>,
+
+    /// Where the original lightstates of managed scenes are kept. Defaults to the platform data
+    /// directory.
+    #[serde(default)]
+    pub scene_backup_path: Option<String>,
+
+    /// Where `hue_mie set` stores overrides for the daemon. Defaults to the platform data
+    /// directory.
+    #[serde(default)]
+    pub overrides_path: Option<String>,
+
+    /// The unix socket `hue_mie resync` talks to the daemon through. Defaults to the runtime
+    /// directory.
+    #[serde(default)]
+    pub control_socket: Option<String>,
+
+    /// Rooms containing a scene with this name are left alone, so anyone can opt a room out
+    /// from the Hue app.
+    #[serde(default = "Config::default_sentinel_scene_name")]
+    pub sentinel_scene_name: String,
+
+    /// Bridge ids of scenes hue_mie never touches, even with "dayshift" in their name. Added
+    /// with `hue_mie list scenes --exclude <id>`.
+    #[serde(default)]
+    pub excluded_scenes: Vec<String>,
+
+    /// Leave dayshift scenes untouched and maintain a "hue_mie <room>" scene per room instead.
+    #[serde(default)]
+    pub shadow_scenes: bool,
+
+    /// Create a "<room> dayshift" scene for rooms that have none, so new rooms are managed
+    /// without setting them up in the Hue app.
+    #[serde(default)]
+    pub auto_create_scenes: bool,
+
+    /// Leave smart plugs in scenes alone, so hue_mie never switches appliances.
+    #[serde(default = "Config::default_exclude_plugs")]
+    pub exclude_plugs: bool,
+
+    /// Add lights that appear on the bridge to the managed scenes of their room.
+    #[serde(default)]
+    pub add_new_lights_to_scenes: bool,
+
+    /// Control rooms in which the Hue app's natural light or another auto-dynamic scene is
+    /// running, instead of leaving them to the app.
+    #[serde(default)]
+    pub take_over_adaptive_rooms: bool,
+
+    /// Mark scenes hue_mie writes to in their v2 metadata, see `hue_mie list-managed`.
+    #[serde(default)]
+    pub mark_managed_scenes: bool,
+
+    /// Write the curve as v2 dynamic scene palettes instead of v1 scene lightstates.
+    #[serde(default)]
+    pub dynamic_palette: Option<DynamicPaletteConfig>,
+
+    #[serde(default)]
+    pub clock_drift: ClockDriftConfig,
+
+    /// Limits for rooms marked `accessible`.
+    #[serde(default)]
+    pub accessibility: AccessibilityConfig,
+
+    /// How fast lights fade and how far apart writes are sent.
+    #[serde(default)]
+    pub pacing: PacingConfig,
+
+    /// Brightness bounds per time of day, applied after everything else.
+    #[serde(default)]
+    pub clamp: Option<ClampConfig>,
+
+    /// Hard limits per room (by group name) during some hours, e.g. for a nursery.
+    #[serde(default)]
+    pub caps: BTreeMap<String, RoomCap>,
+
+    /// When too many writes fail, hue_mie stops writing for a while instead of hammering the
+    /// bridge.
+    #[serde(default)]
+    pub error_budget: ErrorBudgetConfig,
+
+    /// Keep the power-on state of managed lights at the light target.
+    #[serde(default)]
+    pub powerup: Option<PowerupConfig>,
+
+    /// Spread writes over the update interval with random gaps instead of sending them in one
+    /// burst.
+    #[serde(default)]
+    pub spread_updates: bool,
+
+    /// Seconds between exact solar position computations; altitudes in between are
+    /// interpolated. Zero computes every tick exactly.
+    #[serde(default = "Config::default_sun_cache_secs")]
+    pub sun_cache_secs: i64,
+
+    /// Gradient settings for gradient lightstrips, keyed by v1 light id.
+    #[serde(default)]
+    pub gradients: BTreeMap<String, GradientConfig>,
+
+    /// White-point calibration, keyed by light id or model id (light ids take precedence).
+    #[serde(default)]
+    pub calibration: BTreeMap<String, LightCalibration>,
+
+    /// Model quirks by model id, replacing the built-in entries; see `quirks`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quirks: BTreeMap<String, Quirks>,
+
+    #[serde(default)]
+    pub scene_defaults: SceneConfig,
+
+    /// Virtual zones: sets of lights controlled directly, independent of bridge rooms and
+    /// scenes, keyed by zone name.
+    #[serde(default)]
+    pub zones: BTreeMap<String, ZoneConfig>,
+
+    /// Per-scene settings, keyed by scene name or by the name of the group the scene is
+    /// recalled in. Matching ignores case.
+    #[serde(default)]
+    pub scenes: BTreeMap<String, SceneConfig>,
 }
 
+use log::debug;
 use std::fs::File;
 use std::io::Read;
+#[cfg(feature = "daemon")]
+use std::io::Write;
 
-use philipshue::bridge;
+#[cfg(feature = "daemon")]
+use crate::bridge;
+#[cfg(feature = "daemon")]
+use log::warn;
+#[cfg(feature = "daemon")]
 use std::thread;
+#[cfg(feature = "daemon")]
 use std::time::Duration;
 
-//#[cfg(feature = "upnp")]
+/// Finds bridges with SSDP, falling back to the Hue discovery portal.
+#[cfg(feature = "daemon")]
 pub fn discover() -> Vec<String> {
-    let mut ips = bridge::discover_upnp().unwrap();
+    let mut ips = match bridge::discover_upnp() {
+        Ok(ips) => ips,
+        Err(err) => {
+            warn!("UPnP discovery failed: {}", err);
+            Vec::new()
+        }
+    };
+    if ips.is_empty() {
+        ips = bridge::discover_nupnp().unwrap_or_else(|err| {
+            warn!("N-UPnP discovery failed: {}", err);
+            Vec::new()
+        });
+    }
     ips.dedup();
     ips
 }
 
-#[cfg(all(feature = "nupnp", not(feature = "upnp")))]
-pub fn discover() -> Vec<String> {
-    use philipshue::hue::Discovery;
-    Bridge::discover()
-        .unwrap()
-        .into_iter()
-        .map(Discovery::into_ip)
+/// Prefix of environment variables that set config fields, see `env_settings`.
+const ENV_PREFIX: &str = "HUE_MIE_";
+
+/// Config fields set from the environment: `HUE_MIE_TRANSITIONS__DAY_BRIGHTNESS=0.8` sets
+/// `transitions.day_brightness`, as `--set transitions.day_brightness=0.8` does.
+pub fn env_settings() -> Vec<(String, String)> {
+    std::env::vars()
+        .filter(|(key, _)| key.starts_with(ENV_PREFIX) && key != "HUE_MIE_CONFIG")
+        .map(|(key, value)| {
+            let path = key[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+            (path, value)
+        })
         .collect()
 }
 
-/*
-#[cfg(all(not(feature = "nupnp"), not(feature = "upnp")))]
-pub fn discover() -> Vec<String> {
-    panic!("Either UPnP or NUPnP is required for discovering!")
+/// Sets the field at the dotted `path` in `value`, creating tables on the way. The raw value
+/// is read as a TOML value (`0.8`, `true`, `[1, 2]`), or taken as a string if it isn't one.
+fn apply_setting(
+    value: &mut Value,
+    path: &str,
+    raw: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parsed = toml::from_str::<Value>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut table| table.as_table_mut().and_then(|table| table.remove("v")))
+        .unwrap_or_else(|| Value::String(raw.to_owned()));
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = keys
+        .pop()
+        .filter(|key| !key.is_empty())
+        .ok_or("Empty setting name")?;
+    let mut table = value;
+    for key in keys {
+        table = table
+            .as_table_mut()
+            .ok_or_else(|| format!("Cannot set {}: not a table", path))?
+            .entry(key.to_owned())
+            .or_insert_with(|| Value::Table(toml::value::Table::new()));
+    }
+    table
+        .as_table_mut()
+        .ok_or_else(|| format!("Cannot set {}: not a table", path))?
+        .insert(last.to_owned(), parsed);
+    Ok(())
+}
+
+impl Config {
+    pub fn default_sentinel_scene_name() -> String {
+        String::from("hue_mie off")
+    }
+
+    pub fn default_sun_cache_secs() -> i64 {
+        300
+    }
+
+    pub fn default_persist_changes() -> bool {
+        true
+    }
+
+    pub fn default_exclude_plugs() -> bool {
+        true
+    }
 }
-*/
 
+/// Bridge pairing and the platform config location, which the curve core built without the
+/// `daemon` feature (e.g. for wasm32) does without.
+#[cfg(feature = "daemon")]
 impl Config {
-    pub fn get_hue_config() -> Result<HueConfig, Box<dyn std::error::Error>> {
-        let ip: String = discover().pop().unwrap();
+    /// Asks which of several bridges to pair with.
+    fn choose_bridge(
+        candidates: &[(String, Option<BridgeInfo>)],
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        println!("Found {} bridges:", candidates.len());
+        for (idx, (ip, info)) in candidates.iter().enumerate() {
+            match info {
+                Some(info) => {
+                    println!("  {}) {} at {} ({})", idx + 1, info.bridgeid, ip, info.name)
+                }
+                None => println!("  {}) unknown bridge at {}", idx + 1, ip),
+            }
+        }
+        loop {
+            print!("Which one should hue_mie control? ");
+            std::io::stdout().flush()?;
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer)? == 0 {
+                return Err("Several bridges found; pass --bridge-id to pick one".into());
+            }
+            match answer.trim().parse::<usize>() {
+                Ok(choice) if choice >= 1 && choice <= candidates.len() => return Ok(choice - 1),
+                _ => println!("Please enter a number from 1 to {}", candidates.len()),
+            }
+        }
+    }
+
+    /// Pairs with a discovered bridge: the one with `bridge_id` if given, otherwise the only one
+    /// found or the one the user picks. Only bridges in `allowed_bridges` are considered, if set.
+    pub fn get_hue_config(
+        allowed_bridges: &[String],
+        bridge_id: Option<&str>,
+    ) -> Result<HueConfig, Box<dyn std::error::Error>> {
+        let mut candidates: Vec<(String, Option<BridgeInfo>)> = discover()
+            .into_iter()
+            .filter_map(|ip| match BridgeInfo::fetch_public(&ip) {
+                Ok(ref info) if !info.is_allowed(allowed_bridges) => {
+                    warn!(
+                        "Ignoring bridge {} at {}, it is not allowed",
+                        info.bridgeid, ip
+                    );
+                    None
+                }
+                Ok(info) => Some((ip, Some(info))),
+                Err(err) => {
+                    warn!("Could not identify bridge at {}: {}", ip, err);
+                    if allowed_bridges.is_empty() {
+                        Some((ip, None))
+                    } else {
+                        None
+                    }
+                }
+            })
+            .collect();
+        if let Some(bridge_id) = bridge_id {
+            candidates.retain(|(_, info)| {
+                info.as_ref()
+                    .is_some_and(|info| info.bridgeid.eq_ignore_ascii_case(bridge_id))
+            });
+        }
+        let chosen = match candidates.len() {
+            0 => return Err("No bridge found".into()),
+            1 => 0,
+            _ => Config::choose_bridge(&candidates)?,
+        };
+        let (ip, info) = candidates.swap_remove(chosen);
+        let bridge_id = info.map(|info| info.bridgeid);
 
         loop {
             match bridge::register_user(&ip, "hue_cycle") {
@@ -59,15 +449,10 @@ impl Config {
                     return Ok(HueConfig {
                         bridge_ip: ip,
                         bridge_password: bridge,
+                        bridge_id,
                     });
                 }
-                Err(HueError(
-                    HueErrorKind::BridgeError {
-                        error: BridgeError::LinkButtonNotPressed,
-                        ..
-                    },
-                    _,
-                )) => {
+                Err(ref e) if e.is_link_button_not_pressed() => {
                     println!("Please, press the link on the bridge. Retrying in 5 seconds");
                     thread::sleep(Duration::from_secs(5));
                 }
@@ -78,39 +463,365 @@ impl Config {
         }
     }
 
-    fn path() -> PathBuf {
+    /// The config file: `HUE_MIE_CONFIG` if set, otherwise config.toml in the platform config
+    /// directory, or config.json, config.yaml or config.yml if one of those exists instead.
+    pub fn path() -> PathBuf {
+        if let Some(path) = std::env::var_os("HUE_MIE_CONFIG") {
+            return PathBuf::from(path);
+        }
         let mut config_dir: PathBuf = dirs::config_dir().unwrap();
         config_dir.push("hue_mie");
         config_dir.push("config");
+        for extension in &["json", "yaml", "yml"] {
+            let candidate = config_dir.with_extension(extension);
+            if candidate.exists() {
+                return candidate;
+            }
+        }
         config_dir.set_extension("toml");
         config_dir
     }
 
+    pub fn scene_backup_path(self: &Config) -> PathBuf {
+        self.scene_backup_path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(SceneBackup::default_path)
+    }
+
+    pub fn overrides_path(self: &Config) -> PathBuf {
+        self.overrides_path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(Overrides::default_path)
+    }
+
+    pub fn control_socket_path(self: &Config) -> PathBuf {
+        self.control_socket
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(control::default_path)
+    }
+
     pub fn from_file() -> Result<Config, Box<dyn std::error::Error>> {
-        Config::parse(Config::path().to_str().unwrap())
+        Config::from_file_with(&[])
     }
 
-    pub fn write_file_to(self: &Config, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let str = toml::to_string(self)?;
-        std::fs::write(path, str)?;
+    /// Reads the config file with `settings` (dotted field paths and raw values) applied
+    /// over it.
+    pub fn from_file_with(
+        settings: &[(String, String)],
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        Config::parse_with(Config::path().to_str().unwrap(), settings)
+    }
+
+    /// Writes `DEFAULT_CONFIG` to the config path, unless a config exists and `force` is not set.
+    pub fn init(force: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = Config::path();
+        if path.exists() && !force {
+            return Err(format!("{:?} exists, use --force to overwrite it", path).into());
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, DEFAULT_CONFIG)?;
+        Ok(path)
+    }
+
+    /// Writes the pairing details and location into the config file, leaving everything else
+    /// in it as it is. JSON and YAML files are rewritten whole.
+    pub fn write_setup(self: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Config::path();
+        if self.read_only {
+            return Err(format!("{:?} is read-only", path).into());
+        }
+        if ConfigFormat::from_path(&path) != ConfigFormat::Toml {
+            return self.write_file();
+        }
+        let mut document = std::fs::read_to_string(&path)?.parse::<toml_edit::Document>()?;
+        if let Some(hue) = &self.hue {
+            if !document["hue"].is_table() {
+                document["hue"] = toml_edit::table();
+            }
+            document["hue"]["bridge_ip"] = toml_edit::value(hue.bridge_ip.as_str());
+            document["hue"]["bridge_password"] = toml_edit::value(hue.bridge_password.as_str());
+            if let Some(bridge_id) = &hue.bridge_id {
+                document["hue"]["bridge_id"] = toml_edit::value(bridge_id.as_str());
+            }
+        }
+        let mut allowed_bridges = toml_edit::Array::default();
+        for bridge_id in &self.allowed_bridges {
+            let _ = allowed_bridges.push(bridge_id.as_str());
+        }
+        document["allowed_bridges"] = toml_edit::value(allowed_bridges);
+        if !document["location"].is_table() {
+            document["location"] = toml_edit::table();
+        }
+        document["location"]["lat"] = toml_edit::value(self.location.lat);
+        document["location"]["long"] = toml_edit::value(self.location.long);
+        if let Some(location) = document["location"].as_table_mut() {
+            location.remove("address");
+        }
+        std::fs::write(&path, document.to_string())?;
+        Ok(())
+    }
+
+    /// Writes suggested values (by table path) into the config file, leaving the rest of it as
+    /// it is. Only TOML files are edited.
+    pub fn apply_fixes(fixes: &[ConfigFix]) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Config::path();
+        if ConfigFormat::from_path(&path) != ConfigFormat::Toml {
+            return Err(format!("{:?} is not TOML, apply the fixes by hand", path).into());
+        }
+        let mut document = std::fs::read_to_string(&path)?.parse::<toml_edit::Document>()?;
+        for (keys, value) in fixes {
+            let (last, tables) = keys.split_last().ok_or("Empty field path")?;
+            let mut item = &mut document.root;
+            for key in tables {
+                if !item[key.as_str()].is_table_like() {
+                    item[key.as_str()] = toml_edit::table();
+                }
+                item = &mut item[key.as_str()];
+            }
+            item[last.as_str()] = Config::edit_item(value)?;
+        }
+        std::fs::write(&path, document.to_string())?;
+        Ok(())
+    }
+
+    /// A value as written into a toml_edit document.
+    fn edit_item(value: &Value) -> Result<toml_edit::Item, Box<dyn std::error::Error>> {
+        Ok(match value {
+            Value::Integer(integer) => toml_edit::value(*integer),
+            Value::Float(float) => toml_edit::value(*float),
+            Value::Boolean(boolean) => toml_edit::value(*boolean),
+            Value::String(string) => toml_edit::value(string.as_str()),
+            Value::Array(values) => {
+                let mut array = toml_edit::Array::default();
+                for value in values.iter() {
+                    let pushed = match value {
+                        Value::String(string) => array.push(string.as_str()),
+                        other => return Err(format!("Cannot write {}", other).into()),
+                    };
+                    pushed.map_err(|_| "Arrays must hold values of one type")?;
+                }
+                toml_edit::value(array)
+            }
+            Value::Table(fields) => {
+                let mut table = toml_edit::table();
+                for (key, value) in fields.iter() {
+                    table[key.as_str()] = Config::edit_item(value)?;
+                }
+                table
+            }
+            other => return Err(format!("Cannot write {}", other).into()),
+        })
+    }
+
+    /// Replaces the transitions in the config file. A TOML file is edited in place, keeping its
+    /// comments and other settings; JSON and YAML files get only their `transitions` replaced.
+    pub fn write_transitions(transitions: &Transitions) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Config::path();
+        let format = ConfigFormat::from_path(&path);
+        let text = std::fs::read_to_string(&path)?;
+        let fields = match Value::try_from(transitions)? {
+            Value::Table(fields) => fields,
+            _ => return Err("transitions are not a table".into()),
+        };
+        if format != ConfigFormat::Toml {
+            let mut value = format.read(&text)?;
+            let table = value.as_table_mut().ok_or("The config is not a table")?;
+            table.insert("transitions".to_owned(), Value::Table(fields));
+            std::fs::write(&path, format.write(&value)?)?;
+            return Ok(());
+        }
+        let mut document = text.parse::<toml_edit::Document>()?;
+        if !document["transitions"].is_table() {
+            document["transitions"] = toml_edit::table();
+        }
+        let table = document["transitions"]
+            .as_table_mut()
+            .ok_or("transitions is not a table")?;
+        let unset: Vec<String> = table
+            .iter()
+            .map(|(key, _)| key.to_owned())
+            .filter(|key| !fields.contains_key(key))
+            .collect();
+        for key in unset {
+            table.remove(&key);
+        }
+        for (key, value) in fields.iter() {
+            table[key.as_str()] = Config::edit_item(value)?;
+        }
+        std::fs::write(&path, document.to_string())?;
         Ok(())
     }
 
     pub fn write_file(self: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        if self.read_only {
+            return Err(format!("{:?} is read-only", Config::path()).into());
+        }
         self.write_file_to(Config::path().to_str().unwrap())
     }
+}
+
+impl Config {
+    pub fn write_file_to(self: &Config, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let str = ConfigFormat::from_path(Path::new(path)).write(self)?;
+        std::fs::write(path, str)?;
+        Ok(())
+    }
+
+    /// Rewrites the config file `input` in the format of `output`. Only what is in `input`
+    /// is written; includes are kept as references, not merged.
+    pub fn convert(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let str = std::fs::read_to_string(input)
+            .map_err(|err| format!("Could not read {}: {}", input, err))?;
+        let value = ConfigFormat::from_path(Path::new(input)).read(&str)?;
+        let _: Config = value.clone().try_into()?;
+        std::fs::write(
+            output,
+            ConfigFormat::from_path(Path::new(output)).write(&value)?,
+        )?;
+        Ok(())
+    }
+
+    /// `target` within the configured brightness bounds for `hour`.
+    pub fn clamp_target(self: &Config, hour: u8, target: LightTarget) -> LightTarget {
+        match &self.clamp {
+            Some(clamp) => clamp.apply(hour, &target),
+            None => target,
+        }
+    }
+
+    /// A copy that writes every light regardless of how little it changed, to bring everything
+    /// back onto the curve at once, e.g. after an outage.
+    pub fn for_resync(self: &Config) -> Config {
+        let mut config = self.clone();
+        let policies = std::iter::once(&mut config.scene_defaults)
+            .chain(config.scenes.values_mut())
+            .chain(config.zones.values_mut().map(|zone| &mut zone.policy));
+        for policy in policies {
+            policy.min_bri_delta = 0;
+            policy.min_mired_delta = 0;
+        }
+        config
+    }
+
+    /// Settings for a scene: the first entry in `scenes` matching one of `names` (the scene name
+    /// followed by its group names), or `scene_defaults` if none matches.
+    pub fn scene_config(self: &Config, names: &[&str]) -> SceneConfig {
+        names
+            .iter()
+            .filter_map(|name| {
+                self.scenes
+                    .iter()
+                    .find(|(key, _)| key.to_lowercase() == name.to_lowercase())
+                    .map(|(_, scene_config)| scene_config.clone())
+            })
+            .next()
+            .unwrap_or_else(|| self.scene_defaults.clone())
+    }
 
     pub fn parse(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
-        println!("Reading path {:?}", path);
-        let str = File::open(&path)
+        Config::parse_with(path, &[])
+    }
+
+    pub fn parse_with(
+        path: &str,
+        settings: &[(String, String)],
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        debug!("Reading path {:?}", path);
+        let str = File::open(path)
             .and_then(|mut file| {
                 let mut config_toml = String::new();
                 file.read_to_string(&mut config_toml)?;
                 Ok(config_toml)
             })
-            .unwrap_or_else(|_| String::from(""));
-        let parsed = toml::from_str(&str)?;
-        Ok(parsed)
+            .map_err(|err| {
+                format!(
+                    "Could not read {}: {}. Run `hue_mie config init` to create it",
+                    path, err
+                )
+            })?;
+        let path = Path::new(path);
+        let mut value =
+            Config::resolve_includes(path, ConfigFormat::from_path(path).read(&str)?, 0)?;
+        for (key, raw) in settings {
+            apply_setting(&mut value, key, raw)?;
+        }
+        let config: Config = value.try_into()?;
+        config.pacing.validate()?;
+        config.transitions.validate("transitions")?;
+        for (name, profile) in config.profiles.iter() {
+            profile.validate(&format!("profiles.{}", name))?;
+        }
+        for (name, zone) in config.zones.iter() {
+            if let Some(transitions) = &zone.transitions {
+                transitions.validate(&format!("zones.{}.transitions", name))?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn read_include(path: &Path, depth: usize) -> Result<Value, Box<dyn std::error::Error>> {
+        debug!("Reading include {:?}", path);
+        let str = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read include {:?}: {}", path, err))?;
+        Config::resolve_includes(path, ConfigFormat::from_path(path).read(&str)?, depth)
+    }
+
+    /// Merges the files listed under `include` into `value`: tables are merged key by key,
+    /// anything else is replaced, later includes win over earlier ones and the including file
+    /// wins over all of them.
+    fn resolve_includes(
+        path: &Path,
+        value: Value,
+        depth: usize,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let includes: Vec<String> = value
+            .get("include")
+            .and_then(Value::as_array)
+            .map(|includes| {
+                includes
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if includes.is_empty() {
+            return Ok(value);
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(format!("Includes nested too deeply at {:?}", path).into());
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut merged = Value::Table(toml::value::Table::new());
+        for include in includes {
+            merge(
+                &mut merged,
+                Config::read_include(&dir.join(include), depth + 1)?,
+            );
+        }
+        merge(&mut merged, value);
+        Ok(merged)
+    }
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
     }
 }
 
@@ -121,6 +832,10 @@ pub struct HueConfig {
 
     #[serde(default = "HueConfig::default_bridge_password")]
     pub bridge_password: String,
+
+    /// The id of the paired bridge, as reported by its /config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge_id: Option<String>,
 }
 
 impl HueConfig {
@@ -137,6 +852,7 @@ impl Default for HueConfig {
         HueConfig {
             bridge_ip: String::from("192.168.178.50"),
             bridge_password: String::from("a-zKQed-fmtva4-gc0VJuVGrqaBf8t7xMEuJzUH2"),
+            bridge_id: None,
         }
     }
 }
@@ -181,6 +897,49 @@ pub struct Transitions {
 
     #[serde(default = "Transitions::default_temperature_cycle_amplitude")]
     pub temperature_cycle_amplitude: f64,
+
+    /// Rescale the sun altitude against the day's lowest and highest altitude, so evening
+    /// transitions stay equally gradual at high latitudes in summer.
+    #[serde(default = "Transitions::default_normalize_altitude")]
+    pub normalize_altitude: bool,
+
+    /// The altitude (degrees) the day's highest altitude is mapped to when normalizing.
+    #[serde(default = "Transitions::default_normalized_reference_altitude")]
+    pub normalized_reference_altitude: f64,
+
+    /// Sun altitude (degrees) halfway through the colour temperature transition. The
+    /// brightness transition uses `sun_altitude_dawn_point`.
+    #[serde(default = "Transitions::default_temperature_altitude_dawn_point")]
+    pub temperature_altitude_dawn_point: f64,
+
+    /// Width (degrees of sun altitude) of the colour temperature transition.
+    #[serde(default = "Transitions::default_temperature_transition_time")]
+    pub temperature_transition_time: f64,
+
+    /// Multiplies the slope of the brightness transition: above 1 it is more abrupt, below 1
+    /// more gradual.
+    #[serde(default = "Transitions::default_transition_steepness")]
+    pub brightness_transition_steepness: f64,
+
+    /// Multiplies the slope of the colour temperature transition.
+    #[serde(default = "Transitions::default_transition_steepness")]
+    pub temperature_transition_steepness: f64,
+
+    /// Follow the sun as it stood this many hours earlier, for people who keep other hours
+    /// than the sun: with 12 the evening gets the morning's light. The deep night window stays
+    /// in clock hours.
+    #[serde(default)]
+    pub solar_offset_hours: f64,
+
+    /// Follow the sun upside down: day light while it is below the horizon and night light
+    /// while it is up.
+    #[serde(default)]
+    pub invert_sun: bool,
+
+    /// Reach the day values this many minutes after sunrise, at an even pace, instead of
+    /// following the sun's altitude through the morning. Ignored with `invert_sun`.
+    #[serde(default)]
+    pub sunrise_duration_minutes: Option<f64>,
 }
 
 impl Transitions {
@@ -223,6 +982,64 @@ impl Transitions {
     pub fn default_temperature_cycle_amplitude() -> f64 {
         50.0
     }
+    pub fn default_normalize_altitude() -> bool {
+        false
+    }
+    pub fn default_normalized_reference_altitude() -> f64 {
+        45.0
+    }
+    pub fn default_temperature_altitude_dawn_point() -> f64 {
+        0.0
+    }
+    pub fn default_temperature_transition_time() -> f64 {
+        3.0
+    }
+    pub fn default_transition_steepness() -> f64 {
+        1.0
+    }
+}
+
+impl Transitions {
+    /// Whether `hour` falls in the deep night window, which may wrap around midnight or lie
+    /// within one day (for those who sleep during the day). Equal hours make it last all day.
+    pub fn is_deep_night(self: &Transitions, hour: u8) -> bool {
+        if self.deep_night_start_hour < self.deep_night_end_hour {
+            hour >= self.deep_night_start_hour && hour < self.deep_night_end_hour
+        } else {
+            hour >= self.deep_night_start_hour || hour < self.deep_night_end_hour
+        }
+    }
+
+    pub fn validate(self: &Transitions, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.transition_time <= 0. || self.temperature_transition_time <= 0. {
+            return Err(format!("{}: transition times must be positive", name).into());
+        }
+        let steepness = [
+            (
+                "brightness_transition_steepness",
+                self.brightness_transition_steepness,
+            ),
+            (
+                "temperature_transition_steepness",
+                self.temperature_transition_steepness,
+            ),
+        ];
+        for (field, value) in steepness.iter() {
+            if !(0.1..=10.).contains(value) {
+                return Err(format!("{}.{} must be between 0.1 and 10", name, field).into());
+            }
+        }
+        if self.solar_offset_hours.abs() > 24. {
+            return Err(format!("{}.solar_offset_hours must be between -24 and 24", name).into());
+        }
+        if let Some(minutes) = self.sunrise_duration_minutes {
+            if !(1.0..=360.).contains(&minutes) {
+                let field = "sunrise_duration_minutes";
+                return Err(format!("{}.{} must be between 1 and 360", name, field).into());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for Transitions {
@@ -241,6 +1058,15 @@ impl Default for Transitions {
             temperature_cycle_length: 700_f64,
             brightness_cycle_amplitude: 30.0,
             temperature_cycle_amplitude: 50.0,
+            normalize_altitude: false,
+            normalized_reference_altitude: 45.0,
+            temperature_altitude_dawn_point: 0.0,
+            temperature_transition_time: 3.0,
+            brightness_transition_steepness: 1.0,
+            temperature_transition_steepness: 1.0,
+            solar_offset_hours: 0.0,
+            invert_sun: false,
+            sunrise_duration_minutes: None,
         }
     }
 }
@@ -252,6 +1078,39 @@ pub struct Location {
 
     #[serde(default = "Location::default_lat")]
     pub lat: f64,
+
+    /// A city name or address, resolved to `lat`/`long` when the config is next read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+
+    /// Height of the observer above the surrounding terrain, in metres.
+    #[serde(default = "Location::default_elevation")]
+    pub elevation: f64,
+
+    /// Altitude (degrees) of whatever blocks the view of the sky, by azimuth (degrees from
+    /// north), e.g. `[[90, 5], [180, 2], [270, 8]]`. Interpolated between points.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub horizon: Vec<(f64, f64)>,
+
+    /// Use the apparent solar altitude, including atmospheric refraction. The dawn points are
+    /// calibrated against the apparent altitude.
+    #[serde(default = "Location::default_refraction")]
+    pub refraction: bool,
+
+    /// "vsop87" (precise) or "fast"; see `SolarModel`.
+    #[serde(default)]
+    pub solar_model: SolarModel,
+
+    /// IANA timezone of the location, e.g. "Europe/Amsterdam", for showing sun times. Defaults
+    /// to the system timezone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// Ask GeoClue (Linux, over D-Bus) for the current position once a day, so a laptop
+    /// follows its owner around. `lat`/`long` are used until the first answer. Needs the
+    /// `geoclue` feature.
+    #[serde(default)]
+    pub geoclue: bool,
 }
 
 impl Location {
@@ -262,10 +1121,56 @@ impl Location {
         }
     }
 
-    pub fn default_long() -> f64 {
-        5.387_826_6_f64
+    pub fn horizon(self: &Location) -> Horizon {
+        Horizon::new(self.elevation, &self.horizon)
     }
-    pub fn default_lat() -> f64 {
+
+    /// An altitude cache for this location, its horizon and solar model.
+    pub fn altitude_cache(self: &Location, step_secs: i64) -> AltitudeCache {
+        AltitudeCache::new(&self.as_geograph_point(), step_secs)
+            .with_horizon(self.horizon())
+            .with_options(SolarOptions {
+                refraction: self.refraction,
+                model: self.solar_model,
+            })
+    }
+
+    /// Today's sunrise, sunset and twilight times, in the configured timezone.
+    pub fn describe_sun_times(
+        self: &Location,
+        now: DateTime<Utc>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        fn describe<Tz: TimeZone>(location: &Location, now: DateTime<Utc>, tz: &Tz) -> String
+        where
+            Tz::Offset: std::fmt::Display,
+        {
+            let midnight = curve::midnight(&now.with_timezone(tz));
+            location
+                .altitude_cache(0)
+                .sun_times(midnight.with_timezone(&Utc))
+                .describe(tz)
+        }
+        Ok(match &self.timezone {
+            Some(name) => {
+                let tz: chrono_tz::Tz = name
+                    .parse()
+                    .map_err(|err| format!("Unknown timezone {:?}: {}", name, err))?;
+                describe(self, now, &tz)
+            }
+            None => describe(self, now, &Local),
+        })
+    }
+
+    pub fn default_long() -> f64 {
+        5.387_826_6_f64
+    }
+    pub fn default_elevation() -> f64 {
+        0.0
+    }
+    pub fn default_refraction() -> bool {
+        true
+    }
+    pub fn default_lat() -> f64 {
         52.156_111_3_f64
     }
 }
@@ -275,6 +1180,1228 @@ impl Default for Location {
         Location {
             long: 5.387_826_6_f64,
             lat: 52.156_111_3_f64,
+            address: None,
+            elevation: 0.0,
+            horizon: Vec::new(),
+            refraction: true,
+            solar_model: SolarModel::Vsop87,
+            timezone: None,
+            geoclue: false,
+        }
+    }
+}
+
+/// Whether hue_mie may switch lights on and off, or only adjusts lights that are already on.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerPolicy {
+    #[default]
+    Toggle,
+    OnlyWhenOn,
+}
+
+/// What hue_mie does about scene lights that are currently off.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OffLightPolicy {
+    /// Rewrite the scene and recall it as usual, which may switch the lights on.
+    #[default]
+    Recall,
+    /// Rewrite brightness and colour, but never change whether an off light is on in the scene.
+    KeepOff,
+    /// Rewrite the stored scene, but don't recall it while any of its lights is off.
+    StoreOnly,
+}
+
+/// Presets for what a room is used for, adjusting the curve without per-scene tuning; see
+/// `planner::apply_room_type`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomType {
+    /// Cooler light and a subdued wobble, for offices and studies.
+    Work,
+    /// Slightly warmer light.
+    Relax,
+    /// No wobble, so passing through doesn't catch the lights mid-swing.
+    Hallway,
+    /// Warmer, dimmer light with a subdued wobble.
+    Bedroom,
+}
+
+/// How active scenes are brought onto the curve: by recalling the rewritten scene, or by nudging
+/// each light with `bri_inc`/`ct_inc` when it is already close to the target.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStrategy {
+    #[default]
+    Absolute,
+    Relative,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SceneConfig {
+    #[serde(default)]
+    pub power: PowerPolicy,
+
+    #[serde(default)]
+    pub strategy: UpdateStrategy,
+
+    /// Keep the transition time stored in the scene instead of overwriting it.
+    #[serde(default)]
+    pub preserve_transitiontime: bool,
+
+    /// Smallest brightness change worth writing; smaller changes are skipped to spare the
+    /// ZigBee network.
+    #[serde(default = "SceneConfig::default_min_bri_delta")]
+    pub min_bri_delta: u8,
+
+    /// Smallest colour temperature change (mired) worth writing.
+    #[serde(default = "SceneConfig::default_min_mired_delta")]
+    pub min_mired_delta: u16,
+
+    /// Largest brightness step still sent as a relative update.
+    #[serde(default = "SceneConfig::default_relative_max_bri_delta")]
+    pub relative_max_bri_delta: u8,
+
+    /// Largest colour temperature step (mired) still sent as a relative update.
+    #[serde(default = "SceneConfig::default_relative_max_mired_delta")]
+    pub relative_max_mired_delta: u16,
+
+    /// Largest brightness difference between a light and the scene for which the scene still
+    /// counts as active.
+    #[serde(default = "SceneConfig::default_active_bri_tolerance")]
+    pub active_bri_tolerance: u8,
+
+    /// Largest colour temperature difference (mired) for which the scene still counts as active.
+    #[serde(default = "SceneConfig::default_active_mired_tolerance")]
+    pub active_mired_tolerance: u16,
+
+    /// When set, the activity tolerances are this percentage of the light's current value
+    /// instead of the fixed values above.
+    #[serde(default)]
+    pub active_tolerance_percent: Option<f64>,
+
+    /// Brightness multiplier per light id, e.g. for accent lights. See `hue_mie apply-template`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub light_brightness: BTreeMap<String, f64>,
+
+    /// Brightness (0-255) the curve must reach before an off light is switched on.
+    #[serde(default = "SceneConfig::default_on_threshold")]
+    pub on_threshold: u8,
+
+    /// An on light is switched off once the curve drops to this brightness or below. Keeping it
+    /// below `on_threshold` stops lights flickering on and off around the deep night boundary.
+    #[serde(default = "SceneConfig::default_off_threshold")]
+    pub off_threshold: u8,
+
+    /// Minimum time between switching a light on and off again.
+    #[serde(default = "SceneConfig::default_min_switch_interval_secs")]
+    pub min_switch_interval_secs: u64,
+
+    /// What the room is used for, selecting a preset for the wobble and colour temperature.
+    #[serde(default)]
+    pub room_type: Option<RoomType>,
+
+    /// Apply the `accessibility` limits in this room, for household members with low vision.
+    #[serde(default)]
+    pub accessible: bool,
+
+    #[serde(default)]
+    pub off_lights: OffLightPolicy,
+
+    /// Scenes with a higher priority are written first. Below zero they also wait every other
+    /// iteration while hue_mie is behind schedule.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Make dimmed lights warmer, like incandescent bulbs: 0 (off) to 1 (a light dimmed to
+    /// nothing is at 2000 K).
+    #[serde(default)]
+    pub warm_dimming: f64,
+
+    /// Candle-like flicker on top of the wobble, for cosy evening rooms.
+    #[serde(default)]
+    pub flicker: Option<FlickerConfig>,
+}
+
+impl SceneConfig {
+    pub fn default_min_bri_delta() -> u8 {
+        1
+    }
+    pub fn default_min_mired_delta() -> u16 {
+        1
+    }
+    pub fn default_relative_max_bri_delta() -> u8 {
+        30
+    }
+    pub fn default_relative_max_mired_delta() -> u16 {
+        60
+    }
+    pub fn default_active_bri_tolerance() -> u8 {
+        15
+    }
+    pub fn default_active_mired_tolerance() -> u16 {
+        60
+    }
+    pub fn default_on_threshold() -> u8 {
+        1
+    }
+    pub fn default_off_threshold() -> u8 {
+        0
+    }
+    pub fn default_min_switch_interval_secs() -> u64 {
+        0
+    }
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        SceneConfig {
+            power: PowerPolicy::Toggle,
+            strategy: UpdateStrategy::Absolute,
+            preserve_transitiontime: false,
+            min_bri_delta: 1,
+            min_mired_delta: 1,
+            relative_max_bri_delta: 30,
+            relative_max_mired_delta: 60,
+            active_bri_tolerance: 15,
+            active_mired_tolerance: 60,
+            active_tolerance_percent: None,
+            light_brightness: BTreeMap::new(),
+            on_threshold: 1,
+            off_threshold: 0,
+            min_switch_interval_secs: 0,
+            room_type: None,
+            accessible: false,
+            off_lights: OffLightPolicy::Recall,
+            priority: 0,
+            warm_dimming: 0.,
+            flicker: None,
+        }
+    }
+}
+
+/// Slow random brightness variations per light.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FlickerConfig {
+    /// Largest deviation, as a fraction of the light's brightness.
+    #[serde(default = "FlickerConfig::default_amplitude")]
+    pub amplitude: f64,
+
+    /// Shortest period of the variations in seconds; faster changes are filtered out.
+    #[serde(default = "FlickerConfig::default_cutoff_secs")]
+    pub cutoff_secs: f64,
+}
+
+impl FlickerConfig {
+    pub fn default_amplitude() -> f64 {
+        0.08
+    }
+    pub fn default_cutoff_secs() -> f64 {
+        60.
+    }
+}
+
+impl Default for FlickerConfig {
+    fn default() -> Self {
+        FlickerConfig {
+            amplitude: 0.08,
+            cutoff_secs: 60.,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CalendarConfig {
+    /// Path or http(s) URL of an .ics file.
+    pub source: String,
+
+    #[serde(default = "CalendarConfig::default_refresh_secs")]
+    pub refresh_secs: u64,
+
+    #[serde(default)]
+    pub rules: Vec<CalendarRule>,
+}
+
+impl CalendarConfig {
+    pub fn default_refresh_secs() -> u64 {
+        3600
+    }
+}
+
+/// Events whose summary contains `keyword` switch to the named profile, or pause hue_mie when no
+/// profile is given.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CalendarRule {
+    pub keyword: String,
+
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogConfig {
+    pub path: String,
+
+    #[serde(default = "AuditLogConfig::default_max_bytes")]
+    pub max_bytes: u64,
+
+    /// Number of rotated files to keep.
+    #[serde(default = "AuditLogConfig::default_keep")]
+    pub keep: usize,
+}
+
+impl AuditLogConfig {
+    pub fn default_max_bytes() -> u64 {
+        10 * 1024 * 1024
+    }
+    pub fn default_keep() -> usize {
+        3
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DynamicPaletteConfig {
+    /// Number of curve points in each palette.
+    #[serde(default = "DynamicPaletteConfig::default_points")]
+    pub points: usize,
+
+    /// How far ahead (minutes) the palette reaches.
+    #[serde(default = "DynamicPaletteConfig::default_span_minutes")]
+    pub span_minutes: i64,
+
+    #[serde(default = "DynamicPaletteConfig::default_update_interval_secs")]
+    pub update_interval_secs: u64,
+}
+
+impl DynamicPaletteConfig {
+    pub fn default_points() -> usize {
+        4
+    }
+    pub fn default_span_minutes() -> i64 {
+        30
+    }
+    pub fn default_update_interval_secs() -> u64 {
+        300
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClockDriftConfig {
+    #[serde(default = "ClockDriftConfig::default_check_interval_secs")]
+    pub check_interval_secs: u64,
+
+    /// Drift (seconds) between host and bridge clock above which a warning is logged.
+    #[serde(default = "ClockDriftConfig::default_warn_threshold_secs")]
+    pub warn_threshold_secs: i64,
+
+    /// Follow the bridge clock instead of the host clock when they drift apart, so the curve
+    /// lines up with schedules running on the bridge.
+    #[serde(default = "ClockDriftConfig::default_compensate")]
+    pub compensate: bool,
+}
+
+impl ClockDriftConfig {
+    pub fn default_check_interval_secs() -> u64 {
+        3600
+    }
+    pub fn default_warn_threshold_secs() -> i64 {
+        30
+    }
+    pub fn default_compensate() -> bool {
+        false
+    }
+}
+
+impl Default for ClockDriftConfig {
+    fn default() -> Self {
+        ClockDriftConfig {
+            check_interval_secs: 3600,
+            warn_threshold_secs: 30,
+            compensate: false,
         }
     }
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GradientConfig {
+    #[serde(default = "GradientConfig::default_points")]
+    pub points: usize,
+
+    /// Mired added at the ends of the strip relative to the centre; positive is warmer.
+    #[serde(default = "GradientConfig::default_edge_mired_offset")]
+    pub edge_mired_offset: f64,
+}
+
+impl GradientConfig {
+    pub fn default_points() -> usize {
+        5
+    }
+    pub fn default_edge_mired_offset() -> f64 {
+        30.0
+    }
+}
+
+/// Lights hue_mie controls directly rather than through a scene. Lights that are off are left
+/// alone; with the `toggle` power policy lights are switched off when the curve says so.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ZoneConfig {
+    pub lights: Vec<usize>,
+
+    /// Transitions for this zone instead of the global ones. Fields not given take their
+    /// defaults.
+    #[serde(default)]
+    pub transitions: Option<Transitions>,
+
+    #[serde(default)]
+    pub policy: SceneConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EnergyConfig {
+    pub tibber_token: String,
+
+    /// Hours priced at or above this quantile of the known prices count as peak hours.
+    #[serde(default = "EnergyConfig::default_peak_quantile")]
+    pub peak_quantile: f64,
+
+    /// How much (percent) the brightness ceiling is lowered during peak hours.
+    #[serde(default = "EnergyConfig::default_dimming_percentage")]
+    pub dimming_percentage: f64,
+
+    #[serde(default = "EnergyConfig::default_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+impl EnergyConfig {
+    pub fn default_peak_quantile() -> f64 {
+        0.75
+    }
+    pub fn default_dimming_percentage() -> f64 {
+        20.0
+    }
+    pub fn default_refresh_secs() -> u64 {
+        3600
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BedtimeConfig {
+    /// Names of the bedroom groups; deep night starts once all their lights are off.
+    pub groups: Vec<String>,
+
+    #[serde(default = "BedtimeConfig::default_off_minutes")]
+    pub off_minutes: i64,
+
+    /// Lights switched off before this hour do not count as going to bed.
+    #[serde(default = "BedtimeConfig::default_earliest_hour")]
+    pub earliest_hour: u8,
+
+    /// Keep the house subdued while the bedroom stays dark past `deep_night_end_hour`.
+    #[serde(default)]
+    pub quiet_morning: Option<QuietMorningConfig>,
+}
+
+impl BedtimeConfig {
+    pub fn default_off_minutes() -> i64 {
+        30
+    }
+    pub fn default_earliest_hour() -> u8 {
+        20
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuietMorningConfig {
+    /// Brightness ceiling, as a fraction of full brightness.
+    #[serde(default = "QuietMorningConfig::default_brightness")]
+    pub brightness: f64,
+
+    /// Colour temperature ceiling, in kelvin.
+    #[serde(default = "QuietMorningConfig::default_temperature")]
+    pub temperature: f64,
+
+    /// Once a bedroom light is on, the curve takes over gradually over this many minutes.
+    #[serde(default = "QuietMorningConfig::default_ramp_minutes")]
+    pub ramp_minutes: i64,
+}
+
+impl QuietMorningConfig {
+    pub fn default_brightness() -> f64 {
+        0.3
+    }
+    pub fn default_temperature() -> f64 {
+        2700.
+    }
+    pub fn default_ramp_minutes() -> i64 {
+        30
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LearningConfig {
+    /// Bounds for the learned `sun_altitude_dawn_point`, in degrees.
+    #[serde(default = "LearningConfig::default_min_dawn_point")]
+    pub min_dawn_point: f64,
+
+    #[serde(default = "LearningConfig::default_max_dawn_point")]
+    pub max_dawn_point: f64,
+
+    /// Fraction of the way towards the observed altitude the dawn point moves each day.
+    #[serde(default = "LearningConfig::default_rate")]
+    pub rate: f64,
+
+    /// The dawn point stays put until this many switches have been observed.
+    #[serde(default = "LearningConfig::default_min_samples")]
+    pub min_samples: usize,
+
+    /// Where the learned dawn point is kept; defaults to the data directory.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl LearningConfig {
+    pub fn default_min_dawn_point() -> f64 {
+        -6.
+    }
+    pub fn default_max_dawn_point() -> f64 {
+        6.
+    }
+    pub fn default_rate() -> f64 {
+        0.1
+    }
+    pub fn default_min_samples() -> usize {
+        3
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HooksConfig {
+    /// Fired at the first update after hue_mie started.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_startup: Vec<HookAction>,
+
+    /// Fired after pairing with a bridge; scene actions are ignored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_pairing_completed: Vec<HookAction>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_sunset: Vec<HookAction>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_sunrise: Vec<HookAction>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_deep_night_start: Vec<HookAction>,
+
+    /// Fired when writes are paused because the error budget is exceeded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_error_budget_exceeded: Vec<HookAction>,
+}
+
+/// One thing to do when an event occurs; any combination of the fields may be set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookAction {
+    /// Name of a scene to recall in its group.
+    #[serde(default)]
+    pub scene: Option<String>,
+
+    /// Shell command, run with `sh -c` and the event context in `HUE_MIE_*` variables.
+    #[serde(default)]
+    pub command: Option<String>,
+
+    /// URL to POST to.
+    #[serde(default)]
+    pub webhook: Option<String>,
+}
+
+/// Corrections that make bulbs of different generations look alike at the same setting.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightCalibration {
+    /// Added to the colour temperature (mired) before it is sent.
+    #[serde(default = "LightCalibration::default_mired_offset")]
+    pub mired_offset: f64,
+
+    /// Applied to the brightness fraction: values above 1 dim the low end.
+    #[serde(default = "LightCalibration::default_gamma")]
+    pub gamma: f64,
+
+    /// Lowest brightness the bulb shows without flickering. Dimmer targets are raised to it;
+    /// whether the light goes off instead is left to the scene's thresholds and power policy.
+    /// A light's entry without one falls back to its model's.
+    #[serde(default)]
+    pub min_bri: Option<u8>,
+}
+
+impl LightCalibration {
+    pub fn default_mired_offset() -> f64 {
+        0.0
+    }
+    pub fn default_gamma() -> f64 {
+        1.0
+    }
+
+    pub fn apply(self: &LightCalibration, bri: u8, ct: u16) -> (u8, u16) {
+        let bri = ((f64::from(bri) / 255.).powf(self.gamma) * 255.).round();
+        let ct = (f64::from(ct) + self.mired_offset).round();
+        (bri.clamp(0., 255.) as u8, ct.clamp(153., 500.) as u16)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SummaryConfig {
+    /// Local time of day of the report.
+    #[serde(default = "SummaryConfig::default_hour")]
+    pub hour: u8,
+
+    #[serde(default = "SummaryConfig::default_minute")]
+    pub minute: u8,
+
+    /// URL the summary is POSTed to as JSON, in addition to being logged.
+    #[serde(default)]
+    pub webhook: Option<String>,
+}
+
+impl SummaryConfig {
+    pub fn default_hour() -> u8 {
+        7
+    }
+    pub fn default_minute() -> u8 {
+        0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessibilityConfig {
+    /// Lowest brightness (a fraction of full brightness) while lights are on.
+    #[serde(default = "AccessibilityConfig::default_min_brightness")]
+    pub min_brightness: f64,
+
+    /// Warmest colour temperature in kelvin; very warm light makes colours hard to tell apart.
+    #[serde(default = "AccessibilityConfig::default_min_temperature")]
+    pub min_temperature: f64,
+
+    /// Keep the slow brightness and colour wobble.
+    #[serde(default)]
+    pub wobble: bool,
+}
+
+impl AccessibilityConfig {
+    pub fn default_min_brightness() -> f64 {
+        0.5
+    }
+    pub fn default_min_temperature() -> f64 {
+        3000.
+    }
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> Self {
+        AccessibilityConfig {
+            min_brightness: 0.5,
+            min_temperature: 3000.,
+            wobble: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PacingConfig {
+    /// Fade time (in 100 ms steps) written into scene lightstates and zone updates.
+    #[serde(default = "PacingConfig::default_scene_write_transitiontime")]
+    pub scene_write_transitiontime: u16,
+
+    /// Pause after each write. The bridge handles about ten light commands a second.
+    #[serde(default = "PacingConfig::default_inter_light_delay_ms")]
+    pub inter_light_delay_ms: u64,
+
+    /// Extra pause before writing to the next scene, for bridges that fall behind on scene
+    /// writes. The default keeps the quarter second earlier versions paused after each scene.
+    #[serde(default = "PacingConfig::default_inter_scene_delay_ms")]
+    pub inter_scene_delay_ms: u64,
+
+    /// Seconds between iterations.
+    #[serde(default = "PacingConfig::default_update_interval_secs")]
+    pub update_interval_secs: u64,
+
+    /// Interval while the curve is changing around dawn and dusk, when set.
+    #[serde(default)]
+    pub twilight_update_interval_secs: Option<u64>,
+
+    /// Interval during the day and the night, when the curve barely moves, when set.
+    #[serde(default)]
+    pub steady_update_interval_secs: Option<u64>,
+
+    /// After starting, move lights that are on from where they are to the curve over this
+    /// many seconds instead of at the first update. 0 disables the warm-up.
+    #[serde(default)]
+    pub warmup_secs: u64,
+}
+
+impl PacingConfig {
+    pub fn default_scene_write_transitiontime() -> u16 {
+        15
+    }
+    pub fn default_inter_light_delay_ms() -> u64 {
+        100
+    }
+    pub fn default_inter_scene_delay_ms() -> u64 {
+        250
+    }
+    pub fn default_update_interval_secs() -> u64 {
+        15
+    }
+
+    /// The time between iterations, depending on whether the curve is in a dawn or dusk
+    /// transition.
+    pub fn update_interval(self: &PacingConfig, twilight: bool) -> std::time::Duration {
+        let secs = if twilight {
+            self.twilight_update_interval_secs
+        } else {
+            self.steady_update_interval_secs
+        };
+        std::time::Duration::from_secs(secs.unwrap_or(self.update_interval_secs))
+    }
+
+    pub fn validate(self: &PacingConfig) -> Result<(), Box<dyn std::error::Error>> {
+        if self.scene_write_transitiontime > 6000 {
+            return Err("pacing.scene_write_transitiontime is over 10 minutes".into());
+        }
+        let intervals = [
+            ("update_interval_secs", Some(self.update_interval_secs)),
+            (
+                "twilight_update_interval_secs",
+                self.twilight_update_interval_secs,
+            ),
+            (
+                "steady_update_interval_secs",
+                self.steady_update_interval_secs,
+            ),
+        ];
+        for (name, secs) in intervals.iter() {
+            let secs = match secs {
+                Some(secs) => *secs,
+                None => continue,
+            };
+            if secs == 0 || secs > 3600 {
+                return Err(format!("pacing.{} must be between 1 and 3600 seconds", name).into());
+            }
+            // A fade that outlasts the interval is cut short by the next write.
+            if u64::from(self.scene_write_transitiontime) > secs * 10 {
+                return Err(format!(
+                    "pacing.scene_write_transitiontime ({} ms) is longer than pacing.{} ({} s)",
+                    u64::from(self.scene_write_transitiontime) * 100,
+                    name,
+                    secs
+                )
+                .into());
+            }
+        }
+        if self.inter_light_delay_ms > 10_000 {
+            return Err("pacing.inter_light_delay_ms is over 10 seconds".into());
+        }
+        if self.inter_scene_delay_ms > 60_000 {
+            return Err("pacing.inter_scene_delay_ms is over a minute".into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        PacingConfig {
+            scene_write_transitiontime: 15,
+            inter_light_delay_ms: 100,
+            inter_scene_delay_ms: 250,
+            update_interval_secs: 15,
+            twilight_update_interval_secs: None,
+            steady_update_interval_secs: None,
+            warmup_secs: 0,
+        }
+    }
+}
+
+/// Guards against misconfiguration: the day is never darker than `day_floor`, the evening and
+/// the night never brighter than their ceilings. Brightness values are fractions of full.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClampConfig {
+    #[serde(default)]
+    pub day_floor: Option<f64>,
+
+    #[serde(default)]
+    pub evening_ceiling: Option<f64>,
+
+    #[serde(default)]
+    pub night_ceiling: Option<f64>,
+
+    #[serde(default = "ClampConfig::default_day_start_hour")]
+    pub day_start_hour: u8,
+
+    #[serde(default = "ClampConfig::default_evening_start_hour")]
+    pub evening_start_hour: u8,
+
+    #[serde(default = "ClampConfig::default_night_start_hour")]
+    pub night_start_hour: u8,
+}
+
+impl ClampConfig {
+    pub fn default_day_start_hour() -> u8 {
+        8
+    }
+    pub fn default_evening_start_hour() -> u8 {
+        19
+    }
+    pub fn default_night_start_hour() -> u8 {
+        23
+    }
+
+    /// `target` bounded for the band `hour` falls in.
+    pub fn apply(self: &ClampConfig, hour: u8, target: &LightTarget) -> LightTarget {
+        let in_band = |start: u8, end: u8| {
+            if start <= end {
+                hour >= start && hour < end
+            } else {
+                hour >= start || hour < end
+            }
+        };
+        let (floor, ceiling) = if in_band(self.day_start_hour, self.evening_start_hour) {
+            (self.day_floor, None)
+        } else if in_band(self.evening_start_hour, self.night_start_hour) {
+            (None, self.evening_ceiling)
+        } else {
+            (None, self.night_ceiling)
+        };
+        target.clamp_brightness(floor.unwrap_or(0.), ceiling.unwrap_or(1.))
+    }
+}
+
+/// Limits on every write to a room's lights while `start_hour` to `end_hour` lasts: curve,
+/// overrides, hooks and wake-up nudges alike.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomCap {
+    /// Highest brightness, as a fraction of full.
+    #[serde(default = "RoomCap::default_max_brightness")]
+    pub max_brightness: f64,
+
+    /// Coolest colour temperature (K); colours are replaced by this temperature.
+    #[serde(default = "RoomCap::default_max_kelvin")]
+    pub max_kelvin: f64,
+
+    #[serde(default = "RoomCap::default_start_hour")]
+    pub start_hour: u8,
+
+    #[serde(default = "RoomCap::default_end_hour")]
+    pub end_hour: u8,
+}
+
+impl RoomCap {
+    pub fn default_max_brightness() -> f64 {
+        0.01
+    }
+    pub fn default_max_kelvin() -> f64 {
+        2000.
+    }
+    pub fn default_start_hour() -> u8 {
+        19
+    }
+    pub fn default_end_hour() -> u8 {
+        7
+    }
+
+    /// Whether the cap holds at `hour`; the window may wrap around midnight.
+    pub fn is_active(self: &RoomCap, hour: u8) -> bool {
+        if self.start_hour <= self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+
+    pub fn max_bri(self: &RoomCap) -> u8 {
+        ((self.max_brightness * 254.).round() as u8).max(1)
+    }
+
+    pub fn min_mired(self: &RoomCap) -> u16 {
+        crate::curve::kelvin_to_mired(self.max_kelvin).round() as u16
+    }
+
+    /// Lowers `change` to the cap. A light switched on without a brightness gets the cap, as
+    /// it would otherwise come on at whatever it had before; steps up in brightness or towards
+    /// cooler light become the cap itself.
+    pub fn apply(self: &RoomCap, change: &mut LightStateChange) {
+        let colour = change.xy.is_some()
+            || change.hue.is_some()
+            || change.sat.is_some()
+            || change.hue_inc.is_some()
+            || change.sat_inc.is_some()
+            || change.effect.is_some();
+        if colour || change.ct_inc.is_some_and(|ct_inc| ct_inc < 0) {
+            change.xy = None;
+            change.hue = None;
+            change.sat = None;
+            change.hue_inc = None;
+            change.sat_inc = None;
+            change.effect = None;
+            change.ct_inc = None;
+            change.ct = Some(self.min_mired());
+        }
+        let brighter = change.bri_inc.is_some_and(|bri_inc| bri_inc > 0);
+        if brighter || (change.on == Some(true) && change.bri.is_none()) {
+            change.bri_inc = None;
+            change.bri = Some(self.max_bri());
+        }
+        change.bri = change.bri.map(|bri| bri.min(self.max_bri()));
+        change.ct = change.ct.map(|ct| ct.max(self.min_mired()));
+    }
+
+    /// Whether a light in `change` (e.g. a stored scene lightstate) stays within the cap.
+    pub fn allows(self: &RoomCap, change: &LightStateChange) -> bool {
+        change.on != Some(true)
+            || (change.bri.is_some_and(|bri| bri <= self.max_bri())
+                && change.ct.is_none_or(|ct| ct >= self.min_mired())
+                && change.xy.is_none()
+                && change.hue.is_none())
+    }
+}
+
+/// Writing the power-on state goes to the bulb's flash memory, so it is only rewritten once the
+/// target moved far enough away from what is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PowerupConfig {
+    #[serde(default = "PowerupConfig::default_min_bri_delta")]
+    pub min_bri_delta: u8,
+
+    #[serde(default = "PowerupConfig::default_min_mired_delta")]
+    pub min_mired_delta: u16,
+
+    /// Shortest time between two writes to the same light.
+    #[serde(default = "PowerupConfig::default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+impl PowerupConfig {
+    pub fn default_min_interval_secs() -> u64 {
+        3600
+    }
+    pub fn default_min_bri_delta() -> u8 {
+        20
+    }
+    pub fn default_min_mired_delta() -> u16 {
+        30
+    }
+}
+
+impl Default for PowerupConfig {
+    fn default() -> Self {
+        PowerupConfig {
+            min_bri_delta: 20,
+            min_mired_delta: 30,
+            min_interval_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorBudgetConfig {
+    /// Pause once more than this fraction of the writes in the window failed. 1.0 never pauses.
+    #[serde(default = "ErrorBudgetConfig::default_max_failure_ratio")]
+    pub max_failure_ratio: f64,
+
+    #[serde(default = "ErrorBudgetConfig::default_window_secs")]
+    pub window_secs: u64,
+
+    /// Fewer writes than this in the window never exceed the budget.
+    #[serde(default = "ErrorBudgetConfig::default_min_writes")]
+    pub min_writes: usize,
+
+    /// Length of the first pause; it doubles every time the budget is exceeded again, up to
+    /// `max_pause_secs`.
+    #[serde(default = "ErrorBudgetConfig::default_pause_secs")]
+    pub pause_secs: u64,
+
+    #[serde(default = "ErrorBudgetConfig::default_max_pause_secs")]
+    pub max_pause_secs: u64,
+}
+
+impl ErrorBudgetConfig {
+    pub fn default_max_failure_ratio() -> f64 {
+        0.5
+    }
+    pub fn default_window_secs() -> u64 {
+        600
+    }
+    pub fn default_min_writes() -> usize {
+        20
+    }
+    pub fn default_pause_secs() -> u64 {
+        300
+    }
+    pub fn default_max_pause_secs() -> u64 {
+        3600
+    }
+}
+
+impl Default for ErrorBudgetConfig {
+    fn default() -> Self {
+        ErrorBudgetConfig {
+            max_failure_ratio: 0.5,
+            window_secs: 600,
+            min_writes: 20,
+            pause_secs: 300,
+            max_pause_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LeaderElectionConfig {
+    /// Name of this instance in the lease. Defaults to the host name and process id.
+    #[serde(default)]
+    pub instance_id: Option<String>,
+
+    /// How long the leader may go without renewing the lease before another instance takes
+    /// over.
+    #[serde(default = "LeaderElectionConfig::default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+impl LeaderElectionConfig {
+    pub fn default_lease_secs() -> u64 {
+        60
+    }
+
+    pub fn instance_id(self: &LeaderElectionConfig) -> String {
+        self.instance_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME")
+                .ok()
+                .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+                .map(|host| host.trim().to_owned())
+                .filter(|host| !host.is_empty())
+                .unwrap_or_else(|| String::from("hue_mie"));
+            format!("{}:{}", host, std::process::id())
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// The SQLite database. Defaults to the platform data directory.
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// Seconds between samples.
+    #[serde(default = "HistoryConfig::default_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Samples older than this are deleted.
+    #[serde(default = "HistoryConfig::default_retention_days")]
+    pub retention_days: u32,
+}
+
+impl HistoryConfig {
+    pub fn default_interval_secs() -> u64 {
+        60
+    }
+    pub fn default_retention_days() -> u32 {
+        90
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebUiConfig {
+    /// Port on 127.0.0.1.
+    #[serde(default = "WebUiConfig::default_port")]
+    pub port: u16,
+}
+
+impl WebUiConfig {
+    pub fn default_port() -> u16 {
+        8765
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DesktopConfig {
+    /// Notify when the bridge becomes unreachable, writes keep failing or hue_mie is paused.
+    #[serde(default = "DesktopConfig::default_notifications")]
+    pub notifications: bool,
+
+    /// Show the phase of the day (day, dawn, dusk, night) as a tray icon.
+    #[serde(default)]
+    pub tray: bool,
+}
+
+impl DesktopConfig {
+    pub fn default_notifications() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScriptConfig {
+    /// The rhai script, defining `fn adjust(target, context)`.
+    pub path: PathBuf,
+
+    /// Operations one call may take before it is aborted and the target is left alone.
+    #[serde(default = "ScriptConfig::default_max_operations")]
+    pub max_operations: u64,
+}
+
+impl ScriptConfig {
+    pub fn default_max_operations() -> u64 {
+        100_000
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetUpNudgeConfig {
+    #[serde(default = "GetUpNudgeConfig::default_morning_hour")]
+    pub morning_hour: u8,
+
+    #[serde(default = "GetUpNudgeConfig::default_morning_minute")]
+    pub morning_minute: u8,
+
+    /// How long after the morning time the lights must still be off before nudging.
+    #[serde(default = "GetUpNudgeConfig::default_after_minutes")]
+    pub after_minutes: i64,
+
+    /// How long the ramp from `start_brightness` to the curve takes.
+    #[serde(default = "GetUpNudgeConfig::default_ramp_minutes")]
+    pub ramp_minutes: i64,
+
+    #[serde(default = "GetUpNudgeConfig::default_start_brightness")]
+    pub start_brightness: u8,
+}
+
+impl GetUpNudgeConfig {
+    pub fn default_morning_hour() -> u8 {
+        7
+    }
+    pub fn default_morning_minute() -> u8 {
+        0
+    }
+    pub fn default_after_minutes() -> i64 {
+        30
+    }
+    pub fn default_ramp_minutes() -> i64 {
+        15
+    }
+    pub fn default_start_brightness() -> u8 {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` (path, contents) into a directory of their own under the temp dir.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hue_mie-config-{}-{}", name, std::process::id()));
+        for (file, contents) in files.iter() {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn parse_in(dir: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let parsed = Config::parse(dir.join("config.toml").to_str().unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+        parsed
+    }
+
+    #[test]
+    fn later_includes_and_then_the_including_file_win() {
+        let dir = write_files(
+            "precedence",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    include = ["first.toml", "second.toml"]
+
+                    [transitions]
+                    deep_night_end_hour = 7
+                    "#,
+                ),
+                (
+                    "first.toml",
+                    r#"
+                    [transitions]
+                    deep_night_start_hour = 22
+                    deep_night_end_hour = 5
+
+                    [scene_defaults]
+                    on_threshold = 20
+                    "#,
+                ),
+                (
+                    "second.toml",
+                    r#"
+                    [transitions]
+                    deep_night_start_hour = 21
+                    "#,
+                ),
+            ],
+        );
+        let config = parse_in(&dir).unwrap();
+        assert_eq!(config.transitions.deep_night_start_hour, 21);
+        assert_eq!(config.transitions.deep_night_end_hour, 7);
+        // Tables are merged key by key, so what only the first include sets is kept.
+        assert_eq!(config.scene_defaults.on_threshold, 20);
+    }
+
+    #[test]
+    fn includes_nest_relative_to_the_including_file() {
+        let dir = write_files(
+            "nested",
+            &[
+                ("config.toml", r#"include = ["rooms/living.toml"]"#),
+                (
+                    "rooms/living.toml",
+                    r#"
+                    include = ["common.toml"]
+
+                    [scene_defaults]
+                    on_threshold = 30
+                    "#,
+                ),
+                (
+                    "rooms/common.toml",
+                    r#"
+                    [scene_defaults]
+                    on_threshold = 10
+                    off_threshold = 5
+                    "#,
+                ),
+            ],
+        );
+        let config = parse_in(&dir).unwrap();
+        assert_eq!(config.scene_defaults.on_threshold, 30);
+        assert_eq!(config.scene_defaults.off_threshold, 5);
+
+        let dir = write_files("cycle", &[("config.toml", r#"include = ["config.toml"]"#)]);
+        let err = parse_in(&dir).unwrap_err().to_string();
+        assert!(err.contains("nested too deeply"), "{}", err);
+    }
+
+    #[test]
+    fn a_missing_include_is_an_error() {
+        let dir = write_files("missing", &[("config.toml", r#"include = ["gone.toml"]"#)]);
+        let err = parse_in(&dir).unwrap_err().to_string();
+        assert!(err.contains("Could not read include"), "{}", err);
+        assert!(err.contains("gone.toml"), "{}", err);
+    }
+
+    #[test]
+    fn pacing_defaults_match_without_a_pacing_table() {
+        let pacing: PacingConfig = toml::from_str("").unwrap();
+        let default = PacingConfig::default();
+        assert_eq!(pacing.inter_light_delay_ms, default.inter_light_delay_ms);
+        assert_eq!(pacing.inter_scene_delay_ms, default.inter_scene_delay_ms);
+        assert_eq!(default.inter_scene_delay_ms, 250);
+    }
+}
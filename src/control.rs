@@ -0,0 +1,258 @@
+//! A unix socket through which the CLI talks to the running daemon: each connection carries one
+//! JSON request on one line and gets one JSON response line back. Windows has no unix sockets
+//! in std, so there the CLI can only act on its own.
+
+use crate::overrides::Override;
+use chrono::prelude::*;
+use log::{error, info};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+#[cfg(unix)]
+use std::time::Duration;
+
+/// How long a connection waits for the daemon, which answers between ticks.
+#[cfg(unix)]
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a client may take to send its request. Connections are served one at a time, so a
+/// client that never finishes its line would otherwise hold up every other one.
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the daemon can be asked to do.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    /// Stop writing, for `minutes` or until resumed.
+    Pause {
+        minutes: Option<i64>,
+    },
+    Resume,
+    /// Set a group by hand and leave it alone until the override expires.
+    Set(Override),
+    /// Fetch the whole bridge again and rewrite every managed light, ignoring the minimum
+    /// deltas.
+    Resync,
+}
+
+/// What the daemon is doing, as reported by `Request::Status`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub paused: bool,
+    pub paused_until: Option<DateTime<Local>>,
+    pub last_tick: Option<DateTime<Local>>,
+    /// The light target of the last tick, in bridge units.
+    pub bri: Option<u8>,
+    pub ct: Option<u16>,
+    /// Commands planned in the last tick.
+    pub commands: usize,
+    /// The brightness ceiling while electricity is at its peak price, as a fraction of full
+    /// brightness.
+    #[serde(default)]
+    pub energy_ceiling: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DaemonStatus>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response::default()
+    }
+
+    pub fn error(err: &dyn std::fmt::Display) -> Response {
+        Response {
+            error: Some(err.to_string()),
+            status: None,
+        }
+    }
+}
+
+/// A request waiting for the daemon's answer.
+pub struct Call {
+    pub request: Request,
+    reply: Sender<Response>,
+}
+
+impl Call {
+    pub fn answer(self: Call, response: Response) {
+        // The client may have given up waiting; nothing to do about that.
+        let _ = self.reply.send(response);
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    let mut path: PathBuf = dirs::runtime_dir()
+        .or_else(dirs::data_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    path.push("hue_mie");
+    path.push("control.sock");
+    path
+}
+
+#[cfg(unix)]
+fn answer(stream: &std::os::unix::net::UnixStream, calls: &Sender<Call>) -> Response {
+    use std::io::{BufRead, BufReader};
+    let mut line = String::new();
+    if let Err(err) = stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .and_then(|_| BufReader::new(stream).read_line(&mut line))
+    {
+        return Response::error(&err);
+    }
+    let request: Request = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(err) => return Response::error(&err),
+    };
+    info!("Received {:?} over the control socket", request);
+    let (reply, replied) = std::sync::mpsc::channel();
+    if calls.send(Call { request, reply }).is_err() {
+        return Response::error(&"The daemon is shutting down");
+    }
+    replied
+        .recv_timeout(ANSWER_TIMEOUT)
+        .unwrap_or_else(|_| Response::error(&"The daemon did not answer in time"))
+}
+
+#[cfg(unix)]
+fn serve(listener: std::os::unix::net::UnixListener, calls: Sender<Call>) {
+    use std::io::Write;
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!("Control socket connection failed: {}", err);
+                continue;
+            }
+        };
+        let response = answer(&stream, &calls);
+        let written = serde_json::to_string(&response)
+            .map_err(|err| err.to_string())
+            .and_then(|json| writeln!(stream, "{}", json).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            error!("Could not answer on the control socket: {}", err);
+        }
+    }
+}
+
+/// Listens on `path` in the background. Requests arrive on the returned receiver and are
+/// answered through `Call::answer`.
+#[cfg(unix)]
+pub fn listen(path: &Path) -> Result<Receiver<Call>, Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // A socket left behind by a daemon that did not shut down cleanly.
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    info!("Listening for commands on {}", path.display());
+    let (calls, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || serve(listener, calls));
+    Ok(receiver)
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: &Path) -> Result<Receiver<Call>, Box<dyn Error>> {
+    Err("The control socket needs a unix platform".into())
+}
+
+/// Whether a daemon is listening on `path`.
+#[cfg(unix)]
+pub fn reachable(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+pub fn reachable(_path: &Path) -> bool {
+    false
+}
+
+/// Sends `request` to the daemon listening on `path` and returns its answer; errors reported by
+/// the daemon become `Err`.
+#[cfg(unix)]
+pub fn send(path: &Path, request: &Request) -> Result<Response, Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+    let mut stream = std::os::unix::net::UnixStream::connect(path)
+        .map_err(|err| format!("Is the daemon running? {}: {}", path.display(), err))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line)?;
+    match response.error {
+        Some(err) => Err(err.into()),
+        None => Ok(response),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_path: &Path, _request: &Request) -> Result<Response, Box<dyn Error>> {
+    Err("The control socket needs a unix platform".into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Listens on a socket of its own and answers every request like an idle daemon would.
+    fn daemon(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("hue_mie-{}-{}", name, std::process::id()))
+            .join("control.sock");
+        let calls = listen(&path).unwrap();
+        std::thread::spawn(move || {
+            for call in calls {
+                let response = match call.request {
+                    Request::Status => Response {
+                        error: None,
+                        status: Some(DaemonStatus {
+                            commands: 3,
+                            ..DaemonStatus::default()
+                        }),
+                    },
+                    Request::Resync => Response::error(&"Not now"),
+                    _ => Response::ok(),
+                };
+                call.answer(response);
+            }
+        });
+        path
+    }
+
+    #[test]
+    fn answers_requests_over_the_socket() {
+        let path = daemon("control-round-trip");
+        assert!(reachable(&path));
+        let response = send(&path, &Request::Status).unwrap();
+        assert_eq!(response.status.unwrap().commands, 3);
+        assert!(send(&path, &Request::Pause { minutes: Some(5) })
+            .unwrap()
+            .status
+            .is_none());
+        assert_eq!(
+            send(&path, &Request::Resync).unwrap_err().to_string(),
+            "Not now"
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_silent_client_does_not_hold_up_the_others() {
+        let path = daemon("control-silent");
+        let _silent = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let start = Instant::now();
+        let response = send(&path, &Request::Status).unwrap();
+        assert_eq!(response.status.unwrap().commands, 3);
+        assert!(start.elapsed() < READ_TIMEOUT * 2, "{:?}", start.elapsed());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+use crate::astro_calc::{self, AltitudeCache};
+use crate::clock::Clock;
+use crate::config::{Location, Transitions};
+use chrono::prelude::*;
+use log::debug;
+use std::f64::consts::PI;
+
+trait ExtraMath<T> {
+    fn sigmoid(self) -> T;
+}
+
+impl ExtraMath<f64> for f64 {
+    fn sigmoid(self) -> f64 {
+        self.exp() / (self.exp() + 1_f64)
+    }
+}
+
+impl ExtraMath<f32> for f32 {
+    fn sigmoid(self) -> f32 {
+        self.exp() / (self.exp() + 1_f32)
+    }
+}
+
+pub fn kelvin_to_mired(kelvin: f64) -> f64 {
+    1_000_000_f64 / kelvin
+}
+
+/// Approximates the CIE 1931 chromaticity of a black body at `kelvin` (valid for 1667 K -
+/// 25000 K), for lights and APIs that only take colours, such as gradient points.
+pub fn kelvin_to_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667., 25000.);
+    let x = if t <= 4000. {
+        -0.266_123_9e9 / t.powi(3) - 0.234_358_9e6 / t.powi(2) + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t.powi(3) + 2.107_037_9e6 / t.powi(2) + 0.222_634_7e3 / t + 0.240_390
+    };
+    let y = if t <= 2222. {
+        -1.106_381_4 * x.powi(3) - 1.348_110_20 * x.powi(2) + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000. {
+        -0.954_947_6 * x.powi(3) - 1.374_185_93 * x.powi(2) + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x.powi(3) - 5.873_386_70 * x.powi(2) + 3.751_129_97 * x - 0.370_014_83
+    };
+    (x, y)
+}
+
+/// A deterministic pseudo-random number in [-1, 1] for `seed` and `index` (SplitMix64).
+fn hash_noise(seed: u64, index: i64) -> f64 {
+    let mut z = seed
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .wrapping_add(index as u64)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 52) as f64 - 1.
+}
+
+/// Low-pass filtered noise in [-1, 1] at `time` (seconds): random values every `cutoff_secs`,
+/// smoothly interpolated, so nothing changes faster than that. The same `seed` and `time` always
+/// give the same value, which keeps previews and restarts consistent.
+pub fn filtered_noise(seed: u64, time: f64, cutoff_secs: f64) -> f64 {
+    let position = time / cutoff_secs.max(1.);
+    let index = position.floor();
+    let fraction = position - index;
+    let smooth = fraction * fraction * (3. - 2. * fraction);
+    let (a, b) = (
+        hash_noise(seed, index as i64),
+        hash_noise(seed, index as i64 + 1),
+    );
+    a + (b - a) * smooth
+}
+
+/// The base targets of the curve at a moment in time, before the per-light wobble is applied.
+/// `bri` is a fraction of full brightness (0.0 - 1.0).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Targets {
+    pub bri: f64,
+    pub kelvin: f64,
+    pub mired: f64,
+}
+
+/// Whether the sun is close enough to either dawn point for the brightness or colour
+/// temperature curve to be noticeably changing (beyond three transition times the sigmoid is
+/// within 5% of its end values).
+pub fn in_transition(transitions: &Transitions, sun_altitude: f64) -> bool {
+    let degrees = sun_altitude.to_degrees();
+    (degrees - transitions.sun_altitude_dawn_point).abs()
+        < 3. * transitions.transition_time / transitions.brightness_transition_steepness
+        || (degrees - transitions.temperature_altitude_dawn_point).abs()
+            < 3. * transitions.temperature_transition_time
+                / transitions.temperature_transition_steepness
+}
+
+/// Computes what the curve prescribes at `datetime`. The time zone of `datetime` is taken as the
+/// local time for the deep night window, so no bridge or system clock is involved.
+pub fn targets_at<Tz: TimeZone>(
+    datetime: &DateTime<Tz>,
+    transitions: &Transitions,
+    location: &Location,
+) -> Targets {
+    let mut exact = location.altitude_cache(0);
+    targets_at_cached(datetime, transitions, &mut exact)
+}
+
+/// The targets every `step_minutes` over the 24 hours from `start`, keyed by minutes since
+/// `start`; the data behind a curve plot.
+pub fn day_curve<Tz: TimeZone>(
+    start: &DateTime<Tz>,
+    transitions: &Transitions,
+    location: &Location,
+    step_minutes: i64,
+) -> Vec<(i64, Targets)> {
+    let mut cache = location.altitude_cache(0);
+    (0..24 * 60)
+        .step_by(step_minutes.max(1) as usize)
+        .map(|minute| {
+            let at = start.clone() + chrono::Duration::minutes(minute);
+            (minute, targets_at_cached(&at, transitions, &mut cache))
+        })
+        .collect()
+}
+
+/// Midnight at the start of the day of `datetime`, in its time zone. Where the clocks skip
+/// midnight this is the UTC midnight of that day instead.
+pub fn midnight<Tz: TimeZone>(datetime: &DateTime<Tz>) -> DateTime<Tz> {
+    let naive = datetime.date_naive().and_time(NaiveTime::MIN);
+    let tz = datetime.timezone();
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(&naive))
+}
+
+/// The moment whose sun the curve follows at `datetime`.
+fn solar_time<Tz: TimeZone>(datetime: &DateTime<Tz>, transitions: &Transitions) -> DateTime<Tz> {
+    datetime.clone() - chrono::Duration::seconds((transitions.solar_offset_hours * 3600.) as i64)
+}
+
+/// How far (0 - 1) the morning has come with `sunrise_duration_minutes`: eased from sunrise to
+/// the end of the duration, and `None` before sunrise and after solar noon.
+fn morning_progress<Tz: TimeZone>(
+    datetime: &DateTime<Tz>,
+    transitions: &Transitions,
+    cache: &mut AltitudeCache,
+) -> Option<f64> {
+    let minutes = transitions.sunrise_duration_minutes?;
+    if transitions.invert_sun {
+        return None;
+    }
+    let solar_time = solar_time(datetime, transitions);
+    let midnight = midnight(&solar_time).with_timezone(&Utc);
+    let solar_time = solar_time.with_timezone(&Utc);
+    let sunrise = cache.sunrise(midnight)?;
+    if solar_time < sunrise || solar_time >= cache.day_altitudes(midnight).solar_noon {
+        return None;
+    }
+    let x = ((solar_time - sunrise).num_seconds() as f64 / 60. / minutes).min(1.);
+    Some(x * x * (3. - 2. * x))
+}
+
+/// The sun altitude (radians) the curve follows at `datetime`: shifted by
+/// `solar_offset_hours`, normalized if configured, and mirrored with `invert_sun`.
+pub fn sun_altitude<Tz: TimeZone>(
+    datetime: &DateTime<Tz>,
+    transitions: &Transitions,
+    cache: &mut AltitudeCache,
+) -> f64 {
+    let solar_time = solar_time(datetime, transitions);
+    let mut sun_altitude = cache.altitude(solar_time.with_timezone(&Utc));
+    debug!("Apparent altitude: {:5}", sun_altitude.to_degrees());
+    if transitions.normalize_altitude {
+        let midnight = midnight(&solar_time).with_timezone(&Utc);
+        let day = cache.day_altitudes(midnight);
+        sun_altitude = astro_calc::normalize_altitude(
+            sun_altitude,
+            &day,
+            transitions.normalized_reference_altitude.to_radians(),
+        );
+        debug!("Normalized altitude: {:5}", sun_altitude.to_degrees());
+    }
+    if transitions.invert_sun {
+        sun_altitude = -sun_altitude;
+    }
+    sun_altitude
+}
+
+/// Like `targets_at`, taking solar altitudes from `cache`.
+pub fn targets_at_cached<Tz: TimeZone>(
+    datetime: &DateTime<Tz>,
+    transitions: &Transitions,
+    cache: &mut AltitudeCache,
+) -> Targets {
+    let hour = datetime.hour() as u8;
+    let (bri, kelvin) = match morning_progress(datetime, transitions, cache) {
+        Some(progress) => {
+            let sunrise = astro_calc::SUNRISE_ALTITUDE.to_radians();
+            let from_bri = LightTarget::target_brightness(transitions, sunrise, hour);
+            let from_kelvin = LightTarget::target_color_temperature(transitions, sunrise);
+            let bri = if transitions.is_deep_night(hour) {
+                from_bri
+            } else {
+                from_bri + (transitions.day_brightness - from_bri) * progress
+            };
+            let kelvin = from_kelvin + (transitions.day_temperature - from_kelvin) * progress;
+            (bri, kelvin)
+        }
+        None => {
+            let sun_altitude = sun_altitude(datetime, transitions, cache);
+            (
+                LightTarget::target_brightness(transitions, sun_altitude, hour),
+                LightTarget::target_color_temperature(transitions, sun_altitude),
+            )
+        }
+    };
+    Targets {
+        bri,
+        kelvin,
+        mired: kelvin_to_mired(kelvin),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LightTarget {
+    bri: f64,
+    mired: f64,
+    bri_phase: f64,
+    mired_phase: f64,
+    bri_amplitude: f64,
+    mired_amplitude: f64,
+    /// Bounds on the final brightness (fractions of full), applied after the wobble and every
+    /// adjustment.
+    bri_floor: f64,
+    bri_ceiling: f64,
+    /// The moment of the target, in seconds since the epoch.
+    time: f64,
+    /// How far dimming pulls the colour temperature towards `WARMEST_MIRED`, 0 - 1.
+    warm_dimming: f64,
+}
+
+/// The warmest colour temperature Hue bulbs show (2000 K).
+const WARMEST_MIRED: f64 = 500.;
+
+impl LightTarget {
+    fn target_color_temperature(transitions: &Transitions, sun_altitude: f64) -> f64 {
+        ((sun_altitude.to_degrees() - transitions.temperature_altitude_dawn_point)
+            / transitions.temperature_transition_time
+            * transitions.temperature_transition_steepness)
+            .sigmoid()
+            * (transitions.day_temperature - transitions.night_temperature)
+            + transitions.night_temperature
+    }
+
+    fn target_brightness(transitions: &Transitions, sun_altitude: f64, hour: u8) -> f64 {
+        if transitions.is_deep_night(hour) {
+            transitions.deep_night_brightness
+        } else {
+            ((sun_altitude.to_degrees() - transitions.sun_altitude_dawn_point)
+                / transitions.transition_time
+                * transitions.brightness_transition_steepness)
+                .sigmoid()
+                * (transitions.day_brightness - transitions.night_brightness)
+                + transitions.night_brightness
+        }
+    }
+
+    pub fn new(clock: &dyn Clock, transitions: &Transitions, location: &Location) -> LightTarget {
+        LightTarget::at(&clock.now(), transitions, location)
+    }
+
+    pub fn at<Tz: TimeZone>(
+        datetime: &DateTime<Tz>,
+        transitions: &Transitions,
+        location: &Location,
+    ) -> LightTarget {
+        let mut exact = location.altitude_cache(0);
+        LightTarget::at_cached(datetime, transitions, &mut exact)
+    }
+
+    pub fn at_cached<Tz: TimeZone>(
+        datetime: &DateTime<Tz>,
+        transitions: &Transitions,
+        cache: &mut AltitudeCache,
+    ) -> LightTarget {
+        let targets = targets_at_cached(datetime, transitions, cache);
+        let seconds_from_midnight = datetime.num_seconds_from_midnight();
+
+        LightTarget {
+            bri: targets.bri,
+            mired: targets.mired,
+            bri_phase: (f64::from(seconds_from_midnight) * 2.0 * PI
+                / transitions.brightness_cycle_length)
+                % (2.0 * PI),
+            mired_phase: (f64::from(seconds_from_midnight) * 2.0 * PI
+                / transitions.temperature_cycle_length)
+                % (2.0 * PI),
+            bri_amplitude: transitions.brightness_cycle_amplitude,
+            mired_amplitude: transitions.temperature_cycle_amplitude,
+            bri_floor: 0.,
+            bri_ceiling: 1.,
+            time: datetime.timestamp() as f64,
+            warm_dimming: 0.,
+        }
+    }
+
+    pub fn rotate(self: &LightTarget, angle: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri_phase = (c.bri_phase + angle) % (PI * 2.);
+        c.mired_phase = (c.mired_phase + angle) % (PI * 2.);
+        c
+    }
+
+    /// Scales the brightness, including its cycle, by `factor`.
+    pub fn scale_brightness(self: &LightTarget, factor: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri *= factor;
+        c.bri_amplitude *= factor;
+        c
+    }
+
+    /// Scales the brightness and colour temperature cycles by `factor`, 0 stopping them.
+    pub fn scale_cycle(self: &LightTarget, factor: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri_amplitude *= factor;
+        c.mired_amplitude *= factor;
+        c
+    }
+
+    /// Varies the base brightness by up to `amplitude` (a fraction of it) with noise that is
+    /// different for every `seed`.
+    pub fn flicker(self: &LightTarget, seed: u64, amplitude: f64, cutoff_secs: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri *= 1. + amplitude * filtered_noise(seed, self.time, cutoff_secs);
+        c
+    }
+
+    /// Couples the colour temperature to the final brightness like a dimmed incandescent bulb:
+    /// at `coupling` 1 a light dimmed to nothing is as warm as it gets, at 0 nothing changes.
+    pub fn warm_dim(self: &LightTarget, coupling: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.warm_dimming = coupling.clamp(0., 1.);
+        c
+    }
+
+    /// Caps the base brightness at `ceiling` (a fraction of full brightness).
+    pub fn limit_brightness(self: &LightTarget, ceiling: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri = c.bri.min(ceiling);
+        c
+    }
+
+    /// Raises the base brightness to at least `floor` (a fraction of full brightness).
+    pub fn floor_brightness(self: &LightTarget, floor: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri = c.bri.max(floor);
+        c
+    }
+
+    /// Keeps the colour temperature at or above `kelvin`.
+    pub fn limit_warmth(self: &LightTarget, kelvin: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.mired = c.mired.min(kelvin_to_mired(kelvin));
+        c
+    }
+
+    /// Keeps the colour temperature at or below `kelvin`.
+    pub fn limit_coolness(self: &LightTarget, kelvin: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.mired = c.mired.max(kelvin_to_mired(kelvin));
+        c
+    }
+
+    /// Moves the base brightness, colour temperature and cycle amplitudes `weight` (0.0 - 1.0)
+    /// of the way towards `other`.
+    pub fn blend(self: &LightTarget, other: &LightTarget, weight: f64) -> LightTarget {
+        let mix = |a: f64, b: f64| a + (b - a) * weight;
+        let mut c = self.clone();
+        c.bri = mix(self.bri, other.bri);
+        c.mired = mix(self.mired, other.mired);
+        c.bri_amplitude = mix(self.bri_amplitude, other.bri_amplitude);
+        c.mired_amplitude = mix(self.mired_amplitude, other.mired_amplitude);
+        c
+    }
+
+    /// Keeps the final brightness between `floor` and `ceiling` (fractions of full brightness),
+    /// whatever else is done to the target afterwards.
+    pub fn clamp_brightness(self: &LightTarget, floor: f64, ceiling: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri_floor = c.bri_floor.max(floor);
+        c.bri_ceiling = c.bri_ceiling.min(ceiling);
+        c
+    }
+
+    /// Shifts the colour temperature by `kelvin`, then by `mired`.
+    pub fn shift_temperature(self: &LightTarget, kelvin: f64, mired: f64) -> LightTarget {
+        let mut c = self.clone();
+        if kelvin != 0. {
+            c.mired = kelvin_to_mired((1_000_000. / c.mired + kelvin).max(1000.));
+        }
+        c.mired += mired;
+        c
+    }
+
+    /// The base brightness (a fraction of full) and colour temperature (kelvin), before the
+    /// cycle and bounds.
+    pub fn base(self: &LightTarget) -> (f64, f64) {
+        (self.bri, 1_000_000. / self.mired)
+    }
+
+    /// Replaces the base brightness and colour temperature, keeping the cycle and bounds.
+    pub fn with_base(self: &LightTarget, bri: f64, kelvin: f64) -> LightTarget {
+        let mut c = self.clone();
+        c.bri = bri.max(0.);
+        c.mired = kelvin_to_mired(kelvin.max(1000.));
+        c
+    }
+
+    pub fn ct(self: &LightTarget) -> u16 {
+        let mired = self.mired_phase.cos() * self.mired_amplitude + self.mired;
+        let dimmed = 1. - f64::from(self.bri()) / 255.;
+        let mired = mired + self.warm_dimming * dimmed * (WARMEST_MIRED - mired).max(0.);
+        mired.clamp(0., 65535.) as u16
+    }
+
+    pub fn bri(self: &LightTarget) -> u8 {
+        (self.bri_phase.cos() * self.bri_amplitude + self.bri * 255.)
+            .min(self.bri_ceiling * 255.)
+            .max(self.bri_floor * 255.)
+            .clamp(0., 255.) as u8
+    }
+
+    pub fn on(self: &LightTarget) -> bool {
+        self.bri() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// `targets_at` in Amsterdam with the default transitions, at `hour`:`minute` on `date` in
+    /// the time zone `utc_offset` hours east of UTC.
+    fn targets(date: (i32, u32, u32), hour: u32, minute: u32, utc_offset: i32) -> Targets {
+        let config = testing::config("[location]\nlat = 52.37\nlong = 4.89");
+        let at = FixedOffset::east_opt(utc_offset * 3600)
+            .unwrap()
+            .with_ymd_and_hms(date.0, date.1, date.2, hour, minute, 0)
+            .unwrap();
+        targets_at(&at, &config.transitions, &config.location)
+    }
+
+    fn assert_targets(targets: Targets, bri: f64, kelvin: f64) {
+        assert!((targets.bri - bri).abs() < 0.001, "{:?}", targets);
+        assert!((targets.kelvin - kelvin).abs() < 1., "{:?}", targets);
+        assert!(
+            (targets.mired - 1_000_000. / kelvin).abs() < 1.,
+            "{:?}",
+            targets
+        );
+    }
+
+    #[test]
+    fn follows_the_day_night_and_deep_night_values() {
+        // Midsummer afternoon, the sun some 60 degrees up.
+        assert_targets(targets((2024, 6, 21), 14, 0, 2), 1.0, 5700.);
+        // Midwinter evening, the sun far below the horizon.
+        assert_targets(targets((2024, 12, 21), 20, 0, 1), 0.7, 2400.);
+        // The middle of the deep night.
+        assert_targets(targets((2024, 12, 21), 2, 0, 1), 0.0, 2400.);
+    }
+
+    #[test]
+    fn the_deep_night_follows_the_time_zone_of_the_moment() {
+        assert_targets(targets((2024, 12, 21), 22, 30, 0), 0.7, 2400.);
+        assert_targets(targets((2024, 12, 21), 23, 30, 1), 0.0, 2400.);
+    }
+}
@@ -0,0 +1,223 @@
+//! Desktop integration over the session D-Bus, for hosts that are also someone's computer:
+//! notifications on errors and pauses, and a StatusNotifierItem tray icon showing the phase of
+//! the day.
+
+use crate::config::{DesktopConfig, Transitions};
+use crate::curve;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::Message;
+use log::{debug, error, info};
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const ITEM: &str = "org.kde.StatusNotifierItem";
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the tray thread looks for a new phase between D-Bus messages.
+const TRAY_POLL: Duration = Duration::from_millis(500);
+
+/// Where the curve is in the day, as shown in the tray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Day,
+    Dawn,
+    Dusk,
+    Night,
+    DeepNight,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Day => "day",
+            Phase::Dawn => "dawn",
+            Phase::Dusk => "dusk",
+            Phase::Night => "night",
+            Phase::DeepNight => "deep night",
+        })
+    }
+}
+
+impl Phase {
+    /// The phase for the sun altitude (radians) the curve follows, at `hour` local time.
+    pub fn at(transitions: &Transitions, sun_altitude: f64, hour: u8) -> Phase {
+        if transitions.is_deep_night(hour) {
+            Phase::DeepNight
+        } else if curve::in_transition(transitions, sun_altitude) {
+            if hour < 12 {
+                Phase::Dawn
+            } else {
+                Phase::Dusk
+            }
+        } else if sun_altitude.to_degrees() > transitions.sun_altitude_dawn_point {
+            Phase::Day
+        } else {
+            Phase::Night
+        }
+    }
+
+    fn icon_name(self: Phase) -> &'static str {
+        match self {
+            Phase::Day => "weather-clear",
+            Phase::Dawn | Phase::Dusk => "weather-few-clouds",
+            Phase::Night | Phase::DeepNight => "weather-clear-night",
+        }
+    }
+}
+
+/// Shows a desktop notification from hue_mie.
+pub fn notify(summary: &str, body: &str) -> Result<(), Box<dyn Error>> {
+    let connection = Connection::new_session()?;
+    let proxy = connection.with_proxy(
+        NOTIFICATIONS,
+        "/org/freedesktop/Notifications",
+        CALL_TIMEOUT,
+    );
+    let actions: Vec<String> = Vec::new();
+    let (_id,): (u32,) = proxy.method_call(
+        NOTIFICATIONS,
+        "Notify",
+        (
+            "hue_mie",
+            0u32,
+            "",
+            summary,
+            body,
+            actions,
+            PropMap::new(),
+            -1i32,
+        ),
+    )?;
+    Ok(())
+}
+
+fn string_variant(value: &str) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value.to_owned()))
+}
+
+fn item_properties(phase: Phase) -> PropMap {
+    let mut properties = PropMap::new();
+    properties.insert("Category".to_owned(), string_variant("ApplicationStatus"));
+    properties.insert("Id".to_owned(), string_variant("hue_mie"));
+    properties.insert(
+        "Title".to_owned(),
+        string_variant(&format!("hue_mie: {}", phase)),
+    );
+    properties.insert("Status".to_owned(), string_variant("Active"));
+    properties.insert("IconName".to_owned(), string_variant(phase.icon_name()));
+    properties
+}
+
+/// Answers the property requests of tray hosts with the current phase.
+fn answer(message: &Message, phase: Phase) -> Option<Message> {
+    if message.interface().as_deref() != Some("org.freedesktop.DBus.Properties") {
+        return None;
+    }
+    let mut properties = item_properties(phase);
+    match message.member().as_deref() {
+        Some("GetAll") => Some(message.method_return().append1(properties)),
+        Some("Get") => {
+            let (_, name): (&str, &str) = message.read2().ok()?;
+            let value = properties.remove(name)?;
+            Some(message.method_return().append1(value))
+        }
+        _ => None,
+    }
+}
+
+fn serve_tray(phases: Receiver<Phase>) -> Result<(), Box<dyn Error>> {
+    let connection = Connection::new_session()?;
+    let name = format!("{}-{}-1", ITEM, std::process::id());
+    connection.request_name(name.as_str(), false, true, false)?;
+    let phase = Arc::new(Mutex::new(Phase::Day));
+    let shown = phase.clone();
+    let mut rule = MatchRule::new_method_call();
+    rule.path = Some(ITEM_PATH.into());
+    connection.start_receive(
+        rule,
+        Box::new(move |message, connection| {
+            let phase = *shown.lock().unwrap();
+            if let Some(reply) = answer(&message, phase) {
+                let _ = connection.send(reply);
+            }
+            true
+        }),
+    );
+    let watcher = connection.with_proxy(WATCHER, "/StatusNotifierWatcher", CALL_TIMEOUT);
+    watcher.method_call::<(), _, _, _>(WATCHER, "RegisterStatusNotifierItem", (name,))?;
+    info!("Showing the phase of the day in the tray");
+    loop {
+        connection.process(TRAY_POLL)?;
+        let mut changed = false;
+        while let Ok(new) = phases.try_recv() {
+            *phase.lock().unwrap() = new;
+            changed = true;
+        }
+        if changed {
+            for signal in ["NewTitle", "NewIcon"].iter() {
+                let message = Message::new_signal(ITEM_PATH, ITEM, *signal)?;
+                let _ = connection.send(message);
+            }
+        }
+    }
+}
+
+/// The notifications and tray icon configured under `[desktop]`.
+pub struct Desktop {
+    config: DesktopConfig,
+    tray: Option<SyncSender<Phase>>,
+    phase: Option<Phase>,
+}
+
+impl Desktop {
+    /// Starts the tray icon on its own thread, if configured.
+    pub fn new(config: &DesktopConfig) -> Desktop {
+        let tray = if config.tray {
+            let (sender, receiver) = mpsc::sync_channel(8);
+            thread::spawn(move || {
+                if let Err(err) = serve_tray(receiver) {
+                    error!("Tray icon stopped: {}", err);
+                }
+            });
+            Some(sender)
+        } else {
+            None
+        };
+        Desktop {
+            config: config.clone(),
+            tray,
+            phase: None,
+        }
+    }
+
+    /// Shows a notification when enabled; failures are only logged.
+    pub fn notify(self: &Desktop, summary: &str, body: &str) {
+        if !self.config.notifications {
+            return;
+        }
+        if let Err(err) = notify(summary, body) {
+            error!("Could not show a desktop notification: {}", err);
+        }
+    }
+
+    /// Updates the tray icon when the phase changed.
+    pub fn set_phase(self: &mut Desktop, phase: Phase) {
+        if self.phase == Some(phase) {
+            return;
+        }
+        debug!("Phase: {}", phase);
+        self.phase = Some(phase);
+        if let Some(tray) = &self.tray {
+            let _ = tray.try_send(phase);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! What the next update would change in each managed scene, for `hue_mie diff`.
+
+use crate::hue::LightStateChange;
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+
+#[derive(Debug, Serialize)]
+pub struct LightDiff {
+    pub scene_id: String,
+    pub scene_name: String,
+    pub light_id: usize,
+    pub current: Option<LightStateChange>,
+    pub planned: LightStateChange,
+}
+
+/// The scene lightstate writes among `commands`, with the lightstates they would replace.
+pub fn scene_diffs(state: &BridgeState, commands: &[LightCommand]) -> Vec<LightDiff> {
+    commands
+        .iter()
+        .filter_map(|command| match command {
+            LightCommand::SetSceneLightState {
+                scene_id,
+                light_id,
+                state: planned,
+            } => {
+                let scene = state.scenes.get(scene_id);
+                Some(LightDiff {
+                    scene_id: scene_id.clone(),
+                    scene_name: scene.map(|scene| scene.name.clone()).unwrap_or_default(),
+                    light_id: *light_id,
+                    current: scene.and_then(|scene| scene.lightstates.get(light_id).cloned()),
+                    planned: planned.clone(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn show<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| String::from("-"), |value| value.to_string())
+}
+
+/// One table row per diff; changed values are highlighted when `color` is set.
+pub fn format_table(diffs: &[LightDiff], color: bool) -> String {
+    let mut table = format!(
+        "{:<24} {:>5}  {:>9} {:>9} {:>9}\n",
+        "scene", "light", "on", "bri", "ct"
+    );
+    for diff in diffs {
+        let current = diff.current.clone().unwrap_or_default();
+        let column = |old: String, new: String| {
+            let cell = format!("{}→{}", old, new);
+            if color && old != new {
+                format!("\x1b[1;33m{:>9}\x1b[0m", cell)
+            } else {
+                format!("{:>9}", cell)
+            }
+        };
+        table.push_str(&format!(
+            "{:<24} {:>5}  {} {} {}\n",
+            diff.scene_name,
+            diff.light_id,
+            column(show(current.on), show(diff.planned.on)),
+            column(show(current.bri), show(diff.planned.bri)),
+            column(show(current.ct), show(diff.planned.ct)),
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::LightTarget;
+    use crate::planner;
+    use crate::testing::{self, FakeBridge};
+    use chrono::prelude::*;
+    use serde_json::Value;
+
+    #[test]
+    fn json_output_parses() {
+        let bridge = FakeBridge::new(
+            vec![(1, testing::light(true, 200, 250))],
+            vec![(1, testing::room("Living", &[1]))],
+            vec![(
+                "s1",
+                testing::scene("Living dayshift", &[(1, true, 200, 250)]),
+            )],
+        );
+        let state = bridge.state();
+        let config = testing::config("");
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        let light_target = LightTarget::at(&noon, &config.transitions, &config.location)
+            .scale_cycle(0.)
+            .with_base(0.5, 2500.);
+        let diffs = scene_diffs(&state, &planner::plan(&state, &light_target, &config));
+
+        let json = serde_json::to_string_pretty(&diffs).unwrap();
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        let parsed = parsed.as_array().unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0]["scene_id"], "s1");
+        assert_eq!(parsed[0]["scene_name"], "Living dayshift");
+        assert_eq!(parsed[0]["light_id"], 1);
+        assert_eq!(parsed[0]["current"]["bri"], 200);
+        assert_eq!(parsed[0]["planned"]["bri"], 127);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config::EnergyConfig;
+use crate::http;
+use chrono::prelude::*;
+use log::{error, info};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const TIBBER_URL: &str = "https://api.tibber.com/v1-beta/gql";
+const TIBBER_QUERY: &str = "{ viewer { homes { currentSubscription { priceInfo { \
+                            today { total startsAt } tomorrow { total startsAt } } } } } }";
+
+#[derive(Clone, Debug, Deserialize)]
+struct PricePoint {
+    total: f64,
+    #[serde(rename = "startsAt")]
+    starts_at: String,
+}
+
+/// Prices by the start of their hour.
+type HourlyPrices = Vec<(DateTime<FixedOffset>, f64)>;
+
+/// Hourly electricity prices from Tibber, refreshed periodically, used to dim during peak hours.
+pub struct EnergyPrices {
+    config: EnergyConfig,
+    prices: HourlyPrices,
+    fetched: Option<Instant>,
+}
+
+impl EnergyPrices {
+    pub fn new(config: EnergyConfig) -> EnergyPrices {
+        EnergyPrices {
+            config,
+            prices: Vec::new(),
+            fetched: None,
+        }
+    }
+
+    fn load(self: &EnergyPrices) -> Result<HourlyPrices, Box<dyn Error>> {
+        let response: Value = http::client()?
+            .post(TIBBER_URL)
+            .bearer_auth(&self.config.tibber_token)
+            .json(&json!({ "query": TIBBER_QUERY }))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        let price_info = response
+            .pointer("/data/viewer/homes/0/currentSubscription/priceInfo")
+            .ok_or("No price info in Tibber response")?;
+        let mut prices = Vec::new();
+        for day in &["today", "tomorrow"] {
+            let points: Vec<PricePoint> = match price_info.get(*day) {
+                Some(points) if !points.is_null() => serde_json::from_value(points.clone())?,
+                _ => Vec::new(),
+            };
+            for point in points {
+                prices.push((DateTime::parse_from_rfc3339(&point.starts_at)?, point.total));
+            }
+        }
+        Ok(prices)
+    }
+
+    pub fn refresh_if_due(self: &mut EnergyPrices) {
+        let due = self.fetched.is_none_or(|fetched| {
+            fetched.elapsed() >= Duration::from_secs(self.config.refresh_secs)
+        });
+        if !due {
+            return;
+        }
+        self.fetched = Some(Instant::now());
+        match self.load() {
+            Ok(prices) => {
+                info!("Read {} hourly energy prices", prices.len());
+                self.prices = prices;
+            }
+            Err(err) => error!("Could not read energy prices: {}", err),
+        }
+    }
+
+    /// Whether `now` falls in an hour priced at or above the configured quantile of the known
+    /// prices.
+    pub fn is_peak(self: &EnergyPrices, now: &DateTime<Local>) -> bool {
+        let now = now.timestamp();
+        let current = self
+            .prices
+            .iter()
+            .rfind(|(starts_at, _)| starts_at.timestamp() <= now)
+            .filter(|(starts_at, _)| now - starts_at.timestamp() < 3600)
+            .map(|(_, price)| *price);
+        let current = match current {
+            Some(current) => current,
+            None => return false,
+        };
+        let mut sorted: Vec<f64> = self.prices.iter().map(|(_, price)| *price).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let idx = ((sorted.len() - 1) as f64 * self.config.peak_quantile.clamp(0., 1.)) as usize;
+        current >= sorted[idx]
+    }
+
+    /// The brightness ceiling as a fraction of `day_brightness`: lowered by the configured
+    /// percentage during peak hours, unlimited otherwise.
+    pub fn brightness_ceiling(
+        self: &EnergyPrices,
+        now: &DateTime<Local>,
+        day_brightness: f64,
+    ) -> Option<f64> {
+        if self.is_peak(now) {
+            Some(day_brightness * (1. - self.config.dimming_percentage / 100.))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::audit::{AuditLog, Reason};
+use crate::backend::Backend;
+use crate::clock::Clock;
+use crate::health::{self, NetworkHealth};
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use log::{debug, error, info};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// A number in [0, 1) that differs between calls and between processes, without pulling in a
+/// random number crate.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Applies planned commands to a backend, pausing between commands so the bridge is not flooded.
+/// In dry-run mode commands are only logged.
+pub struct Executor {
+    pub command_delay: Duration,
+    pub dry_run: bool,
+    pub audit_log: Option<AuditLog>,
+    /// When set, commands are spread over this period with random gaps instead of being sent
+    /// `command_delay` apart, so several instances or many scenes do not burst together.
+    pub spread_over: Option<Duration>,
+    /// Extra pause whenever the commands move on to another scene.
+    pub scene_delay: Duration,
+    pub health: NetworkHealth,
+}
+
+impl Executor {
+    pub fn new(command_delay: Duration, dry_run: bool, audit_log: Option<AuditLog>) -> Executor {
+        Executor {
+            command_delay,
+            dry_run,
+            audit_log,
+            spread_over: None,
+            scene_delay: Duration::from_millis(0),
+            health: NetworkHealth::default(),
+        }
+    }
+
+    /// Returns the number of commands that failed. Successful commands are recorded in `state`,
+    /// and every attempt in the audit log with the given reason. Commands for lights that keep
+    /// failing are only sent now and then, see `NetworkHealth`. The pauses between commands are
+    /// taken on `clock`.
+    pub fn apply(
+        self: &mut Executor,
+        backend: &dyn Backend,
+        clock: &dyn Clock,
+        commands: &[LightCommand],
+        state: &mut BridgeState,
+        reason: Reason,
+    ) -> usize {
+        let mut failures = 0;
+        let mut last_scene: Option<&str> = None;
+        let spread_gap = self
+            .spread_over
+            .map(|spread_over| spread_over / (commands.len() as u32 + 1));
+        for command in commands {
+            if self.dry_run {
+                info!("Dry run, not applying: {:?}", command);
+                continue;
+            }
+            if let Some(gap) = spread_gap {
+                clock.pause(gap.mul_f64(2. * random_fraction()).max(self.command_delay));
+            }
+            let scene = match command {
+                LightCommand::SetSceneLightState { scene_id, .. }
+                | LightCommand::SetSceneLights { scene_id, .. }
+                | LightCommand::RecallScene { scene_id, .. } => Some(scene_id.as_str()),
+                _ => None,
+            };
+            if scene.is_some() && last_scene.is_some() && scene != last_scene {
+                clock.pause(self.scene_delay);
+            }
+            last_scene = scene.or(last_scene);
+            let light_id = health::command_light(command);
+            if let Some(light_id) = light_id {
+                if !self.health.should_send(light_id) {
+                    debug!(
+                        "Light {} is failing, skipping {:?} this time",
+                        light_id, command
+                    );
+                    continue;
+                }
+            }
+            let result = Executor::apply_one(backend, command);
+            if let Some(light_id) = light_id {
+                self.health.record(light_id, result.is_ok());
+            }
+            if let Some(audit_log) = &self.audit_log {
+                audit_log.record(command, state, reason, result.is_ok());
+            }
+            match result {
+                Ok(()) => state.record(command),
+                Err(err) => {
+                    error!("Could not apply {:?}: {}", command, err);
+                    failures += 1;
+                }
+            }
+            if spread_gap.is_none() {
+                clock.pause(self.command_delay);
+            }
+        }
+        failures
+    }
+
+    fn apply_one(
+        backend: &dyn Backend,
+        command: &LightCommand,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match command {
+            LightCommand::SetLightState { light_id, state } => {
+                backend.set_light_state(*light_id, state)
+            }
+            LightCommand::SetSceneLightState {
+                scene_id,
+                light_id,
+                state,
+            } => backend.set_light_state_in_scene(scene_id, *light_id, state),
+            LightCommand::RecallScene { scene_id, group_id } => {
+                info!("Recall scene {} in group {}", scene_id, group_id);
+                backend.recall_scene_in_group(*group_id, scene_id)
+            }
+            LightCommand::SetSceneLights { scene_id, lights } => {
+                info!("Setting the lights of scene {} to {:?}", scene_id, lights);
+                backend.set_scene_lights(scene_id, lights)
+            }
+            LightCommand::SetLightConfig { light_id, config } => {
+                backend.set_light_config(*light_id, config)
+            }
+            LightCommand::CreateScene { name, lights } => {
+                let scene_id = backend.create_scene(name, lights)?;
+                info!("Created scene {} with id {}", name, scene_id);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! The host's position from GeoClue2, over the system D-Bus.
+
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use log::{debug, info};
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVICE: &str = "org.freedesktop.GeoClue2";
+const CLIENT: &str = "org.freedesktop.GeoClue2.Client";
+const LOCATION: &str = "org.freedesktop.GeoClue2.Location";
+/// GeoClue's accuracy level for city-level positions; plenty for sun times.
+const ACCURACY_CITY: u32 = 4;
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the first fix after starting the client.
+const FIX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns (lat, long) of the host.
+pub fn locate() -> Result<(f64, f64), Box<dyn Error>> {
+    let connection = Connection::new_system()?;
+    let manager = connection.with_proxy(SERVICE, "/org/freedesktop/GeoClue2/Manager", CALL_TIMEOUT);
+    let (client_path,): (dbus::Path<'static>,) =
+        manager.method_call("org.freedesktop.GeoClue2.Manager", "GetClient", ())?;
+    let client = connection.with_proxy(SERVICE, client_path, CALL_TIMEOUT);
+    client.set(CLIENT, "DesktopId", "hue_mie".to_owned())?;
+    client.set(CLIENT, "RequestedAccuracyLevel", ACCURACY_CITY)?;
+    client.method_call::<(), _, _, _>(CLIENT, "Start", ())?;
+
+    let started = Instant::now();
+    let location_path = loop {
+        let path: dbus::Path<'static> = client.get(CLIENT, "Location")?;
+        if &*path != "/" {
+            break path;
+        }
+        if started.elapsed() >= FIX_TIMEOUT {
+            client.method_call::<(), _, _, _>(CLIENT, "Stop", ())?;
+            return Err("GeoClue did not report a position in time".into());
+        }
+        debug!("Waiting for a position from GeoClue");
+        thread::sleep(Duration::from_secs(1));
+    };
+    let location = connection.with_proxy(SERVICE, location_path, CALL_TIMEOUT);
+    let lat: f64 = location.get(LOCATION, "Latitude")?;
+    let long: f64 = location.get(LOCATION, "Longitude")?;
+    client.method_call::<(), _, _, _>(CLIENT, "Stop", ())?;
+    info!("GeoClue puts us at {}, {}", lat, long);
+    Ok((lat, long))
+}
@@ -0,0 +1,228 @@
+//! Turning a city name or address into coordinates.
+
+use crate::config::Location;
+use crate::http;
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const NOMINATIM_URL: &str = "https://nominatim.openstreetmap.org/search";
+
+/// (name, lat, long) of some larger cities, used when the geocoding service is unreachable.
+const CITIES: &[(&str, f64, f64)] = &[
+    ("amsterdam", 52.3676, 4.9041),
+    ("rotterdam", 51.9244, 4.4777),
+    ("utrecht", 52.0907, 5.1214),
+    ("den haag", 52.0705, 4.3007),
+    ("the hague", 52.0705, 4.3007),
+    ("eindhoven", 51.4416, 5.4697),
+    ("groningen", 53.2194, 6.5665),
+    ("amersfoort", 52.1561, 5.3878),
+    ("brussels", 50.8503, 4.3517),
+    ("antwerp", 51.2194, 4.4025),
+    ("berlin", 52.5200, 13.4050),
+    ("hamburg", 53.5511, 9.9937),
+    ("munich", 48.1351, 11.5820),
+    ("paris", 48.8566, 2.3522),
+    ("london", 51.5074, -0.1278),
+    ("dublin", 53.3498, -6.2603),
+    ("madrid", 40.4168, -3.7038),
+    ("rome", 41.9028, 12.4964),
+    ("vienna", 48.2082, 16.3738),
+    ("zurich", 47.3769, 8.5417),
+    ("copenhagen", 55.6761, 12.5683),
+    ("stockholm", 59.3293, 18.0686),
+    ("oslo", 59.9139, 10.7522),
+    ("helsinki", 60.1699, 24.9384),
+    ("reykjavik", 64.1466, -21.9426),
+    ("new york", 40.7128, -74.0060),
+    ("chicago", 41.8781, -87.6298),
+    ("los angeles", 34.0522, -118.2437),
+    ("san francisco", 37.7749, -122.4194),
+    ("seattle", 47.6062, -122.3321),
+    ("toronto", 43.6532, -79.3832),
+    ("vancouver", 49.2827, -123.1207),
+    ("sydney", -33.8688, 151.2093),
+    ("melbourne", -37.8136, 144.9631),
+    ("auckland", -36.8485, 174.7633),
+    ("tokyo", 35.6762, 139.6503),
+    ("singapore", 1.3521, 103.8198),
+    ("cape town", -33.9249, 18.4241),
+];
+
+fn lookup_online(address: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let results: Vec<Value> = http::client()?
+        .get(NOMINATIM_URL)
+        .query(&[("q", address), ("format", "json"), ("limit", "1")])
+        .header("User-Agent", concat!("hue_mie/", env!("CARGO_PKG_VERSION")))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let first = results.first().ok_or("No match")?;
+    let coordinate = |key: &str| -> Result<f64, Box<dyn Error>> {
+        Ok(first
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or("Incomplete result")?
+            .parse()?)
+    };
+    Ok((coordinate("lat")?, coordinate("lon")?))
+}
+
+/// Matches the city part (before the first comma) against the bundled list.
+fn lookup_offline(address: &str) -> Option<(f64, f64)> {
+    let city = address.split(',').next()?.trim().to_lowercase();
+    CITIES
+        .iter()
+        .find(|(name, _, _)| *name == city)
+        .map(|(_, lat, long)| (*lat, *long))
+}
+
+/// Where addresses found online are remembered, so a config that is never rewritten does not
+/// ask the geocoding service again at every start.
+fn cache_path() -> PathBuf {
+    let mut path: PathBuf = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("hue_mie");
+    path.push("geocode.json");
+    path
+}
+
+fn cached(path: &Path, address: &str) -> Option<(f64, f64)> {
+    let cache: BTreeMap<String, (f64, f64)> =
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    cache.get(address).cloned()
+}
+
+fn remember(path: &Path, address: &str, coordinates: (f64, f64)) -> Result<(), Box<dyn Error>> {
+    let mut cache: BTreeMap<String, (f64, f64)> = match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_default(),
+        Err(_) => BTreeMap::new(),
+    };
+    cache.insert(address.to_owned(), coordinates);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&cache)?)?;
+    Ok(())
+}
+
+/// Returns (lat, long) for a city name or address: from earlier lookups, the geocoding service
+/// (in builds with TLS) or the city list.
+pub fn lookup(address: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let cache = cache_path();
+    if let Some(coordinates) = cached(&cache, address) {
+        debug!("Found {:?} among earlier lookups", address);
+        return Ok(coordinates);
+    }
+    let online = if http::TLS {
+        lookup_online(address).map_err(|err| {
+            warn!(
+                "Could not geocode {:?} online, trying the city list: {}",
+                address, err
+            )
+        })
+    } else {
+        info!(
+            "This build has no TLS to geocode {:?} online, trying the city list",
+            address
+        );
+        Err(())
+    };
+    match online {
+        Ok(coordinates) => {
+            if let Err(err) = remember(&cache, address, coordinates) {
+                warn!("Could not remember the location of {:?}: {}", address, err);
+            }
+            Ok(coordinates)
+        }
+        Err(()) => {
+            lookup_offline(address).ok_or_else(|| format!("Unknown location {:?}", address).into())
+        }
+    }
+    .map(|(lat, long)| {
+        info!("{} is at {}, {}", address, lat, long);
+        (lat, long)
+    })
+}
+
+impl Location {
+    /// Replaces the coordinates with those of `address`, if set. The address is dropped
+    /// afterwards, so it is only looked up once. An address that cannot be found leaves the
+    /// configured coordinates in place, and is tried again at the next start.
+    pub fn resolve_address(self: &mut Location) {
+        self.resolve_with(&lookup)
+    }
+
+    fn resolve_with(
+        self: &mut Location,
+        lookup: &dyn Fn(&str) -> Result<(f64, f64), Box<dyn Error>>,
+    ) {
+        let address = match &self.address {
+            Some(address) => address.clone(),
+            None => return,
+        };
+        match lookup(&address) {
+            Ok((lat, long)) => {
+                self.lat = lat;
+                self.long = long;
+                self.address = None;
+            }
+            Err(err) => warn!(
+                "{}, using the configured location {}, {} instead",
+                err, self.lat, self.long
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(address: &str) -> Location {
+        let mut location: Location = toml::from_str("lat = 10.0\nlong = 20.0").unwrap();
+        location.address = Some(address.to_owned());
+        location
+    }
+
+    #[test]
+    fn finds_cities_by_the_part_before_the_comma() {
+        assert_eq!(lookup_offline("Utrecht"), Some((52.0907, 5.1214)));
+        assert_eq!(
+            lookup_offline(" new york , NY, USA"),
+            Some((40.7128, -74.0060))
+        );
+        assert_eq!(lookup_offline("Nijmegen, Netherlands"), None);
+    }
+
+    #[test]
+    fn resolving_replaces_the_coordinates_and_drops_the_address() {
+        let mut found = location("Utrecht");
+        found.resolve_with(&|address| Ok(lookup_offline(address).unwrap()));
+        assert_eq!((found.lat, found.long), (52.0907, 5.1214));
+        assert_eq!(found.address, None);
+    }
+
+    #[test]
+    fn unknown_addresses_keep_the_configured_coordinates() {
+        let mut unknown = location("Nowhere");
+        unknown.resolve_with(&|address| Err(format!("Unknown location {:?}", address).into()));
+        assert_eq!((unknown.lat, unknown.long), (10., 20.));
+        assert_eq!(unknown.address.as_deref(), Some("Nowhere"));
+    }
+
+    #[test]
+    fn remembers_earlier_lookups() {
+        let path = std::env::temp_dir()
+            .join(format!("hue_mie-geocode-{}", std::process::id()))
+            .join("geocode.json");
+        assert_eq!(cached(&path, "Nijmegen"), None);
+        remember(&path, "Nijmegen", (51.84, 5.86)).unwrap();
+        remember(&path, "Arnhem", (51.98, 5.91)).unwrap();
+        assert_eq!(cached(&path, "Nijmegen"), Some((51.84, 5.86)));
+        assert_eq!(cached(&path, "Arnhem"), Some((51.98, 5.91)));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+//! Per-light ZigBee health: failed writes and unreachable flags. Lights that keep failing are
+//! updated less often, so one flaky bulb does not eat the bridge's rate budget every tick.
+
+use crate::config::ErrorBudgetConfig;
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::{info, warn};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Failing lights are retried every 2^n ticks, up to this n.
+const MAX_BACKOFF_EXPONENT: u32 = 5;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LightHealth {
+    pub failures: u32,
+    pub successes: u32,
+    pub consecutive_failures: u32,
+    pub unreachable_ticks: u32,
+    #[serde(skip)]
+    reachable: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct NetworkHealth {
+    tick: u64,
+    lights: BTreeMap<usize, LightHealth>,
+}
+
+/// The light a command is sent to, if any.
+pub fn command_light(command: &LightCommand) -> Option<usize> {
+    match command {
+        LightCommand::SetLightState { light_id, .. }
+        | LightCommand::SetSceneLightState { light_id, .. }
+        | LightCommand::SetLightConfig { light_id, .. } => Some(*light_id),
+        _ => None,
+    }
+}
+
+impl NetworkHealth {
+    /// Starts a new tick, taking the reachable flags from `state`.
+    pub fn observe(self: &mut NetworkHealth, state: &BridgeState) {
+        self.tick += 1;
+        for (light_id, light) in state.lights.iter() {
+            let health = self.lights.entry(*light_id).or_default();
+            if !light.state.reachable {
+                if health.reachable || health.unreachable_ticks == 0 {
+                    warn!("Light {} ({}) is unreachable", light_id, light.name);
+                }
+                health.unreachable_ticks += 1;
+            } else if !health.reachable && health.unreachable_ticks > 0 {
+                info!("Light {} ({}) is reachable again", light_id, light.name);
+            }
+            health.reachable = light.state.reachable;
+        }
+    }
+
+    /// Whether a command for `light_id` should be sent this tick.
+    pub fn should_send(self: &NetworkHealth, light_id: usize) -> bool {
+        let health = match self.lights.get(&light_id) {
+            Some(health) => health,
+            None => return true,
+        };
+        let strikes = health.consecutive_failures + if health.reachable { 0 } else { 1 };
+        if strikes == 0 {
+            return true;
+        }
+        let period = 1u64 << strikes.min(MAX_BACKOFF_EXPONENT);
+        self.tick.is_multiple_of(period)
+    }
+
+    pub fn record(self: &mut NetworkHealth, light_id: usize, success: bool) {
+        let health = self.lights.entry(light_id).or_default();
+        if success {
+            health.successes += 1;
+            health.consecutive_failures = 0;
+        } else {
+            health.failures += 1;
+            health.consecutive_failures += 1;
+            if health.consecutive_failures == 3 {
+                warn!(
+                    "Light {} failed 3 times in a row, updating it less often",
+                    light_id
+                );
+            }
+        }
+    }
+
+    /// Ends the backoff of every light, so the next tick sends to all of them.
+    pub fn forgive(self: &mut NetworkHealth) {
+        for health in self.lights.values_mut() {
+            health.consecutive_failures = 0;
+        }
+    }
+
+    /// Lights that failed or were unreachable at some point.
+    pub fn troubled_lights(self: &NetworkHealth) -> BTreeMap<usize, LightHealth> {
+        self.lights
+            .iter()
+            .filter(|(_, health)| health.failures > 0 || health.unreachable_ticks > 0)
+            .map(|(light_id, health)| (*light_id, health.clone()))
+            .collect()
+    }
+
+    /// Forgets the counts, e.g. after they were reported.
+    pub fn reset_counts(self: &mut NetworkHealth) {
+        for health in self.lights.values_mut() {
+            health.failures = 0;
+            health.successes = 0;
+            health.unreachable_ticks = 0;
+        }
+    }
+}
+
+/// Tracks the share of failed writes over a sliding window. Once it exceeds the budget, writes
+/// pause for a while, longer each time the budget is exceeded again.
+#[derive(Debug, Default)]
+pub struct ErrorBudget {
+    /// (when, writes, failures) per batch of commands.
+    batches: VecDeque<(DateTime<Local>, usize, usize)>,
+    paused_until: Option<DateTime<Local>>,
+    pauses: u32,
+}
+
+impl ErrorBudget {
+    /// Whether writes are paused at `now`.
+    pub fn paused(self: &ErrorBudget, now: &DateTime<Local>) -> bool {
+        self.paused_until.is_some_and(|until| *now < until)
+    }
+
+    /// Records a batch of writes. Returns true when this exceeded the budget and started a
+    /// pause.
+    pub fn record(
+        self: &mut ErrorBudget,
+        config: &ErrorBudgetConfig,
+        now: &DateTime<Local>,
+        writes: usize,
+        failures: usize,
+    ) -> bool {
+        if writes == 0 {
+            return false;
+        }
+        let window = chrono::Duration::seconds(config.window_secs as i64);
+        self.batches.push_back((*now, writes, failures));
+        while let Some((when, _, _)) = self.batches.front() {
+            if *now - *when <= window {
+                break;
+            }
+            self.batches.pop_front();
+        }
+        let total: usize = self.batches.iter().map(|(_, writes, _)| writes).sum();
+        let failed: usize = self.batches.iter().map(|(_, _, failures)| failures).sum();
+        if total < config.min_writes || failed as f64 <= total as f64 * config.max_failure_ratio {
+            if failed == 0 {
+                self.pauses = 0;
+            }
+            return false;
+        }
+        let pause_secs = config
+            .pause_secs
+            .saturating_mul(1 << self.pauses.min(MAX_BACKOFF_EXPONENT))
+            .min(config.max_pause_secs);
+        warn!(
+            "{} of the last {} writes failed, pausing for {} seconds",
+            failed, total, pause_secs
+        );
+        self.paused_until = Some(*now + chrono::Duration::seconds(pause_secs as i64));
+        self.pauses += 1;
+        self.batches.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeBridge};
+
+    fn bridge() -> FakeBridge {
+        FakeBridge::new(vec![(1, testing::light(true, 100, 366))], vec![], vec![])
+    }
+
+    /// How many of the next `ticks` ticks send to light 1.
+    fn sends(health: &mut NetworkHealth, state: &BridgeState, ticks: u64) -> usize {
+        (0..ticks)
+            .filter(|_| {
+                health.observe(state);
+                health.should_send(1)
+            })
+            .count()
+    }
+
+    #[test]
+    fn backs_off_exponentially_from_failing_lights() {
+        let state = bridge().state();
+        let mut health = NetworkHealth::default();
+        assert_eq!(sends(&mut health, &state, 8), 8);
+        health.record(1, false);
+        assert_eq!(sends(&mut health, &state, 8), 4);
+        health.record(1, false);
+        assert_eq!(sends(&mut health, &state, 8), 2);
+        health.record(1, false);
+        assert_eq!(sends(&mut health, &state, 8), 1);
+        for _ in 0..10 {
+            health.record(1, false);
+        }
+        // Never less often than every 2^MAX_BACKOFF_EXPONENT ticks.
+        let period = 1 << MAX_BACKOFF_EXPONENT;
+        assert_eq!(sends(&mut health, &state, period * 4), 4);
+        assert_eq!(health.troubled_lights()[&1].failures, 13);
+    }
+
+    #[test]
+    fn a_success_or_forgiving_ends_the_backoff() {
+        let state = bridge().state();
+        let mut health = NetworkHealth::default();
+        health.record(1, false);
+        health.record(1, false);
+        health.record(1, true);
+        assert_eq!(sends(&mut health, &state, 8), 8);
+
+        health.record(1, false);
+        health.record(1, false);
+        health.forgive();
+        assert_eq!(sends(&mut health, &state, 8), 8);
+    }
+
+    #[test]
+    fn unreachable_lights_count_as_a_failure() {
+        let bridge = bridge();
+        bridge
+            .lights
+            .borrow_mut()
+            .get_mut(&1)
+            .unwrap()
+            .state
+            .reachable = false;
+        let unreachable = bridge.state();
+        let mut health = NetworkHealth::default();
+        assert_eq!(sends(&mut health, &unreachable, 8), 4);
+        health.record(1, false);
+        assert_eq!(sends(&mut health, &unreachable, 8), 2);
+        assert_eq!(health.troubled_lights()[&1].unreachable_ticks, 16);
+
+        bridge
+            .lights
+            .borrow_mut()
+            .get_mut(&1)
+            .unwrap()
+            .state
+            .reachable = true;
+        health.record(1, true);
+        assert_eq!(sends(&mut health, &bridge.state(), 8), 8);
+        health.reset_counts();
+        assert!(health.troubled_lights().is_empty());
+    }
+
+    fn budget_config() -> ErrorBudgetConfig {
+        toml::from_str(
+            r#"
+            max_failure_ratio = 0.5
+            window_secs = 600
+            min_writes = 10
+            pause_secs = 60
+            max_pause_secs = 200
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 6, 21, 12, 0, 0)
+            .single()
+            .unwrap()
+            + chrono::Duration::seconds(secs)
+    }
+
+    /// Exceeds the budget at `secs` and returns how long writes are paused, in seconds.
+    fn pause_after(budget: &mut ErrorBudget, config: &ErrorBudgetConfig, secs: i64) -> i64 {
+        assert!(budget.record(config, &at(secs), 10, 10));
+        let mut paused = 0;
+        while budget.paused(&at(secs + paused)) {
+            paused += 1;
+        }
+        paused
+    }
+
+    #[test]
+    fn a_few_failed_writes_stay_within_the_budget() {
+        let config = budget_config();
+        let mut budget = ErrorBudget::default();
+        // Too few writes to judge.
+        assert!(!budget.record(&config, &at(0), 5, 5));
+        // Half of them failing is still within the ratio.
+        assert!(!budget.record(&config, &at(10), 5, 0));
+        assert!(!budget.record(&config, &at(20), 0, 0));
+        assert!(!budget.paused(&at(20)));
+    }
+
+    #[test]
+    fn failures_slide_out_of_the_window() {
+        let config = budget_config();
+        let mut budget = ErrorBudget::default();
+        assert!(!budget.record(&config, &at(0), 5, 5));
+        assert!(!budget.record(&config, &at(700), 5, 5));
+        assert!(budget.record(&config, &at(710), 5, 5));
+        assert!(budget.paused(&at(769)));
+        assert!(!budget.paused(&at(770)));
+    }
+
+    #[test]
+    fn pauses_double_up_to_the_maximum_until_a_clean_batch() {
+        let config = budget_config();
+        let mut budget = ErrorBudget::default();
+        assert_eq!(pause_after(&mut budget, &config, 0), 60);
+        assert_eq!(pause_after(&mut budget, &config, 100), 120);
+        assert_eq!(pause_after(&mut budget, &config, 300), 200);
+        assert_eq!(pause_after(&mut budget, &config, 600), 200);
+        assert!(!budget.record(&config, &at(900), 10, 0));
+        assert_eq!(pause_after(&mut budget, &config, 1000), 60);
+    }
+}
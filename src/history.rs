@@ -0,0 +1,143 @@
+//! Targets and actual light states over time in a local SQLite database, for comparing
+//! before and after tuning. Written by the daemon, read by `hue_mie history`.
+
+use crate::config::HistoryConfig;
+use crate::curve::LightTarget;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::path::PathBuf;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS targets (
+        time INTEGER PRIMARY KEY,
+        bri INTEGER NOT NULL,
+        ct INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS light_states (
+        time INTEGER NOT NULL,
+        light_id INTEGER NOT NULL,
+        on_ INTEGER NOT NULL,
+        bri INTEGER NOT NULL,
+        ct INTEGER,
+        reachable INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS light_states_by_light ON light_states (light_id, time);
+";
+
+/// One sample of a light next to the curve target at that time.
+#[derive(Debug, Clone)]
+pub struct HistoryRow {
+    pub time: DateTime<Utc>,
+    pub light_id: usize,
+    pub target_bri: u8,
+    pub target_ct: u16,
+    pub on: bool,
+    pub bri: u8,
+    pub ct: Option<u16>,
+    pub reachable: bool,
+}
+
+pub struct History {
+    connection: Connection,
+    config: HistoryConfig,
+    last_sample: Option<DateTime<Utc>>,
+}
+
+impl History {
+    /// The configured database file, by default in the platform data directory.
+    pub fn path(config: &HistoryConfig) -> PathBuf {
+        config.path.as_ref().map(PathBuf::from).unwrap_or_else(|| {
+            let mut path: PathBuf = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+            path.push("hue_mie");
+            path.push("history.sqlite");
+            path
+        })
+    }
+
+    pub fn open(config: HistoryConfig) -> Result<History, Box<dyn Error>> {
+        let path = History::path(&config);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let connection = Connection::open(&path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(History {
+            connection,
+            config,
+            last_sample: None,
+        })
+    }
+
+    /// Stores the target and every light's state, at most once per `interval_secs`. Samples
+    /// older than the retention period are dropped along the way.
+    pub fn record_if_due(
+        self: &mut History,
+        now: DateTime<Utc>,
+        target: &LightTarget,
+        state: &BridgeState,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(last) = self.last_sample {
+            if (now - last).num_seconds() < self.config.interval_secs as i64 {
+                return Ok(());
+            }
+        }
+        self.last_sample = Some(now);
+        let time = now.timestamp();
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO targets (time, bri, ct) VALUES (?1, ?2, ?3)",
+            params![time, target.bri(), target.ct()],
+        )?;
+        for (light_id, light) in state.lights.iter() {
+            transaction.execute(
+                "INSERT INTO light_states (time, light_id, on_, bri, ct, reachable)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    time,
+                    *light_id as i64,
+                    light.state.on,
+                    light.state.bri,
+                    light.state.ct,
+                    light.state.reachable
+                ],
+            )?;
+        }
+        let horizon = time - i64::from(self.config.retention_days) * 24 * 3600;
+        transaction.execute("DELETE FROM targets WHERE time < ?1", params![horizon])?;
+        transaction.execute("DELETE FROM light_states WHERE time < ?1", params![horizon])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// The samples since `since`, of one light or of all of them, oldest first.
+    pub fn query(
+        self: &History,
+        light_id: Option<usize>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<HistoryRow>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT s.time, s.light_id, t.bri, t.ct, s.on_, s.bri, s.ct, s.reachable
+             FROM light_states s JOIN targets t ON t.time = s.time
+             WHERE s.time >= ?1 AND (?2 IS NULL OR s.light_id = ?2)
+             ORDER BY s.time, s.light_id",
+        )?;
+        let rows = statement.query_map(
+            params![since.timestamp(), light_id.map(|light_id| light_id as i64)],
+            |row| {
+                Ok(HistoryRow {
+                    time: Utc.timestamp_opt(row.get(0)?, 0).unwrap(),
+                    light_id: row.get::<_, i64>(1)? as usize,
+                    target_bri: row.get(2)?,
+                    target_ct: row.get(3)?,
+                    on: row.get(4)?,
+                    bri: row.get(5)?,
+                    ct: row.get(6)?,
+                    reachable: row.get(7)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
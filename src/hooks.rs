@@ -0,0 +1,327 @@
+//! Actions fired on lifecycle, solar and schedule events: recalling a scene, running a shell
+//! command or calling a webhook. Commands get the event as `HUE_MIE_EVENT` and its context as
+//! further `HUE_MIE_*` environment variables.
+
+use crate::astro_calc::SUNRISE_ALTITUDE;
+use crate::config::{HookAction, HooksConfig};
+use crate::http;
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use log::{error, info, warn};
+use std::fmt;
+use std::process::Command;
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookEvent {
+    Startup,
+    PairingCompleted,
+    Sunset,
+    Sunrise,
+    DeepNightStart,
+    ErrorBudgetExceeded,
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            HookEvent::Startup => "startup",
+            HookEvent::PairingCompleted => "pairing completed",
+            HookEvent::Sunset => "sunset",
+            HookEvent::Sunrise => "sunrise",
+            HookEvent::DeepNightStart => "deep night start",
+            HookEvent::ErrorBudgetExceeded => "error budget exceeded",
+        })
+    }
+}
+
+impl HookEvent {
+    /// The name passed to commands in `HUE_MIE_EVENT`, as in the `on_*` config keys.
+    pub fn key(self: HookEvent) -> &'static str {
+        match self {
+            HookEvent::Startup => "startup",
+            HookEvent::PairingCompleted => "pairing_completed",
+            HookEvent::Sunset => "sunset",
+            HookEvent::Sunrise => "sunrise",
+            HookEvent::DeepNightStart => "deep_night_start",
+            HookEvent::ErrorBudgetExceeded => "error_budget_exceeded",
+        }
+    }
+}
+
+/// Remembers the previous sun position and deep night state, so each event fires once when it
+/// happens rather than at startup. The first call reports `Startup` instead.
+#[derive(Default)]
+pub struct HookTracker {
+    sun_up: Option<bool>,
+    deep_night: Option<bool>,
+}
+
+impl HookTracker {
+    /// The events that occurred since the previous call. `sun_altitude` is in radians.
+    pub fn events(self: &mut HookTracker, sun_altitude: f64, deep_night: bool) -> Vec<HookEvent> {
+        let sun_up = sun_altitude.to_degrees() > SUNRISE_ALTITUDE;
+        let mut events = Vec::new();
+        if self.sun_up.is_none() {
+            events.push(HookEvent::Startup);
+        }
+        match self.sun_up {
+            Some(true) if !sun_up => events.push(HookEvent::Sunset),
+            Some(false) if sun_up => events.push(HookEvent::Sunrise),
+            _ => {}
+        }
+        if self.deep_night == Some(false) && deep_night {
+            events.push(HookEvent::DeepNightStart);
+        }
+        self.sun_up = Some(sun_up);
+        self.deep_night = Some(deep_night);
+        events
+    }
+}
+
+fn actions(hooks: &HooksConfig, event: HookEvent) -> &[HookAction] {
+    match event {
+        HookEvent::Startup => &hooks.on_startup,
+        HookEvent::PairingCompleted => &hooks.on_pairing_completed,
+        HookEvent::Sunset => &hooks.on_sunset,
+        HookEvent::Sunrise => &hooks.on_sunrise,
+        HookEvent::DeepNightStart => &hooks.on_deep_night_start,
+        HookEvent::ErrorBudgetExceeded => &hooks.on_error_budget_exceeded,
+    }
+}
+
+/// Runs the shell command and calls the webhook of one action, if set. Both run in the
+/// background, so a slow hook does not hold up the updates.
+fn run(action: &HookAction, event: HookEvent, context: &[(&str, String)], dry_run: bool) {
+    if let Some(command) = &action.command {
+        if dry_run {
+            info!("Dry run, not running {:?}", command);
+        } else {
+            let child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("HUE_MIE_EVENT", event.key())
+                .envs(context.iter().map(|(name, value)| (*name, value)))
+                .spawn();
+            match child {
+                Ok(mut child) => {
+                    let command = command.clone();
+                    thread::spawn(move || match child.wait() {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => error!("Hook command {:?} failed: {}", command, status),
+                        Err(err) => {
+                            error!("Could not wait for hook command {:?}: {}", command, err)
+                        }
+                    });
+                }
+                Err(err) => error!("Could not run hook command {:?}: {}", command, err),
+            }
+        }
+    }
+    if let Some(webhook) = &action.webhook {
+        if dry_run {
+            info!("Dry run, not calling {}", webhook);
+        } else {
+            let webhook = webhook.clone();
+            thread::spawn(move || {
+                if let Err(err) = http::client()
+                    .and_then(|client| client.post(&webhook).send())
+                    .and_then(|response| response.error_for_status())
+                {
+                    error!("Could not call webhook {}: {}", webhook, err);
+                }
+            });
+        }
+    }
+}
+
+/// Runs the shell commands and webhooks configured for `event` and returns the scene recalls,
+/// which go through the executor like any other command. `context` holds the environment
+/// variables for the commands.
+pub fn fire(
+    hooks: &HooksConfig,
+    event: HookEvent,
+    state: &BridgeState,
+    context: &[(&str, String)],
+    dry_run: bool,
+) -> Vec<LightCommand> {
+    info!("Firing {} hooks", event);
+    let mut commands = Vec::new();
+    for action in actions(hooks, event) {
+        if let Some(scene_name) = &action.scene {
+            match state
+                .scenes
+                .iter()
+                .find(|(_, scene)| &scene.name == scene_name)
+            {
+                Some((scene_id, scene)) => commands.push(LightCommand::RecallScene {
+                    scene_id: scene_id.clone(),
+                    group_id: scene
+                        .group
+                        .as_ref()
+                        .and_then(|group| group.parse().ok())
+                        .unwrap_or(0),
+                }),
+                None => error!("Hook refers to unknown scene {}", scene_name),
+            }
+        }
+        run(action, event, context, dry_run);
+    }
+    commands
+}
+
+/// `fire` for events before the bridge state is known, such as pairing: scenes are skipped.
+pub fn fire_without_state(
+    hooks: &HooksConfig,
+    event: HookEvent,
+    context: &[(&str, String)],
+    dry_run: bool,
+) {
+    info!("Firing {} hooks", event);
+    for action in actions(hooks, event) {
+        if let Some(scene_name) = &action.scene {
+            warn!(
+                "Not recalling {} on {}: scenes are not known yet",
+                scene_name, event
+            );
+        }
+        run(action, event, context, dry_run);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeBridge};
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn output(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hue_mie-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// Hooks whose `key` action writes the environment of its command to `path`.
+    fn env_hooks(key: &str, path: &PathBuf) -> HooksConfig {
+        let command = format!("env > {0}.part && mv {0}.part {0}", path.display());
+        toml::from_str(&format!(
+            "{} = [{{ scene = \"Living bright\", command = {:?} }}]",
+            key, command
+        ))
+        .unwrap()
+    }
+
+    /// The contents of `path` once the hook command has written it.
+    fn wait_for(path: &PathBuf) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !path.exists() {
+            assert!(Instant::now() < deadline, "{:?} was not written", path);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let contents = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        contents
+    }
+
+    fn degrees(altitude: f64) -> f64 {
+        altitude.to_radians()
+    }
+
+    #[test]
+    fn reports_startup_once_instead_of_the_current_state() {
+        let mut tracker = HookTracker::default();
+        assert_eq!(
+            tracker.events(degrees(-20.), true),
+            vec![HookEvent::Startup]
+        );
+        assert!(tracker.events(degrees(-20.), true).is_empty());
+    }
+
+    #[test]
+    fn reports_sunset_and_sunrise_at_the_horizon() {
+        let mut tracker = HookTracker::default();
+        tracker.events(degrees(10.), false);
+        assert!(tracker.events(degrees(0.), false).is_empty());
+        assert!(tracker.events(degrees(-0.8), false).is_empty());
+        assert_eq!(
+            tracker.events(degrees(-0.9), false),
+            vec![HookEvent::Sunset]
+        );
+        assert!(tracker.events(degrees(-10.), false).is_empty());
+        assert_eq!(
+            tracker.events(degrees(-0.8), false),
+            vec![HookEvent::Sunrise]
+        );
+        assert!(tracker.events(degrees(5.), false).is_empty());
+    }
+
+    #[test]
+    fn reports_the_start_of_deep_night_only() {
+        let mut tracker = HookTracker::default();
+        tracker.events(degrees(-20.), false);
+        assert_eq!(
+            tracker.events(degrees(-20.), true),
+            vec![HookEvent::DeepNightStart]
+        );
+        assert!(tracker.events(degrees(-20.), true).is_empty());
+        assert!(tracker.events(degrees(-20.), false).is_empty());
+        assert_eq!(
+            tracker.events(degrees(-0.9), true),
+            vec![HookEvent::DeepNightStart]
+        );
+    }
+
+    #[test]
+    fn commands_get_the_event_and_its_context() {
+        let path = output("hook-pairing");
+        let hooks = env_hooks("on_pairing_completed", &path);
+        let context = [
+            ("HUE_MIE_BRIDGE_IP", "192.168.1.2".to_owned()),
+            ("HUE_MIE_BRIDGE_ID", "001788FFFE123456".to_owned()),
+        ];
+        fire_without_state(&hooks, HookEvent::PairingCompleted, &context, false);
+        let env = wait_for(&path);
+        assert!(env.contains("HUE_MIE_EVENT=pairing_completed\n"), "{}", env);
+        assert!(env.contains("HUE_MIE_BRIDGE_IP=192.168.1.2\n"), "{}", env);
+        assert!(
+            env.contains("HUE_MIE_BRIDGE_ID=001788FFFE123456\n"),
+            "{}",
+            env
+        );
+    }
+
+    #[test]
+    fn fires_commands_and_recalls_for_events_with_state() {
+        let path = output("hook-sunset");
+        let hooks = env_hooks("on_sunset", &path);
+        let bridge = FakeBridge::new(
+            vec![(1, testing::light(true, 100, 366))],
+            vec![(1, testing::room("Living", &[1]))],
+            vec![(
+                "s1",
+                testing::scene("Living bright", &[(1, true, 254, 153)]),
+            )],
+        );
+        let context = [("HUE_MIE_SUN_ALTITUDE", "-0.90".to_owned())];
+        let recalls = fire(&hooks, HookEvent::Sunset, &bridge.state(), &context, false);
+        match recalls.as_slice() {
+            [LightCommand::RecallScene { scene_id, .. }] => assert_eq!(scene_id, "s1"),
+            recalls => panic!("{:?}", recalls),
+        }
+        let env = wait_for(&path);
+        assert!(env.contains("HUE_MIE_EVENT=sunset\n"), "{}", env);
+        assert!(env.contains("HUE_MIE_SUN_ALTITUDE=-0.90\n"), "{}", env);
+    }
+
+    #[test]
+    fn dry_runs_do_not_run_commands() {
+        let path = output("hook-dry-run");
+        let hooks = env_hooks("on_startup", &path);
+        let bridge = FakeBridge::new(vec![], vec![], vec![]);
+        fire(&hooks, HookEvent::Startup, &bridge.state(), &[], true);
+        fire_without_state(&hooks, HookEvent::Startup, &[], true);
+        thread::sleep(Duration::from_millis(200));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,46 @@
+//! The HTTP client used for the bridge and for public services. The TLS
+//! implementation is chosen at compile time: `native-tls` links the platform's TLS library,
+//! `rustls` is pure Rust and needs no OpenSSL when cross-compiling, e.g. for musl or armv6
+//! Raspberry Pi targets. Without either, only plain HTTP (the bridge's v1 API) works.
+
+use reqwest::blocking::{Client, ClientBuilder};
+use std::time::Duration;
+
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+fn with_tls(builder: ClientBuilder) -> ClientBuilder {
+    builder.use_rustls_tls()
+}
+
+#[cfg(feature = "native-tls")]
+fn with_tls(builder: ClientBuilder) -> ClientBuilder {
+    builder.use_native_tls()
+}
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+fn with_tls(builder: ClientBuilder) -> ClientBuilder {
+    builder
+}
+
+/// Whether this build can talk HTTPS.
+pub const TLS: bool = cfg!(any(feature = "rustls", feature = "native-tls"));
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A client for public services such as calendars and geocoding.
+pub fn client() -> reqwest::Result<Client> {
+    with_tls(Client::builder().timeout(TIMEOUT)).build()
+}
+
+/// A client for talking to the bridge, which serves a certificate signed by the Signify root
+/// rather than by a public CA.
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub fn bridge_client() -> reqwest::Result<Client> {
+    with_tls(Client::builder().timeout(TIMEOUT))
+        .danger_accept_invalid_certs(true)
+        .build()
+}
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+pub fn bridge_client() -> reqwest::Result<Client> {
+    Client::builder().timeout(TIMEOUT).build()
+}
@@ -0,0 +1,349 @@
+//! Serde models of the Hue v1 API resources hue_mie works with. Ids of lights are numeric
+//! strings on the wire and `usize` here.
+
+use serde::de::{Deserializer, Error as DeError};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+fn de_ids<'de, D>(deserializer: D) -> Result<Vec<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let ids: Vec<String> = Vec::deserialize(deserializer)?;
+    ids.iter()
+        .map(|id| id.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+fn de_id_map<'de, D, T>(deserializer: D) -> Result<BTreeMap<usize, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let map: BTreeMap<String, T> = BTreeMap::deserialize(deserializer)?;
+    map.into_iter()
+        .map(|(id, value)| id.parse().map(|id| (id, value)).map_err(D::Error::custom))
+        .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The current state of a light as reported by the bridge.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LightState {
+    pub on: bool,
+
+    /// Missing for on/off-only devices such as smart plugs.
+    #[serde(default)]
+    pub bri: u8,
+
+    #[serde(default)]
+    pub hue: Option<u16>,
+
+    #[serde(default)]
+    pub sat: Option<u8>,
+
+    #[serde(default)]
+    pub xy: Option<(f32, f32)>,
+
+    #[serde(default)]
+    pub ct: Option<u16>,
+
+    #[serde(default)]
+    pub alert: Option<String>,
+
+    #[serde(default)]
+    pub effect: Option<String>,
+
+    #[serde(default)]
+    pub colormode: Option<String>,
+
+    #[serde(default = "default_true")]
+    pub reachable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Light {
+    pub name: String,
+
+    #[serde(rename = "type", default)]
+    pub light_type: String,
+
+    #[serde(default)]
+    pub modelid: String,
+
+    #[serde(default)]
+    pub manufacturername: String,
+
+    #[serde(default)]
+    pub productname: Option<String>,
+
+    #[serde(default)]
+    pub uniqueid: Option<String>,
+
+    #[serde(default)]
+    pub swversion: Option<String>,
+
+    pub state: LightState,
+
+    #[serde(default)]
+    pub capabilities: Option<Value>,
+
+    #[serde(default)]
+    pub config: Option<Value>,
+}
+
+impl Light {
+    /// A smart plug, which switches whatever is plugged into it.
+    pub fn is_plug(self: &Light) -> bool {
+        let archetype = self
+            .config
+            .as_ref()
+            .and_then(|config| config.get("archetype"))
+            .and_then(Value::as_str);
+        archetype == Some("plug") || self.light_type.eq_ignore_ascii_case("On/Off plug-in unit")
+    }
+
+    /// Whether the device takes a brightness; on/off lights and plugs don't.
+    pub fn is_dimmable(self: &Light) -> bool {
+        !self.is_plug() && !self.light_type.to_lowercase().starts_with("on/off")
+    }
+}
+
+/// A change to a light's state, as sent to a light or stored in a scene. Only the fields that
+/// are set are sent.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct LightStateChange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bri: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sat: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xy: Option<(f32, f32)>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ct: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transitiontime: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bri_inc: Option<i16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sat_inc: Option<i16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue_inc: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ct_inc: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct GroupState {
+    #[serde(default)]
+    pub all_on: bool,
+
+    #[serde(default)]
+    pub any_on: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Group {
+    pub name: String,
+
+    #[serde(deserialize_with = "de_ids")]
+    pub lights: Vec<usize>,
+
+    /// "Room", "Zone", "Entertainment", "LightGroup", ...
+    #[serde(rename = "type", default)]
+    pub group_type: String,
+
+    #[serde(default)]
+    pub class: Option<String>,
+
+    #[serde(default)]
+    pub state: Option<GroupState>,
+
+    #[serde(default)]
+    pub action: Option<LightStateChange>,
+
+    /// Streaming status of an entertainment area.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<GroupStream>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct GroupStream {
+    /// Whether a Hue Sync box or app is streaming to the area right now.
+    #[serde(default)]
+    pub active: bool,
+
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Scene {
+    pub name: String,
+
+    /// "LightScene" or "GroupScene".
+    #[serde(rename = "type", default)]
+    pub scene_type: Option<String>,
+
+    /// The group a GroupScene belongs to.
+    #[serde(default)]
+    pub group: Option<String>,
+
+    #[serde(deserialize_with = "de_ids")]
+    pub lights: Vec<usize>,
+
+    #[serde(default)]
+    pub owner: Option<String>,
+
+    #[serde(default)]
+    pub recycle: bool,
+
+    #[serde(default)]
+    pub locked: bool,
+
+    #[serde(default)]
+    pub appdata: Option<Value>,
+
+    #[serde(default)]
+    pub picture: Option<String>,
+
+    #[serde(default)]
+    pub lastupdated: Option<String>,
+
+    #[serde(default)]
+    pub version: Option<u8>,
+
+    /// Only present when a single scene is fetched.
+    #[serde(default, deserialize_with = "de_id_map")]
+    pub lightstates: BTreeMap<usize, LightStateChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sensor {
+    pub name: String,
+
+    #[serde(rename = "type", default)]
+    pub sensor_type: String,
+
+    #[serde(default)]
+    pub modelid: String,
+
+    #[serde(default)]
+    pub state: Value,
+
+    #[serde(default)]
+    pub config: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    /// When it runs, e.g. "W127/T06:30:00" for every day at 06:30 local time.
+    #[serde(default)]
+    pub localtime: Option<String>,
+}
+
+/// A named set of links to other resources. hue_mie keeps its leader lease in one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResourceLink {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: String,
+}
+
+/// An error reported by the bridge in an API response.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiError {
+    #[serde(rename = "type")]
+    pub code: u16,
+
+    #[serde(default)]
+    pub address: String,
+
+    #[serde(default)]
+    pub description: String,
+}
+
+impl ApiError {
+    pub const LINK_BUTTON_NOT_PRESSED: u16 = 101;
+}
+
+#[derive(Debug)]
+pub enum HueError {
+    #[cfg(feature = "daemon")]
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    Api(ApiError),
+}
+
+impl HueError {
+    pub fn is_link_button_not_pressed(self: &HueError) -> bool {
+        match self {
+            HueError::Api(error) => error.code == ApiError::LINK_BUTTON_NOT_PRESSED,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for HueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "daemon")]
+            HueError::Http(err) => write!(f, "HTTP error: {}", err),
+            HueError::Json(err) => write!(f, "Unexpected response: {}", err),
+            HueError::Api(err) => write!(
+                f,
+                "Bridge error {} at {}: {}",
+                err.code, err.address, err.description
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HueError {}
+
+#[cfg(feature = "daemon")]
+impl From<reqwest::Error> for HueError {
+    fn from(err: reqwest::Error) -> HueError {
+        HueError::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for HueError {
+    fn from(err: serde_json::Error) -> HueError {
+        HueError::Json(err)
+    }
+}
@@ -0,0 +1,191 @@
+//! Leader election between several hue_mie instances controlling the same bridge, through a
+//! lease kept on the bridge itself: the leader renews it every tick, the others stand by and
+//! take over once it expires.
+
+use crate::backend::Backend;
+use crate::config::LeaderElectionConfig;
+use chrono::prelude::*;
+use log::{debug, info, warn};
+use std::error::Error;
+
+/// Name of the resource link holding the lease.
+const LEASE_NAME: &str = "hue_mie leader";
+
+/// The lease as stored in the resource link description: "<instance>@<expiry timestamp>".
+#[derive(Debug, Clone, PartialEq)]
+struct Lease {
+    holder: String,
+    until: i64,
+}
+
+impl Lease {
+    fn parse(description: &str) -> Option<Lease> {
+        let at = description.rfind('@')?;
+        Some(Lease {
+            holder: description[..at].to_owned(),
+            until: description[at + 1..].parse().ok()?,
+        })
+    }
+
+    fn to_description(self: &Lease) -> String {
+        format!("{}@{}", self.holder, self.until)
+    }
+}
+
+pub struct LeaderElection {
+    backend: Box<dyn Backend>,
+    instance_id: String,
+    lease_secs: i64,
+    link_id: Option<String>,
+    leading: bool,
+}
+
+impl LeaderElection {
+    pub fn new(backend: Box<dyn Backend>, config: &LeaderElectionConfig) -> LeaderElection {
+        LeaderElection {
+            backend,
+            instance_id: config.instance_id(),
+            lease_secs: config.lease_secs as i64,
+            link_id: None,
+            leading: false,
+        }
+    }
+
+    fn current(self: &mut LeaderElection) -> Result<Option<Lease>, Box<dyn Error>> {
+        let links = self.backend.get_all_resourcelinks()?;
+        match links.iter().find(|(_, link)| link.name == LEASE_NAME) {
+            Some((id, link)) => {
+                self.link_id = Some(id.clone());
+                Ok(Lease::parse(&link.description))
+            }
+            None => {
+                self.link_id = None;
+                Ok(None)
+            }
+        }
+    }
+
+    fn write(self: &mut LeaderElection, lease: &Lease) -> Result<(), Box<dyn Error>> {
+        match &self.link_id {
+            Some(id) => {
+                self.backend
+                    .set_resourcelink_description(id, &lease.to_description())?;
+            }
+            None => {
+                let id = self
+                    .backend
+                    .create_resourcelink(LEASE_NAME, &lease.to_description())?;
+                self.link_id = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether this instance may write this tick: it holds the lease, or the lease expired and
+    /// this instance took it. When the bridge can't be asked, a leader keeps leading until its
+    /// own lease runs out and a standby stays idle.
+    pub fn is_leader(self: &mut LeaderElection, now: DateTime<Utc>) -> bool {
+        let leading = match self.try_lead(now.timestamp()) {
+            Ok(leading) => leading,
+            Err(err) => {
+                warn!("Could not check the leader lease: {}", err);
+                self.leading
+            }
+        };
+        if leading != self.leading {
+            if leading {
+                info!("{} is now the leader", self.instance_id);
+            } else {
+                info!("{} is standing by", self.instance_id);
+            }
+        }
+        self.leading = leading;
+        leading
+    }
+
+    fn try_lead(self: &mut LeaderElection, now: i64) -> Result<bool, Box<dyn Error>> {
+        let ours = Lease {
+            holder: self.instance_id.clone(),
+            until: now + self.lease_secs,
+        };
+        match self.current()? {
+            Some(lease) if lease.holder != self.instance_id && lease.until > now => {
+                debug!("{} holds the lease until {}", lease.holder, lease.until);
+                return Ok(false);
+            }
+            _ => self.write(&ours)?,
+        }
+        // Another instance may have taken the expired lease at the same moment; the last
+        // write wins and both see it.
+        Ok(self
+            .current()?
+            .is_some_and(|lease| lease.holder == self.instance_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::ResourceLink;
+    use crate::testing::FakeBridge;
+
+    fn election_with(leases: &[(&str, &str)]) -> LeaderElection {
+        let bridge = FakeBridge::default();
+        for (id, description) in leases {
+            bridge.resourcelinks.borrow_mut().insert(
+                id.to_string(),
+                ResourceLink {
+                    name: LEASE_NAME.to_owned(),
+                    description: description.to_string(),
+                },
+            );
+        }
+        let config: LeaderElectionConfig =
+            toml::from_str("instance_id = \"kitchen@pi\"\nlease_secs = 30").unwrap();
+        LeaderElection::new(Box::new(bridge), &config)
+    }
+
+    #[test]
+    fn parses_a_holder_containing_an_at_sign() {
+        let lease = Lease::parse("kitchen@pi@1700000000").unwrap();
+        assert_eq!(lease.holder, "kitchen@pi");
+        assert_eq!(lease.until, 1_700_000_000);
+        assert_eq!(Lease::parse(&lease.to_description()), Some(lease));
+        assert_eq!(Lease::parse("kitchen@pi@soon"), None);
+        assert_eq!(Lease::parse("kitchen"), None);
+    }
+
+    #[test]
+    fn takes_a_missing_or_expired_lease() {
+        let mut election = election_with(&[]);
+        assert!(election.try_lead(1000).unwrap());
+        assert_eq!(
+            election.current().unwrap(),
+            Some(Lease {
+                holder: "kitchen@pi".to_owned(),
+                until: 1030,
+            })
+        );
+
+        let mut election = election_with(&[("7", "attic@nas@999")]);
+        assert!(election.try_lead(1000).unwrap());
+        assert_eq!(election.link_id.as_deref(), Some("7"));
+        assert_eq!(election.current().unwrap().unwrap().holder, "kitchen@pi");
+    }
+
+    #[test]
+    fn respects_a_live_foreign_lease() {
+        let mut election = election_with(&[("7", "attic@nas@1001")]);
+        assert!(!election.try_lead(1000).unwrap());
+        assert_eq!(election.current().unwrap().unwrap().holder, "attic@nas");
+        // Once it runs out, this instance takes over.
+        assert!(election.try_lead(1001).unwrap());
+    }
+
+    #[test]
+    fn renews_its_own_lease() {
+        let mut election = election_with(&[("7", "kitchen@pi@1010")]);
+        assert!(election.try_lead(1000).unwrap());
+        assert_eq!(election.current().unwrap().unwrap().until, 1030);
+    }
+}
@@ -0,0 +1,237 @@
+//! Learning the dawn point from the household: the sun altitudes at which people switch lights
+//! on themselves around dusk and dawn pull `sun_altitude_dawn_point` along, a little each day.
+
+use crate::config::LearningConfig;
+use crate::state::BridgeState;
+use chrono::NaiveDate;
+use log::{debug, error, info};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Switches outside this sun altitude range (degrees) say nothing about dusk or dawn.
+const TWILIGHT_RANGE: (f64, f64) = (-12., 12.);
+/// A light that came on this soon after we switched it was our doing.
+const OWN_SWITCH_WINDOW: Duration = Duration::from_secs(120);
+/// Switches are kept until there are enough of them, but no longer than this many days.
+const MAX_SAMPLE_AGE_DAYS: i64 = 14;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Learned {
+    #[serde(default)]
+    dawn_point: Option<f64>,
+    /// Sun altitudes of the switches observed since the dawn point last moved.
+    #[serde(default)]
+    samples: Vec<f64>,
+    /// The day the samples started being collected.
+    #[serde(default)]
+    collecting_since: Option<NaiveDate>,
+    #[serde(default)]
+    updated: Option<NaiveDate>,
+}
+
+pub struct DawnLearner {
+    path: PathBuf,
+    learned: Learned,
+}
+
+impl DawnLearner {
+    pub fn default_path() -> PathBuf {
+        let mut path: PathBuf = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("hue_mie");
+        path.push("learning.json");
+        path
+    }
+
+    pub fn load(config: &LearningConfig) -> Result<DawnLearner, Box<dyn Error>> {
+        let path = config
+            .path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(DawnLearner::default_path);
+        let learned = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            Learned::default()
+        };
+        Ok(DawnLearner { path, learned })
+    }
+
+    fn save(self: &DawnLearner) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.learned)?)?;
+        Ok(())
+    }
+
+    /// The learned dawn point, once there is one.
+    pub fn dawn_point(self: &DawnLearner) -> Option<f64> {
+        self.learned.dawn_point
+    }
+
+    /// Records the lights someone switched on since `was_on` was taken, at `sun_altitude`
+    /// (degrees).
+    pub fn observe(
+        self: &mut DawnLearner,
+        was_on: &BTreeSet<usize>,
+        state: &BridgeState,
+        sun_altitude: f64,
+    ) {
+        if sun_altitude < TWILIGHT_RANGE.0 || sun_altitude > TWILIGHT_RANGE.1 {
+            return;
+        }
+        let switched_on = state
+            .lights_on()
+            .difference(was_on)
+            .filter(|&&light_id| !state.light_switched_within(light_id, OWN_SWITCH_WINDOW))
+            .count();
+        if switched_on > 0 {
+            debug!(
+                "{} lights switched on at sun altitude {:.1}",
+                switched_on, sun_altitude
+            );
+            // Several lights switched at once are one decision.
+            self.learned.samples.push(sun_altitude);
+        }
+    }
+
+    /// Once a day, moves the dawn point a step towards the median observed altitude once enough
+    /// switches have been observed.
+    pub fn update_if_due(
+        self: &mut DawnLearner,
+        config: &LearningConfig,
+        today: NaiveDate,
+        configured: f64,
+    ) {
+        let first_day = match self.learned.updated {
+            Some(updated) if updated == today => return,
+            updated => updated.is_none(),
+        };
+        self.learned.updated = Some(today);
+        let stale = self.learned.collecting_since.is_none_or(|since| {
+            today.signed_duration_since(since) > chrono::Duration::days(MAX_SAMPLE_AGE_DAYS)
+        });
+        if first_day {
+            debug!("Starting to learn the dawn point");
+            self.learned.samples.clear();
+            self.learned.collecting_since = Some(today);
+        } else if self.learned.samples.len() >= config.min_samples {
+            let mut samples = std::mem::take(&mut self.learned.samples);
+            self.learned.collecting_since = Some(today);
+            samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let observed = samples[samples.len() / 2];
+            let current = self.learned.dawn_point.unwrap_or(configured);
+            let next = (current + (observed - current) * config.rate)
+                .max(config.min_dawn_point)
+                .min(config.max_dawn_point);
+            info!(
+                "Lights were switched on around sun altitude {:.1}, moving the dawn point from \
+                 {:.2} to {:.2}",
+                observed, current, next
+            );
+            self.learned.dawn_point = Some(next);
+        } else if stale {
+            debug!(
+                "Only {} switches observed in {} days, starting over",
+                self.learned.samples.len(),
+                MAX_SAMPLE_AGE_DAYS
+            );
+            self.learned.samples.clear();
+            self.learned.collecting_since = Some(today);
+        } else {
+            debug!(
+                "Only {} switches observed so far, keeping the dawn point",
+                self.learned.samples.len()
+            );
+        }
+        if let Err(err) = self.save() {
+            error!("Could not save the learned dawn point: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeBridge};
+
+    fn learner(name: &str) -> DawnLearner {
+        let path = std::env::temp_dir().join(format!(
+            "hue_mie-{}-{}-learning.json",
+            name,
+            std::process::id()
+        ));
+        DawnLearner {
+            path,
+            learned: Learned::default(),
+        }
+    }
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, day).unwrap()
+    }
+
+    /// A bridge state with the one light on, switched by someone else.
+    fn light_on() -> BridgeState {
+        FakeBridge::new(vec![(1, testing::light(true, 100, 366))], vec![], vec![]).state()
+    }
+
+    #[test]
+    fn keeps_switches_until_there_are_enough() {
+        let config: LearningConfig = toml::from_str("min_samples = 3").unwrap();
+        let mut learner = learner("accumulate");
+        learner.update_if_due(&config, day(1), 0.);
+
+        learner.observe(&BTreeSet::new(), &light_on(), -3.);
+        // Outside twilight, or the light was already on.
+        learner.observe(&BTreeSet::new(), &light_on(), -20.);
+        learner.observe(&[1].iter().cloned().collect(), &light_on(), -3.);
+        learner.update_if_due(&config, day(2), 0.);
+        assert_eq!(learner.dawn_point(), None);
+        assert_eq!(learner.learned.samples, vec![-3.]);
+
+        learner.observe(&BTreeSet::new(), &light_on(), -3.);
+        learner.update_if_due(&config, day(3), 0.);
+        learner.observe(&BTreeSet::new(), &light_on(), -3.);
+        // Only once a day.
+        learner.update_if_due(&config, day(3), 0.);
+        assert_eq!(learner.dawn_point(), None);
+        learner.update_if_due(&config, day(4), 0.);
+        assert!((learner.dawn_point().unwrap() + 0.3).abs() < 1e-9);
+        assert!(learner.learned.samples.is_empty());
+        std::fs::remove_file(&learner.path).unwrap();
+    }
+
+    #[test]
+    fn forgets_switches_that_are_too_old() {
+        let config: LearningConfig = toml::from_str("min_samples = 3").unwrap();
+        let mut learner = learner("stale");
+        learner.update_if_due(&config, day(1), 0.);
+        learner.observe(&BTreeSet::new(), &light_on(), -3.);
+        learner.update_if_due(&config, day(15), 0.);
+        assert_eq!(learner.learned.samples.len(), 1);
+        learner.update_if_due(&config, day(16), 0.);
+        assert!(learner.learned.samples.is_empty());
+        assert_eq!(learner.dawn_point(), None);
+        std::fs::remove_file(&learner.path).unwrap();
+    }
+
+    #[test]
+    fn clamps_the_dawn_point_to_its_bounds() {
+        let config: LearningConfig = toml::from_str(
+            "min_samples = 1\nrate = 1.0\nmin_dawn_point = -6.0\nmax_dawn_point = 6.0",
+        )
+        .unwrap();
+        let mut learner = learner("clamp");
+        learner.update_if_due(&config, day(1), 0.);
+        learner.observe(&BTreeSet::new(), &light_on(), -10.);
+        learner.update_if_due(&config, day(2), 0.);
+        assert_eq!(learner.dawn_point(), Some(-6.));
+        learner.observe(&BTreeSet::new(), &light_on(), 10.);
+        learner.update_if_due(&config, day(3), 0.);
+        assert_eq!(learner.dawn_point(), Some(6.));
+        std::fs::remove_file(&learner.path).unwrap();
+    }
+}
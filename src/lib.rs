@@ -0,0 +1,81 @@
+//! hue_mie keeps Hue scenes following the sun.
+//!
+//! The curve core (`astro_calc`, `curve`, `config`, `planner` and the bridge model) does no
+//! network I/O and builds without default features, e.g. for `wasm32-unknown-unknown`, so
+//! a web page can render exactly the curves the daemon will run:
+//!
+//! ```sh
+//! cargo build --lib --target wasm32-unknown-unknown --no-default-features
+//! ```
+//!
+//! The `daemon` feature (on by default through `hue-v1`) adds the bridge client, the scheduler
+//! and everything else that talks to the network. HTTPS needs `native-tls` or `rustls` on top;
+//! `web-ui`, `history`, `geoclue` and `scripting` pull in further dependencies.
+
+pub mod astro_calc;
+pub mod audit;
+pub mod backend;
+#[cfg(feature = "daemon")]
+pub mod backup;
+#[cfg(feature = "daemon")]
+pub mod bridge;
+#[cfg(feature = "daemon")]
+pub mod bridge_info;
+#[cfg(feature = "daemon")]
+pub mod bundle;
+#[cfg(feature = "daemon")]
+pub mod calendar;
+pub mod cli;
+pub mod clock;
+pub mod config;
+#[cfg(feature = "daemon")]
+pub mod control;
+pub mod curve;
+#[cfg(feature = "desktop")]
+pub mod desktop;
+pub mod diff;
+#[cfg(feature = "daemon")]
+pub mod energy;
+pub mod executor;
+#[cfg(feature = "geoclue")]
+pub mod geoclue;
+#[cfg(feature = "daemon")]
+pub mod geocode;
+pub mod health;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "daemon")]
+pub mod hooks;
+#[cfg(feature = "daemon")]
+pub mod http;
+pub mod hue;
+#[cfg(feature = "daemon")]
+pub mod leader;
+#[cfg(feature = "daemon")]
+pub mod learning;
+pub mod lint;
+pub mod nudge;
+#[cfg(feature = "daemon")]
+pub mod overrides;
+pub mod planner;
+pub mod quirks;
+pub mod scene_params;
+#[cfg(feature = "daemon")]
+pub mod scheduler;
+#[cfg(feature = "daemon")]
+pub mod schedules;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod state;
+#[cfg(feature = "daemon")]
+pub mod summary;
+pub mod template;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "daemon")]
+pub mod v2;
+#[cfg(feature = "web-ui")]
+pub mod web_ui;
+
+#[macro_use]
+extern crate serde_derive;
@@ -0,0 +1,336 @@
+//! Semantic checks of a parsed config: values that parse fine but make the curve misbehave.
+//! `hue_mie check` prints them with a suggested value, and writes the suggestions with
+//! `--apply-fixes`.
+
+use crate::config::{Config, ConfigFix, Transitions};
+use std::fmt;
+use toml::Value;
+
+/// The warmest and coolest colour temperatures (mired) Hue bulbs show.
+const MIRED_RANGE: (f64, f64) = (153., 500.);
+
+/// Sun altitudes (degrees) beyond which a dawn point is almost certainly a mistake: the sun
+/// is below -18 degrees all night, and a point above 18 degrees shifts the whole day.
+const PLAUSIBLE_DAWN_POINT: f64 = 18.;
+
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub message: String,
+    /// Suggested values by field path (table keys from the root of the config).
+    pub fixes: Vec<ConfigFix>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for (path, value) in self.fixes.iter() {
+            write!(f, "\n    suggested: {} = {}", path.join("."), value)?;
+        }
+        Ok(())
+    }
+}
+
+fn field(prefix: &[String], name: &str) -> Vec<String> {
+    let mut path = prefix.to_vec();
+    path.push(name.to_owned());
+    path
+}
+
+/// Checks one set of transitions; `prefix` is the path of its table.
+fn lint_transitions(prefix: &[String], transitions: &Transitions) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let name = prefix.join(".");
+
+    let lowest = transitions
+        .night_brightness
+        .min(transitions.day_brightness)
+        .max(0.)
+        * 255.;
+    if lowest > 0. && transitions.brightness_cycle_amplitude > lowest {
+        lints.push(Lint {
+            message: format!(
+                "{}.brightness_cycle_amplitude ({}) is larger than the lowest brightness ({:.0} \
+                 steps), so the wobble switches lights off",
+                name, transitions.brightness_cycle_amplitude, lowest
+            ),
+            fixes: vec![(
+                field(prefix, "brightness_cycle_amplitude"),
+                Value::Float((lowest / 2.).floor()),
+            )],
+        });
+    }
+    let mired_span = (MIRED_RANGE.1 - MIRED_RANGE.0) / 2.;
+    if transitions.temperature_cycle_amplitude > mired_span {
+        lints.push(Lint {
+            message: format!(
+                "{}.temperature_cycle_amplitude ({}) swings beyond the colour temperature range \
+                 of the bulbs",
+                name, transitions.temperature_cycle_amplitude
+            ),
+            fixes: vec![(
+                field(prefix, "temperature_cycle_amplitude"),
+                Value::Float(Transitions::default_temperature_cycle_amplitude()),
+            )],
+        });
+    }
+
+    if transitions.day_temperature < transitions.night_temperature {
+        lints.push(Lint {
+            message: format!(
+                "{}.day_temperature ({} K) is warmer than night_temperature ({} K)",
+                name, transitions.day_temperature, transitions.night_temperature
+            ),
+            fixes: vec![
+                (
+                    field(prefix, "day_temperature"),
+                    Value::Float(transitions.night_temperature),
+                ),
+                (
+                    field(prefix, "night_temperature"),
+                    Value::Float(transitions.day_temperature),
+                ),
+            ],
+        });
+    }
+
+    let whole_day = (0..24).all(|hour| transitions.is_deep_night(hour));
+    if whole_day {
+        lints.push(Lint {
+            message: format!(
+                "{}.deep_night_start_hour ({}) and deep_night_end_hour ({}) make the deep night \
+                 last all day",
+                name, transitions.deep_night_start_hour, transitions.deep_night_end_hour
+            ),
+            fixes: vec![
+                (
+                    field(prefix, "deep_night_start_hour"),
+                    Value::Integer(i64::from(Transitions::default_deep_night_start_hour())),
+                ),
+                (
+                    field(prefix, "deep_night_end_hour"),
+                    Value::Integer(i64::from(Transitions::default_deep_night_end_hour())),
+                ),
+            ],
+        });
+    }
+
+    let dawn_points = [
+        (
+            "sun_altitude_dawn_point",
+            transitions.sun_altitude_dawn_point,
+            Transitions::default_sun_altitude_dawn_point(),
+        ),
+        (
+            "temperature_altitude_dawn_point",
+            transitions.temperature_altitude_dawn_point,
+            Transitions::default_temperature_altitude_dawn_point(),
+        ),
+    ];
+    for (key, value, default) in dawn_points.iter() {
+        if value.abs() > PLAUSIBLE_DAWN_POINT {
+            lints.push(Lint {
+                message: format!(
+                    "{}.{} ({} degrees) is outside the plausible range of -{} to {} degrees",
+                    name, key, value, PLAUSIBLE_DAWN_POINT, PLAUSIBLE_DAWN_POINT
+                ),
+                fixes: vec![(field(prefix, key), Value::Float(*default))],
+            });
+        }
+    }
+    lints
+}
+
+/// Everything suspicious in `config`: the main transitions, profiles and zone transitions.
+pub fn lint(config: &Config) -> Vec<Lint> {
+    let mut lints = lint_transitions(&["transitions".to_owned()], &config.transitions);
+    for (name, profile) in config.profiles.iter() {
+        lints.extend(lint_transitions(
+            &["profiles".to_owned(), name.clone()],
+            profile,
+        ));
+    }
+    for (name, zone) in config.zones.iter() {
+        if let Some(transitions) = &zone.transitions {
+            let prefix = ["zones".to_owned(), name.clone(), "transitions".to_owned()];
+            lints.extend(lint_transitions(&prefix, transitions));
+        }
+    }
+    lints
+}
+
+/// `config` as TOML followed by `lints` as comments, so the output stays a valid config.
+pub fn report(config: &Config, lints: &[Lint]) -> Result<String, toml::ser::Error> {
+    let mut report = toml::to_string(config)?;
+    for found in lints.iter() {
+        report.push_str(&format!("\n# {}", found.to_string().replace('\n', "\n# ")));
+    }
+    report.push('\n');
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// The lints of a config with `transitions` as its `[transitions]` table.
+    fn lints(transitions: &str) -> Vec<Lint> {
+        lint(&testing::config(&format!("[transitions]\n{}", transitions)))
+    }
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn the_report_is_a_valid_config() {
+        let config = testing::config(
+            r#"
+            [transitions]
+            day_temperature = 2000.0
+            "#,
+        );
+        let found = lint(&config);
+        let report = report(&config, &found).unwrap();
+        assert!(report.contains("\n# transitions.day_temperature (2000 K)"));
+        assert!(report.contains("\n#     suggested: transitions.day_temperature = 2400"));
+        let parsed: Config = toml::from_str(&report).unwrap();
+        assert_eq!(parsed.transitions.day_temperature, 2000.);
+        assert_eq!(lint(&parsed).len(), found.len());
+    }
+
+    #[test]
+    fn the_defaults_have_no_lints() {
+        assert!(lint(&testing::config("")).is_empty());
+        assert!(lints("").is_empty());
+    }
+
+    #[test]
+    fn brightness_cycle_must_fit_the_lowest_brightness() {
+        let found = lints("night_brightness = 0.1\nbrightness_cycle_amplitude = 30.0");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].message.contains("brightness_cycle_amplitude"));
+        assert_eq!(
+            found[0].fixes,
+            vec![(
+                path(&["transitions", "brightness_cycle_amplitude"]),
+                Value::Float(12.)
+            )]
+        );
+
+        assert!(lints("night_brightness = 0.2\nbrightness_cycle_amplitude = 30.0").is_empty());
+        // Lights that are off at night have no brightness to wobble around.
+        assert!(lints("night_brightness = 0.0\nbrightness_cycle_amplitude = 30.0").is_empty());
+    }
+
+    #[test]
+    fn temperature_cycle_must_fit_the_bulbs() {
+        let found = lints("temperature_cycle_amplitude = 200.0");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(
+            found[0].fixes,
+            vec![(
+                path(&["transitions", "temperature_cycle_amplitude"]),
+                Value::Float(50.)
+            )]
+        );
+
+        assert!(lints("temperature_cycle_amplitude = 170.0").is_empty());
+    }
+
+    #[test]
+    fn day_must_not_be_warmer_than_night() {
+        let found = lints("day_temperature = 2000.0\nnight_temperature = 2400.0");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(
+            found[0].fixes,
+            vec![
+                (
+                    path(&["transitions", "day_temperature"]),
+                    Value::Float(2400.)
+                ),
+                (
+                    path(&["transitions", "night_temperature"]),
+                    Value::Float(2000.)
+                ),
+            ]
+        );
+
+        assert!(lints("day_temperature = 2400.0\nnight_temperature = 2400.0").is_empty());
+    }
+
+    #[test]
+    fn deep_night_must_not_last_all_day() {
+        let found = lints("deep_night_start_hour = 9\ndeep_night_end_hour = 9");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].message.contains("last all day"));
+        assert_eq!(
+            found[0].fixes,
+            vec![
+                (
+                    path(&["transitions", "deep_night_start_hour"]),
+                    Value::Integer(23)
+                ),
+                (
+                    path(&["transitions", "deep_night_end_hour"]),
+                    Value::Integer(6)
+                ),
+            ]
+        );
+
+        assert!(lints("deep_night_start_hour = 9\ndeep_night_end_hour = 16").is_empty());
+        assert!(lints("deep_night_start_hour = 0\ndeep_night_end_hour = 23").is_empty());
+    }
+
+    #[test]
+    fn dawn_points_must_be_plausible() {
+        let found =
+            lints("sun_altitude_dawn_point = -20.0\ntemperature_altitude_dawn_point = 19.0");
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!(
+            found[0].fixes,
+            vec![(
+                path(&["transitions", "sun_altitude_dawn_point"]),
+                Value::Float(-0.4)
+            )]
+        );
+        assert_eq!(
+            found[1].fixes,
+            vec![(
+                path(&["transitions", "temperature_altitude_dawn_point"]),
+                Value::Float(0.)
+            )]
+        );
+
+        assert!(
+            lints("sun_altitude_dawn_point = -18.0\ntemperature_altitude_dawn_point = 18.0")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn profiles_and_zones_are_checked_under_their_own_paths() {
+        let found = lint(&testing::config(
+            r#"
+            [profiles.night_shift]
+            temperature_cycle_amplitude = 200.0
+
+            [zones.hall]
+            lights = [1]
+            transitions = { day_temperature = 2000.0 }
+            "#,
+        ));
+        let paths: Vec<String> = found
+            .iter()
+            .flat_map(|lint| lint.fixes.iter().map(|(path, _)| path.join(".")))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "profiles.night_shift.temperature_cycle_amplitude",
+                "zones.hall.transitions.day_temperature",
+                "zones.hall.transitions.night_temperature",
+            ]
+        );
+    }
+}
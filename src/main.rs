@@ -1,262 +1,686 @@
 use chrono::prelude::*;
-use log::{debug, error, info};
-use philipshue::bridge::Bridge;
-use philipshue::hue::LightStateChange;
-use philipshue::hue::Scene;
+use hue_mie::audit::{AuditLog, Reason};
+use hue_mie::backup::SceneBackup;
+use hue_mie::bridge::Bridge;
+use hue_mie::bridge_info::BridgeInfo;
+use hue_mie::bundle::ProfileBundle;
+use hue_mie::cli::{self, Command, ListKind, SetArgs};
+use hue_mie::clock::{ScaledClock, SystemClock};
+use hue_mie::config;
+use hue_mie::config::Config;
+use hue_mie::control::{self, Request, Response};
+use hue_mie::curve::{self, LightTarget};
+use hue_mie::diff;
+use hue_mie::executor::Executor;
+use hue_mie::hooks::{self, HookEvent};
+use hue_mie::lint;
+use hue_mie::overrides::{Override, Overrides};
+use hue_mie::planner;
+use hue_mie::scheduler::Scheduler;
+use hue_mie::schedules;
+use hue_mie::state::BridgeState;
+use hue_mie::template::Template;
+use hue_mie::v2::V2Client;
+use log::{error, info, warn};
 use std::collections::BTreeMap;
-use std::f64::consts::PI;
-use std::ops::Add;
+use std::io::Write;
 use std::time::Duration;
-use std::time::SystemTime;
-use std::{thread, time};
-
-mod astro_calc;
-mod config;
-
-use config::Config;
-
-use crate::config::Location;
-use crate::config::Transitions;
 
 extern crate env_logger;
-#[macro_use]
-extern crate serde_derive;
 
-trait ExtraMath<T> {
-    fn sigmoid(self) -> T;
-}
+fn setup_and_get_config(
+    bridge_id: Option<&str>,
+    settings: &[(String, String)],
+    dry_run: bool,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = Config::from_file_with(settings)?;
+    let mut changed = config.location.address.is_some();
+    let mut paired = false;
 
-impl ExtraMath<f64> for f64 {
-    fn sigmoid(self) -> f64 {
-        self.exp() / (self.exp() + 1_f64)
+    let mut hue_config = match config.hue {
+        Some(hue_config) => hue_config,
+        None if config.read_only => {
+            return Err(
+                "No bridge configured and the config is read-only; set hue.bridge_ip \
+                        and hue.bridge_password"
+                    .into(),
+            )
+        }
+        None => {
+            let hue_config = Config::get_hue_config(&config.allowed_bridges, bridge_id)?;
+            changed = true;
+            paired = true;
+            if config.location.address.is_none() {
+                config.location.address = ask_address()?;
+            }
+            hue_config
+        }
+    };
+    config.location.resolve_address();
+    let info = BridgeInfo::fetch(&hue_config)?;
+    if config.allowed_bridges.is_empty() {
+        info!("Allowing only bridge {} from now on", info.bridgeid);
+        config.allowed_bridges.push(info.bridgeid.clone());
+        changed = true;
+    } else if !info.is_allowed(&config.allowed_bridges) {
+        return Err(format!(
+            "Bridge {} at {} is not in allowed_bridges, refusing to control it",
+            info.bridgeid, hue_config.bridge_ip
+        )
+        .into());
+    }
+    changed |= hue_config.bridge_id.as_ref() != Some(&info.bridgeid);
+    hue_config.bridge_id = Some(info.bridgeid.clone());
+    config.hue = Some(hue_config.clone());
+    if paired {
+        let context = [
+            ("HUE_MIE_BRIDGE_IP", hue_config.bridge_ip.clone()),
+            ("HUE_MIE_BRIDGE_ID", info.bridgeid.clone()),
+        ];
+        hooks::fire_without_state(
+            &config.hooks,
+            HookEvent::PairingCompleted,
+            &context,
+            dry_run,
+        );
+    }
+    info!("Config: {:?}", config);
+    if !changed {
+        return Ok(config);
+    }
+    if config.read_only || !config.persist_changes {
+        info!("Not storing the new pairing details, the config is read-only");
+    } else if !settings.is_empty() {
+        info!("Not writing back a config with settings from the command line or environment");
+    } else {
+        config.write_setup()?;
     }
+
+    Ok(config)
 }
 
-impl ExtraMath<f32> for f32 {
-    fn sigmoid(self) -> f32 {
-        self.exp() / (self.exp() + 1_f32)
-    }
+/// Asks during first-time setup where the user lives; an empty answer keeps the default
+/// location.
+fn ask_address() -> Result<Option<String>, Box<dyn std::error::Error>> {
+    print!("Your city or address (empty to set lat/long in the config later): ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        None
+    } else {
+        Some(answer.to_owned())
+    })
 }
 
-fn kelvin_to_mired(kelvin: f64) -> f64 {
-    1_000_000_f64 / kelvin
+fn create_bridge(config: &config::HueConfig) -> Bridge {
+    Bridge::new(config.bridge_ip.clone(), config.bridge_password.clone())
 }
 
-mod i16_extra {
-    pub fn diff(left: u16, right: u16) -> u16 {
-        if left > right {
-            left - right
-        } else {
-            right - left
+/// Prints the configuration after includes are merged, as hue_mie will use it, followed by
+/// the lints. With `apply_fixes` the suggested values are written to the config file.
+fn check(
+    settings: &[(String, String)],
+    apply_fixes: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::from_file_with(settings)?;
+    println!("# Effective configuration read from {:?}", Config::path());
+    let lints = lint::lint(&config);
+    print!("{}", lint::report(&config, &lints)?);
+    if apply_fixes && !lints.is_empty() {
+        if config.read_only {
+            return Err(format!("{:?} is read-only", Config::path()).into());
         }
+        let fixes: Vec<_> = lints.into_iter().flat_map(|found| found.fixes).collect();
+        Config::apply_fixes(&fixes)?;
+        println!(
+            "# Wrote {} suggested values to {:?}",
+            fixes.len(),
+            Config::path()
+        );
     }
+    Ok(())
+}
 
-    pub fn is_close(left: u16, right: u16) -> bool {
-        diff(left, right) < 60
+/// Shows what a shared profile would change and stores it under `[profiles]`.
+fn import_profile(
+    settings: &[(String, String)],
+    source: &str,
+    name: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::from_file_with(settings)?;
+    let bundle = ProfileBundle::load(source)?;
+    let name = name.unwrap_or(bundle.name.as_str());
+    for line in bundle.preview(&config, name)? {
+        println!("{}", line);
+    }
+    if dry_run {
+        return Ok(());
     }
+    if config.read_only {
+        return Err(format!("{:?} is read-only", Config::path()).into());
+    }
+    Config::apply_fixes(&bundle.fields(name)?)?;
+    println!("Stored profile {} in {:?}", name, Config::path());
+    Ok(())
 }
 
-mod i8_extra {
-    pub fn diff(left: u8, right: u8) -> u8 {
-        if left > right {
-            left - right
-        } else {
-            right - left
+/// Replaces the exported native schedules with the current curve, or only removes them.
+fn export_schedules(
+    config: &Config,
+    bridge: &Bridge,
+    steps: usize,
+    remove: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if remove {
+        if !dry_run {
+            println!("Removed {} schedules", schedules::remove(bridge)?);
         }
+        return Ok(());
     }
-
-    pub fn is_close(left: u8, right: u8) -> bool {
-        diff(left, right) < 15
+    let state = BridgeState::fetch(bridge, |_| false)?;
+    let planned = schedules::plan_steps(&state, config, steps);
+    if dry_run {
+        for step in planned.iter() {
+            println!(
+                "{} group {}: {}",
+                step.time.format("%H:%M"),
+                step.group_id,
+                step.body
+            );
+        }
+        return Ok(());
     }
+    schedules::export(bridge, &state, &planned)?;
+    println!("Exported {} schedules", planned.len());
+    Ok(())
 }
 
-fn scene_is_active(bridge: &Bridge, scene: &Scene) -> bool {
-    scene.lightstates.iter().fold(true, |b, (id, ls)| {
-        if !b {
-            false
-        } else {
-            debug!("Lightstate: {:?}", ls);
-            let light = bridge.get_light(*id).unwrap();
-            debug!("Light: {:?}", light);
-            debug!("Scene: {:?}", ls);
-            let tl = &(light.state);
-            b && ls.bri.map_or(true, |b| i8_extra::is_close(b, tl.bri))
-                && tl.ct.map_or(true, |c1| {
-                    ls.ct.map_or(true, |c2| i16_extra::is_close(c1, c2))
-                })
-                && Some(tl.on) == ls.on
-        }
-    })
+/// Prints the recorded samples of one light, or all, over the last `last`.
+#[cfg(feature = "history")]
+fn print_history(
+    config: &Config,
+    light: Option<usize>,
+    last: chrono::Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let history_config = config
+        .history
+        .clone()
+        .ok_or("No [history] in the config, nothing is recorded")?;
+    let history = hue_mie::history::History::open(history_config)?;
+    println!("time                 light  target bri/ct  on   bri   ct    reachable");
+    for row in history.query(light, Utc::now() - last)? {
+        println!(
+            "{}  {:>5}  {:>6}/{:<5}  {:<3}  {:>4}  {:>4}  {}",
+            row.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            row.light_id,
+            row.target_bri,
+            row.target_ct,
+            if row.on { "on" } else { "off" },
+            row.bri,
+            row.ct.map_or_else(|| "-".to_owned(), |ct| ct.to_string()),
+            row.reachable
+        );
+    }
+    Ok(())
 }
 
-fn update_scene(bridge: &Bridge, id: &str, scene: &Scene, light_target: &LightTarget) {
-    for (light, state) in scene.lightstates.iter() {
-        match scene.lights.binary_search(&light) {
-            Ok(idx) => {
-                let mut ls: LightStateChange = state.clone();
+#[cfg(not(feature = "history"))]
+fn print_history(
+    _config: &Config,
+    _light: Option<usize>,
+    _last: chrono::Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("hue_mie was built without the history feature".into())
+}
 
-                ls.transitiontime = Some(15);
-                let rotation = ((idx as f64) / (scene.lights.len() as f64)) * PI * 2.;
-                let this_light_target = light_target.clone().rotate(rotation);
-                info!("Light target for {:?}: {:?}", light, this_light_target);
-                ls.bri = Some(this_light_target.bri());
-                ls.ct = Some(this_light_target.ct());
-                ls.on = Some(this_light_target.on());
-                info!("Light state for {:?} : {:?}", light, ls);
-                match bridge.set_light_state_in_scene(&id, *light, &ls) {
-                    Ok(_vec) => {
-                        // Do nothing
-                    }
-                    Err(err) => error!("Could not set light state {:?} in scene id {:?}: {}", ls, id, err),
-                }
+/// Prints where hue_mie thinks it is, today's sun times there and the current target.
+fn status(settings: &[(String, String)]) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::from_file_with(settings)?;
+    let location = &config.location;
+    println!(
+        "Location: {:.4}, {:.4} ({})",
+        location.lat,
+        location.long,
+        location
+            .timezone
+            .as_ref()
+            .map_or("system timezone", String::as_str)
+    );
+    println!("Sun: {}", location.describe_sun_times(Utc::now())?);
+    let now = Local::now();
+    let target = config.clamp_target(
+        now.hour() as u8,
+        LightTarget::at(&now, &config.transitions, location),
+    );
+    println!("Target now: bri {}, ct {}", target.bri(), target.ct());
+    match control::send(&config.control_socket_path(), &Request::Status) {
+        Ok(Response {
+            status: Some(daemon),
+            ..
+        }) => {
+            let paused = match (daemon.paused, daemon.paused_until) {
+                (true, Some(until)) => format!(", paused until {}", until.format("%H:%M")),
+                (true, None) => ", paused".to_owned(),
+                (false, _) => String::new(),
+            };
+            let peak = match daemon.energy_ceiling {
+                Some(ceiling) => format!(", peak energy price (bri at most {:.2})", ceiling),
+                None => String::new(),
+            };
+            match (daemon.last_tick, daemon.bri, daemon.ct) {
+                (Some(last_tick), Some(bri), Some(ct)) => println!(
+                    "Daemon: last update {}, bri {}, ct {}, {} commands{}{}",
+                    last_tick.format("%H:%M:%S"),
+                    bri,
+                    ct,
+                    daemon.commands,
+                    peak,
+                    paused
+                ),
+                _ => println!("Daemon: running, no update yet{}", paused),
             }
-            Err(err) => error!("Could not find light {:?}: {}", light, err)
         }
+        Ok(_) => println!("Daemon: running"),
+        Err(err) => println!("Daemon: not reachable ({})", err),
     }
-    //thread::sleep(time::Duration::from_millis(100));
+    Ok(())
 }
 
-#[derive(Clone, Debug)]
-struct LightTarget {
-    bri: f64,
-    mired: f64,
-    bri_phase: f64,
-    mired_phase: f64,
-    bri_amplitude: f64,
-    mired_amplitude: f64,
+/// Reverts every scene hue_mie has rewritten to the lightstates it had before.
+fn restore_scenes(config: &Config, bridge: &Bridge) -> Result<(), Box<dyn std::error::Error>> {
+    let backup = SceneBackup::load(config.scene_backup_path())?;
+    if backup.scenes().is_empty() {
+        println!("No scene backups found at {:?}", config.scene_backup_path());
+        return Ok(());
+    }
+    let failures = backup.restore(bridge);
+    if failures > 0 {
+        return Err(format!("{} lightstates could not be restored", failures).into());
+    }
+    println!("Restored {} scenes", backup.scenes().len());
+    Ok(())
 }
 
-impl LightTarget {
-    fn target_color_temperature(transitions: &Transitions, sun_altitude: f64) -> f64 {
-        (sun_altitude.to_degrees() / 3.).sigmoid()
-            * (transitions.day_temperature - transitions.night_temperature)
-            + transitions.night_temperature
+/// Prints what the curve would write to the managed scenes right now, next to what they hold.
+fn print_diff(
+    config: &Config,
+    bridge: &Bridge,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = BridgeState::fetch(bridge, planner::needs_states)?;
+    let now = Local::now();
+    let light_target = config.clamp_target(
+        now.hour() as u8,
+        LightTarget::at(&now, &config.transitions, &config.location),
+    );
+    let commands = planner::plan(&state, &light_target, config);
+    let diffs = diff::scene_diffs(&state, &commands);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+    } else if diffs.is_empty() {
+        println!("All managed scenes are up to date");
+    } else {
+        let color = std::env::var_os("NO_COLOR").is_none();
+        print!("{}", diff::format_table(&diffs, color));
     }
+    Ok(())
+}
 
-    fn target_brightness(transitions: &Transitions, sun_altitude: f64, hour: u8) -> f64 {
-        if hour >= transitions.deep_night_start_hour || hour < transitions.deep_night_end_hour {
-            transitions.deep_night_brightness
-        } else {
-            ((sun_altitude.to_degrees() - transitions.sun_altitude_dawn_point)
-                / transitions.transition_time)
-                .sigmoid()
-                * (transitions.day_brightness - transitions.night_brightness)
-                + transitions.night_brightness
+/// Prints every managed scene with the time hue_mie last marked it on the bridge.
+fn list_managed(config: &Config, bridge: &Bridge) -> Result<(), Box<dyn std::error::Error>> {
+    let last_managed: BTreeMap<String, String> = match V2Client::new(config.hue.as_ref().unwrap()) {
+        Ok(client) => client
+            .get_scenes()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|scene| {
+                let last = scene.last_managed()?;
+                Some((scene.v1_id()?.to_owned(), last.to_rfc3339()))
+            })
+            .collect(),
+        Err(_) => BTreeMap::new(),
+    };
+    for (scene_id, scene) in bridge.get_all_scenes()? {
+        if !planner::needs_states(&scene) || scene.recycle {
+            continue;
         }
+        println!(
+            "{:<16} {:<32} {}",
+            scene_id,
+            scene.name,
+            last_managed
+                .get(&scene_id)
+                .map(String::as_str)
+                .unwrap_or("never marked")
+        );
     }
+    Ok(())
+}
 
-    fn new(transitions: &Transitions, location: &Location) -> LightTarget {
-        let sun_altitude = astro_calc::sun_altitude(Utc::now(), location.as_geograph_point());
-        let now = Local::now();
-        let seconds_from_midnight = now.num_seconds_from_midnight();
-
-        debug!("Apparent altitude: {:5}", sun_altitude.to_degrees());
-        LightTarget {
-            bri: LightTarget::target_brightness(transitions, sun_altitude, now.hour() as u8),
-            mired: kelvin_to_mired(LightTarget::target_color_temperature(
-                transitions,
-                sun_altitude,
-            )),
-            bri_phase: (f64::from(seconds_from_midnight) * 2.0 * PI
-                / transitions.brightness_cycle_length) % (2.0 * PI),
-            mired_phase: (f64::from(seconds_from_midnight) * 2.0 * PI
-                / transitions.temperature_cycle_length) % (2.0 * PI),
-            bri_amplitude: transitions.brightness_cycle_amplitude,
-            mired_amplitude: transitions.temperature_cycle_amplitude,
+/// Adds scenes to `excluded_scenes` and stores the list in the config file.
+fn exclude_scenes(
+    config: &mut Config,
+    state: &BridgeState,
+    exclude: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for scene_id in exclude.iter() {
+        let scene = state
+            .scenes
+            .get(scene_id)
+            .ok_or_else(|| format!("No scene with id {:?}", scene_id))?;
+        if !config.excluded_scenes.contains(scene_id) {
+            config.excluded_scenes.push(scene_id.clone());
         }
+        println!("Excluding {} ({})", scene_id, scene.name);
     }
-
-    pub fn rotate(self: &LightTarget, angle: f64) -> LightTarget {
-        let mut c = self.clone();
-        c.bri_phase = (c.bri_phase + angle) % (PI * 2.);
-        c.mired_phase = (c.mired_phase + angle) % (PI * 2.);
-        c
-    }
-
-    pub fn ct(self: &LightTarget) -> u16 {
-        (self.mired_phase.cos() * self.mired_amplitude + self.mired)
-            .max(0.)
-            .min(65535.) as u16
-    }
-
-    pub fn bri(self: &LightTarget) -> u8 {
-        (self.bri_phase.cos() * self.bri_amplitude + self.bri * 255.)
-            .max(0.)
-            .min(255.) as u8
+    if dry_run {
+        return Ok(());
     }
-
-    pub fn on(self: &LightTarget) -> bool {
-        self.bri() != 0
+    if config.read_only {
+        return Err(format!("{:?} is read-only", Config::path()).into());
     }
-}
-
-fn update_scenes(bridge: &Bridge, scenes: BTreeMap<String, Scene>, light_target: &LightTarget) {
-    scenes
+    let ids = config
+        .excluded_scenes
         .iter()
-        .filter(|&(_, scene)| scene.name.to_lowercase().contains("dayshift"))
-        .filter(|&(_, scene)| !scene.recycle)
-        .for_each(|(scene_id, scene)| {
-            debug!("Updating scene {}, scene_id: {}", scene.name, scene_id);
-            match bridge.get_scene_with_states(&scene_id) {
-                Ok(s) => {
-                    let scene_active = scene_is_active(&bridge, &s);
-
-                    update_scene(&bridge, &scene_id, &s, &light_target);
+        .cloned()
+        .map(toml::Value::String)
+        .collect();
+    Config::apply_fixes(&[(vec!["excluded_scenes".to_owned()], toml::Value::Array(ids))])
+}
 
-                    let sleep_duration = time::Duration::from_millis(250);
-                    thread::sleep(sleep_duration);
-                    info!(
-                        "Scene {} is {}!",
-                        scene.name,
-                        if scene_active { "active" } else { "inactive" }
-                    );
-                    if scene_active {
-                        bridge
-                            .get_all_groups()
-                            .unwrap()
-                            .iter()
-                            .filter(|&(_, group)| group.lights.clone().sort() == scene.lights.clone().sort())
-                            .for_each(|(group_id, _)| {
-                                debug!("Recall scene {} in group {}", scene_id, group_id);
-                                match bridge.recall_scene_in_group(*group_id, &scene_id) {
-                                    Ok(_) => {
-                                        info!("Recalled scene with id {:?}", scene_id)
-                                    }
-                                    Err(e) => {
-                                        error!("Could not recall scene with id {:?}: {}", scene_id, e)
-                                    }
-                                }
-                            })
-                    }
-                }
-                Err(e) => {
-                    error!("Could not find scene with id {:?}: {}", scene_id, e)
-                }
+/// Prints scenes, groups or lights with their room, how many of their lights are reachable and
+/// whether hue_mie manages them, to find out why a room does not follow the curve.
+fn list(
+    config: &mut Config,
+    bridge: &Bridge,
+    kind: ListKind,
+    exclude: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = BridgeState::fetch(bridge, |_| false)?;
+    if !exclude.is_empty() {
+        exclude_scenes(config, &state, exclude, dry_run)?;
+    }
+    let room_of = |lights: &[usize]| {
+        state
+            .groups
+            .values()
+            .find(|group| {
+                group.group_type == "Room"
+                    && !lights.is_empty()
+                    && lights.iter().all(|light| group.lights.contains(light))
+            })
+            .map_or("-", |group| group.name.as_str())
+    };
+    let reachable = |lights: &[usize]| {
+        let count = lights
+            .iter()
+            .filter(|light_id| {
+                state
+                    .lights
+                    .get(*light_id)
+                    .is_some_and(|l| l.state.reachable)
+            })
+            .count();
+        format!("{}/{}", count, lights.len())
+    };
+    let status = |reason: Option<&str>| match reason {
+        Some(reason) => format!("not managed: {}", reason),
+        None => "managed".to_owned(),
+    };
+    println!(
+        "{:<16} {:<32} {:<20} {:<9} status",
+        "id", "name", "room", "reachable"
+    );
+    match kind {
+        ListKind::Scenes => {
+            for (scene_id, scene) in state.scenes.iter().filter(|(_, scene)| !scene.recycle) {
+                println!(
+                    "{:<16} {:<32} {:<20} {:<9} {}",
+                    scene_id,
+                    scene.name,
+                    room_of(&scene.lights),
+                    reachable(&scene.lights),
+                    status(planner::unmanaged_reason(&state, config, scene_id, scene))
+                );
+            }
+        }
+        ListKind::Groups => {
+            let managed = planner::managed_groups(&state);
+            let disabled = planner::disabled_groups(&state, &config.sentinel_scene_name);
+            for (group_id, group) in state.groups.iter() {
+                let reason = if disabled.contains(group_id) {
+                    Some("opted out by a sentinel scene")
+                } else if !managed.contains(group_id) {
+                    Some("no dayshift scene")
+                } else {
+                    None
+                };
+                println!(
+                    "{:<16} {:<32} {:<20} {:<9} {}",
+                    group_id,
+                    group.name,
+                    group.group_type,
+                    reachable(&group.lights),
+                    status(reason)
+                );
             }
-        });
+        }
+        ListKind::Lights => {
+            for (light_id, light) in state.lights.iter() {
+                println!(
+                    "{:<16} {:<32} {:<20} {:<9} {}",
+                    light_id,
+                    light.name,
+                    room_of(&[*light_id]),
+                    if light.state.reachable { "yes" } else { "no" },
+                    status(planner::unmanaged_light_reason(&state, config, *light_id))
+                );
+            }
+        }
+    }
+    Ok(())
 }
 
-fn setup_and_get_config() -> Result<Config, Box<dyn std::error::Error>> {
-    let mut config = Config::from_file()?.clone();
-
-    let hue_config = match config.hue {
-        Some(hue_config) => hue_config,
-        None => Config::get_hue_config()?,
+/// Sets a group through the running daemon when there is one, directly otherwise. The setting
+/// is recorded so the daemon leaves the group alone until it expires.
+fn set(
+    config: &Config,
+    bridge: &Bridge,
+    set_args: &SetArgs,
+    executor: &mut Executor,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let new = Override {
+        group: set_args.group.clone(),
+        bri: set_args.bri,
+        ct: set_args.ct,
+        until: Utc::now() + set_args.duration,
     };
-    config.hue = Some(hue_config.clone());
-    info!("Config: {:?}", config);
-    config.write_file()?;
-
-    Ok(config)
+    let socket = config.control_socket_path();
+    if !executor.dry_run && control::reachable(&socket) {
+        control::send(&socket, &Request::Set(new.clone()))?;
+        println!(
+            "Set {} until {}",
+            new.group,
+            new.until.with_timezone(&Local)
+        );
+        return Ok(());
+    }
+    let mut state = BridgeState::fetch(bridge, |_| false)?;
+    let commands = new.commands(&state)?;
+    let commands = planner::apply_caps(&state, config, Local::now().hour() as u8, commands);
+    let failures = executor.apply(
+        bridge,
+        &SystemClock,
+        &commands,
+        &mut state,
+        Reason::Override,
+    );
+    if failures > 0 {
+        return Err(format!("{} lights could not be set", failures).into());
+    }
+    if !executor.dry_run {
+        Overrides::load(config.overrides_path())?.add(new.clone())?;
+    }
+    println!(
+        "Set {} until {}",
+        new.group,
+        new.until.with_timezone(&Local)
+    );
+    Ok(())
 }
 
-fn create_bridge(config: &config::HueConfig) -> philipshue::bridge::Bridge {
-    Bridge::new(config.bridge_ip.clone(), config.bridge_password.clone())
+/// Provisions the scenes described by a template and stores their roles in the config.
+fn apply_template(
+    config: &mut Config,
+    bridge: &Bridge,
+    path: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let template = Template::from_file(path)?;
+    let configured: Vec<String> = config.scenes.keys().cloned().collect();
+    template.apply(bridge, config, dry_run)?;
+    // Only the templated scenes are written, leaving the rest of the config file as it is. Scenes
+    // already in the config get just their per-light brightness; new ones start from
+    // `scene_defaults` like they did before.
+    let mut fixes = Vec::new();
+    let mut written = toml::value::Table::new();
+    for room in template.rooms.iter() {
+        let scene_name = room.scene_name();
+        let scene_config = match config.scenes.get(&scene_name) {
+            Some(scene_config) => scene_config,
+            None => continue,
+        };
+        let scene_path = vec!["scenes".to_owned(), scene_name.clone()];
+        let value = if configured.contains(&scene_name) {
+            let light_brightness = toml::Value::try_from(&scene_config.light_brightness)?;
+            let mut light_brightness_path = scene_path;
+            light_brightness_path.push("light_brightness".to_owned());
+            fixes.push((light_brightness_path, light_brightness.clone()));
+            let mut fields = toml::value::Table::new();
+            fields.insert("light_brightness".to_owned(), light_brightness);
+            toml::Value::Table(fields)
+        } else {
+            let value = toml::Value::try_from(scene_config)?;
+            fixes.push((scene_path, value.clone()));
+            value
+        };
+        written.insert(scene_name, value);
+    }
+    let mut scenes = toml::value::Table::new();
+    scenes.insert("scenes".to_owned(), toml::Value::Table(written));
+    if dry_run {
+        println!("{}", toml::to_string(&scenes)?);
+    } else if !config.include.is_empty() || config.read_only || !config.persist_changes {
+        println!("Config uses includes or is read-only, add these scene settings by hand:");
+        println!("{}", toml::to_string(&scenes)?);
+    } else {
+        Config::apply_fixes(&fixes)?;
+        println!("Applied {} rooms from {}", template.rooms.len(), path);
+    }
+    Ok(())
 }
 
 fn main() {
     env_logger::init();
-    let config = match setup_and_get_config() {
+    let args = match cli::parse_args(std::env::args()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = &args.config_path {
+        std::env::set_var("HUE_MIE_CONFIG", path);
+    }
+    let mut settings = config::env_settings();
+    settings.extend(args.settings.iter().cloned());
+    if let Command::ConfigInit { force, stdout } = args.command {
+        if stdout {
+            print!("{}", config::DEFAULT_CONFIG);
+            return;
+        }
+        match Config::init(force) {
+            Ok(path) => println!("Wrote {:?}", path),
+            Err(err) => {
+                error!("Could not write the default config: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Command::ConfigConvert { input, output } = &args.command {
+        match Config::convert(input, output) {
+            Ok(()) => println!("Wrote {}", output),
+            Err(err) => {
+                error!("Could not convert {}: {}", input, err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Command::History { light, last } = args.command {
+        let printed = Config::from_file_with(&settings)
+            .and_then(|config| print_history(&config, light, last));
+        if let Err(err) = printed {
+            error!("Could not show the history: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.command == Command::Status {
+        if let Err(err) = status(&settings) {
+            error!("Could not show the status: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let daemon_request = match &args.command {
+        Command::Resync => Some((Request::Resync, "Resync requested")),
+        Command::Pause { duration } => Some((
+            Request::Pause {
+                minutes: duration.map(|duration| duration.num_minutes().max(1)),
+            },
+            "Paused",
+        )),
+        Command::Resume => Some((Request::Resume, "Resumed")),
+        _ => None,
+    };
+    if let Some((request, done)) = daemon_request {
+        let sent = Config::from_file_with(&settings)
+            .and_then(|config| control::send(&config.control_socket_path(), &request));
+        match sent {
+            Ok(_) => println!("{}", done),
+            Err(err) => {
+                error!("Could not reach the daemon: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Command::ImportProfile { source, name } = &args.command {
+        let name = name.as_ref().map(String::as_str);
+        if let Err(err) = import_profile(&settings, source, name, args.dry_run) {
+            error!("Could not import {}: {}", source, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Command::Check { apply_fixes } = args.command {
+        if let Err(err) = check(&settings, apply_fixes) {
+            error!("Configuration is invalid: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let bridge_id = args.bridge_id.as_deref();
+    let mut config = match setup_and_get_config(bridge_id, &settings, args.dry_run) {
         Ok(config) => config,
         Err(err) => {
             error!("Error while retrieving config: {:?}", err);
@@ -264,19 +688,108 @@ fn main() {
         }
     };
 
-    let bridge: Bridge = create_bridge(&(config.hue.unwrap()));
-    loop {
-        let next_step = SystemTime::now().add(Duration::from_secs(15));
-        let light_target = LightTarget::new(&(config.transitions), &(config.location));
-        debug!("target: {:?}", light_target);
-
-        match bridge.get_all_scenes() {
-            Ok(scenes) => update_scenes(&bridge, scenes, &light_target),
-            Err(err) => error!("Error: {}", err),
-        }
-        let sleep = next_step
-            .duration_since(SystemTime::now())
-            .unwrap_or(Duration::from_secs(0));
-        thread::sleep(sleep);
+    let bridge: Bridge = create_bridge(config.hue.as_ref().unwrap());
+    if let Command::Diff { json } = args.command {
+        if let Err(err) = print_diff(&config, &bridge, json) {
+            error!("Could not compute the diff: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Command::ExportSchedules { steps, remove } = args.command {
+        if let Err(err) = export_schedules(&config, &bridge, steps, remove, args.dry_run) {
+            error!("Could not export schedules: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.command == Command::ListManaged {
+        if let Err(err) = list_managed(&config, &bridge) {
+            error!("Could not list managed scenes: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Command::List { kind, exclude } = &args.command {
+        if let Err(err) = list(&mut config, &bridge, *kind, exclude, args.dry_run) {
+            error!("Could not list: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.command == Command::RestoreScenes {
+        if let Err(err) = restore_scenes(&config, &bridge) {
+            error!("Could not restore scenes: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Command::ApplyTemplate(path) = &args.command {
+        if let Err(err) = apply_template(&mut config, &bridge, path, args.dry_run) {
+            error!("Could not apply template {}: {}", path, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let audit_log = config.audit_log.clone().map(AuditLog::new);
+    let mut executor = Executor::new(
+        Duration::from_millis(config.pacing.inter_light_delay_ms),
+        args.dry_run,
+        audit_log,
+    );
+    executor.scene_delay = Duration::from_millis(config.pacing.inter_scene_delay_ms);
+    if let Command::Set(set_args) = &args.command {
+        if let Err(err) = set(&config, &bridge, set_args, &mut executor) {
+            error!("Could not set {}: {}", set_args.group, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(feature = "web-ui")]
+    {
+        if let Some(web_ui) = &config.web_ui {
+            hue_mie::web_ui::spawn(config.clone(), web_ui.port);
+        }
+    }
+    #[cfg(not(feature = "web-ui"))]
+    {
+        if config.web_ui.is_some() {
+            error!("[web_ui] is configured but hue_mie was built without the web-ui feature");
+        }
+    }
+    #[cfg(not(feature = "desktop"))]
+    {
+        if config.desktop.is_some() {
+            error!("[desktop] is configured but hue_mie was built without the desktop feature");
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    {
+        if config.script.is_some() {
+            error!("[script] is configured but hue_mie was built without the scripting feature");
+        }
+    }
+    #[cfg(not(feature = "geoclue"))]
+    {
+        if config.location.geoclue {
+            error!("location.geoclue is set but hue_mie was built without the geoclue feature");
+        }
+    }
+    match args.simulate_day_minutes {
+        Some(minutes) => {
+            let start = curve::midnight(&Local::now());
+            let clock = ScaledClock::new(start, 24. * 60. / minutes);
+            info!("Simulating a day from {} in {} minutes", start, minutes);
+            Scheduler::new(&config, &clock, &bridge, executor)
+                .run_until(start + chrono::Duration::days(1));
+        }
+        None => {
+            let mut scheduler = Scheduler::new(&config, &SystemClock, &bridge, executor);
+            match control::listen(&config.control_socket_path()) {
+                Ok(control) => scheduler.set_control(control),
+                Err(err) => warn!("Not listening for commands: {}", err),
+            }
+            scheduler.run()
+        }
     }
 }
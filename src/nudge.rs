@@ -0,0 +1,90 @@
+//! A gentle fallback wake-up: when a bedroom is still dark some time after the morning time, its
+//! lights are switched on at minimum brightness and ramped up to the curve.
+
+use crate::config::GetUpNudgeConfig;
+use crate::curve::LightTarget;
+use crate::hue::LightStateChange;
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::info;
+
+#[derive(Debug, Default)]
+pub struct GetUpNudge {
+    /// The last day the nudge ran or was found unnecessary.
+    handled: Option<NaiveDate>,
+    started: Option<DateTime<Local>>,
+}
+
+impl GetUpNudge {
+    /// The light updates for this tick for the group named `group_name`.
+    pub fn plan(
+        self: &mut GetUpNudge,
+        group_name: &str,
+        config: &GetUpNudgeConfig,
+        state: &BridgeState,
+        now: &DateTime<Local>,
+        light_target: &LightTarget,
+    ) -> Vec<LightCommand> {
+        let lights = match state.groups.values().find(|group| group.name == group_name) {
+            Some(group) => &group.lights,
+            None => return Vec::new(),
+        };
+        let all_off = lights
+            .iter()
+            .all(|light_id| state.lights.get(light_id).is_none_or(|l| !l.state.on));
+        let start_brightness = config.start_brightness.max(1);
+        let bri = match self.started {
+            Some(started) => {
+                let progress = (*now - started).num_seconds() as f64
+                    / (config.ramp_minutes.max(1) * 60) as f64;
+                if progress >= 1. || all_off {
+                    // Done, or someone switched the lights off again.
+                    self.started = None;
+                    return Vec::new();
+                }
+                let target = f64::from(light_target.bri().max(start_brightness));
+                let start = f64::from(start_brightness);
+                (start + (target - start) * progress) as u8
+            }
+            None => {
+                let nudge_time = match NaiveTime::from_hms_opt(
+                    u32::from(config.morning_hour),
+                    u32::from(config.morning_minute),
+                    0,
+                )
+                .and_then(|time| {
+                    Local
+                        .from_local_datetime(&now.date_naive().and_time(time))
+                        .earliest()
+                }) {
+                    Some(time) => time + chrono::Duration::minutes(config.after_minutes),
+                    None => return Vec::new(),
+                };
+                if self.handled == Some(now.date_naive()) || *now < nudge_time {
+                    return Vec::new();
+                }
+                self.handled = Some(now.date_naive());
+                if !all_off || *now - nudge_time > chrono::Duration::hours(1) {
+                    return Vec::new();
+                }
+                info!("{} is still dark, nudging", group_name);
+                self.started = Some(*now);
+                start_brightness
+            }
+        };
+        lights
+            .iter()
+            .map(|light_id| LightCommand::SetLightState {
+                light_id: *light_id,
+                state: LightStateChange {
+                    on: Some(true),
+                    bri: Some(bri),
+                    ct: Some(light_target.ct()),
+                    transitiontime: Some(15),
+                    ..LightStateChange::default()
+                },
+            })
+            .collect()
+    }
+}
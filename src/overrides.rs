@@ -0,0 +1,140 @@
+use crate::hue::LightStateChange;
+use crate::planner::LightCommand;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::debug;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// A manual setting for a group that hue_mie leaves alone until it expires.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Override {
+    pub group: String,
+    pub bri: Option<u8>,
+    pub ct: Option<u16>,
+    pub until: DateTime<Utc>,
+}
+
+impl Override {
+    /// The light updates that put the group in the overridden state.
+    pub fn commands(
+        self: &Override,
+        state: &BridgeState,
+    ) -> Result<Vec<LightCommand>, Box<dyn Error>> {
+        let group = state
+            .groups
+            .values()
+            .find(|group| group.name.to_lowercase() == self.group.to_lowercase())
+            .ok_or_else(|| format!("No group named {:?}", self.group))?;
+        Ok(group
+            .lights
+            .iter()
+            .map(|light_id| LightCommand::SetLightState {
+                light_id: *light_id,
+                state: LightStateChange {
+                    on: Some(true),
+                    bri: self.bri,
+                    ct: self.ct,
+                    ..LightStateChange::default()
+                },
+            })
+            .collect())
+    }
+}
+
+/// Overrides shared between `hue_mie set` and the daemon through a file, so the daemon picks
+/// them up without a control connection.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    path: PathBuf,
+    overrides: Vec<Override>,
+}
+
+impl Overrides {
+    pub fn default_path() -> PathBuf {
+        let mut path: PathBuf = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("hue_mie");
+        path.push("overrides.json");
+        path
+    }
+
+    pub fn load(path: PathBuf) -> Result<Overrides, Box<dyn Error>> {
+        let overrides = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Overrides { path, overrides })
+    }
+
+    fn save(self: &Overrides) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.overrides)?)?;
+        Ok(())
+    }
+
+    /// Stores `new`, replacing any override of the same group and dropping expired ones.
+    pub fn add(self: &mut Overrides, new: Override) -> Result<(), Box<dyn Error>> {
+        let now = Utc::now();
+        self.overrides
+            .retain(|o| o.until > now && o.group.to_lowercase() != new.group.to_lowercase());
+        self.overrides.push(new);
+        self.save()
+    }
+
+    /// The lights of every group with an override in effect at `now`.
+    pub fn overridden_lights(
+        self: &Overrides,
+        state: &BridgeState,
+        now: DateTime<Utc>,
+    ) -> BTreeSet<usize> {
+        self.overrides
+            .iter()
+            .filter(|o| o.until > now)
+            .flat_map(|o| {
+                state
+                    .groups
+                    .values()
+                    .filter(move |group| group.name.to_lowercase() == o.group.to_lowercase())
+            })
+            .flat_map(|group| group.lights.iter().cloned())
+            .collect()
+    }
+}
+
+/// Drops the commands that would touch any of `lights`.
+pub fn without_lights(
+    state: &BridgeState,
+    commands: Vec<LightCommand>,
+    lights: &BTreeSet<usize>,
+) -> Vec<LightCommand> {
+    if lights.is_empty() {
+        return commands;
+    }
+    commands
+        .into_iter()
+        .filter(|command| {
+            let touches = match command {
+                LightCommand::SetLightState { light_id, .. }
+                | LightCommand::SetSceneLightState { light_id, .. } => lights.contains(light_id),
+                LightCommand::RecallScene { scene_id, group_id } => state
+                    .groups
+                    .get(group_id)
+                    .map(|group| &group.lights)
+                    // Group 0 is not listed; a recall there changes the scene's lights.
+                    .or_else(|| state.scenes.get(scene_id).map(|scene| &scene.lights))
+                    .is_some_and(|group_lights| group_lights.iter().any(|l| lights.contains(l))),
+                LightCommand::CreateScene { .. }
+                | LightCommand::SetSceneLights { .. }
+                | LightCommand::SetLightConfig { .. } => false,
+            };
+            if touches {
+                debug!("Skipping {:?}, overridden", command);
+            }
+            !touches
+        })
+        .collect()
+}
@@ -0,0 +1,1494 @@
+use crate::config::{
+    AccessibilityConfig, Config, LightCalibration, OffLightPolicy, PowerPolicy, PowerupConfig,
+    RoomCap, RoomType, SceneConfig, UpdateStrategy, ZoneConfig,
+};
+use crate::curve::LightTarget;
+use crate::hue::{LightStateChange, Scene};
+use crate::quirks::{self, Quirks};
+use crate::scene_params::SceneParams;
+use crate::state::BridgeState;
+use log::{debug, error, info};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::f64::consts::PI;
+
+/// A single write the executor should perform against the bridge.
+#[derive(Clone, Debug)]
+pub enum LightCommand {
+    SetLightState {
+        light_id: usize,
+        state: LightStateChange,
+    },
+    SetSceneLightState {
+        scene_id: String,
+        light_id: usize,
+        state: LightStateChange,
+    },
+    RecallScene {
+        scene_id: String,
+        group_id: usize,
+    },
+    CreateScene {
+        name: String,
+        lights: Vec<usize>,
+    },
+    /// Replaces the lights of a LightScene.
+    SetSceneLights {
+        scene_id: String,
+        lights: Vec<usize>,
+    },
+    /// Changes fields of a light's device config, such as its power-on state.
+    SetLightConfig {
+        light_id: usize,
+        config: Value,
+    },
+}
+
+const SHADOW_SCENE_PREFIX: &str = "hue_mie ";
+
+/// The bridge's implicit group of all lights.
+pub const ALL_LIGHTS_GROUP: usize = 0;
+
+mod i16_extra {
+    pub fn diff(left: u16, right: u16) -> u16 {
+        left.abs_diff(right)
+    }
+
+    pub fn is_close(left: u16, right: u16, tolerance: u16) -> bool {
+        diff(left, right) < tolerance
+    }
+}
+
+mod i8_extra {
+    pub fn diff(left: u8, right: u8) -> u8 {
+        left.abs_diff(right)
+    }
+
+    pub fn is_close(left: u8, right: u8, tolerance: u8) -> bool {
+        diff(left, right) < tolerance
+    }
+}
+
+pub fn is_managed_name(name: &str) -> bool {
+    name.to_lowercase().contains("dayshift")
+}
+
+pub fn is_managed(scene: &Scene) -> bool {
+    is_managed_name(&scene.name) && !scene.recycle
+}
+
+/// The brightness scale encoded as a percentage at the end of a scene name, e.g.
+/// "Kitchen dayshift 80%".
+pub fn name_brightness_scale(name: &str) -> Option<f64> {
+    let last = name.split_whitespace().last()?;
+    if !last.ends_with('%') {
+        return None;
+    }
+    last.trim_end_matches('%')
+        .parse::<f64>()
+        .ok()
+        .filter(|percentage| *percentage >= 0.)
+        .map(|percentage| percentage / 100.)
+}
+
+/// Whether every light of the scene is (close to) the state the scene prescribes, within the
+/// activity tolerances of `scene_config`.
+pub fn scene_is_active(state: &BridgeState, scene: &Scene, scene_config: &SceneConfig) -> bool {
+    let bri_tolerance = |current: u8| match scene_config.active_tolerance_percent {
+        Some(percent) => (f64::from(current) * percent / 100.).clamp(1., 255.) as u8,
+        None => scene_config.active_bri_tolerance,
+    };
+    let mired_tolerance = |current: u16| match scene_config.active_tolerance_percent {
+        Some(percent) => (f64::from(current) * percent / 100.).clamp(1., 65535.) as u16,
+        None => scene_config.active_mired_tolerance,
+    };
+    scene.lightstates.iter().all(|(id, ls)| {
+        debug!("Lightstate: {:?}", ls);
+        match state.lights.get(id) {
+            Some(light) => {
+                debug!("Light: {:?}", light);
+                let tl = &(light.state);
+                ls.bri
+                    .is_none_or(|b| i8_extra::is_close(b, tl.bri, bri_tolerance(tl.bri)))
+                    && tl.ct.is_none_or(|c1| {
+                        ls.ct
+                            .is_none_or(|c2| i16_extra::is_close(c1, c2, mired_tolerance(c1)))
+                    })
+                    && Some(tl.on) == ls.on
+            }
+            None => {
+                error!("Could not find light {:?}", id);
+                false
+            }
+        }
+    })
+}
+
+/// Whether `new` differs from `old` by at least the configured minimum steps. Switching a light
+/// on or off always counts.
+fn changed_enough(
+    old: &LightStateChange,
+    new: &LightStateChange,
+    scene_config: &SceneConfig,
+) -> bool {
+    let bri_changed = match (old.bri, new.bri) {
+        (Some(old_bri), Some(new_bri)) => {
+            i8_extra::diff(old_bri, new_bri) >= scene_config.min_bri_delta
+        }
+        (old_bri, new_bri) => old_bri != new_bri,
+    };
+    let ct_changed = match (old.ct, new.ct) {
+        (Some(old_ct), Some(new_ct)) => {
+            i16_extra::diff(old_ct, new_ct) >= scene_config.min_mired_delta
+        }
+        (old_ct, new_ct) => old_ct != new_ct,
+    };
+    bri_changed || ct_changed || old.on != new.on
+}
+
+/// Whether any light of the scene moved away from its stored lightstate by at least the minimum
+/// steps, e.g. because a bulb missed the last recall.
+fn drifted(state: &BridgeState, scene: &Scene, scene_config: &SceneConfig) -> bool {
+    scene.lightstates.iter().any(|(light_id, stored)| {
+        state.lights.get(light_id).is_some_and(|light| {
+            let live = LightStateChange {
+                on: Some(light.state.on),
+                bri: stored.bri.map(|_| light.state.bri),
+                // Without a reported colour temperature there is nothing to compare.
+                ct: match (light.state.ct, stored.ct) {
+                    (Some(ct), Some(_)) => Some(ct),
+                    _ => stored.ct,
+                },
+                ..LightStateChange::default()
+            };
+            changed_enough(stored, &live, scene_config)
+        })
+    })
+}
+
+/// The target brightness and colour temperature for one light, corrected by its calibration
+/// entry (by light id, else by model id) if any, and raised to its minimum brightness (looked up
+/// the same way, but on its own).
+fn calibrated(
+    state: &BridgeState,
+    calibration: &BTreeMap<String, LightCalibration>,
+    light_id: usize,
+    light_target: &LightTarget,
+) -> (u8, u16) {
+    let by_id = calibration.get(&light_id.to_string());
+    let by_model = state
+        .lights
+        .get(&light_id)
+        .and_then(|light| calibration.get(&light.modelid));
+    let (bri, ct) = match by_id.or(by_model) {
+        Some(entry) => entry.apply(light_target.bri(), light_target.ct()),
+        None => (light_target.bri(), light_target.ct()),
+    };
+    let min_bri = by_id
+        .and_then(|entry| entry.min_bri)
+        .or_else(|| by_model.and_then(|entry| entry.min_bri));
+    (bri.max(min_bri.unwrap_or(0)), ct)
+}
+
+/// Whether a light that is `was_on` should be on at `bri`: switched on only from `on_threshold`
+/// and off only at `off_threshold`, and left as it is within `min_switch_interval_secs` of our
+/// last switch.
+fn switch_on(
+    state: &BridgeState,
+    scene_id: Option<&str>,
+    light_id: usize,
+    was_on: bool,
+    bri: u8,
+    scene_config: &SceneConfig,
+) -> bool {
+    let wanted = if was_on {
+        bri > scene_config.off_threshold
+    } else {
+        bri >= scene_config.on_threshold
+    };
+    let interval = std::time::Duration::from_secs(scene_config.min_switch_interval_secs);
+    if wanted != was_on && state.switched_within(scene_id, light_id, interval) {
+        debug!(
+            "Light {} switched recently, keeping it {}",
+            light_id,
+            if was_on { "on" } else { "off" }
+        );
+        return was_on;
+    }
+    wanted
+}
+
+/// Adjusts the light target to the preset for `room_type`.
+pub fn apply_room_type(room_type: RoomType, light_target: &LightTarget) -> LightTarget {
+    match room_type {
+        RoomType::Work => light_target.shift_temperature(400., 0.).scale_cycle(0.5),
+        RoomType::Relax => light_target.shift_temperature(-200., 0.),
+        RoomType::Hallway => light_target.scale_cycle(0.),
+        RoomType::Bedroom => light_target
+            .shift_temperature(-300., 0.)
+            .scale_brightness(0.8)
+            .scale_cycle(0.5),
+    }
+}
+
+/// Applies the accessibility limits. Lights the curve switches off stay off.
+pub fn apply_accessibility(
+    accessibility: &AccessibilityConfig,
+    light_target: &LightTarget,
+) -> LightTarget {
+    let mut target = light_target.limit_warmth(accessibility.min_temperature);
+    if !accessibility.wobble {
+        target = target.scale_cycle(0.);
+    }
+    if target.on() {
+        target = target.floor_brightness(accessibility.min_brightness);
+    }
+    target
+}
+
+fn plan_scene(
+    bridge_state: &BridgeState,
+    scene_id: &str,
+    scene: &Scene,
+    scene_config: &SceneConfig,
+    config: &Config,
+    light_target: &LightTarget,
+) -> Vec<LightCommand> {
+    let mut commands = Vec::new();
+    let mut light_target = match name_brightness_scale(&scene.name) {
+        Some(scale) => light_target.scale_brightness(scale),
+        None => light_target.clone(),
+    };
+    if let Some(room_type) = scene_config.room_type {
+        light_target = apply_room_type(room_type, &light_target);
+    }
+    match SceneParams::parse(&scene.name) {
+        Ok(Some(params)) => light_target = params.apply(&light_target),
+        Ok(None) => {}
+        Err(err) => error!("Ignoring parameters of scene {}: {}", scene.name, err),
+    }
+    for (light, state) in scene.lightstates.iter() {
+        match scene.lights.binary_search(light) {
+            Ok(idx) => {
+                let device = bridge_state.lights.get(light);
+                if config.exclude_plugs && device.is_some_and(|l| l.is_plug()) {
+                    debug!(
+                        "Light {:?} is a smart plug, leaving it alone in {}",
+                        light, scene_id
+                    );
+                    continue;
+                }
+                let light_is_on = device.is_some_and(|l| l.state.on);
+                if scene_config.power == PowerPolicy::OnlyWhenOn && !light_is_on {
+                    debug!(
+                        "Light {:?} is off, leaving it alone in scene {}",
+                        light, scene_id
+                    );
+                    continue;
+                }
+                let mut ls: LightStateChange = state.clone();
+
+                if !scene_config.preserve_transitiontime || ls.transitiontime.is_none() {
+                    ls.transitiontime = Some(config.pacing.scene_write_transitiontime);
+                }
+                let rotation = ((idx as f64) / (scene.lights.len() as f64)) * PI * 2.;
+                let mut this_light_target = light_target.clone().rotate(rotation);
+                if let Some(scale) = scene_config.light_brightness.get(&light.to_string()) {
+                    this_light_target = this_light_target.scale_brightness(*scale);
+                }
+                if scene_config.warm_dimming > 0. {
+                    this_light_target = this_light_target.warm_dim(scene_config.warm_dimming);
+                }
+                if let Some(flicker) = &scene_config.flicker {
+                    this_light_target = this_light_target.flicker(
+                        *light as u64,
+                        flicker.amplitude,
+                        flicker.cutoff_secs,
+                    );
+                }
+                if scene_config.accessible {
+                    this_light_target =
+                        apply_accessibility(&config.accessibility, &this_light_target);
+                }
+                debug!("Light target for {:?}: {:?}", light, this_light_target);
+                let (bri, ct) = calibrated(
+                    bridge_state,
+                    &config.calibration,
+                    *light,
+                    &this_light_target,
+                );
+                if device.is_none_or(|l| l.is_dimmable()) {
+                    ls.bri = Some(bri);
+                    ls.ct = Some(ct);
+                }
+                let light_quirks =
+                    device.and_then(|l| quirks::for_model(&config.quirks, &l.modelid));
+                if let Some(light_quirks) = light_quirks {
+                    light_quirks.apply(&mut ls, true);
+                }
+                let keep_off = scene_config.off_lights == OffLightPolicy::KeepOff && !light_is_on;
+                if scene_config.power == PowerPolicy::Toggle && !keep_off {
+                    ls.on = Some(switch_on(
+                        bridge_state,
+                        Some(scene_id),
+                        *light,
+                        state.on.unwrap_or(false),
+                        this_light_target.bri(),
+                        scene_config,
+                    ));
+                }
+                if !changed_enough(state, &ls, scene_config) {
+                    debug!(
+                        "Light state for {:?} in scene {} unchanged",
+                        light, scene_id
+                    );
+                    continue;
+                }
+                info!("Light state for {:?} : {:?}", light, ls);
+                commands.push(LightCommand::SetSceneLightState {
+                    scene_id: scene_id.to_owned(),
+                    light_id: *light,
+                    state: ls,
+                });
+            }
+            Err(err) => error!("Could not find light {:?}: {}", light, err),
+        }
+    }
+    commands
+}
+
+/// Translates the rewritten scene states into `bri_inc`/`ct_inc` updates of the live lights.
+/// Returns `None` when any light is too far from its target (or needs switching), in which case
+/// the scene should be recalled instead.
+fn relative_updates(
+    state: &BridgeState,
+    scene_commands: &[LightCommand],
+    scene_config: &SceneConfig,
+) -> Option<Vec<LightCommand>> {
+    let mut commands = Vec::new();
+    for command in scene_commands {
+        if let LightCommand::SetSceneLightState {
+            light_id,
+            state: target,
+            ..
+        } = command
+        {
+            let live = &state.lights.get(light_id)?.state;
+            if target.on.is_some_and(|on| on != live.on) {
+                return None;
+            }
+            let bri_inc = target
+                .bri
+                .map_or(0, |bri| i16::from(bri) - i16::from(live.bri));
+            let ct_inc = match (target.ct, live.ct) {
+                (Some(target_ct), Some(live_ct)) => i32::from(target_ct) - i32::from(live_ct),
+                _ => 0,
+            };
+            if bri_inc.abs() > i16::from(scene_config.relative_max_bri_delta)
+                || ct_inc.abs() > i32::from(scene_config.relative_max_mired_delta)
+            {
+                return None;
+            }
+            if bri_inc == 0 && ct_inc == 0 {
+                continue;
+            }
+            let change = LightStateChange {
+                bri_inc: if bri_inc != 0 { Some(bri_inc) } else { None },
+                ct_inc: if ct_inc != 0 { Some(ct_inc) } else { None },
+                transitiontime: target.transitiontime,
+                ..LightStateChange::default()
+            };
+            commands.push(LightCommand::SetLightState {
+                light_id: *light_id,
+                state: change,
+            });
+        }
+    }
+    Some(commands)
+}
+
+fn groups_for_scene(state: &BridgeState, scene: &Scene) -> Vec<usize> {
+    let mut scene_lights = scene.lights.clone();
+    scene_lights.sort();
+    state
+        .groups
+        .iter()
+        .filter(|&(_, group)| {
+            let mut group_lights = group.lights.clone();
+            group_lights.sort();
+            group_lights == scene_lights
+        })
+        .map(|(group_id, _)| *group_id)
+        .collect()
+}
+
+/// Where to recall an active scene: every group (room or zone) with exactly the scene's
+/// lights, else the group a GroupScene belongs to, else group 0. Group 0 holds every light, and
+/// recalling a scene in it only changes the scene's own lights, so scenes spanning several
+/// rooms are recalled too.
+fn recall_groups(state: &BridgeState, scene: &Scene) -> Vec<usize> {
+    let exact = groups_for_scene(state, scene);
+    if !exact.is_empty() {
+        return exact;
+    }
+    let own_group = scene
+        .group
+        .as_ref()
+        .and_then(|group| group.parse().ok())
+        .filter(|group_id| state.groups.contains_key(group_id));
+    if let Some(group_id) = own_group {
+        return vec![group_id];
+    }
+    if scene.lights.is_empty() {
+        return Vec::new();
+    }
+    debug!(
+        "No group has exactly the lights of scene {}, recalling it in group 0",
+        scene.name
+    );
+    vec![ALL_LIGHTS_GROUP]
+}
+
+/// Groups that contain a sentinel scene, and are therefore opted out of management.
+pub fn disabled_groups(state: &BridgeState, sentinel_scene_name: &str) -> Vec<usize> {
+    let sentinel = sentinel_scene_name.to_lowercase();
+    state
+        .scenes
+        .values()
+        .filter(|scene| scene.name.trim().to_lowercase() == sentinel)
+        .flat_map(|scene| groups_for_scene(state, scene))
+        .collect()
+}
+
+fn in_disabled_group(state: &BridgeState, scene: &Scene, disabled_groups: &[usize]) -> bool {
+    disabled_groups
+        .iter()
+        .filter_map(|group_id| state.groups.get(group_id))
+        .any(|group| {
+            scene
+                .lights
+                .iter()
+                .all(|light| group.lights.contains(light))
+        })
+}
+
+/// Whether the scene is in `excluded_scenes`, by its v1 id.
+pub fn is_excluded(config: &Config, scene_id: &str) -> bool {
+    config.excluded_scenes.iter().any(|id| id == scene_id)
+}
+
+/// Why hue_mie leaves the scene alone under the current config, or `None` when it manages it.
+pub fn unmanaged_reason(
+    state: &BridgeState,
+    config: &Config,
+    scene_id: &str,
+    scene: &Scene,
+) -> Option<&'static str> {
+    if !is_managed(scene) {
+        return Some("not a dayshift scene");
+    }
+    if is_excluded(config, scene_id) {
+        return Some("excluded");
+    }
+    let disabled = disabled_groups(state, &config.sentinel_scene_name);
+    if in_disabled_group(state, scene, &disabled) {
+        return Some("opted out by a sentinel scene");
+    }
+    None
+}
+
+/// Why hue_mie leaves the light alone, or `None` when a managed scene covers it.
+pub fn unmanaged_light_reason(
+    state: &BridgeState,
+    config: &Config,
+    light_id: usize,
+) -> Option<&'static str> {
+    if config.exclude_plugs && state.lights.get(&light_id).is_some_and(|l| l.is_plug()) {
+        return Some("smart plug");
+    }
+    let covered = state.scenes.iter().any(|(scene_id, scene)| {
+        scene.lights.contains(&light_id)
+            && unmanaged_reason(state, config, scene_id, scene).is_none()
+    });
+    if covered {
+        None
+    } else {
+        Some("in no managed scene")
+    }
+}
+
+/// The name of the scene hue_mie maintains for a room in shadow mode.
+pub fn shadow_scene_name(group_name: &str) -> String {
+    format!("{}{}", SHADOW_SCENE_PREFIX, group_name)
+}
+
+fn any_light_off(state: &BridgeState, scene: &Scene) -> bool {
+    scene.lights.iter().any(|light_id| {
+        state
+            .lights
+            .get(light_id)
+            .is_some_and(|light| !light.state.on)
+    })
+}
+
+/// The settings of a scene, looked up by its name and the names of the groups it is recalled in.
+fn scene_config_for(state: &BridgeState, config: &Config, scene: &Scene) -> SceneConfig {
+    let mut names = vec![scene.name.as_str()];
+    names.extend(
+        recall_groups(state, scene)
+            .iter()
+            .filter_map(|group_id| state.groups.get(group_id))
+            .map(|group| group.name.as_str()),
+    );
+    config.scene_config(&names)
+}
+
+/// Splits planned commands into those that matter now and those that can wait an iteration when
+/// the loop falls behind: rewrites of scenes nobody sees (not recalled in this batch, no light
+/// on) and everything for scenes with a negative priority.
+pub fn split_inactive(
+    state: &BridgeState,
+    config: &Config,
+    commands: Vec<LightCommand>,
+) -> (Vec<LightCommand>, Vec<LightCommand>) {
+    let low_priority = |scene_id: &str| {
+        state
+            .scenes
+            .get(scene_id)
+            .is_some_and(|scene| scene_config_for(state, config, scene).priority < 0)
+    };
+    let recalled: BTreeSet<String> = commands
+        .iter()
+        .filter_map(|command| match command {
+            LightCommand::RecallScene { scene_id, .. } => Some(scene_id.clone()),
+            _ => None,
+        })
+        .collect();
+    commands.into_iter().partition(|command| match command {
+        LightCommand::SetSceneLightState { scene_id, .. }
+        | LightCommand::RecallScene { scene_id, .. }
+            if low_priority(scene_id.as_str()) =>
+        {
+            false
+        }
+        LightCommand::SetSceneLightState { scene_id, .. } => {
+            recalled.contains(scene_id)
+                || state.scenes.get(scene_id).is_none_or(|scene| {
+                    scene.lights.iter().any(|light_id| {
+                        state
+                            .lights
+                            .get(light_id)
+                            .is_some_and(|light| light.state.on)
+                    })
+                })
+        }
+        _ => true,
+    })
+}
+
+/// Moves the brightness and colour temperature written to the lights in `from` only
+/// `progress` (0 - 1) of the way from where each light was, by light id as (bri, ct), to what
+/// was planned.
+pub fn fade_in(
+    commands: Vec<LightCommand>,
+    from: &BTreeMap<usize, (u8, Option<u16>)>,
+    progress: f64,
+) -> Vec<LightCommand> {
+    let between = |from: f64, to: f64| (from + (to - from) * progress.clamp(0., 1.)).round();
+    commands
+        .into_iter()
+        .map(|mut command| {
+            match &mut command {
+                LightCommand::SetLightState { light_id, state }
+                | LightCommand::SetSceneLightState {
+                    light_id, state, ..
+                } => {
+                    if let Some((bri, ct)) = from.get(light_id) {
+                        if let Some(to) = state.bri {
+                            state.bri = Some(between(f64::from(*bri), f64::from(to)) as u8);
+                        }
+                        if let (Some(ct), Some(to)) = (ct, state.ct) {
+                            state.ct = Some(between(f64::from(*ct), f64::from(to)) as u16);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            command
+        })
+        .collect()
+}
+
+/// The caps active at `hour`, by light.
+fn capped_lights<'a>(
+    state: &BridgeState,
+    config: &'a Config,
+    hour: u8,
+) -> BTreeMap<usize, &'a RoomCap> {
+    let mut capped = BTreeMap::new();
+    for (room, cap) in config.caps.iter().filter(|(_, cap)| cap.is_active(hour)) {
+        let group = state
+            .groups
+            .values()
+            .find(|group| group.name.to_lowercase() == room.to_lowercase());
+        match group {
+            Some(group) => {
+                for light_id in group.lights.iter() {
+                    capped.entry(*light_id).or_insert(cap);
+                }
+            }
+            None => debug!("No group named {:?} to cap", room),
+        }
+    }
+    capped
+}
+
+/// Whether writing `change` would leave a light with `on`, `bri` and `ct` as it is.
+fn no_change(
+    change: &LightStateChange,
+    on: Option<bool>,
+    bri: Option<u8>,
+    ct: Option<u16>,
+) -> bool {
+    change.on.is_none_or(|value| Some(value) == on)
+        && change.bri.is_none_or(|value| Some(value) == bri)
+        && change.ct.is_none_or(|value| Some(value) == ct)
+        && change.xy.is_none()
+        && change.hue.is_none()
+        && change.sat.is_none()
+        && change.alert.is_none()
+        && change.bri_inc.is_none()
+        && change.ct_inc.is_none()
+}
+
+/// Holds the writes to lights in capped rooms to the caps active at `hour`. Meant as the very
+/// last step before the executor, on everything that is about to be written. Writes the cap
+/// turns into no-ops are dropped, and a scene is only recalled once its stored lightstates for
+/// capped lights are within the cap.
+pub fn apply_caps(
+    state: &BridgeState,
+    config: &Config,
+    hour: u8,
+    commands: Vec<LightCommand>,
+) -> Vec<LightCommand> {
+    let capped = capped_lights(state, config, hour);
+    if capped.is_empty() {
+        return commands;
+    }
+    let mut written: BTreeMap<(String, usize), LightStateChange> = BTreeMap::new();
+    let mut kept = Vec::with_capacity(commands.len());
+    for mut command in commands.into_iter() {
+        let keep = match &mut command {
+            LightCommand::SetLightState {
+                light_id,
+                state: change,
+            } => match capped.get(light_id) {
+                Some(cap) => {
+                    cap.apply(change);
+                    state.lights.get(light_id).is_none_or(|light| {
+                        let current = &light.state;
+                        !no_change(change, Some(current.on), Some(current.bri), current.ct)
+                    })
+                }
+                None => true,
+            },
+            LightCommand::SetSceneLightState {
+                scene_id,
+                light_id,
+                state: change,
+            } => match capped.get(light_id) {
+                Some(cap) => {
+                    cap.apply(change);
+                    written.insert((scene_id.clone(), *light_id), change.clone());
+                    let stored = state
+                        .scenes
+                        .get(scene_id.as_str())
+                        .and_then(|scene| scene.lightstates.get(light_id));
+                    stored.is_none_or(|stored| !no_change(change, stored.on, stored.bri, stored.ct))
+                }
+                None => true,
+            },
+            LightCommand::RecallScene { scene_id, .. } => {
+                let scene_id = scene_id.as_str();
+                state.scenes.get(scene_id).is_none_or(|scene| {
+                    scene
+                        .lights
+                        .iter()
+                        .all(|light_id| match capped.get(light_id) {
+                            Some(cap) => written
+                                .get(&(scene_id.to_owned(), *light_id))
+                                .or_else(|| scene.lightstates.get(light_id))
+                                .is_some_and(|lightstate| cap.allows(lightstate)),
+                            None => true,
+                        })
+                })
+            }
+            LightCommand::SetLightConfig {
+                light_id,
+                config: light_config,
+            } => {
+                let settings = light_config.pointer_mut("/startup/customsettings");
+                if let (Some(cap), Some(settings)) = (capped.get(light_id), settings) {
+                    if let Some(bri) = settings["bri"].as_u64() {
+                        settings["bri"] = json!(bri.min(u64::from(cap.max_bri())));
+                    }
+                    if let Some(ct) = settings["ct"].as_u64() {
+                        settings["ct"] = json!(ct.max(u64::from(cap.min_mired())));
+                    }
+                }
+                true
+            }
+            LightCommand::CreateScene { .. } | LightCommand::SetSceneLights { .. } => true,
+        };
+        if keep {
+            kept.push(command);
+        } else {
+            debug!("Skipping {:?}, capped", command);
+        }
+    }
+    kept
+}
+
+/// Cheap pre-filter for `scene_is_active`: a scene that switches a light on cannot be active
+/// while all of its groups are off.
+fn rooms_off(state: &BridgeState, scene: &Scene, group_ids: &[usize]) -> bool {
+    !group_ids.is_empty()
+        && scene.lightstates.values().any(|ls| ls.on == Some(true))
+        && group_ids
+            .iter()
+            .all(|&group_id| state.any_on(group_id) == Some(false))
+}
+
+/// Whether the planner needs the lightstates of this scene.
+pub fn needs_states(scene: &Scene) -> bool {
+    is_managed(scene) || scene.name.starts_with(SHADOW_SCENE_PREFIX)
+}
+
+/// Rewrites one scene onto the light target and, when the scene is active, brings its lights
+/// along: relatively if configured and possible, by recalling it in `group_ids` otherwise.
+fn plan_target_scene(
+    state: &BridgeState,
+    scene_id: &str,
+    scene: &Scene,
+    scene_config: &SceneConfig,
+    config: &Config,
+    light_target: &LightTarget,
+    group_ids: Vec<usize>,
+) -> Vec<LightCommand> {
+    let scene_active = if rooms_off(state, scene, &group_ids) {
+        debug!(
+            "Skipping the activity check of scene {}: its rooms are off",
+            scene.name
+        );
+        false
+    } else {
+        scene_is_active(state, scene, scene_config)
+    };
+    info!(
+        "Scene {} is {}!",
+        scene.name,
+        if scene_active { "active" } else { "inactive" }
+    );
+
+    let mut commands = plan_scene(state, scene_id, scene, scene_config, config, light_target);
+    let relative = if scene_active && scene_config.strategy == UpdateStrategy::Relative {
+        relative_updates(state, &commands, scene_config)
+    } else {
+        None
+    };
+
+    if let Some(relative) = relative {
+        debug!(
+            "Nudging scene {} with {} relative updates",
+            scene.name,
+            relative.len()
+        );
+        commands.extend(relative);
+    } else if scene_active
+        && scene_config.off_lights == OffLightPolicy::StoreOnly
+        && any_light_off(state, scene)
+    {
+        debug!(
+            "Not recalling scene {}: some of its lights are off",
+            scene.name
+        );
+    } else if scene_active && commands.is_empty() && !drifted(state, scene, scene_config) {
+        // Recalling an unchanged scene makes some bulbs visibly fade again.
+        debug!("Scene {} is unchanged, not recalling it", scene.name);
+    } else if scene_active {
+        for group_id in group_ids {
+            commands.push(LightCommand::RecallScene {
+                scene_id: scene_id.to_owned(),
+                group_id,
+            });
+        }
+    }
+    commands
+}
+
+/// Post-processes the light target of each scene before it is planned, e.g. a user script.
+pub trait TargetAdjuster: Sync {
+    fn adjust(&self, scene_name: &str, light_target: &LightTarget) -> LightTarget;
+}
+
+fn adjusted(
+    adjuster: Option<&dyn TargetAdjuster>,
+    scene: &Scene,
+    light_target: &LightTarget,
+) -> LightTarget {
+    match adjuster {
+        Some(adjuster) => adjuster.adjust(&scene.name, light_target),
+        None => light_target.clone(),
+    }
+}
+
+/// Plans each scene on its own. With the `parallel` feature the scenes are spread over threads
+/// (`benches/planner.rs` compares both); the commands keep the order of `scenes`.
+fn plan_scenes(
+    state: &BridgeState,
+    config: &Config,
+    light_target: &LightTarget,
+    adjuster: Option<&dyn TargetAdjuster>,
+    scenes: &[(&String, &Scene, SceneConfig)],
+) -> Vec<LightCommand> {
+    let plan_one = |(scene_id, scene, scene_config): &(&String, &Scene, SceneConfig)| {
+        debug!("Planning scene {}, scene_id: {}", scene.name, scene_id);
+        let recall_ids = recall_groups(state, scene);
+        plan_target_scene(
+            state,
+            scene_id,
+            scene,
+            scene_config,
+            config,
+            &adjusted(adjuster, scene, light_target),
+            recall_ids,
+        )
+    };
+    #[cfg(feature = "parallel")]
+    let planned: Vec<Vec<LightCommand>> = scenes.par_iter().map(plan_one).collect();
+    #[cfg(not(feature = "parallel"))]
+    let planned: Vec<Vec<LightCommand>> = scenes.iter().map(plan_one).collect();
+    planned.into_iter().flatten().collect()
+}
+
+/// Computes the commands needed to move every managed scene onto the light target, without
+/// touching the bridge. Active scenes are recalled in their groups after being rewritten.
+///
+/// In shadow mode the dayshift scenes only select the rooms: hue_mie writes to its own
+/// "hue_mie <room>" scene instead, creating it when missing.
+pub fn plan(state: &BridgeState, light_target: &LightTarget, config: &Config) -> Vec<LightCommand> {
+    plan_adjusted(state, light_target, config, None)
+}
+
+/// `plan`, with the light target of every scene passed through `adjuster` first.
+pub fn plan_adjusted(
+    state: &BridgeState,
+    light_target: &LightTarget,
+    config: &Config,
+    adjuster: Option<&dyn TargetAdjuster>,
+) -> Vec<LightCommand> {
+    let mut commands = Vec::new();
+    let disabled_groups = disabled_groups(state, &config.sentinel_scene_name);
+    let mut shadowed_groups = BTreeSet::new();
+    let mut scenes = Vec::new();
+    for (scene_id, scene) in state.scenes.iter().filter(|&(_, scene)| is_managed(scene)) {
+        if in_disabled_group(state, scene, &disabled_groups) {
+            info!(
+                "Scene {} is in a room opted out by a sentinel scene",
+                scene.name
+            );
+            continue;
+        }
+        if is_excluded(config, scene_id) {
+            debug!("Scene {} is excluded", scene.name);
+            continue;
+        }
+        scenes.push((scene_id, scene, scene_config_for(state, config, scene)));
+    }
+    // Higher priorities first; the sort is stable, so equal priorities keep the id order.
+    scenes.sort_by_key(|(_, _, scene_config)| std::cmp::Reverse(scene_config.priority));
+    if !config.shadow_scenes {
+        commands.extend(plan_scenes(state, config, light_target, adjuster, &scenes));
+    } else {
+        for (scene_id, scene, scene_config) in scenes {
+            debug!("Planning scene {}, scene_id: {}", scene.name, scene_id);
+            let scene_target = adjusted(adjuster, scene, light_target);
+            for group_id in groups_for_scene(state, scene) {
+                if !shadowed_groups.insert(group_id) {
+                    continue;
+                }
+                let group = &state.groups[&group_id];
+                let shadow_name = shadow_scene_name(&group.name);
+                match state.scenes.iter().find(|&(_, s)| s.name == shadow_name) {
+                    Some((shadow_id, shadow)) => commands.extend(plan_target_scene(
+                        state,
+                        shadow_id,
+                        shadow,
+                        &scene_config,
+                        config,
+                        &scene_target,
+                        vec![group_id],
+                    )),
+                    None => {
+                        info!("Creating shadow scene {}", shadow_name);
+                        commands.push(LightCommand::CreateScene {
+                            name: shadow_name,
+                            lights: group.lights.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    if config.auto_create_scenes {
+        commands.extend(plan_missing_scenes(state, &disabled_groups));
+    }
+    if config.add_new_lights_to_scenes {
+        commands.extend(plan_new_lights(state, config));
+    }
+    commands
+}
+
+/// Adds lights that just appeared on the bridge to the managed scenes of the rooms they were
+/// put in. LightScenes get the light added; GroupScenes already follow their room and only need
+/// a lightstate, copied from another light in the scene until the curve rewrites it.
+fn plan_new_lights(state: &BridgeState, config: &Config) -> Vec<LightCommand> {
+    let mut commands = Vec::new();
+    if state.new_lights().is_empty() {
+        return commands;
+    }
+    let managed = state
+        .scenes
+        .iter()
+        .filter(|&(scene_id, scene)| is_managed(scene) && !is_excluded(config, scene_id));
+    for (scene_id, scene) in managed {
+        let room = state.groups.values().find(|group| {
+            group.group_type == "Room"
+                && scene
+                    .lights
+                    .iter()
+                    .all(|light| group.lights.contains(light))
+        });
+        let room = match room {
+            Some(room) => room,
+            None => continue,
+        };
+        let added: Vec<usize> = room
+            .lights
+            .iter()
+            .filter(|light| state.new_lights().contains(*light) && !scene.lights.contains(light))
+            .cloned()
+            .collect();
+        if added.is_empty() {
+            continue;
+        }
+        info!("Adding new lights {:?} to scene {}", added, scene.name);
+        if scene.scene_type.as_ref().is_some_and(|t| t == "GroupScene") {
+            let template = match scene.lightstates.values().next() {
+                Some(template) => template,
+                None => continue,
+            };
+            commands.extend(
+                added
+                    .into_iter()
+                    .map(|light_id| LightCommand::SetSceneLightState {
+                        scene_id: scene_id.clone(),
+                        light_id,
+                        state: template.clone(),
+                    }),
+            );
+        } else {
+            let mut lights = scene.lights.clone();
+            lights.extend(added);
+            lights.sort();
+            commands.push(LightCommand::SetSceneLights {
+                scene_id: scene_id.clone(),
+                lights,
+            });
+        }
+    }
+    commands
+}
+
+/// Points the power-on state of every managed light at the light target, so lights switched on
+/// at the wall come up at the right brightness and warmth. Only lights that report a `startup`
+/// config are written, and only when the stored setting is off by the configured steps.
+pub fn plan_powerup(
+    state: &BridgeState,
+    config: &Config,
+    powerup: &PowerupConfig,
+    light_target: &LightTarget,
+) -> Vec<LightCommand> {
+    // The wobble would make every comparison fail sooner or later.
+    let light_target = light_target.scale_cycle(0.);
+    let mut commands = Vec::new();
+    for (light_id, light) in state.lights.iter() {
+        let startup = match light.config.as_ref().and_then(|c| c.get("startup")) {
+            Some(startup) => startup,
+            None => continue,
+        };
+        if !light.is_dimmable() || unmanaged_light_reason(state, config, *light_id).is_some() {
+            continue;
+        }
+        let (bri, ct) = calibrated(state, &config.calibration, *light_id, &light_target);
+        let stored = startup.get("customsettings");
+        let stored_bri = stored.and_then(|s| s.get("bri")).and_then(Value::as_u64);
+        let stored_ct = stored.and_then(|s| s.get("ct")).and_then(Value::as_u64);
+        let off_by = |stored: Option<u64>, wanted: u64, min_delta: u64| {
+            stored.is_none_or(|stored| (stored as i64 - wanted as i64).unsigned_abs() >= min_delta)
+        };
+        let has_ct = light.state.ct.is_some();
+        let changed = startup.get("mode").and_then(Value::as_str) != Some("custom")
+            || off_by(stored_bri, u64::from(bri), u64::from(powerup.min_bri_delta))
+            || (has_ct && off_by(stored_ct, u64::from(ct), u64::from(powerup.min_mired_delta)));
+        if !changed {
+            continue;
+        }
+        let mut customsettings = json!({ "bri": bri });
+        if has_ct {
+            customsettings["ct"] = json!(ct);
+        }
+        debug!("Power-on state of light {}: {}", light_id, customsettings);
+        commands.push(LightCommand::SetLightConfig {
+            light_id: *light_id,
+            config: json!({ "startup": { "mode": "custom", "customsettings": customsettings } }),
+        });
+    }
+    commands
+}
+
+/// The groups with a managed scene.
+pub fn managed_groups(state: &BridgeState) -> BTreeSet<usize> {
+    state
+        .scenes
+        .values()
+        .filter(|scene| is_managed(scene))
+        .flat_map(|scene| groups_for_scene(state, scene))
+        .collect()
+}
+
+/// Creates a "<room> dayshift" scene for every room with lights that no managed scene covers,
+/// so new rooms are picked up from the next update on.
+fn plan_missing_scenes(state: &BridgeState, disabled_groups: &[usize]) -> Vec<LightCommand> {
+    let covered = managed_groups(state);
+    state
+        .groups
+        .iter()
+        .filter(|&(group_id, group)| {
+            group.group_type == "Room"
+                && !group.lights.is_empty()
+                && !covered.contains(group_id)
+                && !disabled_groups.contains(group_id)
+        })
+        .map(|(_, group)| {
+            let name = format!("{} dayshift", group.name);
+            info!(
+                "Room {} has no managed scene, creating {}",
+                group.name, name
+            );
+            LightCommand::CreateScene {
+                name,
+                lights: group.lights.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Computes direct light updates for a virtual zone from config.
+pub fn plan_zone(
+    state: &BridgeState,
+    zone_name: &str,
+    zone: &ZoneConfig,
+    calibration: &BTreeMap<String, LightCalibration>,
+    quirks: &BTreeMap<String, Quirks>,
+    transitiontime: u16,
+    light_target: &LightTarget,
+) -> Vec<LightCommand> {
+    let mut commands = Vec::new();
+    for (idx, light_id) in zone.lights.iter().enumerate() {
+        let (live, light_quirks) = match state.lights.get(light_id) {
+            Some(light) if !light.is_dimmable() => {
+                debug!(
+                    "Zone {} light {} cannot be dimmed, skipping it",
+                    zone_name, light_id
+                );
+                continue;
+            }
+            Some(light) => (&light.state, quirks::for_model(quirks, &light.modelid)),
+            None => {
+                error!("Zone {} refers to unknown light {}", zone_name, light_id);
+                continue;
+            }
+        };
+        if zone.policy.power == PowerPolicy::OnlyWhenOn && !live.on {
+            debug!(
+                "Zone {} light {} is off, leaving it alone",
+                zone_name, light_id
+            );
+            continue;
+        }
+        let rotation = ((idx as f64) / (zone.lights.len() as f64)) * PI * 2.;
+        let mut this_light_target = light_target.clone().rotate(rotation);
+        if zone.policy.warm_dimming > 0. {
+            this_light_target = this_light_target.warm_dim(zone.policy.warm_dimming);
+        }
+        let keep_off = zone.policy.off_lights == OffLightPolicy::KeepOff && !live.on;
+        let on = if zone.policy.power == PowerPolicy::Toggle && !keep_off {
+            switch_on(
+                state,
+                None,
+                *light_id,
+                live.on,
+                this_light_target.bri(),
+                &zone.policy,
+            )
+        } else {
+            live.on
+        };
+        if !on && !live.on {
+            // The bridge does not take brightness or colour for a light that stays off.
+            continue;
+        }
+
+        let current = LightStateChange {
+            on: Some(live.on),
+            bri: Some(live.bri),
+            ct: live.ct,
+            ..LightStateChange::default()
+        };
+        let mut wanted = current.clone();
+        wanted.on = Some(on);
+        let (bri, ct) = calibrated(state, calibration, *light_id, &this_light_target);
+        wanted.bri = Some(bri);
+        wanted.ct = live.ct.map(|_| ct);
+        if !changed_enough(&current, &wanted, &zone.policy) {
+            continue;
+        }
+
+        let mut change = LightStateChange {
+            on: if on != live.on { Some(on) } else { None },
+            bri: wanted.bri,
+            ct: wanted.ct,
+            transitiontime: Some(transitiontime),
+            ..LightStateChange::default()
+        };
+        if let Some(light_quirks) = &light_quirks {
+            light_quirks.apply(&mut change, false);
+        }
+        info!("Zone {} light {}: {:?}", zone_name, light_id, change);
+        commands.push(LightCommand::SetLightState {
+            light_id: *light_id,
+            state: change,
+        });
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeBridge};
+    use chrono::prelude::*;
+
+    /// A light target without the wobble, at `bri` (a fraction of full) and `kelvin`.
+    fn target(bri: f64, kelvin: f64) -> LightTarget {
+        let config = testing::config("");
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        LightTarget::at(&noon, &config.transitions, &config.location)
+            .scale_cycle(0.)
+            .with_base(bri, kelvin)
+    }
+
+    /// A living room with two lights and a dayshift scene for them, the lights showing
+    /// `(on, bri, ct)` and the scene storing `stored`.
+    fn living_room(live: (bool, u8, u16), stored: (bool, u8, u16)) -> FakeBridge {
+        let (on, bri, ct) = stored;
+        FakeBridge::new(
+            vec![
+                (1, testing::light(live.0, live.1, live.2)),
+                (2, testing::light(live.0, live.1, live.2)),
+            ],
+            vec![(1, testing::room("Living", &[1, 2]))],
+            vec![(
+                "s1",
+                testing::scene("Living dayshift", &[(1, on, bri, ct), (2, on, bri, ct)]),
+            )],
+        )
+    }
+
+    fn scene_states(commands: &[LightCommand]) -> Vec<(usize, LightStateChange)> {
+        commands
+            .iter()
+            .filter_map(|command| match command {
+                LightCommand::SetSceneLightState {
+                    light_id, state, ..
+                } => Some((*light_id, state.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn recalls(commands: &[LightCommand]) -> Vec<(String, usize)> {
+        commands
+            .iter()
+            .filter_map(|command| match command {
+                LightCommand::RecallScene { scene_id, group_id } => {
+                    Some((scene_id.clone(), *group_id))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rewrites_and_recalls_active_scenes() {
+        let state = living_room((true, 200, 250), (true, 200, 250)).state();
+        let commands = plan(&state, &target(0.5, 2500.), &testing::config(""));
+        let states = scene_states(&commands);
+        assert_eq!(states.len(), 2);
+        for (_, change) in states {
+            assert_eq!(change.on, Some(true));
+            assert_eq!(change.bri, Some(127));
+            assert_eq!(change.ct, Some(400));
+        }
+        assert_eq!(recalls(&commands), vec![("s1".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn rewrites_inactive_scenes_without_recalling_them() {
+        let state = living_room((true, 40, 450), (true, 200, 250)).state();
+        let commands = plan(&state, &target(0.5, 2500.), &testing::config(""));
+        assert_eq!(scene_states(&commands).len(), 2);
+        assert!(recalls(&commands).is_empty());
+    }
+
+    #[test]
+    fn leaves_unmanaged_and_unchanged_scenes_alone() {
+        let bridge = living_room((true, 127, 400), (true, 127, 400));
+        let renamed = testing::scene("Living bright", &[(1, true, 254, 153)]);
+        bridge.scenes.borrow_mut().insert("s2".to_owned(), renamed);
+        let commands = plan(&bridge.state(), &target(0.5, 2500.), &testing::config(""));
+        assert!(commands.is_empty(), "{:?}", commands);
+    }
+
+    #[test]
+    fn scene_name_parameters_shape_the_planned_states() {
+        let bridge = living_room((true, 40, 450), (true, 200, 250));
+        let capped = testing::scene(
+            "Living dayshift [max=20%,ct+=10]",
+            &[(1, true, 200, 250), (2, true, 200, 250)],
+        );
+        bridge.scenes.borrow_mut().insert("s1".to_owned(), capped);
+        let commands = plan(&bridge.state(), &target(0.5, 2500.), &testing::config(""));
+        let states = scene_states(&commands);
+        assert_eq!(states.len(), 2);
+        for (_, change) in states {
+            assert_eq!(change.bri, Some(51));
+            assert_eq!(change.ct, Some(410));
+        }
+    }
+
+    #[test]
+    fn malformed_scene_name_parameters_are_ignored() {
+        let bridge = living_room((true, 40, 450), (true, 200, 250));
+        let broken = testing::scene(
+            "Living dayshift [max=20%",
+            &[(1, true, 200, 250), (2, true, 200, 250)],
+        );
+        bridge.scenes.borrow_mut().insert("s1".to_owned(), broken);
+        let commands = plan(&bridge.state(), &target(0.5, 2500.), &testing::config(""));
+        let states = scene_states(&commands);
+        assert_eq!(states.len(), 2);
+        for (_, change) in states {
+            assert_eq!(change.bri, Some(127));
+            assert_eq!(change.ct, Some(400));
+        }
+    }
+
+    fn zone_commands(zone_toml: &str, live_on: bool) -> Vec<LightCommand> {
+        let config = testing::config(zone_toml);
+        let bridge = FakeBridge::new(vec![(1, testing::light(live_on, 1, 400))], vec![], vec![]);
+        plan_zone(
+            &bridge.state(),
+            "hall",
+            &config.zones["hall"],
+            &config.calibration,
+            &config.quirks,
+            4,
+            &target(0.5, 2500.),
+        )
+    }
+
+    #[test]
+    fn zones_switch_lights_on_as_their_power_policy_allows() {
+        let toggle = zone_commands("[zones.hall]\nlights = [1]", false);
+        match toggle.as_slice() {
+            [LightCommand::SetLightState { light_id: 1, state }] => {
+                assert_eq!(state.on, Some(true));
+                assert_eq!(state.bri, Some(127));
+            }
+            commands => panic!("{:?}", commands),
+        }
+
+        let only_when_on = "[zones.hall]\nlights = [1]\npolicy = { power = \"only_when_on\" }";
+        assert!(zone_commands(only_when_on, false).is_empty());
+        match zone_commands(only_when_on, true).as_slice() {
+            [LightCommand::SetLightState { state, .. }] => assert_eq!(state.on, None),
+            commands => panic!("{:?}", commands),
+        }
+
+        let keep_off = "[zones.hall]\nlights = [1]\npolicy = { off_lights = \"keep_off\" }";
+        assert!(zone_commands(keep_off, false).is_empty());
+    }
+
+    #[test]
+    fn scene_activity_uses_the_tolerances() {
+        let state = living_room((true, 110, 300), (true, 100, 300)).state();
+        let scene = &state.scenes["s1"];
+        let mut scene_config = SceneConfig::default();
+        assert!(scene_is_active(&state, scene, &scene_config));
+        scene_config.active_bri_tolerance = 10;
+        assert!(!scene_is_active(&state, scene, &scene_config));
+        scene_config.active_tolerance_percent = Some(10.);
+        assert!(scene_is_active(&state, scene, &scene_config));
+
+        let off = living_room((false, 100, 300), (true, 100, 300)).state();
+        assert!(!scene_is_active(&off, &off.scenes["s1"], &scene_config));
+    }
+
+    fn planned_on(stored_on: bool, bri: f64, config: &Config) -> Option<bool> {
+        let state = living_room((false, 1, 400), (stored_on, 100, 400)).state();
+        let scene = &state.scenes["s1"];
+        let commands = plan_scene(
+            &state,
+            "s1",
+            scene,
+            &config.scene_defaults,
+            config,
+            &target(bri, 2500.),
+        );
+        scene_states(&commands)[0].1.on
+    }
+
+    #[test]
+    fn switches_lights_with_hysteresis() {
+        let config = testing::config(
+            r#"
+            [scene_defaults]
+            on_threshold = 20
+            off_threshold = 10
+            "#,
+        );
+        // 0.06 and 0.1 of full brightness are 15 and 25, between and above the thresholds.
+        assert_eq!(planned_on(false, 0.06, &config), Some(false));
+        assert_eq!(planned_on(false, 0.1, &config), Some(true));
+        assert_eq!(planned_on(true, 0.06, &config), Some(true));
+        assert_eq!(planned_on(true, 0.02, &config), Some(false));
+    }
+
+    #[test]
+    fn keeps_lights_that_switched_recently() {
+        let config = testing::config(
+            r#"
+            [scene_defaults]
+            min_switch_interval_secs = 600
+            "#,
+        );
+        let mut state = living_room((true, 100, 400), (true, 100, 400)).state();
+        let start = Utc.with_ymd_and_hms(2024, 6, 21, 20, 0, 0).unwrap();
+        state.set_time(start);
+        state.record(&LightCommand::SetSceneLightState {
+            scene_id: "s1".to_owned(),
+            light_id: 1,
+            state: LightStateChange {
+                on: Some(false),
+                ..LightStateChange::default()
+            },
+        });
+        let on_of_light_1 = |state: &BridgeState| {
+            let commands = plan_scene(
+                state,
+                "s1",
+                &state.scenes["s1"],
+                &config.scene_defaults,
+                &config,
+                &target(0.5, 2500.),
+            );
+            scene_states(&commands)
+                .into_iter()
+                .find(|(light_id, _)| *light_id == 1)
+                .and_then(|(_, change)| change.on)
+        };
+        state.set_time(start + chrono::Duration::minutes(5));
+        assert_eq!(on_of_light_1(&state), Some(false));
+        state.set_time(start + chrono::Duration::minutes(10));
+        assert_eq!(on_of_light_1(&state), Some(true));
+    }
+
+    #[test]
+    fn raises_dim_targets_to_the_model_minimum() {
+        let config = testing::config(
+            r#"
+            [calibration.1]
+            gamma = 1.0
+
+            [calibration.LTW001]
+            min_bri = 10
+            "#,
+        );
+        let state = living_room((true, 100, 400), (true, 100, 400)).state();
+        let commands = plan(&state, &target(0.01, 2500.), &config);
+        for (_, change) in scene_states(&commands) {
+            assert_eq!(change.bri, Some(10));
+        }
+    }
+
+    #[test]
+    fn caps_writes_to_capped_rooms() {
+        let config = testing::config(
+            r#"
+            [caps.Living]
+            max_brightness = 0.1
+            max_kelvin = 2000
+            "#,
+        );
+        let state = living_room((true, 25, 500), (true, 25, 500)).state();
+        let commands = plan(&state, &target(0.8, 4000.), &config);
+        assert!(!recalls(&commands).is_empty());
+
+        let daytime = apply_caps(&state, &config, 12, commands.clone());
+        assert_eq!(daytime.len(), commands.len());
+
+        // The capped scene only stores what it already had, so there is nothing to write and
+        // the recall is safe.
+        let evening = apply_caps(&state, &config, 20, commands);
+        assert!(scene_states(&evening).is_empty(), "{:?}", evening);
+        assert_eq!(recalls(&evening), vec![("s1".to_owned(), 1)]);
+
+        let bright = living_room((true, 100, 300), (true, 200, 250)).state();
+        let recall_only = vec![LightCommand::RecallScene {
+            scene_id: "s1".to_owned(),
+            group_id: 1,
+        }];
+        assert!(apply_caps(&bright, &config, 20, recall_only).is_empty());
+        let direct = vec![LightCommand::SetLightState {
+            light_id: 1,
+            state: LightStateChange {
+                bri: Some(200),
+                ct: Some(250),
+                ..LightStateChange::default()
+            },
+        }];
+        match &apply_caps(&bright, &config, 20, direct.clone())[..] {
+            [LightCommand::SetLightState { state, .. }] => {
+                assert_eq!(state.bri, Some(25));
+                assert_eq!(state.ct, Some(500));
+            }
+            other => panic!("{:?}", other),
+        }
+        // A light already at the cap needs no write at all.
+        assert!(apply_caps(&state, &config, 20, direct).is_empty());
+    }
+}
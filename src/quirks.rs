@@ -0,0 +1,91 @@
+//! Known peculiarities of bulb models, mostly third-party ZigBee bulbs paired to a Hue bridge.
+//! The built-in table covers common models; entries under `[quirks]` in the config, keyed by
+//! model id, replace the built-in entry for that model.
+
+use crate::hue::LightStateChange;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Quirks {
+    /// The bulb has no colour temperature; never send `ct`.
+    #[serde(default)]
+    pub no_ct: bool,
+
+    /// Coolest colour temperature (mired) the bulb can show.
+    #[serde(default)]
+    pub ct_min: Option<u16>,
+
+    /// Warmest colour temperature (mired) the bulb can show.
+    #[serde(default)]
+    pub ct_max: Option<u16>,
+
+    /// Transition time (100 ms steps) to use instead of the configured one, for bulbs that fade
+    /// slower or faster than asked.
+    #[serde(default)]
+    pub transitiontime: Option<u16>,
+
+    /// The bulb misbehaves when a scene recall carries a transition time; store scene
+    /// lightstates without one.
+    #[serde(default)]
+    pub ignores_scene_transitiontime: bool,
+}
+
+impl Quirks {
+    /// Adjusts a change for this model. `in_scene` is set for scene lightstates.
+    pub fn apply(self: &Quirks, change: &mut LightStateChange, in_scene: bool) {
+        if self.no_ct {
+            change.ct = None;
+        } else if let Some(ct) = change.ct {
+            let ct = ct.max(self.ct_min.unwrap_or(0));
+            change.ct = Some(ct.min(self.ct_max.unwrap_or(u16::MAX)));
+        }
+        if change.transitiontime.is_some() && self.transitiontime.is_some() {
+            change.transitiontime = self.transitiontime;
+        }
+        if in_scene && self.ignores_scene_transitiontime {
+            change.transitiontime = None;
+        }
+    }
+}
+
+/// IKEA TRADFRI white spectrum bulbs: 2200 K - 4000 K, and they stutter when a scene fades
+/// brightness and colour temperature at once.
+fn tradfri_white_spectrum() -> Quirks {
+    Quirks {
+        ct_min: Some(250),
+        ct_max: Some(454),
+        ignores_scene_transitiontime: true,
+        ..Quirks::default()
+    }
+}
+
+fn white_only() -> Quirks {
+    Quirks {
+        no_ct: true,
+        ..Quirks::default()
+    }
+}
+
+fn builtin(modelid: &str) -> Option<Quirks> {
+    match modelid {
+        // Hue White
+        "LWB004" | "LWB006" | "LWB007" | "LWB010" | "LWB014" | "LWA001" | "LWA004" => {
+            Some(white_only())
+        }
+        "TRADFRI bulb E27 WW 806lm"
+        | "TRADFRI bulb E27 WW clear 250lm"
+        | "TRADFRI bulb GU10 WW 400lm" => Some(white_only()),
+        model if model.starts_with("TRADFRI bulb") && model.contains(" WS ") => {
+            Some(tradfri_white_spectrum())
+        }
+        _ => None,
+    }
+}
+
+/// The quirks of `modelid`: the configured entry if any, else the built-in one.
+pub fn for_model(configured: &BTreeMap<String, Quirks>, modelid: &str) -> Option<Quirks> {
+    configured
+        .get(modelid)
+        .cloned()
+        .or_else(|| builtin(modelid))
+}
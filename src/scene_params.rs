@@ -0,0 +1,203 @@
+//! Parameters encoded in a scene name, so they live on the bridge and travel with the scene,
+//! e.g. "Office dayshift [max=90%,ct+=200K,phase=0.3]". Keep in mind the bridge limits scene
+//! names to 32 characters.
+//!
+//! - `max=90%` / `min=10%`: cap or floor the brightness
+//! - `scale=80%`: scale the brightness, like a trailing "80%" in the name
+//! - `ct+=200K` / `ct-=15`: shift the colour temperature, in kelvin or mired
+//! - `phase=0.3`: shift the brightness and temperature cycles by a fraction of a cycle
+
+use crate::curve::LightTarget;
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SceneParams {
+    pub max: Option<f64>,
+    pub min: Option<f64>,
+    pub scale: Option<f64>,
+    pub kelvin_offset: f64,
+    pub mired_offset: f64,
+    pub phase: f64,
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let (number, divisor) = if value.ends_with('%') {
+        (value.trim_end_matches('%'), 100.)
+    } else {
+        (value, 1.)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .map(|number| number / divisor)
+        .map_err(|_| format!("{:?} is not a number or percentage", value))
+}
+
+/// Parses `value` as kelvin ("200K") or mired ("15") into (kelvin, mired) offsets.
+fn parse_ct(value: &str, sign: f64) -> Result<(f64, f64), String> {
+    let value = value.trim();
+    let number = |text: &str| {
+        text.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("{:?} is not a colour temperature", value))
+    };
+    if value.ends_with('K') || value.ends_with('k') {
+        Ok((sign * number(&value[..value.len() - 1])?, 0.))
+    } else {
+        Ok((0., sign * number(value)?))
+    }
+}
+
+impl SceneParams {
+    /// The parameters in the last `[...]` of `name`, or `None` if it has none.
+    pub fn parse(name: &str) -> Result<Option<SceneParams>, String> {
+        let open = match name.rfind('[') {
+            Some(open) => open,
+            None => return Ok(None),
+        };
+        let close = name[open..]
+            .find(']')
+            .ok_or_else(|| format!("Unclosed parameters in {:?}", name))?;
+        let mut params = SceneParams::default();
+        for item in name[open + 1..open + close].split(',') {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+            if let Some(value) = item.strip_prefix("ct+=") {
+                let (kelvin, mired) = parse_ct(value, 1.)?;
+                params.kelvin_offset += kelvin;
+                params.mired_offset += mired;
+                continue;
+            }
+            if let Some(value) = item.strip_prefix("ct-=") {
+                let (kelvin, mired) = parse_ct(value, -1.)?;
+                params.kelvin_offset += kelvin;
+                params.mired_offset += mired;
+                continue;
+            }
+            let mut parts = item.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("Parameter {:?} has no value", item))?;
+            match key {
+                "max" => params.max = Some(parse_fraction(value)?),
+                "min" => params.min = Some(parse_fraction(value)?),
+                "scale" => params.scale = Some(parse_fraction(value)?),
+                "phase" => params.phase = parse_fraction(value)?,
+                _ => return Err(format!("Unknown scene parameter {:?}", key)),
+            }
+        }
+        Ok(Some(params))
+    }
+
+    pub fn apply(self: &SceneParams, light_target: &LightTarget) -> LightTarget {
+        let mut target = light_target.rotate(self.phase * 2. * PI);
+        if let Some(scale) = self.scale {
+            target = target.scale_brightness(scale);
+        }
+        if let Some(max) = self.max {
+            target = target.limit_brightness(max);
+        }
+        if let Some(min) = self.min {
+            target = target.floor_brightness(min);
+        }
+        target.shift_temperature(self.kelvin_offset, self.mired_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use chrono::prelude::*;
+
+    /// A light target without the wobble, at `bri` (a fraction of full) and `kelvin`.
+    fn target(bri: f64, kelvin: f64) -> LightTarget {
+        let config = testing::config("");
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        LightTarget::at(&noon, &config.transitions, &config.location)
+            .scale_cycle(0.)
+            .with_base(bri, kelvin)
+    }
+
+    #[test]
+    fn names_without_parameters_have_none() {
+        assert_eq!(SceneParams::parse("Office dayshift"), Ok(None));
+        assert_eq!(SceneParams::parse("Office dayshift 80%"), Ok(None));
+        assert_eq!(
+            SceneParams::parse("Office dayshift []"),
+            Ok(Some(SceneParams::default()))
+        );
+    }
+
+    #[test]
+    fn parses_every_parameter() {
+        let params = SceneParams::parse("Office dayshift [max=90%, min=0.1,scale=80%,phase=0.25]")
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.max, Some(0.9));
+        assert_eq!(params.min, Some(0.1));
+        assert_eq!(params.scale, Some(0.8));
+        assert_eq!(params.phase, 0.25);
+        assert_eq!(params.kelvin_offset, 0.);
+        assert_eq!(params.mired_offset, 0.);
+    }
+
+    #[test]
+    fn parses_temperature_shifts_in_kelvin_and_mired() {
+        let params = SceneParams::parse("Desk dayshift [ct+=200K,ct-=15,ct-=50k]")
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.kelvin_offset, 150.);
+        assert_eq!(params.mired_offset, -15.);
+    }
+
+    #[test]
+    fn only_the_last_brackets_hold_parameters() {
+        let params = SceneParams::parse("Hall [max=10%] dayshift [min=5%]")
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.max, None);
+        assert_eq!(params.min, Some(0.05));
+    }
+
+    #[test]
+    fn rejects_malformed_parameters() {
+        assert!(SceneParams::parse("Hall dayshift [max=10%")
+            .unwrap_err()
+            .contains("Unclosed"));
+        assert!(SceneParams::parse("Hall dayshift [max]")
+            .unwrap_err()
+            .contains("has no value"));
+        assert!(SceneParams::parse("Hall dayshift [hue=10]")
+            .unwrap_err()
+            .contains("Unknown scene parameter"));
+        assert!(SceneParams::parse("Hall dayshift [max=lots]").is_err());
+        assert!(SceneParams::parse("Hall dayshift [ct+=warmK]").is_err());
+    }
+
+    #[test]
+    fn applies_brightness_bounds_and_scale() {
+        let base = target(0.5, 2500.);
+        let parse = |name: &str| SceneParams::parse(name).unwrap().unwrap();
+        assert_eq!(base.bri(), 127);
+        assert_eq!(parse("[max=20%]").apply(&base).bri(), 51);
+        assert_eq!(parse("[min=80%]").apply(&base).bri(), 204);
+        assert_eq!(parse("[scale=50%]").apply(&base).bri(), 63);
+        // The scale goes first, so the floor still holds.
+        assert_eq!(parse("[scale=10%,min=20%]").apply(&base).bri(), 51);
+    }
+
+    #[test]
+    fn applies_temperature_shifts() {
+        let base = target(0.5, 2500.);
+        let parse = |name: &str| SceneParams::parse(name).unwrap().unwrap();
+        assert_eq!(base.ct(), 400);
+        assert_eq!(parse("[ct+=10]").apply(&base).ct(), 410);
+        assert_eq!(parse("[ct+=1500K]").apply(&base).ct(), 250);
+        let unchanged = SceneParams::default().apply(&base);
+        assert_eq!((unchanged.bri(), unchanged.ct()), (127, 400));
+    }
+}
@@ -0,0 +1,1668 @@
+use crate::astro_calc::AltitudeCache;
+use crate::audit::Reason;
+use crate::backend::Backend;
+use crate::backup::SceneBackup;
+use crate::bridge::Bridge;
+use crate::bridge_info::{BridgeInfo, Capabilities};
+use crate::calendar::{Calendar, Exception};
+use crate::clock::Clock;
+use crate::config::{BedtimeConfig, Config, Location, PowerupConfig, Transitions};
+use crate::control::{Call, DaemonStatus, Request, Response};
+use crate::curve::{self, LightTarget};
+#[cfg(feature = "desktop")]
+use crate::desktop::{Desktop, Phase};
+use crate::energy::EnergyPrices;
+use crate::executor::Executor;
+#[cfg(feature = "geoclue")]
+use crate::geoclue;
+use crate::health::ErrorBudget;
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::hooks::{self, HookEvent, HookTracker};
+use crate::http;
+use crate::leader::LeaderElection;
+use crate::learning::DawnLearner;
+use crate::nudge::GetUpNudge;
+use crate::overrides::{self, Override, Overrides};
+use crate::planner::{self, LightCommand, TargetAdjuster};
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+use crate::state::{BridgeState, GROUP_REFRESH_INTERVAL};
+use crate::summary::DailySummary;
+use crate::v2::{self, V2Client};
+use chrono::prelude::*;
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Drives the update loop: every interval it refreshes the bridge model, plans against the curve
+/// for the clock's current time and hands the commands to the executor.
+pub struct Scheduler<'a> {
+    config: &'a Config,
+    clock: &'a dyn Clock,
+    backend: &'a dyn Backend,
+    executor: Executor,
+    state: Option<BridgeState>,
+    /// The configured location, or the host's position once GeoClue reported it.
+    location: Location,
+    /// The day the position was last asked from GeoClue.
+    #[cfg(feature = "geoclue")]
+    located: Option<NaiveDate>,
+    altitude_cache: AltitudeCache,
+    calendar: Option<Calendar>,
+    energy: Option<EnergyPrices>,
+    /// The brightness ceiling of peak energy prices in the last tick, if it was a peak hour.
+    energy_ceiling: Option<f64>,
+    hook_tracker: HookTracker,
+    summary: DailySummary,
+    #[cfg(feature = "history")]
+    history: Option<History>,
+    #[cfg(feature = "desktop")]
+    desktop: Option<Desktop>,
+    #[cfg(feature = "scripting")]
+    script: Option<Script>,
+    nudges: BTreeMap<String, GetUpNudge>,
+    backup: Option<SceneBackup>,
+    capabilities: Capabilities,
+    v2_client: Option<V2Client>,
+    leader_election: Option<LeaderElection>,
+    palette_updated: Option<DateTime<Local>>,
+    /// When each scene (by v1 id) was last marked as managed on the bridge.
+    marked_scenes: BTreeMap<String, DateTime<Local>>,
+    /// v2 ids of the configured gradient lights, keyed by v1 id.
+    gradient_lights: Option<BTreeMap<usize, String>>,
+    drift_checked: Option<DateTime<Local>>,
+    /// The day whose sun times were last logged.
+    sun_times_logged: Option<NaiveDate>,
+    /// Since when the bridge could not be reached, if it can't.
+    unreachable_since: Option<DateTime<Local>>,
+    /// Requests from the control socket, answered while waiting for the next tick.
+    control: Option<Receiver<Call>>,
+    /// Paused from the control socket, until the given time or until resumed.
+    paused: Option<Option<DateTime<Local>>>,
+    last_target: Option<LightTarget>,
+    last_tick: Option<DateTime<Local>>,
+    last_commands: usize,
+    /// Rewrite every light in the next tick, as after an outage.
+    resync_requested: bool,
+    error_budget: ErrorBudget,
+    /// Rooms (v1 group ids) running the Hue app's own adaptive lighting, left alone.
+    adaptive_groups: BTreeSet<usize>,
+    adaptive_checked: Option<DateTime<Local>>,
+    /// Lights of the entertainment areas that were streaming in the previous iteration.
+    streaming_lights: BTreeSet<usize>,
+    /// Since when all bedroom lights have been off.
+    bedroom_off_since: Option<DateTime<Local>>,
+    asleep: bool,
+    /// The bedroom stayed dark past the end of deep night.
+    sleeping_in: bool,
+    /// When the first bedroom light came on after sleeping in, while ramping up to the curve.
+    woke_up: Option<DateTime<Local>>,
+    learner: Option<DawnLearner>,
+    /// When the power-on state of each light was last written.
+    powerup_written: BTreeMap<usize, DateTime<Local>>,
+    /// When the first refresh succeeded, and the brightness and colour temperature of the
+    /// lights that were on then, to fade from during the warm-up.
+    started: Option<DateTime<Local>>,
+    warmup_from: BTreeMap<usize, (u8, Option<u16>)>,
+    /// Time between iterations, which may differ between twilight and the rest of the day.
+    interval: Duration,
+    /// The last iteration took longer than the update interval; inactive scenes are only
+    /// updated every other iteration until it catches up.
+    overloaded: bool,
+    /// Whether this iteration skips inactive scenes, alternating while overloaded.
+    defer_inactive: bool,
+    /// How far the host clock runs ahead of the bridge clock.
+    drift: chrono::Duration,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(
+        config: &'a Config,
+        clock: &'a dyn Clock,
+        backend: &'a dyn Backend,
+        mut executor: Executor,
+    ) -> Scheduler<'a> {
+        let interval = config.pacing.update_interval(false);
+        if config.spread_updates {
+            // Leave half the interval for reading the bridge and the other writes.
+            executor.spread_over = Some(interval / 2);
+        }
+        let capabilities = match config.hue.as_ref().map(BridgeInfo::fetch) {
+            Some(Ok(info)) => {
+                info!(
+                    "Bridge {} ({}), API {}, firmware {}",
+                    info.name, info.modelid, info.apiversion, info.swversion
+                );
+                info.capabilities()
+            }
+            Some(Err(err)) => {
+                warn!(
+                    "Could not read bridge config, assuming all features: {}",
+                    err
+                );
+                Capabilities::all()
+            }
+            None => Capabilities::all(),
+        };
+        capabilities.log();
+        if config.powerup.is_some() && !capabilities.startup_settings {
+            warn!("This bridge has no power-on settings (API 1.26), not writing them");
+        }
+        if config.dynamic_palette.is_some() && !capabilities.dynamic_scenes {
+            warn!("This bridge has no dynamic scenes, writing scene lightstates instead");
+        }
+        Scheduler {
+            config,
+            clock,
+            backend,
+            executor,
+            state: None,
+            location: config.location.clone(),
+            #[cfg(feature = "geoclue")]
+            located: None,
+            altitude_cache: config.location.altitude_cache(config.sun_cache_secs),
+            calendar: config.calendar.clone().map(Calendar::new),
+            energy: config.energy.clone().map(EnergyPrices::new),
+            energy_ceiling: None,
+            hook_tracker: HookTracker::default(),
+            summary: DailySummary::default(),
+            #[cfg(feature = "history")]
+            history: config.history.clone().and_then(|history_config| {
+                History::open(history_config)
+                    .map_err(|err| error!("Could not open the history database: {}", err))
+                    .ok()
+            }),
+            #[cfg(feature = "desktop")]
+            desktop: config.desktop.as_ref().map(Desktop::new),
+            #[cfg(feature = "scripting")]
+            script: config.script.as_ref().and_then(|script_config| {
+                Script::load(script_config)
+                    .map_err(|err| error!("Could not load the script, not using it: {}", err))
+                    .ok()
+            }),
+            nudges: BTreeMap::new(),
+            backup: match SceneBackup::load(config.scene_backup_path()) {
+                Ok(backup) => Some(backup),
+                Err(err) => {
+                    error!(
+                        "Could not read scene backup, not backing up scenes: {}",
+                        err
+                    );
+                    None
+                }
+            },
+            v2_client: match &config.hue {
+                Some(_) if !capabilities.v2 => {
+                    if config.dynamic_palette.is_some()
+                        || !config.gradients.is_empty()
+                        || config.mark_managed_scenes
+                    {
+                        warn!("This bridge has no v2 API, palettes, gradients and scene marks are off");
+                    }
+                    None
+                }
+                // Adaptive room detection is on by default, so without TLS it silently stays off
+                // unless a v2 feature was asked for explicitly.
+                Some(_)
+                    if !http::TLS
+                        && config.dynamic_palette.is_none()
+                        && config.gradients.is_empty()
+                        && !config.mark_managed_scenes =>
+                {
+                    debug!(
+                        "Built without TLS, not checking for adaptive lighting from the Hue app"
+                    );
+                    None
+                }
+                Some(hue_config)
+                    if config.dynamic_palette.is_some()
+                        || !config.gradients.is_empty()
+                        || config.mark_managed_scenes
+                        || !config.take_over_adaptive_rooms =>
+                {
+                    match V2Client::new(hue_config) {
+                        Ok(client) => Some(client),
+                        Err(err) => {
+                            error!("Could not set up v2 client, using v1 only: {}", err);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            },
+            leader_election: match (&config.hue, &config.leader_election) {
+                (Some(hue_config), Some(election_config)) => Some(LeaderElection::new(
+                    Box::new(Bridge::new(
+                        hue_config.bridge_ip.clone(),
+                        hue_config.bridge_password.clone(),
+                    )),
+                    election_config,
+                )),
+                _ => None,
+            },
+            capabilities,
+            marked_scenes: BTreeMap::new(),
+            sun_times_logged: None,
+            unreachable_since: None,
+            control: None,
+            paused: None,
+            last_target: None,
+            last_tick: None,
+            last_commands: 0,
+            resync_requested: false,
+            error_budget: ErrorBudget::default(),
+            adaptive_groups: BTreeSet::new(),
+            adaptive_checked: None,
+            streaming_lights: BTreeSet::new(),
+            gradient_lights: None,
+            palette_updated: None,
+            drift_checked: None,
+            bedroom_off_since: None,
+            asleep: false,
+            sleeping_in: false,
+            woke_up: None,
+            learner: config.learning.as_ref().and_then(|learning_config| {
+                DawnLearner::load(learning_config)
+                    .map_err(|err| error!("Could not read the learned dawn point: {}", err))
+                    .ok()
+            }),
+            interval,
+            powerup_written: BTreeMap::new(),
+            started: None,
+            warmup_from: BTreeMap::new(),
+            overloaded: false,
+            defer_inactive: false,
+            drift: chrono::Duration::zero(),
+        }
+    }
+
+    /// Takes requests from the control socket. Waiting on them replaces the clock's sleep, so
+    /// only use this with the system clock.
+    pub fn set_control(self: &mut Scheduler<'a>, control: Receiver<Call>) {
+        self.control = Some(control);
+    }
+
+    /// Compares the bridge clock with ours once per check interval and warns when they drift
+    /// apart.
+    fn check_clock_drift(self: &mut Scheduler<'a>, now: &DateTime<Local>) {
+        let drift_config = &self.config.clock_drift;
+        let due = self.drift_checked.is_none_or(|checked| {
+            *now - checked >= chrono::Duration::seconds(drift_config.check_interval_secs as i64)
+        });
+        let hue_config = match &self.config.hue {
+            Some(hue_config) if due => hue_config,
+            _ => return,
+        };
+        self.drift_checked = Some(*now);
+        let bridge_utc = match BridgeInfo::fetch(hue_config).map(|info| info.utc()) {
+            Ok(Some(bridge_utc)) => bridge_utc,
+            Ok(None) => {
+                warn!("Bridge did not report its time");
+                return;
+            }
+            Err(err) => {
+                error!("Could not read bridge time: {}", err);
+                return;
+            }
+        };
+        self.drift = now.with_timezone(&Utc) - bridge_utc;
+        if self.drift.num_seconds().abs() > drift_config.warn_threshold_secs {
+            warn!(
+                "Bridge clock differs {} seconds from the host clock{}",
+                self.drift.num_seconds(),
+                if drift_config.compensate {
+                    ", following the bridge clock"
+                } else {
+                    ""
+                }
+            );
+        } else {
+            debug!("Bridge clock drift: {} seconds", self.drift.num_seconds());
+        }
+    }
+
+    /// Asks GeoClue for the host's position once a day and moves the sun along when it
+    /// changed.
+    #[cfg(feature = "geoclue")]
+    fn follow_geoclue(self: &mut Scheduler<'a>, now: &DateTime<Local>) {
+        let today = now.naive_local().date();
+        if !self.config.location.geoclue || self.located == Some(today) {
+            return;
+        }
+        self.located = Some(today);
+        match geoclue::locate() {
+            Ok((lat, long)) => {
+                let moved = (lat - self.location.lat).abs() >= 0.01
+                    || (long - self.location.long).abs() >= 0.01;
+                if !moved {
+                    return;
+                }
+                info!("Moved to {}, {}", lat, long);
+                self.location.lat = lat;
+                self.location.long = long;
+                self.altitude_cache = self.location.altitude_cache(self.config.sun_cache_secs);
+                self.sun_times_logged = None;
+            }
+            Err(err) => warn!(
+                "Could not get the position from GeoClue, staying at {}, {}: {}",
+                self.location.lat, self.location.long, err
+            ),
+        }
+    }
+
+    /// Writes the curve as dynamic palettes when the palette interval has passed. Returns false
+    /// if palettes are not in use, so the v1 path should run instead.
+    fn update_palettes(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) -> bool {
+        let (client, palette_config) = match (&self.v2_client, &self.config.dynamic_palette) {
+            (Some(client), Some(palette_config)) if self.capabilities.dynamic_scenes => {
+                (client, palette_config)
+            }
+            _ => return false,
+        };
+        let due = self.palette_updated.is_none_or(|updated| {
+            *now - updated >= chrono::Duration::seconds(palette_config.update_interval_secs as i64)
+        });
+        if !due {
+            return true;
+        }
+        let palette = v2::curve_palette(
+            now,
+            transitions,
+            &self.location,
+            palette_config.points,
+            palette_config.span_minutes,
+        );
+        if self.executor.dry_run {
+            info!("Dry run, not writing palette {}", palette);
+        } else {
+            let config = self.config;
+            let is_managed = |scene: &v2::V2Scene| {
+                planner::is_managed_name(&scene.metadata.name)
+                    && !scene
+                        .v1_id()
+                        .is_some_and(|id| planner::is_excluded(config, id))
+            };
+            match v2::update_palettes(client, &palette, is_managed) {
+                Ok(count) => info!("Updated the palette of {} scenes", count),
+                Err(err) => error!("Could not update dynamic palettes: {}", err),
+            }
+        }
+        self.palette_updated = Some(*now);
+        true
+    }
+
+    /// The solar and deep night events since the previous update.
+    fn hook_events(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) -> Vec<HookEvent> {
+        let sun_altitude = self.altitude_cache.altitude(now.with_timezone(&Utc));
+        let deep_night = transitions.is_deep_night(now.hour() as u8);
+        self.hook_tracker.events(sun_altitude, deep_night)
+    }
+
+    /// Fires the hooks of `events`. Their scene recalls leave overridden lights alone, like the
+    /// curve does.
+    fn fire_hooks(
+        self: &mut Scheduler<'a>,
+        events: &[HookEvent],
+        fresh: &mut BridgeState,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) {
+        if events.is_empty() {
+            return;
+        }
+        let hour = now.hour() as u8;
+        let sun_altitude = self.altitude_cache.altitude(now.with_timezone(&Utc));
+        let context = [
+            (
+                "HUE_MIE_SUN_ALTITUDE",
+                format!("{:.2}", sun_altitude.to_degrees()),
+            ),
+            (
+                "HUE_MIE_DEEP_NIGHT",
+                transitions.is_deep_night(hour).to_string(),
+            ),
+        ];
+        let overridden = self.overridden_lights(fresh, now);
+        for event in events {
+            let recalls = hooks::fire(
+                &self.config.hooks,
+                *event,
+                fresh,
+                &context,
+                self.executor.dry_run,
+            );
+            let recalls = overrides::without_lights(fresh, recalls, &overridden);
+            let recalls = planner::apply_caps(fresh, self.config, hour, recalls);
+            self.executor
+                .apply(self.backend, self.clock, &recalls, fresh, Reason::Hook);
+        }
+    }
+
+    /// Fires hooks while dynamic palettes run the curve on the bridge. The bridge state is only
+    /// fetched when an event occurred.
+    fn fire_palette_hooks(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) {
+        let events = self.hook_events(now, transitions);
+        if events.is_empty() {
+            return;
+        }
+        let refreshed = match self.state.take() {
+            Some(mut cached) => cached
+                .refresh(self.backend, planner::needs_states)
+                .map(|_| cached),
+            None => BridgeState::fetch(self.backend, planner::needs_states),
+        };
+        match refreshed {
+            Ok(mut fresh) => {
+                fresh.set_time(now.with_timezone(&Utc));
+                self.fire_hooks(&events, &mut fresh, now, transitions);
+                self.state = Some(fresh);
+            }
+            Err(err) => error!("Could not fetch the bridge state for hooks: {}", err),
+        }
+    }
+
+    /// The lights held by `hue_mie set` overrides.
+    fn overridden_lights(
+        self: &Scheduler<'a>,
+        fresh: &BridgeState,
+        now: &DateTime<Local>,
+    ) -> BTreeSet<usize> {
+        match Overrides::load(self.config.overrides_path()) {
+            Ok(active) => active.overridden_lights(fresh, now.with_timezone(&Utc)),
+            Err(err) => {
+                error!("Could not read overrides: {}", err);
+                BTreeSet::new()
+            }
+        }
+    }
+
+    /// The transitions in effect, or `None` while a calendar event pauses hue_mie.
+    fn transitions(self: &mut Scheduler<'a>, now: DateTime<Utc>) -> Option<&'a Transitions> {
+        let config = self.config;
+        let exception = self.calendar.as_mut().and_then(|calendar| {
+            calendar.refresh_if_due();
+            calendar.active_exception(now)
+        });
+        match exception {
+            Some(Exception::Pause) => None,
+            Some(Exception::Profile(name)) => match config.profiles.get(&name) {
+                Some(transitions) => {
+                    debug!("Using profile {} from calendar event", name);
+                    Some(transitions)
+                }
+                None => {
+                    error!("Calendar rule refers to unknown profile {}", name);
+                    Some(&config.transitions)
+                }
+            },
+            None => Some(&config.transitions),
+        }
+    }
+
+    /// With bedtime detection configured, returns `transitions` with the deep night window
+    /// replaced by the detected one: from the moment the bedroom lights have been off long enough
+    /// until `deep_night_end_hour`. Uses the bridge state of the previous iteration.
+    fn bedtime_transitions(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) -> Option<Transitions> {
+        let bedtime = self.config.bedtime.as_ref()?;
+        let hour = now.hour() as u8;
+        let end = transitions.deep_night_end_hour;
+        let night = if bedtime.earliest_hour <= end {
+            hour >= bedtime.earliest_hour && hour < end
+        } else {
+            hour >= bedtime.earliest_hour || hour < end
+        };
+        let all_off = self.bedroom_lights_off(bedtime);
+        if self.asleep && !night {
+            if bedtime.quiet_morning.is_some() && all_off {
+                info!("Leaving deep night, keeping the house quiet until the bedroom wakes up");
+                self.sleeping_in = true;
+            } else {
+                info!("Good morning, leaving deep night");
+            }
+            self.asleep = false;
+        }
+        if night {
+            self.sleeping_in = false;
+        }
+        if !self.asleep {
+            self.bedroom_off_since = if all_off {
+                self.bedroom_off_since.or(Some(*now))
+            } else {
+                None
+            };
+            let off_long_enough = self.bedroom_off_since.is_some_and(|since| {
+                *now - since >= chrono::Duration::minutes(bedtime.off_minutes)
+            });
+            if night && off_long_enough {
+                info!("Bedroom lights are off, entering deep night");
+                self.asleep = true;
+            }
+        }
+        let mut adjusted = transitions.clone();
+        if self.asleep {
+            // A window from this hour, so that it holds before midnight as well.
+            adjusted.deep_night_start_hour = hour;
+        } else {
+            adjusted.deep_night_start_hour = 24;
+            adjusted.deep_night_end_hour = 0;
+        }
+        Some(adjusted)
+    }
+
+    /// Whether all lights of the bedroom groups were off in the previous iteration.
+    fn bedroom_lights_off(self: &Scheduler<'a>, bedtime: &BedtimeConfig) -> bool {
+        self.state.as_ref().is_some_and(|state| {
+            state
+                .groups
+                .values()
+                .filter(|group| bedtime.groups.contains(&group.name))
+                .flat_map(|group| group.lights.iter())
+                .all(|light_id| state.lights.get(light_id).is_none_or(|l| !l.state.on))
+        })
+    }
+
+    /// Subdues `target` while sleeping in, and ramps back to it once the bedroom wakes up.
+    fn quiet_morning(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        target: LightTarget,
+    ) -> LightTarget {
+        let config = self.config;
+        let bedtime = match &config.bedtime {
+            Some(bedtime) => bedtime,
+            None => return target,
+        };
+        let quiet = match &bedtime.quiet_morning {
+            Some(quiet) => quiet,
+            None => return target,
+        };
+        if self.sleeping_in && !self.bedroom_lights_off(bedtime) {
+            info!(
+                "Good morning, ramping up to the curve over {} minutes",
+                quiet.ramp_minutes
+            );
+            self.sleeping_in = false;
+            self.woke_up = Some(*now);
+        }
+        let weight = if self.sleeping_in {
+            1.
+        } else if let Some(woke_up) = self.woke_up {
+            let ramp_secs = (quiet.ramp_minutes * 60).max(1) as f64;
+            let ramped = (*now - woke_up).num_seconds() as f64 / ramp_secs;
+            if ramped >= 1. {
+                self.woke_up = None;
+            }
+            (1. - ramped).max(0.)
+        } else {
+            0.
+        };
+        if weight <= 0. {
+            return target;
+        }
+        let subdued = target
+            .limit_brightness(quiet.brightness)
+            .limit_coolness(quiet.temperature);
+        target.blend(&subdued, weight)
+    }
+
+    /// With learning configured, returns `transitions` with the learned dawn point, updating it
+    /// once a day.
+    fn learned_transitions(
+        self: &mut Scheduler<'a>,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) -> Option<Transitions> {
+        let learning_config = self.config.learning.as_ref()?;
+        let learner = self.learner.as_mut()?;
+        learner.update_if_due(
+            learning_config,
+            now.naive_local().date(),
+            transitions.sun_altitude_dawn_point,
+        );
+        let mut adjusted = transitions.clone();
+        adjusted.sun_altitude_dawn_point = learner.dawn_point()?;
+        Some(adjusted)
+    }
+
+    /// Spreads the current target over the zones of each configured gradient light that is on.
+    fn update_gradients(
+        self: &mut Scheduler<'a>,
+        state: &BridgeState,
+        now: &DateTime<Local>,
+        transitions: &Transitions,
+    ) {
+        let client = match &self.v2_client {
+            Some(client) if !self.config.gradients.is_empty() => client,
+            _ => return,
+        };
+        if self.gradient_lights.is_none() {
+            match client.get_lights() {
+                Ok(lights) => {
+                    self.gradient_lights = Some(
+                        lights
+                            .into_iter()
+                            .filter(|light| light.gradient.is_some())
+                            .filter_map(|light| light.v1_id().map(|v1_id| (v1_id, light.id)))
+                            .collect(),
+                    )
+                }
+                Err(err) => {
+                    error!("Could not list v2 lights: {}", err);
+                    return;
+                }
+            }
+        }
+        let gradient_lights = self.gradient_lights.as_ref().unwrap();
+        let targets = curve::targets_at_cached(now, transitions, &mut self.altitude_cache);
+        for (light_id, gradient) in self.config.gradients.iter() {
+            let light_id: usize = match light_id.parse() {
+                Ok(light_id) => light_id,
+                Err(_) => {
+                    error!("Gradient light id {:?} is not a number", light_id);
+                    continue;
+                }
+            };
+            let v2_id = match gradient_lights.get(&light_id) {
+                Some(v2_id) => v2_id,
+                None => {
+                    debug!("Light {} is not a gradient light", light_id);
+                    continue;
+                }
+            };
+            if !state
+                .lights
+                .get(&light_id)
+                .is_some_and(|light| light.state.on)
+            {
+                continue;
+            }
+            let body = v2::gradient_body(targets.kelvin, targets.bri, gradient);
+            if self.executor.dry_run {
+                info!(
+                    "Dry run, not writing gradient {} to light {}",
+                    body, light_id
+                );
+            } else if let Err(err) = client.put_resource(&format!("light/{}", v2_id), &body) {
+                error!("Could not write gradient to light {}: {}", light_id, err);
+            }
+        }
+    }
+
+    /// Marks the scenes written to in this iteration as managed on the bridge, at most once an
+    /// hour per scene.
+    fn mark_scenes(self: &mut Scheduler<'a>, commands: &[LightCommand], now: &DateTime<Local>) {
+        let client = match &self.v2_client {
+            Some(client) if self.config.mark_managed_scenes && !self.executor.dry_run => client,
+            _ => return,
+        };
+        let marked_scenes = &self.marked_scenes;
+        let due: Vec<&String> = commands
+            .iter()
+            .filter_map(|command| match command {
+                LightCommand::SetSceneLightState { scene_id, .. } => Some(scene_id),
+                _ => None,
+            })
+            .filter(|scene_id| {
+                marked_scenes
+                    .get(*scene_id)
+                    .is_none_or(|marked| *now - *marked >= chrono::Duration::hours(1))
+            })
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        let scenes = match client.get_scenes() {
+            Ok(scenes) => scenes,
+            Err(err) => {
+                error!("Could not list v2 scenes: {}", err);
+                return;
+            }
+        };
+        for scene in scenes {
+            let v1_id = match scene.v1_id() {
+                Some(v1_id) if due.iter().any(|due_id| due_id.as_str() == v1_id) => {
+                    v1_id.to_owned()
+                }
+                _ => continue,
+            };
+            match v2::mark_managed(client, &scene, now) {
+                Ok(()) => {
+                    debug!("Marked scene {} as managed", scene.metadata.name);
+                    self.marked_scenes.insert(v1_id, *now);
+                }
+                Err(err) => error!("Could not mark scene {}: {}", scene.metadata.name, err),
+            }
+        }
+    }
+
+    /// The lights in rooms where the Hue app runs adaptive lighting itself, unless the config
+    /// says to take those rooms over. The rooms are checked as often as groups are refreshed.
+    fn adaptive_lights(
+        self: &mut Scheduler<'a>,
+        state: &BridgeState,
+        now: &DateTime<Local>,
+    ) -> BTreeSet<usize> {
+        let client = match &self.v2_client {
+            Some(client) if !self.config.take_over_adaptive_rooms => client,
+            _ => return BTreeSet::new(),
+        };
+        let due = self.adaptive_checked.is_none_or(|checked| {
+            (*now - checked)
+                .to_std()
+                .map_or(true, |elapsed| elapsed >= GROUP_REFRESH_INTERVAL)
+        });
+        let groups = if !due {
+            self.adaptive_groups.clone()
+        } else {
+            self.adaptive_checked = Some(*now);
+            match v2::adaptive_groups(client) {
+                Ok(groups) => groups,
+                Err(err) => {
+                    error!(
+                        "Could not check for adaptive lighting from the Hue app: {}",
+                        err
+                    );
+                    self.adaptive_groups.clone()
+                }
+            }
+        };
+        for group_id in groups.symmetric_difference(&self.adaptive_groups) {
+            let name = state
+                .groups
+                .get(group_id)
+                .map_or("?", |group| group.name.as_str());
+            if groups.contains(group_id) {
+                info!(
+                    "Room {} runs adaptive lighting from the Hue app, leaving it alone",
+                    name
+                );
+            } else {
+                info!(
+                    "Room {} no longer runs adaptive lighting from the Hue app",
+                    name
+                );
+            }
+        }
+        self.adaptive_groups = groups;
+        self.adaptive_groups
+            .iter()
+            .filter_map(|group_id| state.groups.get(group_id))
+            .flat_map(|group| group.lights.iter().cloned())
+            .collect()
+    }
+
+    /// Keeps the original lightstates of every scene about to be rewritten for the first time.
+    fn backup_scenes(self: &mut Scheduler<'a>, state: &BridgeState, commands: &[LightCommand]) {
+        if self.executor.dry_run {
+            return;
+        }
+        if let Some(backup) = self.backup.as_mut() {
+            for command in commands {
+                if let LightCommand::SetSceneLightState { scene_id, .. } = command {
+                    if let Some(scene) = state.scenes.get(scene_id) {
+                        backup.remember(scene_id, scene);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a single iteration and returns the commands that were planned.
+    pub fn tick(self: &mut Scheduler<'a>) -> Vec<LightCommand> {
+        let commands = self.tick_inner();
+        self.last_tick = Some(self.clock.now());
+        self.last_commands = commands.len();
+        commands
+    }
+
+    fn tick_inner(self: &mut Scheduler<'a>) -> Vec<LightCommand> {
+        let mut now = self.clock.now();
+        self.check_clock_drift(&now);
+        if self.config.clock_drift.compensate {
+            now -= self.drift;
+        }
+        #[cfg(feature = "geoclue")]
+        self.follow_geoclue(&now);
+        if self.sun_times_logged != Some(now.naive_local().date()) {
+            self.sun_times_logged = Some(now.naive_local().date());
+            match self.location.describe_sun_times(now.with_timezone(&Utc)) {
+                Ok(sun_times) => info!("Today: {}", sun_times),
+                Err(err) => warn!("Could not compute today's sun times: {}", err),
+            }
+        }
+        if let Some(election) = self.leader_election.as_mut() {
+            if !election.is_leader(now.with_timezone(&Utc)) {
+                // Whatever was cached may be stale by the time this instance takes over.
+                self.state = None;
+                return Vec::new();
+            }
+        }
+        if let Some(until) = self.paused {
+            if until.is_none_or(|until| now < until) {
+                debug!("Paused from the control socket");
+                return Vec::new();
+            }
+            info!("Pause is over");
+            self.paused = None;
+        }
+        if self.error_budget.paused(&now) {
+            debug!("Too many failed writes, still paused");
+            return Vec::new();
+        }
+
+        let transitions = match self.transitions(now.with_timezone(&Utc)) {
+            Some(transitions) => transitions,
+            None => {
+                info!("Paused by calendar event");
+                return Vec::new();
+            }
+        };
+        let bedtime_transitions = self.bedtime_transitions(&now, transitions);
+        let transitions = bedtime_transitions.as_ref().unwrap_or(transitions);
+        let learned_transitions = self.learned_transitions(&now, transitions);
+        let transitions = learned_transitions.as_ref().unwrap_or(transitions);
+        if self.update_palettes(&now, transitions) {
+            self.fire_palette_hooks(&now, transitions);
+            return Vec::new();
+        }
+        self.update_interval(&now, transitions);
+        let mut light_target = LightTarget::at_cached(&now, transitions, &mut self.altitude_cache);
+        #[cfg(feature = "desktop")]
+        {
+            if let Some(desktop) = self.desktop.as_mut() {
+                let sun_altitude = curve::sun_altitude(&now, transitions, &mut self.altitude_cache);
+                desktop.set_phase(Phase::at(transitions, sun_altitude, now.hour() as u8));
+            }
+        }
+        if let Some(energy) = self.energy.as_mut() {
+            energy.refresh_if_due();
+            let ceiling = energy.brightness_ceiling(&now, transitions.day_brightness);
+            match ceiling {
+                Some(ceiling) if self.energy_ceiling.is_none() => {
+                    info!("Peak energy price, limiting brightness to {:.2}", ceiling)
+                }
+                Some(ceiling) => debug!("Peak energy price, brightness at most {:.2}", ceiling),
+                None if self.energy_ceiling.is_some() => {
+                    info!("Energy price off peak, lifting the brightness limit")
+                }
+                None => {}
+            }
+            if let Some(ceiling) = ceiling {
+                light_target = light_target.clamp_brightness(0., ceiling);
+            }
+            self.energy_ceiling = ceiling;
+        }
+        light_target = self.quiet_morning(&now, light_target);
+        light_target = self.config.clamp_target(now.hour() as u8, light_target);
+        debug!("target: {:?}", light_target);
+        self.last_target = Some(light_target.clone());
+
+        if let Some(summary_config) = &self.config.summary {
+            self.summary.report_if_due(
+                summary_config,
+                &now,
+                self.executor.dry_run,
+                &mut self.executor.health,
+            );
+        }
+
+        // A failed refresh drops the cache, so the next iteration starts from a full fetch.
+        let was_on = self.state.as_ref().map(BridgeState::lights_on);
+        let refresh_started = Instant::now();
+        let refreshed = match self.state.take() {
+            Some(mut cached) => cached
+                .refresh(self.backend, planner::needs_states)
+                .map(|_| cached),
+            None => BridgeState::fetch(self.backend, planner::needs_states),
+        };
+        match refreshed {
+            Ok(mut fresh) => {
+                fresh.set_time(now.with_timezone(&Utc));
+                self.summary.record_latency(refresh_started.elapsed());
+                self.executor.health.observe(&fresh);
+                if let (Some(learner), Some(was_on)) = (self.learner.as_mut(), &was_on) {
+                    let sun_altitude =
+                        curve::sun_altitude(&now, transitions, &mut self.altitude_cache);
+                    learner.observe(was_on, &fresh, sun_altitude.to_degrees());
+                }
+                #[cfg(feature = "history")]
+                {
+                    if let Some(history) = self.history.as_mut() {
+                        let recorded =
+                            history.record_if_due(now.with_timezone(&Utc), &light_target, &fresh);
+                        if let Err(err) = recorded {
+                            error!("Could not record history: {}", err);
+                        }
+                    }
+                }
+                let recovered = self.unreachable_since.take();
+                if let Some(since) = recovered {
+                    info!(
+                        "Bridge reachable again after {} minutes, resyncing all lights",
+                        (now - since).num_minutes()
+                    );
+                    self.notify(
+                        "Bridge reachable again",
+                        "Bringing the lights back to the curve",
+                    );
+                }
+                let resync = recovered.is_some() || self.resync_requested;
+                self.resync_requested = false;
+                let resync_config;
+                let config = if resync {
+                    self.executor.health.forgive();
+                    resync_config = self.config.for_resync();
+                    &resync_config
+                } else {
+                    self.config
+                };
+                #[cfg(feature = "scripting")]
+                let script_tick = match &self.script {
+                    Some(script) => {
+                        // The altitude the curve follows, so scripts agree with the target.
+                        let sun_altitude =
+                            curve::sun_altitude(&now, transitions, &mut self.altitude_cache);
+                        Some(script.at(sun_altitude, &now))
+                    }
+                    None => None,
+                };
+                #[cfg(feature = "scripting")]
+                let adjuster = script_tick.as_ref().map(|tick| tick as &dyn TargetAdjuster);
+                #[cfg(not(feature = "scripting"))]
+                let adjuster: Option<&dyn TargetAdjuster> = None;
+                let mut commands = planner::plan_adjusted(&fresh, &light_target, config, adjuster);
+                if let Some(powerup) = &config.powerup {
+                    let powerup_commands =
+                        self.plan_powerup(&fresh, config, powerup, &now, &light_target);
+                    commands.extend(powerup_commands);
+                }
+                if self.overloaded {
+                    let (active, inactive) = planner::split_inactive(&fresh, config, commands);
+                    commands = active;
+                    self.defer_inactive = !self.defer_inactive;
+                    if self.defer_inactive {
+                        debug!(
+                            "Behind schedule, deferring {} inactive updates",
+                            inactive.len()
+                        );
+                    } else {
+                        commands.extend(inactive);
+                    }
+                }
+                for (zone_name, zone) in config.zones.iter() {
+                    let zone_target = match &zone.transitions {
+                        Some(zone_transitions) => self.config.clamp_target(
+                            now.hour() as u8,
+                            LightTarget::at_cached(
+                                &now,
+                                zone_transitions,
+                                &mut self.altitude_cache,
+                            ),
+                        ),
+                        None => light_target.clone(),
+                    };
+                    commands.extend(planner::plan_zone(
+                        &fresh,
+                        zone_name,
+                        zone,
+                        &self.config.calibration,
+                        &self.config.quirks,
+                        self.config.pacing.scene_write_transitiontime,
+                        &zone_target,
+                    ));
+                }
+                let hour = now.hour() as u8;
+                let events = self.hook_events(&now, transitions);
+                self.fire_hooks(&events, &mut fresh, &now, transitions);
+                let overridden = self.overridden_lights(&fresh, &now);
+                commands = overrides::without_lights(&fresh, commands, &overridden);
+                let adaptive = self.adaptive_lights(&fresh, &now);
+                commands = overrides::without_lights(&fresh, commands, &adaptive);
+                let streaming = fresh.streaming_lights();
+                if streaming != self.streaming_lights {
+                    if streaming.is_empty() {
+                        info!("Entertainment streaming ended, taking the lights back");
+                    } else {
+                        info!(
+                            "Entertainment streaming to lights {:?}, leaving them alone",
+                            streaming
+                        );
+                    }
+                }
+                commands = overrides::without_lights(&fresh, commands, &streaming);
+                self.streaming_lights = streaming;
+                let mut nudge_commands = Vec::new();
+                for (group_name, nudge_config) in self.config.get_up_nudges.iter() {
+                    let nudge = self.nudges.entry(group_name.clone()).or_default();
+                    nudge_commands.extend(nudge.plan(
+                        group_name,
+                        nudge_config,
+                        &fresh,
+                        &now,
+                        &light_target,
+                    ));
+                }
+                let nudged_lights = nudge_commands
+                    .iter()
+                    .filter_map(|command| match command {
+                        LightCommand::SetLightState { light_id, .. } => Some(*light_id),
+                        _ => None,
+                    })
+                    .collect();
+                commands = overrides::without_lights(&fresh, commands, &nudged_lights);
+                commands = self.warm_up(&fresh, &now, commands);
+                let nudge_commands = planner::apply_caps(&fresh, self.config, hour, nudge_commands);
+                self.executor.apply(
+                    self.backend,
+                    self.clock,
+                    &nudge_commands,
+                    &mut fresh,
+                    Reason::Wakeup,
+                );
+                commands = planner::apply_caps(&fresh, self.config, hour, commands);
+                debug!("Planned {} commands", commands.len());
+                self.backup_scenes(&fresh, &commands);
+                let failures = self.executor.apply(
+                    self.backend,
+                    self.clock,
+                    &commands,
+                    &mut fresh,
+                    Reason::Curve,
+                );
+                self.summary.record_commands(&commands, failures);
+                let writes = if self.executor.dry_run {
+                    0
+                } else {
+                    commands.len()
+                };
+                let exceeded =
+                    self.error_budget
+                        .record(&self.config.error_budget, &now, writes, failures);
+                if exceeded {
+                    self.notify("Too many failed writes", "Pausing writes to the bridge");
+                    let recalls = hooks::fire(
+                        &self.config.hooks,
+                        HookEvent::ErrorBudgetExceeded,
+                        &fresh,
+                        &[("HUE_MIE_FAILURES", failures.to_string())],
+                        self.executor.dry_run,
+                    );
+                    let recalls = planner::apply_caps(&fresh, self.config, hour, recalls);
+                    self.executor.apply(
+                        self.backend,
+                        self.clock,
+                        &recalls,
+                        &mut fresh,
+                        Reason::Hook,
+                    );
+                }
+                self.update_gradients(&fresh, &now, transitions);
+                self.mark_scenes(&commands, &now);
+                self.state = Some(fresh);
+                commands
+            }
+            Err(err) => {
+                error!("Error: {}", err);
+                if self.unreachable_since.is_none() {
+                    self.unreachable_since = Some(now);
+                    self.notify("Bridge unreachable", &err.to_string());
+                }
+                self.summary.record_error();
+                Vec::new()
+            }
+        }
+    }
+
+    /// Ticks once per update interval, forever.
+    pub fn run(self: &mut Scheduler<'a>) {
+        loop {
+            self.tick_and_sleep();
+        }
+    }
+
+    /// Ticks once per update interval until the clock passes `end`.
+    pub fn run_until(self: &mut Scheduler<'a>, end: DateTime<Local>) {
+        while self.clock.now() < end {
+            self.tick_and_sleep();
+        }
+    }
+
+    fn tick_and_sleep(self: &mut Scheduler<'a>) {
+        let started = self.clock.now();
+        self.tick();
+        let elapsed = (self.clock.now() - started)
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        if elapsed > self.interval && !self.overloaded {
+            warn!(
+                "Iteration took {:.1}s, longer than the {}s interval; updating active scenes first \
+                 and inactive scenes every other iteration",
+                elapsed.as_secs_f64(),
+                self.interval.as_secs()
+            );
+            self.overloaded = true;
+        } else if elapsed < self.interval / 2 && self.overloaded {
+            info!(
+                "Iteration took {:.1}s, back on schedule",
+                elapsed.as_secs_f64()
+            );
+            self.overloaded = false;
+        }
+        self.wait(self.interval.checked_sub(elapsed).unwrap_or_default());
+    }
+
+    /// The power-on writes that are due, at most one per light per `min_interval_secs`. The
+    /// write time is recorded when planning, so a failing light is not retried every tick.
+    fn plan_powerup(
+        self: &mut Scheduler<'a>,
+        state: &BridgeState,
+        config: &Config,
+        powerup: &PowerupConfig,
+        now: &DateTime<Local>,
+        light_target: &LightTarget,
+    ) -> Vec<LightCommand> {
+        if !self.capabilities.startup_settings {
+            return Vec::new();
+        }
+        let min_interval = chrono::Duration::seconds(powerup.min_interval_secs as i64);
+        let written = &mut self.powerup_written;
+        planner::plan_powerup(state, config, powerup, light_target)
+            .into_iter()
+            .filter(|command| match command {
+                LightCommand::SetLightConfig { light_id, .. } => {
+                    let due = written
+                        .get(light_id)
+                        .is_none_or(|last| *now - *last >= min_interval);
+                    if due {
+                        written.insert(*light_id, *now);
+                    }
+                    due
+                }
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Eases the lights that were on at start from their state then to the curve over the
+    /// warm-up period.
+    fn warm_up(
+        self: &mut Scheduler<'a>,
+        state: &BridgeState,
+        now: &DateTime<Local>,
+        commands: Vec<LightCommand>,
+    ) -> Vec<LightCommand> {
+        let warmup_secs = self.config.pacing.warmup_secs;
+        let started = match self.started {
+            Some(started) => started,
+            None => {
+                self.started = Some(*now);
+                if warmup_secs > 0 {
+                    self.warmup_from = state
+                        .lights
+                        .iter()
+                        .filter(|(_, light)| light.state.on && light.state.reachable)
+                        .map(|(light_id, light)| (*light_id, (light.state.bri, light.state.ct)))
+                        .collect();
+                    info!(
+                        "Warming up {} lights over {} seconds",
+                        self.warmup_from.len(),
+                        warmup_secs
+                    );
+                }
+                *now
+            }
+        };
+        if self.warmup_from.is_empty() {
+            return commands;
+        }
+        let progress = (*now - started).num_seconds() as f64 / warmup_secs as f64;
+        if progress >= 1. {
+            info!("Warm-up done");
+            self.warmup_from.clear();
+            return commands;
+        }
+        planner::fade_in(commands, &self.warmup_from, progress)
+    }
+
+    /// Shows a desktop notification when configured under `[desktop]`.
+    fn notify(self: &Scheduler<'a>, summary: &str, body: &str) {
+        #[cfg(feature = "desktop")]
+        {
+            if let Some(desktop) = &self.desktop {
+                desktop.notify(summary, body);
+            }
+        }
+        #[cfg(not(feature = "desktop"))]
+        {
+            let _ = (summary, body);
+        }
+    }
+
+    /// Picks the twilight or steady interval for the current sun position.
+    fn update_interval(self: &mut Scheduler<'a>, now: &DateTime<Local>, transitions: &Transitions) {
+        let sun_altitude = curve::sun_altitude(now, transitions, &mut self.altitude_cache);
+        let interval = self
+            .config
+            .pacing
+            .update_interval(curve::in_transition(transitions, sun_altitude));
+        if interval != self.interval {
+            info!("Updating every {} seconds", interval.as_secs());
+            self.interval = interval;
+            if self.config.spread_updates {
+                self.executor.spread_over = Some(interval / 2);
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, answering control requests meanwhile. Requests that need a tick
+    /// end the wait early.
+    fn wait(self: &mut Scheduler<'a>, duration: Duration) {
+        let control = match self.control.take() {
+            Some(control) => control,
+            None => return self.clock.sleep(duration),
+        };
+        let deadline = Instant::now() + duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match control.recv_timeout(remaining) {
+                Ok(call) => {
+                    if self.answer(call) {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.clock.sleep(remaining);
+                    return;
+                }
+            }
+        }
+        self.control = Some(control);
+    }
+
+    /// Handles one control request. Returns whether the next tick should run right away.
+    fn answer(self: &mut Scheduler<'a>, call: Call) -> bool {
+        let now = self.clock.now();
+        let (response, tick_now) = match &call.request {
+            Request::Status => {
+                let status = DaemonStatus {
+                    paused: self.paused.is_some(),
+                    paused_until: self.paused.flatten(),
+                    last_tick: self.last_tick,
+                    bri: self.last_target.as_ref().map(LightTarget::bri),
+                    ct: self.last_target.as_ref().map(LightTarget::ct),
+                    commands: self.last_commands,
+                    energy_ceiling: self.energy_ceiling,
+                };
+                let response = Response {
+                    error: None,
+                    status: Some(status),
+                };
+                (response, false)
+            }
+            Request::Pause { minutes } => {
+                let until = minutes.map(|minutes| now + chrono::Duration::minutes(minutes));
+                let paused = match until {
+                    Some(until) => format!("Paused until {}", until.format("%H:%M")),
+                    None => "Paused until resumed".to_owned(),
+                };
+                info!("{}", paused);
+                self.notify("hue_mie paused", &paused);
+                self.paused = Some(until);
+                (Response::ok(), false)
+            }
+            Request::Resume => {
+                info!("Resumed");
+                self.notify("hue_mie resumed", "Following the curve again");
+                self.paused = None;
+                (Response::ok(), true)
+            }
+            Request::Set(new) => match self.apply_override(new) {
+                Ok(()) => (Response::ok(), false),
+                Err(err) => (Response::error(&err), false),
+            },
+            Request::Resync => {
+                info!("Resyncing all lights on request");
+                // Scenes may have been edited and bulbs power-cycled: read everything again.
+                self.state = None;
+                self.resync_requested = true;
+                (Response::ok(), true)
+            }
+        };
+        call.answer(response);
+        tick_now
+    }
+
+    /// Puts a group in the overridden state and stores the override, as `hue_mie set` does.
+    fn apply_override(self: &mut Scheduler<'a>, new: &Override) -> Result<(), Box<dyn Error>> {
+        let mut state = match self.state.take() {
+            Some(state) => state,
+            None => BridgeState::fetch(self.backend, planner::needs_states)?,
+        };
+        let now = self.clock.now();
+        state.set_time(now.with_timezone(&Utc));
+        let hour = now.hour() as u8;
+        let applied = new.commands(&state).map(|commands| {
+            let commands = planner::apply_caps(&state, self.config, hour, commands);
+            self.executor.apply(
+                self.backend,
+                self.clock,
+                &commands,
+                &mut state,
+                Reason::Override,
+            )
+        });
+        self.state = Some(state);
+        let failures = applied?;
+        if failures > 0 {
+            return Err(format!("{} lights could not be set", failures).into());
+        }
+        if !self.executor.dry_run {
+            Overrides::load(self.config.overrides_path())?.add(new.clone())?;
+        }
+        info!(
+            "Set {} until {}",
+            new.group,
+            new.until.with_timezone(&Local)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use crate::testing::{self, FakeBridge};
+    use std::path::PathBuf;
+
+    /// A config for Amsterdam keeping its files in a directory of its own under the temp dir.
+    fn config(name: &str, extra: &str) -> (Config, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("hue_mie-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = testing::config(&format!(
+            r#"
+            scene_backup_path = "{backup}"
+            overrides_path = "{overrides}"
+            {extra}
+
+            [location]
+            lat = 52.37
+            long = 4.9
+            "#,
+            backup = dir.join("backup.json").display(),
+            overrides = dir.join("overrides.json").display(),
+            extra = extra,
+        ));
+        (config, dir)
+    }
+
+    fn living_room() -> FakeBridge {
+        FakeBridge::new(
+            vec![
+                (1, testing::light(true, 100, 366)),
+                (2, testing::light(true, 100, 366)),
+            ],
+            vec![(1, testing::room("Living", &[1, 2]))],
+            vec![(
+                "s1",
+                testing::scene(
+                    "Living dayshift",
+                    &[(1, true, 100, 366), (2, true, 100, 366)],
+                ),
+            )],
+        )
+    }
+
+    fn at_utc(hour: u32) -> DateTime<Local> {
+        Utc.with_ymd_and_hms(2024, 6, 21, hour, 0, 0)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn follows_the_curve_through_a_simulated_day() {
+        let (config, dir) = config("day", "");
+        let bridge = living_room();
+        let clock = FakeClock::new(at_utc(0));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        let interval = chrono::Duration::from_std(config.pacing.update_interval(false)).unwrap();
+        let mut samples = Vec::new();
+        for hour in [1, 12, 23].iter() {
+            scheduler.run_until(at_utc(*hour));
+            assert!(clock.now() >= at_utc(*hour) && clock.now() < at_utc(*hour) + interval);
+            // The scene is active all day, so every rewrite is recalled onto the lights.
+            let scene = bridge.get_scene_with_states("s1").unwrap();
+            for (light_id, light) in bridge.lights.borrow().iter() {
+                let stored = &scene.lightstates[light_id];
+                assert_eq!(stored.bri, Some(light.state.bri));
+                assert_eq!(stored.ct, light.state.ct);
+            }
+            samples.push(bridge.lights.borrow()[&1].state.clone());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+        let (night, noon, evening) = (&samples[0], &samples[1], &samples[2]);
+        assert!(noon.bri > night.bri, "{:?}", samples);
+        assert!(noon.ct < night.ct && noon.ct < evening.ct, "{:?}", samples);
+    }
+
+    fn at_local(hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 6, 21, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    /// The transitions with the deep night window bedtime detection makes of `extra`, once the
+    /// bedroom lights have been off from `hour` on for three quarters of an hour.
+    fn detected_bedtime(name: &str, extra: &str, hour: u32) -> Transitions {
+        let (config, dir) = config(name, extra);
+        let bridge = FakeBridge::new(
+            vec![
+                (1, testing::light(false, 100, 366)),
+                (2, testing::light(false, 100, 366)),
+            ],
+            vec![(1, testing::room("Bedroom", &[1, 2]))],
+            vec![],
+        );
+        let clock = FakeClock::new(at_local(hour, 0));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        scheduler.state = Some(bridge.state());
+        let transitions = &config.transitions;
+        scheduler.bedtime_transitions(&at_local(hour, 0), transitions);
+        let detected = scheduler
+            .bedtime_transitions(&at_local(hour, 45), transitions)
+            .unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        detected
+    }
+
+    #[test]
+    fn enters_deep_night_at_bedtime_before_midnight() {
+        let bedtime = r#"
+            [bedtime]
+            groups = ["Bedroom"]
+            "#;
+        for hour in [22, 23].iter() {
+            let detected = detected_bedtime("bedtime", bedtime, *hour);
+            assert!(detected.is_deep_night(*hour as u8));
+            assert!(detected.is_deep_night(3));
+            assert!(!detected.is_deep_night(6));
+        }
+        let until_midnight = detected_bedtime(
+            "bedtime-midnight",
+            &format!("{}\n[transitions]\ndeep_night_end_hour = 0", bedtime),
+            22,
+        );
+        assert!(until_midnight.is_deep_night(22) && until_midnight.is_deep_night(23));
+        assert!(!until_midnight.is_deep_night(0));
+    }
+
+    #[test]
+    fn enters_deep_night_at_bedtime_during_the_day() {
+        let detected = detected_bedtime(
+            "bedtime-day",
+            r#"
+            [bedtime]
+            groups = ["Bedroom"]
+            earliest_hour = 8
+
+            [transitions]
+            deep_night_end_hour = 16
+            "#,
+            9,
+        );
+        assert!(detected.is_deep_night(9) && detected.is_deep_night(15));
+        assert!(!detected.is_deep_night(16) && !detected.is_deep_night(3));
+
+        // Lights off in the evening are not a day sleeper going to bed.
+        let evening = detected_bedtime(
+            "bedtime-evening",
+            r#"
+            [bedtime]
+            groups = ["Bedroom"]
+            earliest_hour = 8
+
+            [transitions]
+            deep_night_end_hour = 16
+            "#,
+            20,
+        );
+        assert!(!evening.is_deep_night(20));
+    }
+
+    #[test]
+    fn deep_night_windows_wrap_around_midnight_or_stay_within_the_day() {
+        let mut transitions = Transitions::default();
+        transitions.deep_night_start_hour = 23;
+        transitions.deep_night_end_hour = 6;
+        assert!(transitions.is_deep_night(23) && transitions.is_deep_night(0));
+        assert!(!transitions.is_deep_night(6) && !transitions.is_deep_night(12));
+
+        transitions.deep_night_start_hour = 9;
+        transitions.deep_night_end_hour = 16;
+        assert!(transitions.is_deep_night(9) && transitions.is_deep_night(15));
+        assert!(!transitions.is_deep_night(16) && !transitions.is_deep_night(0));
+
+        // Equal hours keep their old meaning: deep night all day.
+        transitions.deep_night_end_hour = 9;
+        assert!((0..24).all(|hour| transitions.is_deep_night(hour)));
+    }
+
+    /// An iCalendar event on the test day, from `start` to `end` o'clock UTC.
+    fn event(summary: &str, start: u32, end: u32) -> String {
+        format!(
+            "BEGIN:VEVENT\r\nSUMMARY:{}\r\nDTSTART:20240621T{:02}0000Z\r\nDTEND:20240621T{:02}0000Z\r\nEND:VEVENT\r\n",
+            summary, start, end
+        )
+    }
+
+    /// A config reading `events` from a calendar in which "away" pauses and "guests" switches to
+    /// the guests profile.
+    fn calendar_config(name: &str, events: &[String]) -> (Config, PathBuf) {
+        let source = std::env::temp_dir()
+            .join(format!("hue_mie-{}-{}", name, std::process::id()))
+            .join("calendar.ics");
+        let (config, dir) = config(
+            name,
+            &format!(
+                r#"
+                [calendar]
+                source = "{}"
+                rules = [{{ keyword = "away" }}, {{ keyword = "guests", profile = "guests" }}]
+
+                [profiles.guests]
+                day_brightness = 0.4
+                "#,
+                source.display()
+            ),
+        );
+        std::fs::write(
+            &source,
+            format!("BEGIN:VCALENDAR\r\n{}END:VCALENDAR\r\n", events.concat()),
+        )
+        .unwrap();
+        (config, dir)
+    }
+
+    #[test]
+    fn pauses_while_a_calendar_event_runs() {
+        let (config, dir) = calendar_config("calendar-active", &[event("Away for lunch", 11, 13)]);
+        let bridge = living_room();
+        let clock = FakeClock::new(at_utc(11));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        scheduler.run_until(at_utc(13));
+        assert!(bridge.writes.borrow().is_empty());
+        assert!(!scheduler.tick().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_calendar_events_that_have_ended() {
+        let (config, dir) = calendar_config("calendar-expired", &[event("Away", 8, 10)]);
+        let bridge = living_room();
+        let clock = FakeClock::new(at_utc(12));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        let transitions = scheduler.transitions(at_utc(12).with_timezone(&Utc));
+        assert!(transitions.is_some_and(|t| std::ptr::eq(t, &config.transitions)));
+        assert!(!scheduler.tick().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn overlapping_calendar_events_follow_the_first_one_listed() {
+        let (config, dir) = calendar_config(
+            "calendar-overlap",
+            &[event("Guests", 12, 14), event("Away", 11, 15)],
+        );
+        let bridge = living_room();
+        let clock = FakeClock::new(at_utc(11));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        let guests = &config.profiles["guests"];
+        let mut at = |hour| scheduler.transitions(at_utc(hour).with_timezone(&Utc));
+        assert!(at(11).is_none());
+        assert!(at(12).is_some_and(|t| std::ptr::eq(t, guests)));
+        assert!(at(13).is_some_and(|t| std::ptr::eq(t, guests)));
+        assert!(at(14).is_none());
+        assert!(at(15).is_some_and(|t| std::ptr::eq(t, &config.transitions)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn plans_nothing_while_paused() {
+        let (config, dir) = config("paused", "");
+        let bridge = living_room();
+        let clock = FakeClock::new(at_utc(12));
+        let executor = Executor::new(Duration::from_secs(0), false, None);
+        let mut scheduler = Scheduler::new(&config, &clock, &bridge, executor);
+        scheduler.paused = Some(Some(at_utc(13)));
+        scheduler.run_until(at_utc(13));
+        assert!(bridge.writes.borrow().is_empty());
+        assert!(!scheduler.tick().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Native bridge schedules approximating the day curve, so managed rooms roughly keep
+//! following it while hue_mie is not running. See `hue_mie export-schedules`.
+
+use crate::bridge::Bridge;
+use crate::config::{Config, PowerPolicy};
+use crate::curve;
+use crate::hue::Schedule;
+use crate::planner;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::info;
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Marks the schedules hue_mie owns, so a new export replaces them.
+const DESCRIPTION: &str = "hue_mie curve";
+
+/// How many schedules a bridge holds.
+const MAX_SCHEDULES: usize = 100;
+
+/// One schedule: the group action body to send at a time of day.
+#[derive(Debug, Clone)]
+pub struct ScheduleStep {
+    pub time: NaiveTime,
+    pub group_id: usize,
+    pub body: Value,
+}
+
+/// The curve in `steps` evenly spaced steps for every group with a managed scene. Each step
+/// fades to the next over the gap between them. Only lights that are on follow; at deep
+/// night lights are switched off if the scene defaults allow it.
+pub fn plan_steps(state: &BridgeState, config: &Config, steps: usize) -> Vec<ScheduleStep> {
+    let step_minutes = (24 * 60 / steps.max(1)) as i64;
+    let transitiontime = (step_minutes * 600).min(i64::from(u16::MAX)) as u16;
+    let midnight = curve::midnight(&Local::now());
+    let curve = curve::day_curve(
+        &midnight,
+        &config.transitions,
+        &config.location,
+        step_minutes,
+    );
+    let mut result = Vec::new();
+    for group_id in planner::managed_groups(state) {
+        for (minute, targets) in curve.iter() {
+            let bri = (targets.bri * 254.).round() as u8;
+            let body = if bri > 0 {
+                json!({
+                    "bri": bri,
+                    "ct": targets.mired.round() as u16,
+                    "transitiontime": transitiontime,
+                })
+            } else if config.scene_defaults.power == PowerPolicy::Toggle {
+                json!({ "on": false, "transitiontime": transitiontime })
+            } else {
+                continue;
+            };
+            result.push(ScheduleStep {
+                time: NaiveTime::MIN + chrono::Duration::minutes(*minute),
+                group_id,
+                body,
+            });
+        }
+    }
+    result
+}
+
+/// Deletes the schedules of an earlier export and returns how many there were.
+pub fn remove(bridge: &Bridge) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+    for (id, schedule) in bridge.get_all_schedules()? {
+        if schedule.description == DESCRIPTION {
+            bridge.delete_schedule(&id)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Replaces earlier exported schedules with `steps`.
+pub fn export(
+    bridge: &Bridge,
+    state: &BridgeState,
+    steps: &[ScheduleStep],
+) -> Result<(), Box<dyn Error>> {
+    let others = bridge
+        .get_all_schedules()?
+        .values()
+        .filter(|schedule| schedule.description != DESCRIPTION)
+        .count();
+    if others + steps.len() > MAX_SCHEDULES {
+        return Err(format!(
+            "{} schedules would not fit next to the {} already on the bridge; use fewer --steps",
+            steps.len(),
+            others
+        )
+        .into());
+    }
+    info!("Removed {} earlier exported schedules", remove(bridge)?);
+    for step in steps {
+        let group_name = state
+            .groups
+            .get(&step.group_id)
+            .map_or("?", |group| group.name.as_str());
+        let schedule = Schedule {
+            name: format!("hue_mie {} {}", group_name, step.time.format("%H:%M")),
+            description: DESCRIPTION.to_owned(),
+            localtime: Some(format!("W127/T{}", step.time.format("%H:%M:%S"))),
+        };
+        let path = format!("groups/{}/action", step.group_id);
+        bridge.create_schedule(&schedule, &path, &step.body)?;
+    }
+    Ok(())
+}
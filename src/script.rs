@@ -0,0 +1,124 @@
+//! User scripts that adjust the light target of each scene, configured under `[script]`.
+//!
+//! The script is written in rhai and defines `fn adjust(target, context)`. `target` has `bri`
+//! (a fraction of full brightness) and `kelvin`; `context` has `scene`, `sun_altitude`
+//! (degrees, as the curve sees it after the horizon, offset and inversion settings), `hour` and
+//! `minute`. The function returns a map with the `bri` and `kelvin` to
+//! use; missing keys keep the computed value. Scripts cannot import modules or call `eval`,
+//! and a call that runs over `max_operations` leaves the target as computed.
+
+use crate::config::ScriptConfig;
+use crate::curve::LightTarget;
+use crate::planner::TargetAdjuster;
+use chrono::prelude::*;
+use log::{debug, error, info};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::error::Error;
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+fn number(map: &Map, key: &str) -> Option<f64> {
+    let value = map.get(key)?;
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|int| int as f64))
+}
+
+impl Script {
+    /// Compiles the configured script in a sandboxed engine.
+    pub fn load(config: &ScriptConfig) -> Result<Script, Box<dyn Error>> {
+        let text = std::fs::read_to_string(&config.path)
+            .map_err(|err| format!("Could not read {}: {}", config.path.display(), err))?;
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(config.max_operations);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(1024);
+        engine.on_print(|text| info!("Script: {}", text));
+        engine.on_debug(|text, _, _| debug!("Script: {}", text));
+        let ast = engine.compile(&text)?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "adjust" && f.params.len() == 2)
+        {
+            return Err(format!(
+                "{} defines no fn adjust(target, context)",
+                config.path.display()
+            )
+            .into());
+        }
+        info!("Adjusting light targets with {}", config.path.display());
+        Ok(Script { engine, ast })
+    }
+
+    /// The script as called during one update, at `sun_altitude` (radians) and `now`.
+    pub fn at(self: &Script, sun_altitude: f64, now: &DateTime<Local>) -> ScriptTick<'_> {
+        ScriptTick {
+            script: self,
+            sun_altitude: sun_altitude.to_degrees(),
+            hour: now.hour(),
+            minute: now.minute(),
+        }
+    }
+}
+
+pub struct ScriptTick<'a> {
+    script: &'a Script,
+    sun_altitude: f64,
+    hour: u32,
+    minute: u32,
+}
+
+impl ScriptTick<'_> {
+    fn call(
+        &self,
+        scene_name: &str,
+        light_target: &LightTarget,
+    ) -> Result<LightTarget, Box<dyn Error>> {
+        let (bri, kelvin) = light_target.base();
+        let mut target = Map::new();
+        target.insert("bri".into(), Dynamic::from(bri));
+        target.insert("kelvin".into(), Dynamic::from(kelvin));
+        let mut context = Map::new();
+        context.insert("scene".into(), Dynamic::from(scene_name.to_owned()));
+        context.insert("sun_altitude".into(), Dynamic::from(self.sun_altitude));
+        context.insert("hour".into(), Dynamic::from(i64::from(self.hour)));
+        context.insert("minute".into(), Dynamic::from(i64::from(self.minute)));
+        let adjusted: Map = self.script.engine.call_fn(
+            &mut Scope::new(),
+            &self.script.ast,
+            "adjust",
+            (target, context),
+        )?;
+        let new_bri = number(&adjusted, "bri").unwrap_or(bri);
+        let new_kelvin = number(&adjusted, "kelvin").unwrap_or(kelvin);
+        if !new_bri.is_finite() || !new_kelvin.is_finite() {
+            return Err("adjust returned a brightness or temperature that is not a number".into());
+        }
+        Ok(light_target.with_base(new_bri, new_kelvin))
+    }
+}
+
+impl TargetAdjuster for ScriptTick<'_> {
+    fn adjust(&self, scene_name: &str, light_target: &LightTarget) -> LightTarget {
+        match self.call(scene_name, light_target) {
+            Ok(adjusted) => adjusted,
+            Err(err) => {
+                error!(
+                    "Script failed for scene {}, using the curve: {}",
+                    scene_name, err
+                );
+                light_target.clone()
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use crate::backend::Backend;
+use crate::hue::{Group, GroupState, Light, Scene};
+use crate::planner::LightCommand;
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// How long cached groups are trusted before they are fetched again. Rooms rarely change, so
+/// there is no need to list them every iteration.
+pub(crate) const GROUP_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+
+/// An in-memory model of the parts of the bridge the planner looks at. Scenes selected by the
+/// filter passed to `fetch`/`refresh` carry their lightstates; all other scenes are stored as
+/// listed.
+///
+/// After the initial fetch the model is refreshed incrementally: lights are listed every time
+/// (their state drives scene activity), groups only every `GROUP_REFRESH_INTERVAL`, and scene
+/// lightstates are only fetched again when the scene's `lastupdated` changed for a reason other
+/// than our own writes.
+#[derive(Clone, Debug)]
+pub struct BridgeState {
+    pub lights: BTreeMap<usize, Light>,
+    pub groups: BTreeMap<usize, Group>,
+    pub scenes: BTreeMap<String, Scene>,
+    groups_fetched: Instant,
+    written_scenes: BTreeSet<String>,
+    /// When we last switched a light on or off, in a scene (by id) or directly (`None`).
+    switched: BTreeMap<(Option<String>, usize), DateTime<Utc>>,
+    /// The time switches are recorded at and measured against; see `set_time`.
+    time: DateTime<Utc>,
+    /// Lights that appeared on the bridge but are not in a room yet.
+    unplaced: BTreeSet<usize>,
+    /// Lights that appeared on the bridge and were put in a room since the previous refresh.
+    new_lights: BTreeSet<usize>,
+}
+
+/// Logs the lights that joined or left each group.
+fn log_group_changes(old: &BTreeMap<usize, Group>, new: &BTreeMap<usize, Group>) {
+    for (group_id, group) in new.iter() {
+        let old_lights = old.get(group_id).map_or(&[][..], |old| &old.lights[..]);
+        for light in group
+            .lights
+            .iter()
+            .filter(|light| !old_lights.contains(light))
+        {
+            info!("Light {} joined group {}", light, group.name);
+        }
+        for light in old_lights
+            .iter()
+            .filter(|light| !group.lights.contains(light))
+        {
+            info!("Light {} left group {}", light, group.name);
+        }
+    }
+}
+
+/// Logs the differences between two versions of a scene: renames, lights added or removed and
+/// lightstates that changed.
+fn log_scene_changes(old: &Scene, new: &Scene) {
+    if old.name != new.name {
+        info!("Scene {} was renamed to {}", old.name, new.name);
+    }
+    for light in new
+        .lights
+        .iter()
+        .filter(|light| !old.lights.contains(light))
+    {
+        info!("Light {} was added to scene {}", light, new.name);
+    }
+    for light in old
+        .lights
+        .iter()
+        .filter(|light| !new.lights.contains(light))
+    {
+        info!("Light {} was removed from scene {}", light, new.name);
+    }
+    for (light, new_state) in new.lightstates.iter() {
+        if let Some(old_state) = old.lightstates.get(light) {
+            if old_state.on != new_state.on
+                || old_state.bri != new_state.bri
+                || old_state.ct != new_state.ct
+            {
+                info!(
+                    "Light {} in scene {} changed from {:?}/{:?}/{:?} to {:?}/{:?}/{:?} (on/bri/ct)",
+                    light,
+                    new.name,
+                    old_state.on,
+                    old_state.bri,
+                    old_state.ct,
+                    new_state.on,
+                    new_state.bri,
+                    new_state.ct
+                );
+            }
+        }
+    }
+}
+
+impl BridgeState {
+    pub fn fetch<F>(backend: &dyn Backend, with_states: F) -> Result<BridgeState, Box<dyn Error>>
+    where
+        F: Fn(&Scene) -> bool,
+    {
+        let lights = backend.get_all_lights()?;
+        let groups = backend.get_all_groups()?;
+        let mut scenes = backend.get_all_scenes()?;
+        for (scene_id, scene) in scenes.iter_mut() {
+            if with_states(scene) {
+                *scene = backend.get_scene_with_states(scene_id)?;
+            }
+        }
+        info!(
+            "Fetched bridge state: {} lights, {} groups, {} scenes",
+            lights.len(),
+            groups.len(),
+            scenes.len()
+        );
+        let mut state = BridgeState {
+            lights,
+            groups,
+            scenes,
+            groups_fetched: Instant::now(),
+            written_scenes: BTreeSet::new(),
+            switched: BTreeMap::new(),
+            time: Utc::now(),
+            unplaced: BTreeSet::new(),
+            new_lights: BTreeSet::new(),
+        };
+        state.update_group_states();
+        Ok(state)
+    }
+
+    /// Recomputes `any_on`/`all_on` of every group from the freshly listed lights, so the
+    /// cached groups reflect the current state without listing them again.
+    fn update_group_states(&mut self) {
+        let lights = &self.lights;
+        for group in self.groups.values_mut() {
+            let on: Vec<bool> = group
+                .lights
+                .iter()
+                .filter_map(|light_id| lights.get(light_id))
+                .map(|light| light.state.on)
+                .collect();
+            group.state = Some(GroupState {
+                all_on: !on.is_empty() && on.iter().all(|&on| on),
+                any_on: on.iter().any(|&on| on),
+            });
+        }
+    }
+
+    /// Lights in entertainment areas that are streaming right now.
+    pub fn streaming_lights(&self) -> BTreeSet<usize> {
+        self.groups
+            .values()
+            .filter(|group| group.stream.as_ref().is_some_and(|stream| stream.active))
+            .flat_map(|group| group.lights.iter().cloned())
+            .collect()
+    }
+
+    /// Whether any light of the group is on, or `None` for an unknown group.
+    pub fn any_on(&self, group_id: usize) -> Option<bool> {
+        self.groups
+            .get(&group_id)
+            .and_then(|group| group.state.as_ref())
+            .map(|state| state.any_on)
+    }
+
+    pub fn refresh<F>(
+        &mut self,
+        backend: &dyn Backend,
+        with_states: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn(&Scene) -> bool,
+    {
+        let lights = backend.get_all_lights()?;
+        let appeared: BTreeSet<usize> = lights
+            .keys()
+            .filter(|light_id| !self.lights.contains_key(light_id))
+            .cloned()
+            .collect();
+        let removed: BTreeSet<usize> = self
+            .lights
+            .keys()
+            .filter(|light_id| !lights.contains_key(light_id))
+            .cloned()
+            .collect();
+        for light_id in appeared.iter() {
+            info!(
+                "Light {} ({}) appeared on the bridge",
+                light_id, lights[light_id].name
+            );
+        }
+        for light_id in removed.iter() {
+            let name = &self.lights[light_id].name;
+            info!("Light {} ({}) was removed from the bridge", light_id, name);
+        }
+        self.lights = lights;
+        self.unplaced.extend(appeared.iter().cloned());
+        self.unplaced.retain(|light_id| !removed.contains(light_id));
+        if !removed.is_empty() {
+            self.forget_lights(&removed);
+        }
+
+        // Streaming starts and stops at any time, so with entertainment areas around the groups
+        // are listed every time.
+        let has_entertainment = self
+            .groups
+            .values()
+            .any(|group| group.group_type == "Entertainment");
+        // New lights are usually put in a room some time after they were added, so groups are
+        // listed every time until they are.
+        let membership_changed = !appeared.is_empty() || !removed.is_empty();
+        if has_entertainment
+            || membership_changed
+            || !self.unplaced.is_empty()
+            || self.groups_fetched.elapsed() >= GROUP_REFRESH_INTERVAL
+        {
+            let groups = backend.get_all_groups()?;
+            log_group_changes(&self.groups, &groups);
+            self.groups = groups;
+            self.groups_fetched = Instant::now();
+        }
+        let groups = &self.groups;
+        self.new_lights = self
+            .unplaced
+            .iter()
+            .filter(|light_id| {
+                groups
+                    .values()
+                    .any(|group| group.group_type == "Room" && group.lights.contains(light_id))
+            })
+            .cloned()
+            .collect();
+        for light_id in self.new_lights.iter() {
+            self.unplaced.remove(light_id);
+        }
+        self.update_group_states();
+
+        let listed = backend.get_all_scenes()?;
+        let written = std::mem::take(&mut self.written_scenes);
+        let mut scenes = BTreeMap::new();
+        for (scene_id, listed_scene) in listed {
+            if !with_states(&listed_scene) {
+                scenes.insert(scene_id, listed_scene);
+                continue;
+            }
+            let scene = match self.scenes.remove(&scene_id) {
+                Some(cached) if cached.lastupdated == listed_scene.lastupdated => cached,
+                Some(mut cached)
+                    if written.contains(&scene_id) && cached.lights == listed_scene.lights =>
+                {
+                    cached.lastupdated = listed_scene.lastupdated;
+                    cached
+                }
+                Some(cached) => {
+                    info!(
+                        "Scene {} was edited outside hue_mie (last updated {:?}), re-reading it",
+                        cached.name, listed_scene.lastupdated
+                    );
+                    let fetched = backend.get_scene_with_states(&scene_id)?;
+                    log_scene_changes(&cached, &fetched);
+                    fetched
+                }
+                None => {
+                    debug!("Fetching lightstates of scene {}", scene_id);
+                    backend.get_scene_with_states(&scene_id)?
+                }
+            };
+            scenes.insert(scene_id, scene);
+        }
+        self.scenes = scenes;
+        Ok(())
+    }
+
+    /// Drops lights that were deleted from the bridge from the cached scenes and switch times.
+    fn forget_lights(&mut self, removed: &BTreeSet<usize>) {
+        for scene in self.scenes.values_mut() {
+            scene.lights.retain(|light_id| !removed.contains(light_id));
+            scene
+                .lightstates
+                .retain(|light_id, _| !removed.contains(light_id));
+        }
+        self.switched
+            .retain(|(_, light_id), _| !removed.contains(light_id));
+    }
+
+    /// Lights that appeared on the bridge and were put in a room since the previous refresh.
+    pub fn new_lights(&self) -> &BTreeSet<usize> {
+        &self.new_lights
+    }
+
+    /// Folds the commands that were successfully applied back into the model, so the next
+    /// refresh does not need to fetch those scenes again.
+    pub fn record(&mut self, command: &LightCommand) {
+        match command {
+            LightCommand::SetSceneLightState {
+                scene_id,
+                light_id,
+                state,
+            } => {
+                if let Some(scene) = self.scenes.get_mut(scene_id) {
+                    let was_on = scene.lightstates.get(light_id).and_then(|old| old.on);
+                    if state.on.is_some() && state.on != was_on {
+                        self.switched
+                            .insert((Some(scene_id.clone()), *light_id), self.time);
+                    }
+                    scene.lightstates.insert(*light_id, state.clone());
+                    self.written_scenes.insert(scene_id.clone());
+                }
+            }
+            LightCommand::SetLightState { light_id, state } if state.on.is_some() => {
+                self.switched.insert((None, *light_id), self.time);
+            }
+            LightCommand::SetLightConfig { light_id, config } => {
+                let light_config = self
+                    .lights
+                    .get_mut(light_id)
+                    .and_then(|l| l.config.as_mut());
+                if let (Some(Value::Object(light_config)), Value::Object(changes)) =
+                    (light_config, config)
+                {
+                    for (key, value) in changes {
+                        light_config.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the time on the scheduler's clock, so switch intervals follow a simulated or
+    /// scaled clock too. Defaults to the wall-clock time of the fetch.
+    pub fn set_time(&mut self, now: DateTime<Utc>) {
+        self.time = now;
+    }
+
+    /// Whether `switched` lies less than `interval` before the current time. A switch after it,
+    /// e.g. after the clock was set back, counts as recent.
+    fn within(&self, switched: &DateTime<Utc>, interval: Duration) -> bool {
+        (self.time - *switched)
+            .to_std()
+            .map_or(true, |elapsed| elapsed < interval)
+    }
+
+    /// Whether we switched the light on or off in any scene or directly less than `interval`
+    /// ago.
+    pub fn light_switched_within(&self, light_id: usize, interval: Duration) -> bool {
+        self.switched.iter().any(|((_, switched_light), switched)| {
+            *switched_light == light_id && self.within(switched, interval)
+        })
+    }
+
+    /// The lights that are on.
+    pub fn lights_on(&self) -> BTreeSet<usize> {
+        self.lights
+            .iter()
+            .filter(|(_, light)| light.state.on)
+            .map(|(light_id, _)| *light_id)
+            .collect()
+    }
+
+    /// Whether we switched the light on or off (in `scene_id`, or directly) less than `interval`
+    /// ago.
+    pub fn switched_within(
+        &self,
+        scene_id: Option<&str>,
+        light_id: usize,
+        interval: Duration,
+    ) -> bool {
+        self.switched
+            .get(&(scene_id.map(str::to_owned), light_id))
+            .is_some_and(|switched| self.within(switched, interval))
+    }
+}
@@ -0,0 +1,125 @@
+//! Statistics gathered over a day and reported once a day, for installs nobody watches.
+
+use crate::config::SummaryConfig;
+use crate::health::NetworkHealth;
+use crate::http;
+use crate::planner::LightCommand;
+use chrono::prelude::*;
+use log::{error, info};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct DailySummary {
+    updates: usize,
+    failures: usize,
+    errors: usize,
+    scenes: BTreeSet<String>,
+    latency_total: Duration,
+    latency_samples: u32,
+    bri_range: Option<(u8, u8)>,
+    ct_range: Option<(u16, u16)>,
+    reported: Option<NaiveDate>,
+}
+
+fn widen<T: Ord + Copy>(range: Option<(T, T)>, value: T) -> Option<(T, T)> {
+    Some(range.map_or((value, value), |(min, max)| {
+        (min.min(value), max.max(value))
+    }))
+}
+
+impl DailySummary {
+    /// Counts the applied commands and the targets they carried.
+    pub fn record_commands(self: &mut DailySummary, commands: &[LightCommand], failures: usize) {
+        self.updates += commands.len();
+        self.failures += failures;
+        for command in commands {
+            let state = match command {
+                LightCommand::SetSceneLightState {
+                    scene_id, state, ..
+                } => {
+                    self.scenes.insert(scene_id.clone());
+                    state
+                }
+                LightCommand::SetLightState { state, .. } => state,
+                _ => continue,
+            };
+            if let Some(bri) = state.bri {
+                self.bri_range = widen(self.bri_range, bri);
+            }
+            if let Some(ct) = state.ct {
+                self.ct_range = widen(self.ct_range, ct);
+            }
+        }
+    }
+
+    pub fn record_error(self: &mut DailySummary) {
+        self.errors += 1;
+    }
+
+    /// Records how long reading the bridge state took.
+    pub fn record_latency(self: &mut DailySummary, latency: Duration) {
+        self.latency_total += latency;
+        self.latency_samples += 1;
+    }
+
+    fn to_json(self: &DailySummary, health: &NetworkHealth) -> Value {
+        let average_latency_ms = if self.latency_samples > 0 {
+            Some(self.latency_total.as_millis() as f64 / f64::from(self.latency_samples))
+        } else {
+            None
+        };
+        json!({
+            "updates": self.updates,
+            "failures": self.failures,
+            "errors": self.errors,
+            "scenes_managed": self.scenes.len(),
+            "average_latency_ms": average_latency_ms,
+            "bri_range": self.bri_range,
+            "ct_range": self.ct_range,
+            "troubled_lights": health.troubled_lights(),
+        })
+    }
+
+    /// Logs (and posts, if configured) the summary once the report time of a new day has passed,
+    /// then starts counting afresh, also in `health`.
+    pub fn report_if_due(
+        self: &mut DailySummary,
+        config: &SummaryConfig,
+        now: &DateTime<Local>,
+        dry_run: bool,
+        health: &mut NetworkHealth,
+    ) {
+        let report_time =
+            match NaiveTime::from_hms_opt(u32::from(config.hour), u32::from(config.minute), 0) {
+                Some(report_time) => report_time,
+                None => return,
+            };
+        if self.reported == Some(now.date_naive()) || now.time() < report_time {
+            return;
+        }
+        if self.reported.is_none() {
+            // Started after today's report time: the first report covers a full day.
+            self.reported = Some(now.date_naive());
+            return;
+        }
+        let summary = self.to_json(health);
+        health.reset_counts();
+        info!("Daily summary: {}", summary);
+        if let Some(webhook) = &config.webhook {
+            if dry_run {
+                info!("Dry run, not posting the summary to {}", webhook);
+            } else if let Err(err) = http::client()
+                .and_then(|client| client.post(webhook).json(&summary).send())
+                .and_then(|response| response.error_for_status())
+            {
+                error!("Could not post the daily summary to {}: {}", webhook, err);
+            }
+        }
+        *self = DailySummary {
+            reported: Some(now.date_naive()),
+            ..DailySummary::default()
+        };
+    }
+}
@@ -0,0 +1,109 @@
+//! Provisioning dayshift scenes from a template describing the light roles in each room.
+
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::state::BridgeState;
+use log::info;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoleTemplate {
+    /// Brightness multiplier for lights in this role.
+    #[serde(default = "RoleTemplate::default_brightness")]
+    pub brightness: f64,
+}
+
+impl RoleTemplate {
+    pub fn default_brightness() -> f64 {
+        1.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomTemplate {
+    /// Name of the bridge group (room or zone).
+    pub group: String,
+
+    /// Name of the scene to maintain, "<group> dayshift" by default.
+    #[serde(default)]
+    pub scene: Option<String>,
+
+    /// Role of each light, keyed by light id. Unlisted lights keep full brightness.
+    #[serde(default)]
+    pub lights: BTreeMap<String, String>,
+}
+
+impl RoomTemplate {
+    pub fn scene_name(self: &RoomTemplate) -> String {
+        self.scene
+            .clone()
+            .unwrap_or_else(|| format!("{} dayshift", self.group))
+    }
+}
+
+/// A template as read by `hue_mie apply-template`, e.g.
+///
+/// ```toml
+/// [roles]
+/// ambient = { brightness = 1.0 }
+/// accent = { brightness = 0.4 }
+///
+/// [[rooms]]
+/// group = "Living room"
+/// lights = { "3" = "ambient", "7" = "accent" }
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Template {
+    #[serde(default)]
+    pub roles: BTreeMap<String, RoleTemplate>,
+
+    #[serde(default)]
+    pub rooms: Vec<RoomTemplate>,
+}
+
+impl Template {
+    pub fn from_file(path: &str) -> Result<Template, Box<dyn Error>> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Creates the scenes of rooms that do not have one yet and stores the role multipliers as
+    /// per-light brightness in `config`. Nothing is created in dry-run mode.
+    pub fn apply(
+        self: &Template,
+        backend: &dyn Backend,
+        config: &mut Config,
+        dry_run: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let state = BridgeState::fetch(backend, |_| false)?;
+        for room in self.rooms.iter() {
+            let group = state
+                .groups
+                .values()
+                .find(|group| group.name.to_lowercase() == room.group.to_lowercase())
+                .ok_or_else(|| format!("No group named {:?}", room.group))?;
+            let scene_name = room.scene_name();
+            if state.scenes.values().any(|scene| scene.name == scene_name) {
+                info!("Scene {} exists, updating its roles", scene_name);
+            } else if dry_run {
+                info!("Dry run, not creating scene {}", scene_name);
+            } else {
+                let scene_id = backend.create_scene(&scene_name, &group.lights)?;
+                info!("Created scene {} with id {}", scene_name, scene_id);
+            }
+
+            let mut light_brightness = BTreeMap::new();
+            for (light_id, role) in room.lights.iter() {
+                let role_template = self
+                    .roles
+                    .get(role)
+                    .ok_or_else(|| format!("Room {} uses unknown role {}", room.group, role))?;
+                light_brightness.insert(light_id.clone(), role_template.brightness);
+            }
+            let mut scene_config = config.scene_config(&[&scene_name]);
+            scene_config.light_brightness = light_brightness;
+            config.scenes.insert(scene_name, scene_config);
+        }
+        Ok(())
+    }
+}
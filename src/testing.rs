@@ -0,0 +1,238 @@
+//! Fixtures for the unit tests: a bridge kept in memory and shorthands for the bridge objects
+//! and configs the tests are built from.
+
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::hue::{Group, Light, LightStateChange, ResourceLink, Scene};
+use crate::state::BridgeState;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// A light with a colour temperature, as the bridge lists it.
+pub fn light(on: bool, bri: u8, ct: u16) -> Light {
+    serde_json::from_value(json!({
+        "name": "Light",
+        "type": "Color temperature light",
+        "modelid": "LTW001",
+        "state": { "on": on, "bri": bri, "ct": ct, "reachable": true },
+    }))
+    .unwrap()
+}
+
+/// A room with `lights`.
+pub fn room(name: &str, lights: &[usize]) -> Group {
+    serde_json::from_value(json!({
+        "name": name,
+        "type": "Room",
+        "lights": lights.iter().map(usize::to_string).collect::<Vec<_>>(),
+    }))
+    .unwrap()
+}
+
+/// A LightScene storing `(light_id, on, bri, ct)` for each of its lights.
+pub fn scene(name: &str, lightstates: &[(usize, bool, u8, u16)]) -> Scene {
+    let states: serde_json::Map<String, Value> = lightstates
+        .iter()
+        .map(|(light_id, on, bri, ct)| {
+            (
+                light_id.to_string(),
+                json!({ "on": on, "bri": bri, "ct": ct }),
+            )
+        })
+        .collect();
+    serde_json::from_value(json!({
+        "name": name,
+        "type": "LightScene",
+        "lights": lightstates
+            .iter()
+            .map(|(light_id, ..)| light_id.to_string())
+            .collect::<Vec<_>>(),
+        "lightstates": states,
+    }))
+    .unwrap()
+}
+
+/// A config read from `toml`, defaults for everything it leaves out.
+pub fn config(toml: &str) -> Config {
+    toml::from_str(toml).unwrap()
+}
+
+/// A bridge in memory. Writes change its lights and scenes like the real bridge would, and are
+/// kept in `writes` in the order they were made.
+#[derive(Default)]
+pub struct FakeBridge {
+    pub lights: RefCell<BTreeMap<usize, Light>>,
+    pub groups: RefCell<BTreeMap<usize, Group>>,
+    pub scenes: RefCell<BTreeMap<String, Scene>>,
+    pub resourcelinks: RefCell<BTreeMap<String, ResourceLink>>,
+    pub writes: RefCell<Vec<String>>,
+}
+
+impl FakeBridge {
+    pub fn new(
+        lights: Vec<(usize, Light)>,
+        groups: Vec<(usize, Group)>,
+        scenes: Vec<(&str, Scene)>,
+    ) -> FakeBridge {
+        FakeBridge {
+            lights: RefCell::new(lights.into_iter().collect()),
+            groups: RefCell::new(groups.into_iter().collect()),
+            scenes: RefCell::new(
+                scenes
+                    .into_iter()
+                    .map(|(scene_id, scene)| (scene_id.to_owned(), scene))
+                    .collect(),
+            ),
+            resourcelinks: RefCell::default(),
+            writes: RefCell::default(),
+        }
+    }
+
+    /// The model of this bridge the planner works on, with the lightstates of every scene.
+    pub fn state(self: &FakeBridge) -> BridgeState {
+        BridgeState::fetch(self, |_| true).unwrap()
+    }
+
+    fn change_light(self: &FakeBridge, light_id: usize, change: &LightStateChange) {
+        if let Some(light) = self.lights.borrow_mut().get_mut(&light_id) {
+            let state = &mut light.state;
+            state.on = change.on.unwrap_or(state.on);
+            state.bri = change.bri.unwrap_or(state.bri);
+            state.ct = change.ct.or(state.ct);
+            if let Some(bri_inc) = change.bri_inc {
+                state.bri = (i16::from(state.bri) + bri_inc).clamp(1, 254) as u8;
+            }
+        }
+    }
+}
+
+impl Backend for FakeBridge {
+    fn get_all_lights(&self) -> Result<BTreeMap<usize, Light>, Box<dyn Error>> {
+        Ok(self.lights.borrow().clone())
+    }
+
+    fn get_all_groups(&self) -> Result<BTreeMap<usize, Group>, Box<dyn Error>> {
+        Ok(self.groups.borrow().clone())
+    }
+
+    fn get_all_scenes(&self) -> Result<BTreeMap<String, Scene>, Box<dyn Error>> {
+        Ok(self.scenes.borrow().clone())
+    }
+
+    fn get_scene_with_states(&self, scene_id: &str) -> Result<Scene, Box<dyn Error>> {
+        self.scenes
+            .borrow()
+            .get(scene_id)
+            .cloned()
+            .ok_or_else(|| format!("No scene {}", scene_id).into())
+    }
+
+    fn set_light_state(
+        &self,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("light {} {:?}", light_id, state));
+        self.change_light(light_id, state);
+        Ok(())
+    }
+
+    fn set_light_state_in_scene(
+        &self,
+        scene_id: &str,
+        light_id: usize,
+        state: &LightStateChange,
+    ) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("scene {} light {} {:?}", scene_id, light_id, state));
+        if let Some(scene) = self.scenes.borrow_mut().get_mut(scene_id) {
+            scene.lightstates.insert(light_id, state.clone());
+        }
+        Ok(())
+    }
+
+    fn recall_scene_in_group(&self, group_id: usize, scene_id: &str) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("recall {} in {}", scene_id, group_id));
+        let lightstates = self.get_scene_with_states(scene_id)?.lightstates;
+        for (light_id, state) in lightstates.iter() {
+            self.change_light(*light_id, state);
+        }
+        Ok(())
+    }
+
+    fn create_scene(&self, name: &str, lights: &[usize]) -> Result<String, Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("create {} {:?}", name, lights));
+        let scene_id = format!("created-{}", self.scenes.borrow().len());
+        let lightstates: Vec<(usize, bool, u8, u16)> = lights
+            .iter()
+            .filter_map(|light_id| {
+                let lights = self.lights.borrow();
+                let state = &lights.get(light_id)?.state;
+                Some((*light_id, state.on, state.bri, state.ct.unwrap_or(366)))
+            })
+            .collect();
+        self.scenes
+            .borrow_mut()
+            .insert(scene_id.clone(), scene(name, &lightstates));
+        Ok(scene_id)
+    }
+
+    fn set_scene_lights(&self, scene_id: &str, lights: &[usize]) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("scene {} lights {:?}", scene_id, lights));
+        if let Some(scene) = self.scenes.borrow_mut().get_mut(scene_id) {
+            scene.lights = lights.to_vec();
+        }
+        Ok(())
+    }
+
+    fn set_light_config(&self, light_id: usize, config: &Value) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("light {} config {}", light_id, config));
+        Ok(())
+    }
+
+    fn get_all_resourcelinks(&self) -> Result<BTreeMap<String, ResourceLink>, Box<dyn Error>> {
+        Ok(self.resourcelinks.borrow().clone())
+    }
+
+    fn create_resourcelink(&self, name: &str, description: &str) -> Result<String, Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("create resourcelink {} {}", name, description));
+        let id = format!("created-{}", self.resourcelinks.borrow().len());
+        self.resourcelinks.borrow_mut().insert(
+            id.clone(),
+            ResourceLink {
+                name: name.to_owned(),
+                description: description.to_owned(),
+            },
+        );
+        Ok(id)
+    }
+
+    fn set_resourcelink_description(
+        &self,
+        id: &str,
+        description: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        self.writes
+            .borrow_mut()
+            .push(format!("resourcelink {} {}", id, description));
+        if let Some(link) = self.resourcelinks.borrow_mut().get_mut(id) {
+            link.description = description.to_owned();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use crate::config::{GradientConfig, HueConfig, Location, Transitions};
+use crate::curve;
+use crate::http;
+use chrono::prelude::*;
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// Resources in CLIP v2 responses come wrapped in `{"errors": [...], "data": [...]}`.
+#[derive(Debug, Deserialize)]
+struct V2Response<T> {
+    #[serde(default)]
+    errors: Vec<V2Error>,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct V2Error {
+    description: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2Metadata {
+    pub name: String,
+
+    #[serde(default)]
+    pub appdata: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2Scene {
+    pub id: String,
+
+    /// The v1 resource path of the same scene, e.g. "/scenes/AbCdEf".
+    #[serde(default)]
+    pub id_v1: Option<String>,
+
+    pub metadata: V2Metadata,
+
+    /// The room or zone the scene belongs to.
+    #[serde(default)]
+    pub group: Option<V2ResourceRef>,
+
+    #[serde(default)]
+    pub status: Option<V2SceneStatus>,
+
+    /// Whether the scene plays its palette dynamically as soon as it is recalled, as the Hue
+    /// app's natural light scene does.
+    #[serde(default)]
+    pub auto_dynamic: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2ResourceRef {
+    pub rid: String,
+    pub rtype: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2SceneStatus {
+    /// "inactive", "static" or "dynamic_palette".
+    pub active: String,
+}
+
+/// A scene that switches between scenes by time of day, like the app's "Natural light".
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2SmartScene {
+    pub id: String,
+    pub metadata: V2Metadata,
+    pub group: V2ResourceRef,
+    /// "active" or "inactive".
+    pub state: String,
+}
+
+/// A room or zone.
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2Group {
+    pub id: String,
+
+    /// The v1 resource path of the same group, e.g. "/groups/3".
+    #[serde(default)]
+    pub id_v1: Option<String>,
+}
+
+impl V2Group {
+    pub fn v1_id(self: &V2Group) -> Option<usize> {
+        self.id_v1
+            .as_ref()
+            .and_then(|path| path.trim_start_matches("/groups/").parse().ok())
+    }
+}
+
+const MANAGED_MARKER: &str = "hue_mie;last_managed=";
+
+impl V2Scene {
+    pub fn v1_id(self: &V2Scene) -> Option<&str> {
+        self.id_v1
+            .as_ref()
+            .map(|path| path.trim_start_matches("/scenes/"))
+    }
+
+    /// When hue_mie last marked this scene as managed, if it did.
+    pub fn last_managed(self: &V2Scene) -> Option<DateTime<FixedOffset>> {
+        let appdata = self.metadata.appdata.as_ref()?;
+        if !appdata.starts_with(MANAGED_MARKER) {
+            return None;
+        }
+        DateTime::parse_from_rfc3339(&appdata[MANAGED_MARKER.len()..]).ok()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct V2Light {
+    pub id: String,
+
+    /// The v1 resource path of the same light, e.g. "/lights/7".
+    #[serde(default)]
+    pub id_v1: Option<String>,
+
+    #[serde(default)]
+    pub gradient: Option<Value>,
+}
+
+impl V2Light {
+    pub fn v1_id(self: &V2Light) -> Option<usize> {
+        self.id_v1
+            .as_ref()
+            .and_then(|path| path.trim_start_matches("/lights/").parse().ok())
+    }
+}
+
+/// A minimal client for the CLIP v2 API of a bridge, authenticated with the same application key
+/// as the v1 API.
+pub struct V2Client {
+    base_url: String,
+    application_key: String,
+    client: reqwest::blocking::Client,
+}
+
+impl V2Client {
+    pub fn new(hue_config: &HueConfig) -> Result<V2Client, Box<dyn Error>> {
+        if !http::TLS {
+            return Err("the v2 API needs HTTPS, build with native-tls or rustls".into());
+        }
+        let client = http::bridge_client()?;
+        Ok(V2Client {
+            base_url: format!("https://{}/clip/v2", hue_config.bridge_ip),
+            application_key: hue_config.bridge_password.clone(),
+            client,
+        })
+    }
+
+    fn check<T>(response: V2Response<T>) -> Result<Vec<T>, Box<dyn Error>> {
+        if let Some(error) = response.errors.first() {
+            return Err(error.description.clone().into());
+        }
+        Ok(response.data)
+    }
+
+    pub fn get_scenes(self: &V2Client) -> Result<Vec<V2Scene>, Box<dyn Error>> {
+        self.get_resources("scene")
+    }
+
+    pub fn get_lights(self: &V2Client) -> Result<Vec<V2Light>, Box<dyn Error>> {
+        self.get_resources("light")
+    }
+
+    fn get_resources<T: DeserializeOwned>(
+        self: &V2Client,
+        kind: &str,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        let response: V2Response<T> = self
+            .client
+            .get(format!("{}/resource/{}", self.base_url, kind))
+            .header("hue-application-key", self.application_key.as_str())
+            .send()?
+            .error_for_status()?
+            .json()?;
+        V2Client::check(response)
+    }
+
+    pub fn get_smart_scenes(self: &V2Client) -> Result<Vec<V2SmartScene>, Box<dyn Error>> {
+        self.get_resources("smart_scene")
+    }
+
+    /// Rooms and zones.
+    pub fn get_groups(self: &V2Client) -> Result<Vec<V2Group>, Box<dyn Error>> {
+        let mut groups: Vec<V2Group> = self.get_resources("room")?;
+        groups.extend(self.get_resources::<V2Group>("zone")?);
+        Ok(groups)
+    }
+
+    pub fn put_resource(self: &V2Client, path: &str, body: &Value) -> Result<(), Box<dyn Error>> {
+        debug!("PUT {}: {}", path, body);
+        let response: V2Response<Value> = self
+            .client
+            .put(format!("{}/resource/{}", self.base_url, path))
+            .header("hue-application-key", self.application_key.as_str())
+            .json(body)
+            .send()?
+            .error_for_status()?
+            .json()?;
+        V2Client::check(response).map(|_| ())
+    }
+}
+
+/// Builds a colour temperature palette from the curve at `points` moments spread evenly over the
+/// next `span_minutes`. When the scene plays dynamically, the bridge moves between these points
+/// by itself.
+pub fn curve_palette(
+    now: &DateTime<Local>,
+    transitions: &Transitions,
+    location: &Location,
+    points: usize,
+    span_minutes: i64,
+) -> Value {
+    let steps = points.max(2) - 1;
+    let color_temperature: Vec<Value> = (0..=steps)
+        .map(|step| {
+            let at = *now + chrono::Duration::minutes(span_minutes * step as i64 / steps as i64);
+            let targets = curve::targets_at(&at, transitions, location);
+            json!({
+                "color_temperature": { "mirek": targets.mired.round().clamp(153., 500.) as u16 },
+                "dimming": { "brightness": (targets.bri * 100.).clamp(0., 100.) },
+            })
+        })
+        .collect();
+    json!({
+        "palette": {
+            "color": [],
+            "dimming": [],
+            "color_temperature": color_temperature,
+            "effects": [],
+        }
+    })
+}
+
+/// Writes the curve palette to every v2 scene that passes `is_managed`. Returns the number of
+/// scenes updated.
+pub fn update_palettes<F>(
+    client: &V2Client,
+    palette: &Value,
+    is_managed: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    F: Fn(&V2Scene) -> bool,
+{
+    let mut updated = 0;
+    for scene in client.get_scenes()? {
+        if !is_managed(&scene) {
+            continue;
+        }
+        info!("Updating dynamic palette of scene {}", scene.metadata.name);
+        client.put_resource(&format!("scene/{}", scene.id), palette)?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+/// A gradient across the zones of a strip: the centre gets `kelvin`, the edges are shifted by the
+/// configured mired offset (positive is warmer), interpolated linearly in between. Gradient points
+/// only take colours, so the whole strip shares one brightness.
+pub fn gradient_body(kelvin: f64, bri: f64, gradient: &GradientConfig) -> Value {
+    let points = gradient.points.max(2);
+    let centre = (points - 1) as f64 / 2.;
+    let mired = curve::kelvin_to_mired(kelvin);
+    let points: Vec<Value> = (0..points)
+        .map(|idx| {
+            let distance = ((idx as f64 - centre) / centre).abs();
+            let (x, y) =
+                curve::kelvin_to_xy(1_000_000. / (mired + distance * gradient.edge_mired_offset));
+            json!({ "color": { "xy": { "x": x, "y": y } } })
+        })
+        .collect();
+    json!({
+        "gradient": { "points": points },
+        "dimming": { "brightness": (bri * 100.).clamp(1., 100.) },
+    })
+}
+
+/// Records in the scene's v2 metadata that hue_mie manages it and when it last wrote to it.
+pub fn mark_managed(
+    client: &V2Client,
+    scene: &V2Scene,
+    now: &DateTime<Local>,
+) -> Result<(), Box<dyn Error>> {
+    let body = json!({
+        "metadata": {
+            "name": scene.metadata.name,
+            "appdata": format!("{}{}", MANAGED_MARKER, now.to_rfc3339()),
+        }
+    });
+    client.put_resource(&format!("scene/{}", scene.id), &body)
+}
+
+/// The v1 ids of rooms and zones the Hue app is running adaptive lighting in: an active smart
+/// scene, or an auto-dynamic scene playing.
+pub fn adaptive_groups(client: &V2Client) -> Result<BTreeSet<usize>, Box<dyn Error>> {
+    let mut group_rids: BTreeSet<String> = client
+        .get_smart_scenes()?
+        .into_iter()
+        .filter(|smart_scene| smart_scene.state == "active")
+        .map(|smart_scene| smart_scene.group.rid)
+        .collect();
+    group_rids.extend(
+        client
+            .get_scenes()?
+            .into_iter()
+            .filter(|scene| {
+                scene.auto_dynamic
+                    && scene
+                        .status
+                        .as_ref()
+                        .is_some_and(|status| status.active == "dynamic_palette")
+            })
+            .filter_map(|scene| scene.group.map(|group| group.rid)),
+    );
+    if group_rids.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+    Ok(client
+        .get_groups()?
+        .into_iter()
+        .filter(|group| group_rids.contains(&group.id))
+        .filter_map(|group| group.v1_id())
+        .collect())
+}
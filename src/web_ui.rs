@@ -0,0 +1,351 @@
+//! A small configuration page on localhost: plots the curve, lets the transitions be tweaked
+//! with sliders, previews them on one room and saves them to the config file. Served with
+//! plain `std::net`, one request at a time, on a thread of its own.
+
+use crate::audit::Reason;
+use crate::bridge::Bridge;
+use crate::clock::SystemClock;
+use crate::config::{Config, Transitions};
+use crate::control::{self, Request};
+use crate::curve::{self, LightTarget};
+use crate::executor::Executor;
+use crate::overrides::{Override, Overrides};
+use crate::planner;
+use crate::state::BridgeState;
+use chrono::prelude::*;
+use log::{error, info};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const PAGE: &str = include_str!("web_ui.html");
+
+/// How long a room preview holds before the curve takes the room back.
+const PREVIEW_MINUTES: i64 = 5;
+const PREVIEW_COMMAND_DELAY: Duration = Duration::from_millis(100);
+/// Requests are served one at a time, so a slow or oversized one must not hold the UI.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct PreviewRequest {
+    group: String,
+    transitions: Transitions,
+    /// Minutes after local midnight to preview.
+    minute: i64,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Refuses requests that another site could have made through the user's browser: a
+    /// `Host` other than localhost (DNS rebinding), an `Origin` other than the page itself, and
+    /// POSTs without a JSON body, which a plain form cannot send.
+    fn check_origin(self: &HttpRequest, port: u16) -> Result<(), String> {
+        let host = self.host.as_deref().ok_or("No Host header")?;
+        let allowed = ["127.0.0.1", "localhost", "[::1]"]
+            .iter()
+            .any(|name| host == format!("{}:{}", name, port));
+        if !allowed {
+            return Err(format!("Refusing request for host {}", host));
+        }
+        if let Some(origin) = &self.origin {
+            if *origin != format!("http://{}", host) {
+                return Err(format!("Refusing request from {}", origin));
+            }
+        }
+        let json = self.content_type.as_deref().is_some_and(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+        });
+        if self.method == "POST" && !json {
+            return Err("POST requests must send application/json".to_owned());
+        }
+        Ok(())
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<HttpRequest, Box<dyn Error>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_BYTES));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or("Empty request")?.to_owned();
+    let path = parts.next().ok_or("No path in request")?.to_owned();
+    let mut content_length = 0;
+    let mut host = None;
+    let mut origin = None;
+    let mut content_type = None;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let mut split = header.splitn(2, ':');
+        let name = split.next().unwrap_or("").trim();
+        let value = split.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse()?;
+        } else if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("origin") {
+            origin = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.to_owned());
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(format!("Request body over {} bytes", MAX_BODY_BYTES).into());
+    }
+    let mut body = vec![0; content_length];
+    reader.get_mut().set_limit(content_length as u64);
+    reader.read_exact(&mut body)?;
+    Ok(HttpRequest {
+        method,
+        path,
+        host,
+        origin,
+        content_type,
+        body,
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        error!("Could not answer web UI request: {}", err);
+    }
+}
+
+fn respond_error(stream: &mut TcpStream, status: &str, error: &str) {
+    let body = json!({ "error": error }).to_string();
+    respond(stream, status, "application/json", &body);
+}
+
+fn midnight() -> DateTime<Local> {
+    curve::midnight(&Local::now())
+}
+
+fn curve_json(transitions: &Transitions, config: &Config) -> Value {
+    let points: Vec<Value> = curve::day_curve(&midnight(), transitions, &config.location, 10)
+        .into_iter()
+        .map(|(minute, targets)| json!({ "minute": minute, "bri": targets.bri, "kelvin": targets.kelvin }))
+        .collect();
+    json!({ "transitions": transitions, "points": points })
+}
+
+/// Shows the transitions at the requested time on one room, held by an override.
+fn preview(config: &Config, request: PreviewRequest) -> Result<Value, Box<dyn Error>> {
+    let hue_config = config.hue.as_ref().ok_or("No bridge configured")?;
+    let bridge = Bridge::new(
+        hue_config.bridge_ip.clone(),
+        hue_config.bridge_password.clone(),
+    );
+    let mut state = BridgeState::fetch(&bridge, |_| false)?;
+    let at = midnight() + chrono::Duration::minutes(request.minute);
+    let target = config.clamp_target(
+        at.hour() as u8,
+        LightTarget::at(&at, &request.transitions, &config.location),
+    );
+    let preview = Override {
+        group: request.group,
+        bri: Some(target.bri()),
+        ct: Some(target.ct()),
+        until: Utc::now() + chrono::Duration::minutes(PREVIEW_MINUTES),
+    };
+    let commands = preview.commands(&state)?;
+    let commands = planner::apply_caps(&state, config, Local::now().hour() as u8, commands);
+    let mut executor = Executor::new(PREVIEW_COMMAND_DELAY, false, None);
+    let failures = executor.apply(
+        &bridge,
+        &SystemClock,
+        &commands,
+        &mut state,
+        Reason::Override,
+    );
+    if failures > 0 {
+        return Err(format!("{} lights could not be set", failures).into());
+    }
+    Overrides::load(config.overrides_path())?.add(preview.clone())?;
+    Ok(json!({ "bri": preview.bri, "ct": preview.ct, "until": preview.until }))
+}
+
+/// Stores new transitions in the config file, leaving the rest of it as it is. The running
+/// scheduler keeps its copy, so they take effect after a restart.
+fn save(running: &Config, transitions: Transitions) -> Result<Value, Box<dyn Error>> {
+    if running.read_only {
+        return Err("The config is read-only".into());
+    }
+    if !running.include.is_empty() {
+        return Err("The config uses includes; edit the transitions by hand".into());
+    }
+    transitions.validate("transitions")?;
+    Config::write_transitions(&transitions)?;
+    Ok(json!({ "saved": Config::path(), "restart_needed": true }))
+}
+
+fn handle(config: &Config, request: HttpRequest) -> Result<(&'static str, String), Box<dyn Error>> {
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => return Ok(("text/html; charset=utf-8", PAGE.to_owned())),
+        ("GET", "/api/curve") => curve_json(&config.transitions, config),
+        ("POST", "/api/curve") => curve_json(&serde_json::from_slice(&request.body)?, config),
+        ("POST", "/api/preview") => preview(config, serde_json::from_slice(&request.body)?)?,
+        ("POST", "/api/save") => save(config, serde_json::from_slice(&request.body)?)?,
+        ("POST", "/api/resync") => {
+            control::send(&config.control_socket_path(), &Request::Resync)?;
+            json!({ "resync": true })
+        }
+        (method, path) => return Err(format!("No route for {} {}", method, path).into()),
+    };
+    Ok(("application/json", response.to_string()))
+}
+
+fn serve(config: Config, port: u16) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    info!("Web UI at http://127.0.0.1:{}/", port);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!("Web UI connection failed: {}", err);
+                continue;
+            }
+        };
+        let request = match read_request(&mut stream) {
+            Ok(request) => request,
+            Err(err) => {
+                respond_error(&mut stream, "400 Bad Request", &err.to_string());
+                continue;
+            }
+        };
+        if let Err(err) = request.check_origin(port) {
+            info!("Web UI: {}", err);
+            respond_error(&mut stream, "403 Forbidden", &err);
+            continue;
+        }
+        match handle(&config, request) {
+            Ok((content_type, body)) => respond(&mut stream, "200 OK", content_type, &body),
+            Err(err) => respond_error(&mut stream, "400 Bad Request", &err.to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// Serves the UI in the background for as long as the process runs.
+pub fn spawn(config: Config, port: u16) {
+    thread::spawn(move || {
+        if let Err(err) = serve(config, port) {
+            error!("Web UI stopped: {}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, host: &str, origin: Option<&str>, json: bool) -> HttpRequest {
+        HttpRequest {
+            method: method.to_owned(),
+            path: "/api/save".to_owned(),
+            host: Some(host.to_owned()),
+            origin: origin.map(str::to_owned),
+            content_type: if json {
+                Some("application/json; charset=utf-8".to_owned())
+            } else {
+                Some("text/plain".to_owned())
+            },
+            body: Vec::new(),
+        }
+    }
+
+    fn read(raw: &'static [u8]) -> Result<HttpRequest, String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(raw).unwrap();
+            stream
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = read_request(&mut stream).map_err(|err| err.to_string());
+        drop(client.join());
+        request
+    }
+
+    #[test]
+    fn reads_a_request() {
+        let request = read(
+            b"POST /api/curve HTTP/1.1\r\nHost: localhost:8765\r\n\
+              Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/curve");
+        assert_eq!(request.host.as_deref(), Some("localhost:8765"));
+        assert_eq!(request.body, b"{}");
+    }
+
+    #[test]
+    fn refuses_oversized_bodies() {
+        let request = read(b"POST /api/save HTTP/1.1\r\nContent-Length: 1000000000\r\n\r\n");
+        assert!(request.is_err());
+    }
+
+    #[test]
+    fn accepts_the_page_itself() {
+        let own = request(
+            "POST",
+            "127.0.0.1:8765",
+            Some("http://127.0.0.1:8765"),
+            true,
+        );
+        assert!(own.check_origin(8765).is_ok());
+        assert!(request("GET", "localhost:8765", None, false)
+            .check_origin(8765)
+            .is_ok());
+    }
+
+    #[test]
+    fn refuses_other_hosts_and_origins() {
+        let rebound = request("GET", "evil.example:8765", None, false);
+        assert!(rebound.check_origin(8765).is_err());
+        let cross_site = request("POST", "127.0.0.1:8765", Some("https://evil.example"), true);
+        assert!(cross_site.check_origin(8765).is_err());
+        let other_port = request("GET", "127.0.0.1:9000", None, false);
+        assert!(other_port.check_origin(8765).is_err());
+    }
+
+    #[test]
+    fn refuses_form_posts() {
+        let form = request(
+            "POST",
+            "127.0.0.1:8765",
+            Some("http://127.0.0.1:8765"),
+            false,
+        );
+        assert!(form.check_origin(8765).is_err());
+    }
+}